@@ -0,0 +1,131 @@
+//! Parsing and writing messages and looking up cached answers, run with `cargo bench`
+
+use std::hint::black_box;
+use std::net::Ipv4Addr;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use dns_server::buffer::TCP_MAX_LEN;
+use dns_server::cache::Cache;
+use dns_server::{BytePacketBuffer, DnsName, DnsPacket, DnsQuestion, DnsRecord, QueryType, RData};
+
+/// Responses as servers send them, with compressed names
+const FIXTURES: &[(&str, &[u8])] = &[
+    ("a", include_bytes!("../tests/fixtures/a.bin")),
+    ("mx", include_bytes!("../tests/fixtures/mx.bin")),
+    (
+        "cname_chain_long",
+        include_bytes!("../tests/fixtures/cname_chain_long.bin"),
+    ),
+];
+
+fn parse(wire: &[u8]) -> DnsPacket {
+    let mut buf = BytePacketBuffer {
+        buf: wire.to_vec(),
+        pos: 0,
+    };
+    DnsPacket::from_buffer(&mut buf).unwrap()
+}
+
+fn write(packet: &mut DnsPacket) -> Vec<u8> {
+    let mut buf = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    packet.write(&mut buf).unwrap();
+    buf.buf.truncate(buf.pos());
+    buf.buf
+}
+
+/// A response with a hundred A records, like a large round-robin set over TCP
+fn large_response() -> Vec<u8> {
+    let name = DnsName::new("pool.example.com").unwrap();
+    let mut packet = DnsPacket::new();
+    packet.header.response = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(name.clone(), QueryType::A));
+    packet.answers = (0..100)
+        .map(|i| {
+            DnsRecord::new(
+                name.clone(),
+                300,
+                RData::A {
+                    addr: Ipv4Addr::new(192, 0, 2, i),
+                },
+            )
+        })
+        .collect();
+
+    write(&mut packet)
+}
+
+fn packets() -> Vec<(&'static str, Vec<u8>)> {
+    let mut packets: Vec<_> = FIXTURES
+        .iter()
+        .map(|&(name, wire)| (name, wire.to_vec()))
+        .collect();
+    packets.push(("large", large_response()));
+    packets
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, wire) in packets() {
+        group.throughput(Throughput::Bytes(wire.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &wire, |b, wire| {
+            b.iter(|| parse(black_box(wire)));
+        });
+    }
+    group.finish();
+}
+
+fn bench_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    for (name, wire) in packets() {
+        let packet = parse(&wire);
+        group.throughput(Throughput::Bytes(wire.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &packet, |b, packet| {
+            b.iter(|| write(&mut black_box(packet).clone()));
+        });
+    }
+    group.finish();
+}
+
+/// Reading names through compression pointers against the same message written out without
+/// them, since the writer doesn't compress
+fn bench_names(c: &mut Criterion) {
+    let mut group = c.benchmark_group("names");
+    for (name, wire) in FIXTURES {
+        let uncompressed = write(&mut parse(wire));
+        group.bench_function(BenchmarkId::new("compressed", name), |b| {
+            b.iter(|| parse(black_box(wire)));
+        });
+        group.bench_function(BenchmarkId::new("uncompressed", name), |b| {
+            b.iter(|| parse(black_box(&uncompressed)));
+        });
+    }
+    group.finish();
+}
+
+fn bench_cache(c: &mut Criterion) {
+    let response = parse(FIXTURES[0].1);
+    let cache = Cache::new(10_000);
+    let names: Vec<_> = (0..1000)
+        .map(|i| DnsName::new(&format!("host{i}.example.com")).unwrap())
+        .collect();
+    for name in &names {
+        cache.insert(None, None, name, QueryType::A, &response);
+    }
+    let missing = DnsName::new("missing.example.com").unwrap();
+
+    let mut group = c.benchmark_group("cache");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("hit", |b| {
+        b.iter(|| cache.get(None, None, black_box(&names[500]), QueryType::A));
+    });
+    group.bench_function("miss", |b| {
+        b.iter(|| cache.get(None, None, black_box(&missing), QueryType::A));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_write, bench_names, bench_cache);
+criterion_main!(benches);
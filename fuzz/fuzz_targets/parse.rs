@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use dns_server::dissect;
+use dns_server::{BytePacketBuffer, DnsPacket};
+
+// Untrusted bytes off the network, which may fail to parse but must never panic
+fuzz_target!(|data: &[u8]| {
+    let mut buf = BytePacketBuffer {
+        buf: data.to_vec(),
+        pos: 0,
+    };
+    let _ = DnsPacket::from_buffer(&mut buf);
+    let _ = dissect::dissect(data);
+});
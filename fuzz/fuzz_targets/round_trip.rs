@@ -0,0 +1,49 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use dns_server::buffer::TCP_MAX_LEN;
+use dns_server::{BytePacketBuffer, DnsPacket, QueryType, RData};
+
+fn write(packet: &mut DnsPacket) -> Option<Vec<u8>> {
+    let mut buf = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    packet.write(&mut buf).ok()?;
+    buf.buf.truncate(buf.pos());
+    Some(buf.buf)
+}
+
+fn read(wire: &[u8]) -> DnsPacket {
+    let mut buf = BytePacketBuffer {
+        buf: wire.to_vec(),
+        pos: 0,
+    };
+    DnsPacket::from_buffer(&mut buf).expect("a written packet should parse")
+}
+
+// Whatever is written must read back, and once read, writing it again must give the same bytes.
+// The first write may lose what the wire can't hold, like opcodes over 4 bits, so the bytes are
+// compared from the second.
+fuzz_target!(|packet: DnsPacket| {
+    let mut packet = packet;
+    // Unknown records claiming a type that is modeled would be read back as that type, if their
+    // rdata parses at all
+    for records in [
+        &mut packet.answers,
+        &mut packet.authorities,
+        &mut packet.resources,
+    ] {
+        records.retain(|rec| match rec.rdata {
+            RData::UNKNOWN { qtype, .. } => matches!(QueryType::from(qtype), QueryType::UNKNOWN(_)),
+            _ => true,
+        });
+    }
+
+    let Some(wire) = write(&mut packet) else {
+        return;
+    };
+    let mut first = read(&wire);
+    let wire = write(&mut first).expect("a read packet should write");
+    let mut second = read(&wire);
+    let rewritten = write(&mut second).expect("a read packet should write");
+    assert_eq!(wire, rewritten);
+});
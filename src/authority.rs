@@ -0,0 +1,397 @@
+use tracing::info;
+
+use crate::config::ZoneConfig;
+use crate::dnssec::{ZoneKey, FLAGS_KSK, FLAGS_ZSK};
+use crate::error::{DnsError, Result};
+use crate::header::ResultCode;
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::{DnsRecord, RData};
+use crate::stack::Resolver;
+use crate::zone::Zone;
+
+/// The type of DS records, which aren't modeled beyond their number
+const DS: QueryType = QueryType::UNKNOWN(43);
+
+/// The zones the server answers for authoritatively
+#[derive(Debug, Default)]
+pub struct Authority {
+    zones: Vec<Zone>,
+    /// Origins of secondary zones that expired, answered with SERVFAIL
+    expired: Vec<DnsName>,
+}
+
+impl Authority {
+    pub const fn new(zones: Vec<Zone>) -> Self {
+        Self {
+            zones,
+            expired: Vec::new(),
+        }
+    }
+
+    /// Load every configured zone file, signing the zones that have keys
+    pub fn load(configs: &[ZoneConfig]) -> Result<Self> {
+        let zones = configs
+            .iter()
+            .map(|config| {
+                let mut zone = Zone::load(&config.file, &config.origin)?;
+
+                let mut keys = Vec::new();
+                if let Some(path) = &config.ksk {
+                    let ksk = ZoneKey::load(path, FLAGS_KSK)?;
+                    info!("DS for {}: {}", zone.origin, ksk.ds(&zone.origin));
+                    keys.push(ksk);
+                }
+                if let Some(path) = &config.zsk {
+                    keys.push(ZoneKey::load(path, FLAGS_ZSK)?);
+                }
+                if !keys.is_empty() {
+                    zone.sign(keys);
+                }
+
+                Ok(zone)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self::new(zones))
+    }
+
+    /// Refresh the signatures of every signed zone that are about to expire. Returns the origins of
+    /// the zones that changed.
+    pub fn resign(&mut self) -> Vec<DnsName> {
+        let mut changed = Vec::new();
+        for zone in self.zones.iter_mut().filter(|zone| zone.is_signed()) {
+            let serial = zone.serial();
+            zone.resign();
+            if zone.serial() != serial {
+                changed.push(zone.origin.clone());
+            }
+        }
+
+        changed
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    /// The most specific zone containing `qname`
+    pub fn find_zone(&self, qname: &DnsName) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| qname.is_subdomain_of(&zone.origin))
+            .max_by_key(|zone| zone.origin.label_count())
+    }
+
+    /// Every record of the zone with its apex at `origin`, bracketed by its SOA as sent in an AXFR
+    pub fn transfer(&self, origin: &DnsName) -> Option<Vec<DnsRecord>> {
+        self.current_zone(origin)?.transfer_records()
+    }
+
+    /// The changes to the zone at `origin` since `serial` as sent in an IXFR, falling back to the
+    /// whole zone when they aren't all in the journal
+    pub fn incremental_transfer(&self, origin: &DnsName, serial: u32) -> Option<Vec<DnsRecord>> {
+        let zone = self.current_zone(origin)?;
+
+        zone.incremental_records(serial)
+            .or_else(|| zone.transfer_records())
+    }
+
+    /// The zone with its apex at `origin`, for changing it
+    pub fn zone_mut(&mut self, origin: &DnsName) -> Option<&mut Zone> {
+        self.zones.iter_mut().find(|zone| zone.origin == *origin)
+    }
+
+    /// The zone with its apex at `origin`
+    pub fn zone(&self, origin: &DnsName) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.origin == *origin)
+    }
+
+    /// Serve `zone`, in place of the one with the same origin if there is one
+    pub fn insert(&mut self, zone: Zone) {
+        self.renew(&zone.origin);
+        match self.zone_mut(&zone.origin) {
+            Some(served) => *served = zone,
+            None => self.zones.push(zone),
+        }
+    }
+
+    /// Stop serving the zone at `origin`, returning it
+    pub fn remove(&mut self, origin: &DnsName) -> Option<Zone> {
+        self.renew(origin);
+        let index = self.zones.iter().position(|zone| zone.origin == *origin)?;
+        Some(self.zones.remove(index))
+    }
+
+    /// Answer SERVFAIL for the zone at `origin` until a new version of it is inserted, for
+    /// secondaries that couldn't be refreshed for too long
+    pub fn expire(&mut self, origin: &DnsName) {
+        if !self.expired.contains(origin) {
+            self.expired.push(origin.clone());
+        }
+    }
+
+    /// Answer from the zone at `origin` again after it expired
+    pub fn renew(&mut self, origin: &DnsName) {
+        self.expired.retain(|expired| expired != origin);
+    }
+
+    /// The zone at `origin`, unless it expired
+    fn current_zone(&self, origin: &DnsName) -> Option<&Zone> {
+        self.zone(origin).filter(|_| !self.expired.contains(origin))
+    }
+
+    /// Answer a question from the loaded zones. Returns `None` when the name isn't inside any of
+    /// them, so the caller can fall back to forwarding. With `dnssec_ok`, answers from signed zones
+    /// carry their signatures and the NSEC records proving what doesn't exist.
+    pub fn lookup(&self, qname: &DnsName, qtype: QueryType, dnssec_ok: bool) -> Option<DnsPacket> {
+        let mut zone = self.find_zone(qname)?;
+        // DS records are on the parent side of a zone cut, so the parent answers for them when it's
+        // served too, RFC 4035 section 3.1.4.1
+        if qtype == DS && zone.origin == *qname {
+            if let Some(parent) = qname.parent().and_then(|parent| self.find_zone(&parent)) {
+                zone = parent;
+            }
+        }
+        let dnssec = dnssec_ok && zone.is_signed();
+        let mut packet = DnsPacket::new();
+
+        if self.expired.contains(&zone.origin) {
+            packet.header.rescode = ResultCode::SERVFAIL;
+            return Some(packet);
+        }
+
+        // Names at or below a delegation point belong to the child zone, so refer the client there
+        if let Some(cut) = delegation(zone, qname, qtype) {
+            packet
+                .authorities
+                .extend(records_at(zone, &cut, QueryType::NS).cloned());
+            // Without a DS, the NSEC at the cut proves the child zone is unsigned
+            if dnssec {
+                add_proof(zone, &cut, &mut packet.authorities);
+            }
+            packet.resources = glue(zone, &packet.authorities);
+            return Some(packet);
+        }
+
+        packet.header.authoritative_answer = true;
+
+        let mut name = qname.clone();
+        // Bound the CNAME chain by the zone size so a loop can't spin forever
+        for _ in 0..=zone.records.len() {
+            let Some(records) = node_records(zone, &name) else {
+                packet.header.rescode = ResultCode::NXDOMAIN;
+                break;
+            };
+            // Answers synthesized from a wildcard are only valid if the name doesn't exist itself
+            if dnssec && !zone.exists(&name) {
+                add_proof(zone, &name, &mut packet.authorities);
+            }
+
+            let owned: Vec<_> = records
+                .iter()
+                .filter(|rec| rec.answers(qtype))
+                .cloned()
+                .collect();
+            if !owned.is_empty() {
+                packet.answers.extend(owned);
+                if dnssec {
+                    packet.answers.extend(signatures(&records, &name, qtype));
+                }
+                return Some(packet);
+            }
+
+            // Follow aliases within the zone; targets elsewhere are left to the client
+            let cname = records
+                .iter()
+                .find(|rec| rec.qtype() == QueryType::CNAME)
+                .cloned();
+            match cname.as_ref().map(|rec| &rec.rdata) {
+                Some(RData::CNAME { host }) if qtype != QueryType::CNAME => {
+                    let host = host.clone();
+                    packet.answers.extend(cname);
+                    if dnssec {
+                        packet
+                            .answers
+                            .extend(signatures(&records, &name, QueryType::CNAME));
+                    }
+                    if !host.is_subdomain_of(&zone.origin) {
+                        return Some(packet);
+                    }
+                    name = host;
+                }
+                _ => break,
+            }
+        }
+
+        // Negative answers carry the SOA so resolvers know how long to cache them
+        packet.authorities.extend(negative_soa(zone));
+        if dnssec {
+            packet
+                .authorities
+                .extend(signatures(&zone.records, &zone.origin, QueryType::SOA));
+            add_proof(zone, &name, &mut packet.authorities);
+        }
+
+        Some(packet)
+    }
+}
+
+/// Answers without DNSSEC records, names outside the zones get no answer
+impl Resolver for Authority {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        self.lookup(&question.name, question.qtype, false)
+            .ok_or_else(|| DnsError::NoAnswer(question.name.to_string()))
+    }
+}
+
+/// All records owned by `name`, or `None` if the name doesn't exist
+///
+/// Names that don't exist are synthesized from a wildcard at their closest encloser if there is
+/// one, following RFC 4592: `*.example.com` answers for `a.example.com` and `a.b.example.com`, but
+/// not for `b.example.com` when that exists itself, or has anything below it.
+fn node_records(zone: &Zone, name: &DnsName) -> Option<Vec<DnsRecord>> {
+    let owned_by = |owner: &DnsName| -> Vec<DnsRecord> {
+        zone.records
+            .iter()
+            .filter(|rec| rec.domain() == owner)
+            .cloned()
+            .collect()
+    };
+
+    if zone.exists(name) {
+        return Some(owned_by(name));
+    }
+
+    let mut candidate = name.parent()?;
+    while !zone.exists(&candidate) {
+        if candidate == zone.origin {
+            return None;
+        }
+        candidate = candidate.parent()?;
+    }
+
+    let wildcard = candidate.child("*").ok()?;
+    if !zone.exists(&wildcard) {
+        return None;
+    }
+
+    let mut records = owned_by(&wildcard);
+    for rec in &mut records {
+        rec.set_domain(name.clone());
+    }
+
+    Some(records)
+}
+
+/// Records in `zone` owned by `name` with type `qtype`
+fn records_at<'a>(
+    zone: &'a Zone,
+    name: &'a DnsName,
+    qtype: QueryType,
+) -> impl Iterator<Item = &'a DnsRecord> {
+    zone.records
+        .iter()
+        .filter(move |rec| rec.qtype() == qtype && rec.domain() == name)
+}
+
+/// The delegation point closest to the apex between the apex and `qname`, if there is one. A DS
+/// query for the cut itself isn't referred, the parent holds those records.
+fn delegation(zone: &Zone, qname: &DnsName, qtype: QueryType) -> Option<DnsName> {
+    let mut cut = None;
+    let mut name = qname.clone();
+    if qtype == DS {
+        name = name.parent()?;
+    }
+    while name != zone.origin {
+        if records_at(zone, &name, QueryType::NS).next().is_some() {
+            cut = Some(name.clone());
+        }
+        name = name.parent()?;
+    }
+
+    cut
+}
+
+/// Addresses from the zone for nameservers named in a referral
+fn glue(zone: &Zone, ns_records: &[DnsRecord]) -> Vec<DnsRecord> {
+    ns_records
+        .iter()
+        .filter_map(|rec| match &rec.rdata {
+            RData::NS { host } => Some(host),
+            _ => None,
+        })
+        .flat_map(|host| {
+            records_at(zone, host, QueryType::A).chain(records_at(zone, host, QueryType::AAAA))
+        })
+        .cloned()
+        .collect()
+}
+
+/// The signatures among `records` over the RRset of `owner` with type `qtype`
+fn signatures(records: &[DnsRecord], owner: &DnsName, qtype: QueryType) -> Vec<DnsRecord> {
+    records
+        .iter()
+        .filter(|rec| {
+            matches!(rec.rdata, RData::RRSIG { type_covered, .. } if type_covered == qtype)
+                && rec.domain() == owner
+        })
+        .cloned()
+        .collect()
+}
+
+/// Add the signed NSEC records proving what the zone has at `name` to a section, skipping those
+/// already in it
+///
+/// That is the NSEC owned by `name` or covering the gap where it would be, and for names that
+/// don't exist the one for the wildcard at their closest encloser, which shows whether the wildcard
+/// could have answered instead.
+fn add_proof(zone: &Zone, name: &DnsName, section: &mut Vec<DnsRecord>) {
+    let mut names = vec![name.clone()];
+    if !zone.exists(name) {
+        let mut encloser = name.clone();
+        while !zone.exists(&encloser) && encloser != zone.origin {
+            let Some(parent) = encloser.parent() else {
+                break;
+            };
+            encloser = parent;
+        }
+        names.extend(encloser.child("*").ok());
+    }
+
+    for name in &names {
+        let Some(nsec) = covering_nsec(zone, name) else {
+            continue;
+        };
+        if section.contains(nsec) {
+            continue;
+        }
+        section.push(nsec.clone());
+        section.extend(signatures(&zone.records, nsec.domain(), QueryType::NSEC));
+    }
+}
+
+/// The NSEC record owned by `name`, or spanning the gap in canonical order it would sort into
+fn covering_nsec<'a>(zone: &'a Zone, name: &DnsName) -> Option<&'a DnsRecord> {
+    zone.records.iter().find(|rec| match &rec.rdata {
+        RData::NSEC { next, .. } => {
+            let domain = &rec.name;
+            // The last NSEC of the chain wraps around to the apex
+            let last = next.canonical_cmp(domain).is_le();
+            domain.canonical_cmp(name).is_le() && (name.canonical_cmp(next).is_lt() || last)
+        }
+        _ => false,
+    })
+}
+
+/// The zone SOA with its TTL lowered to the negative caching TTL from RFC 2308
+fn negative_soa(zone: &Zone) -> Option<DnsRecord> {
+    let mut soa = zone.soa()?.clone();
+    let RData::SOA { minimum, .. } = soa.rdata else {
+        return None;
+    };
+    soa.ttl = soa.ttl.min(minimum);
+
+    Some(soa)
+}
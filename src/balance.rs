@@ -0,0 +1,174 @@
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::config::{BalancedConfig, HealthCheck, TargetConfig};
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::{DnsRecord, RData};
+
+/// How long a health check may take before the target counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A balanced name with whether each of its targets is up
+#[derive(Debug)]
+struct Pool {
+    config: BalancedConfig,
+    /// Up or down for each target, in the order of the config. Targets start out up.
+    up: Mutex<Vec<bool>>,
+}
+
+/// Names answered with addresses picked among healthy targets by weight
+#[derive(Debug, Default)]
+pub struct Balancer {
+    pools: Vec<Pool>,
+}
+
+impl Balancer {
+    pub fn new(configs: &[BalancedConfig]) -> Self {
+        let pools = configs
+            .iter()
+            .map(|config| Pool {
+                up: Mutex::new(vec![true; config.targets.len()]),
+                config: config.clone(),
+            })
+            .collect();
+
+        Self { pools }
+    }
+
+    /// Names with health checks, to check each on its interval by index with [`Self::check`]
+    pub fn checked(&self) -> impl Iterator<Item = (usize, Duration)> + '_ {
+        self.pools.iter().enumerate().filter_map(|(i, pool)| {
+            pool.config
+                .check
+                .as_ref()
+                .map(|_| (i, Duration::from_secs(pool.config.check_interval)))
+        })
+    }
+
+    /// Check every target of the name at `index`, logging the ones that went down or came back
+    pub fn check(&self, index: usize) {
+        let Some(pool) = self.pools.get(index) else {
+            return;
+        };
+        let Some(check) = &pool.config.check else {
+            return;
+        };
+
+        let results: Vec<bool> = pool
+            .config
+            .targets
+            .iter()
+            .map(|target| is_up(target.addr, check))
+            .collect();
+        let mut up = pool.up.lock().unwrap_or_else(PoisonError::into_inner);
+        for ((target, was_up), now_up) in pool.config.targets.iter().zip(up.iter()).zip(&results) {
+            match (was_up, now_up) {
+                (true, false) => warn!("Target {} of {} is down", target.addr, pool.config.name),
+                (false, true) => info!("Target {} of {} is back up", target.addr, pool.config.name),
+                _ => {}
+            }
+        }
+        *up = results;
+    }
+
+    pub fn contains(&self, qname: &DnsName) -> bool {
+        self.pools.iter().any(|pool| pool.config.name == *qname)
+    }
+
+    /// Answer a question about a balanced name, or `None` if it isn't one. The targets of the
+    /// family asked for come in a random order weighted by their weights, healthy ones only unless
+    /// none are.
+    pub fn lookup(&self, qname: &DnsName, qtype: QueryType) -> Option<DnsPacket> {
+        let pool = self.pools.iter().find(|pool| pool.config.name == *qname)?;
+        let mut packet = DnsPacket::new();
+        packet.header.authoritative_answer = true;
+
+        let up = pool
+            .up
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let family: Vec<_> = pool
+            .config
+            .targets
+            .iter()
+            .zip(up)
+            .filter(|(target, _)| match qtype {
+                QueryType::A => target.addr.is_ipv4(),
+                QueryType::AAAA => target.addr.is_ipv6(),
+                _ => false,
+            })
+            .collect();
+
+        // Fail open: answering with a target that may be down beats not answering at all
+        let healthy: Vec<&TargetConfig> = family
+            .iter()
+            .filter(|(_, up)| *up)
+            .map(|(target, _)| *target)
+            .collect();
+        let candidates = if healthy.is_empty() {
+            family.iter().map(|(target, _)| *target).collect()
+        } else {
+            healthy
+        };
+        // Targets without weight are only answered when no other target is
+        let weighted: Vec<&TargetConfig> = candidates
+            .iter()
+            .copied()
+            .filter(|target| target.weight > 0)
+            .collect();
+        let candidates = if weighted.is_empty() {
+            candidates
+        } else {
+            weighted
+        };
+
+        // Weighted sampling without replacement, by sorting on a random key per target that tends
+        // to be larger the heavier the target is (Efraimidis and Spirakis)
+        let mut keyed: Vec<(f64, IpAddr)> = candidates
+            .into_iter()
+            .map(|target| {
+                let weight = f64::from(target.weight.max(1));
+                (rand::random::<f64>().powf(1.0 / weight), target.addr)
+            })
+            .collect();
+        keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let count = pool.config.count.unwrap_or(keyed.len());
+        packet.answers = keyed
+            .into_iter()
+            .take(count)
+            .map(|(_, addr)| match addr {
+                IpAddr::V4(addr) => {
+                    DnsRecord::new(qname.clone(), pool.config.ttl, RData::A { addr })
+                }
+                IpAddr::V6(addr) => {
+                    DnsRecord::new(qname.clone(), pool.config.ttl, RData::AAAA { addr })
+                }
+            })
+            .collect();
+
+        Some(packet)
+    }
+}
+
+fn is_up(addr: IpAddr, check: &HealthCheck) -> bool {
+    match check {
+        HealthCheck::Tcp(port) => {
+            TcpStream::connect_timeout(&SocketAddr::new(addr, *port), CHECK_TIMEOUT).is_ok()
+        }
+        HealthCheck::Http { port, path } => {
+            let url = format!("http://{}{path}", SocketAddr::new(addr, *port));
+            let agent: ureq::Agent = ureq::Agent::config_builder()
+                .timeout_global(Some(CHECK_TIMEOUT))
+                .build()
+                .into();
+            agent.get(&url).call().is_ok()
+        }
+    }
+}
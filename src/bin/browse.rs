@@ -0,0 +1,44 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::Parser;
+
+use dns_server::dnssd::{browse, Transport};
+use dns_server::resolver::{parse_server, RetryPolicy};
+use dns_server::DnsName;
+
+#[derive(Debug, Parser)]
+#[command(about = "List the instances of a service found with DNS-SD")]
+struct Args {
+    /// Service type to browse for, like `_http._tcp.local` or `_ipp._tcp.example.com`
+    #[arg(default_value = "_http._tcp.local")]
+    service: DnsName,
+
+    /// Unicast server to query instead of multicasting to the local network, an IPv4 or IPv6
+    /// address with an optional port
+    #[arg(short, long, value_parser = parse_server)]
+    server: Option<SocketAddr>,
+
+    /// Milliseconds to collect multicast responses for, or to wait for the unicast server
+    #[arg(long, value_name = "MS", default_value_t = 2000)]
+    timeout: u64,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let timeout = Duration::from_millis(args.timeout);
+    let transport = match args.server {
+        Some(server) => Transport::Unicast(server, RetryPolicy::new(timeout, 2)),
+        None => Transport::Multicast(timeout),
+    };
+
+    for instance in browse(&args.service, transport)? {
+        println!("{}\t{}:{}", instance.label(), instance.host, instance.port);
+        for txt in instance.txt.iter().filter(|txt| !txt.is_empty()) {
+            println!("\t{}", String::from_utf8_lossy(txt));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+
+#[derive(Debug, Parser)]
+#[command(about = "Send a command to the control socket of a running server")]
+struct Args {
+    /// Address the server listens for control commands on, `control` in its config
+    #[arg(short, long, default_value = "127.0.0.1:8953")]
+    server: SocketAddr,
+
+    /// flush-cache, flush-name <name>, dump-cache [domain], reload, stats or
+    /// set-log-level <filter>
+    #[arg(required = true)]
+    command: Vec<String>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut stream = TcpStream::connect(args.server)?;
+    writeln!(stream, "{}", args.command.join(" "))?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut output = String::new();
+    stream.read_to_string(&mut output)?;
+    if let Some(e) = output.strip_prefix("error: ") {
+        bail!("{}", e.trim_end());
+    }
+    print!("{output}");
+
+    Ok(())
+}
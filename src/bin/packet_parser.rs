@@ -1,19 +1,77 @@
 #![warn(clippy::all, clippy::nursery, rust_2018_idioms)]
 
-use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::Parser;
 
-use dns_thingy::packet_parser::{BytePacketBuffer, DnsPacket};
+use dns_server::dissect;
+use dns_server::pcap::{self, CapturedMessage};
+use dns_server::{BytePacketBuffer, DnsPacket};
+
+#[derive(Debug, Parser)]
+#[command(about = "Pretty-print DNS messages from raw packet files or pcap and pcapng captures")]
+struct Args {
+    /// Files holding a single message as sent over UDP, or captures whose DNS traffic over UDP
+    /// and TCP is printed message by message
+    #[arg(default_value = "response_packet")]
+    files: Vec<PathBuf>,
+
+    /// Print a hex dump of each message instead, with what every header field, label,
+    /// compression pointer and record field means next to its bytes
+    #[arg(long)]
+    dissect: bool,
+}
 
 fn main() -> Result<()> {
-    let mut f = File::open("response_packet")?;
-    let mut buf = BytePacketBuffer::new();
-    let _ = f.read(&mut buf.buf)?;
+    let args = Args::parse();
+
+    for path in &args.files {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        if !pcap::is_capture(&data) {
+            if args.dissect {
+                print!("{}", dissect::annotate(&data, &dissect::dissect(&data)));
+            } else {
+                println!("{:#?}", parse(data)?);
+            }
+            continue;
+        }
 
-    let packet = DnsPacket::from_buffer(&mut buf)?;
-    println!("{packet:#?}");
+        let messages = pcap::read_messages(&data)
+            .with_context(|| format!("Failed to read capture {}", path.display()))?;
+        for message in messages {
+            print_message(message, args.dissect);
+        }
+    }
 
     Ok(())
 }
+
+fn parse(wire: Vec<u8>) -> dns_server::Result<DnsPacket> {
+    let mut buf = BytePacketBuffer { buf: wire, pos: 0 };
+    DnsPacket::from_buffer(&mut buf)
+}
+
+/// A line with when and between whom the message was sent, then the message itself
+fn print_message(message: CapturedMessage, dissect: bool) {
+    let transport = if message.tcp { "TCP" } else { "UDP" };
+    println!(
+        ";; {}.{:06} {} -> {} {transport}, {} bytes",
+        message.time.as_secs(),
+        message.time.subsec_micros(),
+        message.src,
+        message.dst,
+        message.wire.len()
+    );
+    if dissect {
+        let fields = dissect::dissect(&message.wire);
+        print!("{}", dissect::annotate(&message.wire, &fields));
+    } else {
+        match parse(message.wire) {
+            Ok(packet) => println!("{packet:#?}"),
+            Err(e) => println!(";; Malformed message: {e}"),
+        }
+    }
+    println!();
+}
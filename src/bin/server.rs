@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer};
+
+use dns_server::config::Config;
+use dns_server::control::LogLevel;
+use dns_server::daemon;
+use dns_server::server::{self, Listeners, ServerContext};
+
+#[derive(Debug, Parser)]
+#[command(about = "Serve configured zones authoritatively and forward everything else")]
+struct Args {
+    /// Path to a TOML config file, the defaults are used without one
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Milliseconds to wait for the upstream before retrying, overrides the config
+    #[arg(long, value_name = "MS")]
+    timeout: Option<u64>,
+
+    /// How many times a forwarded query is retried, overrides the config
+    #[arg(long)]
+    retries: Option<u32>,
+
+    /// How log lines are written. Verbosity is set with `RUST_LOG`, e.g. `RUST_LOG=debug` to
+    /// follow each query through to the upstreams.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Append log lines to this file instead of writing them to stdout
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Fork into the background once the config is loaded, detached from the terminal. Log lines
+    /// only go to `--log-file` then.
+    #[arg(long)]
+    daemon: bool,
+
+    /// Write the PID of the server to this file
+    #[arg(long, value_name = "PATH")]
+    pidfile: Option<PathBuf>,
+
+    /// Switch to this user and its group once the sockets are bound, so binding port 53 is the
+    /// only thing done as root
+    #[arg(long)]
+    user: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    // Reloadable, so the control socket can change the level
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    let writer = match &args.log_file {
+        Some(path) => {
+            let file = File::options().create(true).append(true).open(path)?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(io::stdout),
+    };
+    let layer = match args.log_format {
+        LogFormat::Text => fmt::layer()
+            .with_writer(writer)
+            .with_ansi(args.log_file.is_none())
+            .boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .init();
+
+    let mut config = match args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(timeout) = args.timeout {
+        config.query_timeout = timeout;
+    }
+    if let Some(retries) = args.retries {
+        config.query_retries = retries;
+    }
+
+    // Before anything starts a thread, which wouldn't be forked along
+    if args.daemon {
+        daemon::daemonize(args.pidfile.as_deref())?;
+    } else if let Some(path) = &args.pidfile {
+        daemon::write_pidfile(path, std::process::id())?;
+    }
+    let listeners = match Listeners::from_systemd()? {
+        Some(listeners) => listeners,
+        None => Listeners::bind(config.listen)?,
+    };
+    if let Some(user) = &args.user {
+        daemon::drop_privileges(user)?;
+        info!("Running as {user}");
+    }
+
+    let mut context = ServerContext::new(config)?;
+    context.log_level = Some(LogLevel::new(move |filter| {
+        let filter = EnvFilter::try_new(filter).map_err(|e| e.to_string())?;
+        filter_handle.reload(filter).map_err(|e| e.to_string())
+    }));
+    let context = Arc::new(context);
+    server::serve(context, listeners)?;
+
+    Ok(())
+}
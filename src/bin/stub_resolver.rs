@@ -1,33 +1,298 @@
-use std::net::UdpSocket;
+use std::net::{IpAddr, SocketAddr};
+use std::thread;
+use std::time::Duration;
 
 use anyhow::Result;
+use clap::Parser;
 
-use dns_thingy::packet_parser::{BytePacketBuffer, DnsPacket, DnsQuestion, QueryType};
+use dns_server::edns::{EdnsOption, OPTION_NSID};
+use dns_server::resolv_conf::ResolvConf;
+use dns_server::resolver::{
+    lookup_observed, parse_server, recursive_lookup_traced, reverse_name, RetryPolicy, DNS_PORT,
+};
+use dns_server::socks::Socks5Proxy;
+use dns_server::{DnsName, DnsPacket, DnsRecord, QueryType, RData, ResultCode};
+
+#[derive(Debug, Parser)]
+#[command(about = "Send queries to an upstream resolver and print the responses")]
+struct Args {
+    /// Domain names to look up. Names without a trailing dot are completed with the search domains
+    /// of resolv.conf, like getaddrinfo does.
+    #[arg(default_value = "google.com")]
+    qnames: Vec<String>,
+
+    /// Record types to query for, comma separated; every type is queried for every name
+    #[arg(short = 't', long = "type", default_value = "A", value_delimiter = ',')]
+    qtypes: Vec<QueryType>,
+
+    /// Upstream server to send the query to, an IPv4 or IPv6 address with an optional port like
+    /// `[2001:db8::53]:5353`, or `system` for the first nameserver the system uses: from
+    /// resolv.conf, `scutil --dns` on macOS or the registry on Windows
+    #[arg(short, long, default_value = "system", value_parser = parse_upstream)]
+    server: Upstream,
+
+    /// Only look names up as they are given, without the search domains
+    #[arg(long)]
+    no_search: bool,
+
+    /// Refuse names that aren't hostnames, made of letters, digits and hyphens, before sending
+    /// anything
+    #[arg(long)]
+    check_hostnames: bool,
+
+    /// Only print the rdata of the answer records, like `dig +short`
+    #[arg(long)]
+    short: bool,
+
+    /// Reverse lookup: query the PTR record for an IPv4 or IPv6 address and print the hostname
+    #[arg(short = 'x', value_name = "ADDR", conflicts_with = "qnames")]
+    reverse: Option<IpAddr>,
+
+    /// Resolve iteratively from the root servers and print every referral along the way, like
+    /// `dig +trace`
+    #[arg(long, conflicts_with = "server")]
+    trace: bool,
+
+    /// Display internationalized names in their Unicode form instead of punycode
+    #[arg(long)]
+    idn: bool,
+
+    /// Ask the server to identify itself with the NSID option and print what it sends back,
+    /// useful to tell apart instances behind an anycast address
+    #[arg(long, conflicts_with = "trace")]
+    nsid: bool,
+
+    /// Send the queries through the SOCKS5 proxy at this address, such as Tor on
+    /// `127.0.0.1:9050`, over UDP if it relays UDP and TCP otherwise
+    #[arg(long, value_name = "ADDR", conflicts_with = "trace")]
+    socks5: Option<SocketAddr>,
+
+    /// Milliseconds to wait for a response before retrying, doubled on every retry
+    #[arg(long, value_name = "MS", default_value_t = 2000)]
+    timeout: u64,
+
+    /// How many times a query is retried when no response arrives
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+}
 
 fn main() -> Result<()> {
-    let qname = "google.com";
-    let qtype = QueryType::A;
-    let server = ("8.8.8.8", 53);
-    let socket = UdpSocket::bind(("0.0.0.0", 1234))?;
+    let mut args = Args::parse();
+    if let Some(addr) = args.reverse {
+        args.qnames = vec![reverse_name(addr).to_fqdn()];
+        args.qtypes = vec![QueryType::PTR];
+        args.short = true;
+    }
+
+    if args.check_hostnames {
+        for qname in &args.qnames {
+            DnsName::new(qname)?.check_hostname()?;
+        }
+    }
+
+    let resolv_conf = ResolvConf::system();
+    let server = match args.server {
+        Upstream::System => SocketAddr::new(resolv_conf.nameserver(), DNS_PORT),
+        Upstream::Server(server) => server,
+    };
+    // Traces start from the root servers, so only absolute names make sense
+    let search = !args.no_search && !args.trace;
+    let search_names = args
+        .qnames
+        .iter()
+        .map(|qname| {
+            if search {
+                resolv_conf.search_names(qname)
+            } else {
+                DnsName::new(qname).map(|qname| vec![qname])
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let mut packet = DnsPacket::new();
-    packet.header.id = 666;
-    packet.header.questions = 1;
-    packet.header.recursion_desired = true;
-    packet
-        .questions
-        .push(DnsQuestion::new(qname.to_string(), qtype));
+    let questions: Vec<(&str, &[DnsName], QueryType)> = args
+        .qnames
+        .iter()
+        .zip(&search_names)
+        .flat_map(|(qname, names)| {
+            args.qtypes
+                .iter()
+                .map(move |&qtype| (qname.as_str(), names.as_slice(), qtype))
+        })
+        .collect();
+    let grouped = questions.len() > 1;
+    let policy = RetryPolicy::new(Duration::from_millis(args.timeout), args.retries);
+    let proxy = args.socks5.map(Socks5Proxy::new);
 
-    let mut req_buf = BytePacketBuffer::new();
-    packet.write(&mut req_buf)?;
+    if args.trace {
+        // Every step, including the final answer, is printed as it arrives, so traces are run one
+        // after the other to keep their output apart
+        for &(qname, names, qtype) in &questions {
+            if grouped {
+                println!(";; {qname} {qtype:?}");
+            }
+            recursive_lookup_traced(&names[0], qtype, policy, &mut |server, packet| {
+                print_trace_step(server, packet, args.idn);
+            })?;
+        }
+        return Ok(());
+    }
 
-    socket.send_to(&req_buf.buf[0..req_buf.pos], server)?;
+    let responses: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = questions
+            .iter()
+            .map(|&(_, names, qtype)| {
+                let proxy = proxy.as_ref();
+                s.spawn(move || {
+                    search_lookup(names, |qname| {
+                        let options = args.nsid.then(|| {
+                            vec![EdnsOption {
+                                code: OPTION_NSID,
+                                data: Vec::new(),
+                            }]
+                        });
+                        lookup_observed(qname, qtype, server, options, policy, proxy, &|_| {})
+                    })
+                })
+            })
+            .collect();
 
-    let mut res_buf = BytePacketBuffer::new();
-    socket.recv_from(&mut res_buf.buf)?;
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("lookup thread panicked"))
+            .collect()
+    });
 
-    let res_packet = DnsPacket::from_buffer(&mut res_buf)?;
-    println!("{res_packet:#?}");
+    for (&(qname, _, qtype), res_packet) in questions.iter().zip(responses) {
+        if grouped {
+            println!(";; {qname} {qtype:?}");
+        }
+
+        match res_packet {
+            Ok((found, res_packet)) if args.short => {
+                for line in short_answers(&res_packet, found, qtype, args.idn) {
+                    println!("{line}");
+                }
+            }
+            Ok((_, res_packet)) => {
+                println!("{res_packet:#?}");
+                if args.nsid {
+                    println!(";; NSID: {}", format_nsid(res_packet.nsid()));
+                }
+            }
+            Err(e) if grouped => eprintln!(";; {e}"),
+            Err(e) => return Err(e.into()),
+        }
+
+        if grouped {
+            println!();
+        }
+    }
 
     Ok(())
 }
+
+/// Where the queries go
+#[derive(Debug, Clone, Copy)]
+enum Upstream {
+    System,
+    Server(SocketAddr),
+}
+
+fn parse_upstream(text: &str) -> dns_server::Result<Upstream> {
+    match text {
+        "system" => Ok(Upstream::System),
+        text => parse_server(text).map(Upstream::Server),
+    }
+}
+
+/// Look up each of the `names` a query name expands to in turn, until one of them exists. Returns
+/// the name that was found along with the response, or the last response when none exist.
+fn search_lookup(
+    names: &[DnsName],
+    mut lookup: impl FnMut(&DnsName) -> dns_server::Result<DnsPacket>,
+) -> dns_server::Result<(&DnsName, DnsPacket)> {
+    let (last, names) = names
+        .split_last()
+        .expect("a name expands to itself at least");
+    for name in names {
+        let response = lookup(name)?;
+        if response.header.rescode != ResultCode::NXDOMAIN {
+            return Ok((name, response));
+        }
+    }
+
+    Ok((last, lookup(last)?))
+}
+
+/// Collect the rdata of the answers for `qname`, following any CNAME chain towards records of the
+/// requested type.
+fn short_answers(packet: &DnsPacket, qname: &DnsName, qtype: QueryType, idn: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut name = qname.clone();
+
+    // Bound the walk by the number of answers so a CNAME loop can't spin forever
+    for _ in 0..=packet.answers.len() {
+        let mut next = None;
+
+        for rec in packet.answers.iter().filter(|rec| *rec.domain() == name) {
+            if let RData::CNAME { host } = &rec.rdata {
+                if qtype != QueryType::CNAME {
+                    next = Some(host.clone());
+                }
+            } else if rec.qtype() != qtype {
+                continue;
+            }
+
+            lines.push(format_rdata(rec, idn));
+        }
+
+        match next {
+            Some(host) => name = host,
+            None => break,
+        }
+    }
+
+    lines
+}
+
+/// Print the records a nameserver sent back during a trace, followed by where they came from.
+fn print_trace_step(server: IpAddr, packet: &DnsPacket, idn: bool) {
+    let records = packet
+        .answers
+        .iter()
+        .chain(&packet.authorities)
+        .chain(&packet.resources);
+    for rec in records {
+        println!("{}", format_record(rec, idn));
+    }
+    println!(";; Received {:?} from {server}\n", packet.header.rescode);
+}
+
+/// The NSID in hex like `dig +nsid` shows it, followed by the text when it is printable
+fn format_nsid(nsid: Option<&[u8]>) -> String {
+    let Some(nsid) = nsid else {
+        return "none".to_string();
+    };
+
+    let hex: String = nsid.iter().map(|b| format!("{b:02x}")).collect();
+    match std::str::from_utf8(nsid) {
+        Ok(text) if !text.chars().any(char::is_control) => format!("{hex} (\"{text}\")"),
+        _ => hex,
+    }
+}
+
+fn format_record(rec: &DnsRecord, idn: bool) -> String {
+    if idn {
+        format!("{rec:#}")
+    } else {
+        rec.to_string()
+    }
+}
+
+fn format_rdata(rec: &DnsRecord, idn: bool) -> String {
+    if idn {
+        format!("{:#}", rec.display_rdata())
+    } else {
+        rec.display_rdata().to_string()
+    }
+}
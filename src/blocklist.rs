@@ -0,0 +1,198 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::error::{DnsError, Result};
+use crate::header::ResultCode;
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::{DnsRecord, RData};
+use crate::stack::Resolver;
+
+/// TTL of the null addresses answered for blocked names
+const BLOCKED_TTL: u32 = 60;
+
+/// Names hosts-format lists map to themselves rather than block
+const LOCAL_NAMES: [&str; 5] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+];
+
+/// How blocked names are answered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockPolicy {
+    /// The name doesn't exist
+    Nxdomain,
+    /// `0.0.0.0` and `::`, so clients fail fast without retrying other addresses
+    #[default]
+    Null,
+    /// The server refuses to answer
+    Refused,
+}
+
+impl BlockPolicy {
+    /// The answer to a query for a blocked name
+    pub fn response(self, qname: &DnsName, qtype: QueryType) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        match self {
+            Self::Nxdomain => packet.header.rescode = ResultCode::NXDOMAIN,
+            Self::Refused => packet.header.rescode = ResultCode::REFUSED,
+            Self::Null => {
+                let null = match qtype {
+                    QueryType::A => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                    QueryType::AAAA => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+                    _ => None,
+                };
+                packet.answers.extend(null.map(|addr| match addr {
+                    IpAddr::V4(addr) => {
+                        DnsRecord::new(qname.clone(), BLOCKED_TTL, RData::A { addr })
+                    }
+                    IpAddr::V6(addr) => {
+                        DnsRecord::new(qname.clone(), BLOCKED_TTL, RData::AAAA { addr })
+                    }
+                }));
+            }
+        }
+
+        packet
+    }
+}
+
+/// Domains that are blocked, each along with every name below it, and exceptions to them
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    /// The domains of each list, by the path or URL it was loaded from
+    lists: HashMap<String, HashSet<DnsName>>,
+    /// Domains that are never blocked, even when a list has them
+    allowed: HashSet<DnsName>,
+}
+
+impl Blocklist {
+    /// Load every list in `sources`, which are paths or `http://` and `https://` URLs. A list
+    /// that fails to load is left out, or kept as it was in `previous` when refreshing.
+    pub fn load(sources: &[String], allowed: &[DnsName], previous: Option<&Self>) -> Self {
+        let mut lists = HashMap::new();
+        for source in sources {
+            match fetch(source) {
+                Ok(text) => {
+                    lists.insert(source.clone(), parse(&text));
+                }
+                Err(e) => {
+                    warn!("Failed to load blocklist {source}: {e}");
+                    let old = previous.and_then(|previous| previous.lists.get(source));
+                    if let Some(old) = old {
+                        lists.insert(source.clone(), old.clone());
+                    }
+                }
+            }
+        }
+
+        Self {
+            lists,
+            allowed: allowed.iter().cloned().collect(),
+        }
+    }
+
+    /// Number of domains in all of the lists
+    pub fn len(&self) -> usize {
+        self.lists.values().map(HashSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The listed domain `qname` is at or below, if any
+    pub fn blocked_by(&self, qname: &DnsName) -> Option<&DnsName> {
+        self.lists.values().find_map(|list| listed(list, qname))
+    }
+
+    /// The allowed domain that makes an exception for `qname`, if any
+    pub fn allowed_by(&self, qname: &DnsName) -> Option<&DnsName> {
+        listed(&self.allowed, qname)
+    }
+}
+
+/// A resolver answering blocked names by a policy and passing the rest on to the one it wraps
+#[derive(Debug)]
+pub struct Filtered<R> {
+    blocklist: Blocklist,
+    policy: BlockPolicy,
+    inner: R,
+}
+
+impl<R> Filtered<R> {
+    pub const fn new(blocklist: Blocklist, policy: BlockPolicy, inner: R) -> Self {
+        Self {
+            blocklist,
+            policy,
+            inner,
+        }
+    }
+}
+
+impl<R: Resolver> Resolver for Filtered<R> {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        let qname = &question.name;
+        if self.blocklist.blocked_by(qname).is_some() && self.blocklist.allowed_by(qname).is_none()
+        {
+            return Ok(self.policy.response(qname, question.qtype));
+        }
+
+        self.inner.resolve(question)
+    }
+}
+
+/// The domain in `domains` that `qname` is at or below, if any
+fn listed<'a>(domains: &'a HashSet<DnsName>, qname: &DnsName) -> Option<&'a DnsName> {
+    let mut name = Some(qname.clone());
+    while let Some(current) = name {
+        if let Some(domain) = domains.get(&current) {
+            return Some(domain);
+        }
+        name = current.parent().filter(|parent| !parent.is_root());
+    }
+
+    None
+}
+
+fn fetch(source: &str) -> Result<String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let mut response = ureq::get(source)
+            .call()
+            .map_err(|e| DnsError::Download(e.to_string()))?;
+        response
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| DnsError::Download(e.to_string()))
+    } else {
+        Ok(fs::read_to_string(source)?)
+    }
+}
+
+/// Parse a list in hosts format, where each line has an address followed by names, or with one
+/// domain per line. Comments and anything that isn't a valid name are skipped.
+fn parse(text: &str) -> HashSet<DnsName> {
+    let mut domains = HashSet::new();
+
+    for line in text.lines() {
+        let line = line.split(['#', '!']).next().unwrap_or_default();
+        // The address in front of hosts-format lines is skipped along with any other address
+        let names = line
+            .split_whitespace()
+            .filter(|word| word.parse::<IpAddr>().is_err() && !LOCAL_NAMES.contains(word))
+            .filter_map(|word| DnsName::new(word).ok())
+            .filter(|name| !name.is_root());
+        domains.extend(names);
+    }
+
+    domains
+}
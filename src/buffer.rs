@@ -0,0 +1,308 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+use crate::error::{DnsError, Result};
+use crate::name::{push_label, DnsName, MAX_LABEL_LEN, MAX_NAME_LEN};
+
+/// Largest message that fits a plain UDP datagram
+pub const UDP_MAX_LEN: usize = 512;
+
+/// Largest message that can be sent over TCP, where it is prefixed with a two byte length
+pub const TCP_MAX_LEN: usize = u16::MAX as usize;
+
+pub struct BytePacketBuffer {
+    pub buf: Vec<u8>,
+    pub pos: usize,
+}
+
+impl Default for BytePacketBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BytePacketBuffer {
+    /// A buffer sized for a UDP message
+    pub fn new() -> Self {
+        Self::with_len(UDP_MAX_LEN)
+    }
+
+    /// A zeroed buffer of `len` bytes, such as [`TCP_MAX_LEN`] for messages sent over TCP
+    pub fn with_len(len: usize) -> Self {
+        Self {
+            buf: vec![0; len],
+            pos: 0,
+        }
+    }
+
+    /// Read a message prefixed with its two byte length, as sent over TCP
+    #[cfg(feature = "std")]
+    pub fn read_from(stream: &mut impl Read) -> Result<Self> {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+
+        let mut buffer = Self::with_len(u16::from_be_bytes(len).into());
+        stream.read_exact(&mut buffer.buf)?;
+
+        Ok(buffer)
+    }
+
+    /// Write the message up to the current position to a TCP stream, prefixed with its length
+    #[cfg(feature = "std")]
+    pub fn write_to(&self, stream: &mut impl Write) -> Result<()> {
+        // The buffer is never larger than TCP_MAX_LEN, so the length always fits
+        let len = self.pos as u16;
+        stream.write_all(&len.to_be_bytes())?;
+        stream.write_all(&self.buf[..self.pos])?;
+
+        Ok(())
+    }
+
+    /// Current position within buffer
+    pub const fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Step the buffer position forward a specific number of steps
+    #[cfg(feature = "std")]
+    pub(crate) fn step(&mut self, steps: usize) -> Result<()> {
+        self.pos += steps;
+
+        Ok(())
+    }
+
+    /// Change the buffer position
+    pub(crate) fn seek(&mut self, pos: usize) -> Result<()> {
+        self.pos = pos;
+
+        Ok(())
+    }
+
+    /// Read a single byte and move the position one step forward
+    fn read(&mut self) -> Result<u8> {
+        if self.pos >= self.buf.len() {
+            return Err(DnsError::BufferOverrun);
+        }
+        let res = self.buf[self.pos];
+        self.pos += 1;
+
+        Ok(res)
+    }
+
+    /// Get a single byte, without changing the buffer position
+    fn get(&mut self, pos: usize) -> Result<u8> {
+        if pos >= self.buf.len() {
+            return Err(DnsError::BufferOverrun);
+        }
+        Ok(self.buf[pos])
+    }
+
+    /// Get a range of bytes
+    fn get_range(&mut self, start: usize, len: usize) -> Result<&[u8]> {
+        if start + len > self.buf.len() {
+            return Err(DnsError::BufferOverrun);
+        }
+        Ok(&self.buf[start..start + len])
+    }
+
+    /// Read a range of bytes, stepping past them
+    pub(crate) fn read_range(&mut self, len: usize) -> Result<&[u8]> {
+        let start = self.pos;
+        // Checked before stepping so a failed read leaves the position where it was
+        let end = start
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or(DnsError::BufferOverrun)?;
+        self.pos = end;
+
+        Ok(&self.buf[start..end])
+    }
+
+    /// Read two bytes, stepping two steps forward
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let res = ((self.read()? as u16) << 8) | (self.read()? as u16);
+
+        Ok(res)
+    }
+
+    /// Read four bytes, stepping four steps forward
+    pub(crate) fn read_u32(&mut self) -> Result<u32> {
+        let res = ((self.read()? as u32) << 24)
+            | ((self.read()? as u32) << 16)
+            | ((self.read()? as u32) << 8)
+            | (self.read()? as u32);
+
+        Ok(res)
+    }
+
+    /// Read a qname
+    ///
+    /// The tricky part: Reading domain names, taking labels into consideration. Will take something
+    /// like [3]www[6]google[3]com[0] and append www.google.com to outstr.
+    fn read_qname(&mut self, outstr: &mut String) -> Result<()> {
+        // Since we might encounter jumps, we'll keep track of our position locally as opposed to
+        // using the position within the struct. This allows us to move the shared position to a
+        // point past our current qname, while keeping track of our progress on the current qname using this variable.
+        let mut pos = self.pos();
+
+        // track whether or not we've jumped
+        let mut jumped = false;
+        let max_jumps = 5;
+        let mut jumps_performed = 0;
+
+        // Our delimiter which we append for each label. Since we don't want a dot at the beginning
+        // of the domain name we'll leave it empty for now and set it to "." at the end of the first
+        // iteration.
+        let mut delim = "";
+        loop {
+            // Dns Packets are untrusted data, so we need to be paranoid. Someone can craft a packet
+            // with a cycle in the jump instructions. This guards against such packets.
+            if jumps_performed > max_jumps {
+                return Err(DnsError::TooManyJumps(max_jumps));
+            }
+
+            // At this point, we're always at the beginning of a label.
+            let len = self.get(pos)?;
+
+            // If len has the two most significant bit are set, it represents a jump to some other
+            // offset in the packet:
+            if (len & 0xC0) == 0xC0 {
+                // Update the buffer position to a point past the current label.
+                if !jumped {
+                    self.seek(pos + 2)?;
+                }
+
+                // Read another byte, calculate offset and perform the jump by updating our local
+                // position variable
+                let b2 = self.get(pos + 1)? as u16;
+                let offset = (((len as u16) ^ 0xC0) << 8) | b2;
+                pos = offset as usize;
+
+                // Indicate that a jump was performed.
+                jumped = true;
+                jumps_performed += 1;
+
+                continue;
+            }
+            // The base scenario, where we're reading a single label and appending it to the output:
+            else {
+                // Move a single byte forward to move past the length byte.
+                pos += 1;
+
+                // Domain names are terminated by an empty label of length 0, so if the length is
+                // zero we're done.
+                if len == 0 {
+                    break;
+                }
+
+                // Append the delimiter to our output buffer first.
+                outstr.push_str(delim);
+
+                // Extract the actual bytes for this label and append them to the output buffer in
+                // the case they were sent in, names compare ignoring it. Anything but printable
+                // ASCII is escaped.
+                let str_buf = self.get_range(pos, len as usize)?;
+                push_label(outstr, str_buf);
+
+                delim = ".";
+
+                // Move forward the full length of the label.
+                pos += len as usize;
+            }
+        }
+
+        if !jumped {
+            self.seek(pos)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a qname and validate it as a [`DnsName`]
+    pub(crate) fn read_name(&mut self) -> Result<DnsName> {
+        let mut name = String::new();
+        self.read_qname(&mut name)?;
+
+        DnsName::from_wire(name)
+    }
+
+    fn write(&mut self, val: u8) -> Result<()> {
+        if self.pos >= self.buf.len() {
+            return Err(DnsError::BufferOverrun);
+        }
+        self.buf[self.pos] = val;
+        self.pos += 1;
+        Ok(())
+    }
+
+    pub(crate) fn write_u8(&mut self, val: u8) -> Result<()> {
+        self.write(val)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn write_u16(&mut self, val: u16) -> Result<()> {
+        self.write((val >> 8) as u8)?;
+        self.write((val & 0xFF) as u8)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn write_u32(&mut self, val: u32) -> Result<()> {
+        self.write(((val >> 24) & 0xFF) as u8)?;
+        self.write(((val >> 16) & 0xFF) as u8)?;
+        self.write(((val >> 8) & 0xFF) as u8)?;
+        self.write((val & 0xFF) as u8)?;
+
+        Ok(())
+    }
+
+    /// Overwrite a single byte at a previously written position
+    fn set(&mut self, pos: usize, val: u8) -> Result<()> {
+        if pos >= self.buf.len() {
+            return Err(DnsError::BufferOverrun);
+        }
+        self.buf[pos] = val;
+
+        Ok(())
+    }
+
+    /// Overwrite two bytes at a previously written position, used to fill in lengths after the fact
+    pub(crate) fn set_u16(&mut self, pos: usize, val: u16) -> Result<()> {
+        self.set(pos, (val >> 8) as u8)?;
+        self.set(pos + 1, (val & 0xFF) as u8)?;
+
+        Ok(())
+    }
+
+    /// Write a qname as a sequence of length-prefixed labels
+    pub(crate) fn write_qname(&mut self, qname: &DnsName) -> Result<()> {
+        // Names are checked when they are built, but a label that slipped through would corrupt
+        // everything written after it
+        if qname.wire_len() > MAX_NAME_LEN {
+            return Err(DnsError::NameTooLong(qname.to_string()));
+        }
+        if let Some(label) = qname
+            .wire_labels()
+            .find(|label| label.len() > MAX_LABEL_LEN)
+        {
+            return Err(DnsError::LabelTooLong(
+                String::from_utf8_lossy(&label).into_owned(),
+            ));
+        }
+
+        for label in qname.wire_labels() {
+            self.write_u8(label.len() as u8)?;
+            for &b in label.iter() {
+                self.write_u8(b)?;
+            }
+        }
+
+        self.write_u8(0)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use tracing::debug;
+
+use crate::edns::ClientSubnet;
+use crate::error::Result;
+use crate::header::ResultCode;
+use crate::name::DnsName;
+use crate::network::Network;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::{DnsRecord, RData};
+use crate::stack::Resolver;
+use crate::ttl::Ttl;
+
+/// What an answer is cached under: the view whose upstreams gave it, if any, the client network
+/// it was tailored to, and the question
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    view: Option<String>,
+    /// The client subnet cut to the scope the upstream gave, `None` for answers that apply to
+    /// every client, RFC 7871 section 7.3.1
+    network: Option<Network>,
+    qname: DnsName,
+    qtype: QueryType,
+}
+
+impl Key {
+    /// The keys an answer for a client in `subnet` may be under, from the longest scope the
+    /// subnet covers down to the answers for everyone
+    fn candidates<'a>(
+        view: Option<&'a str>,
+        subnet: Option<ClientSubnet>,
+        qname: &'a DnsName,
+        qtype: QueryType,
+    ) -> impl Iterator<Item = Self> + 'a {
+        let networks = subnet.into_iter().flat_map(|subnet| {
+            (1..=subnet.source_prefix)
+                .rev()
+                .map(move |prefix| Some(Network::new(subnet.addr, prefix)))
+        });
+
+        networks.chain([None]).map(move |network| Self {
+            view: view.map(String::from),
+            network,
+            qname: qname.clone(),
+            qtype,
+        })
+    }
+
+    /// Whether the answer under the key is for a client in `subnet`
+    fn applies_to(&self, subnet: Option<ClientSubnet>) -> bool {
+        self.network.is_none_or(|network| {
+            subnet.is_some_and(|subnet| {
+                subnet.source_prefix >= network.prefix() && network.contains(subnet.addr)
+            })
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    response: DnsPacket,
+    stored: Instant,
+    /// How long the whole response may be cached, see [`cache_ttl`]
+    ttl: Ttl,
+    /// Times the entry answered a query
+    hits: u64,
+}
+
+impl Entry {
+    /// The response with its TTLs kept between `min` and `max` and counted down by the time it
+    /// spent in the cache. The response itself keeps the TTLs it came with.
+    fn aged(&self, now: Instant, min: u32, max: u32) -> DnsPacket {
+        let mut response = self.response.clone();
+        for rec in response
+            .answers
+            .iter_mut()
+            .chain(&mut response.authorities)
+            .chain(&mut response.resources)
+            .filter(|rec| !matches!(rec.rdata, RData::OPT { .. }))
+        {
+            rec.set_ttl(rec.ttl_from(self.stored).clamp(min, max).remaining_at(now));
+        }
+
+        response
+    }
+}
+
+/// A cached answer as shown by [`Cache::dump`]
+#[derive(Debug, Clone)]
+pub struct CachedAnswer {
+    pub view: Option<String>,
+    /// The client network the answer is for, `None` when it is for everyone
+    pub network: Option<Network>,
+    pub qname: DnsName,
+    pub qtype: QueryType,
+    pub rescode: ResultCode,
+    /// Seconds until the answer expires
+    pub ttl: u64,
+    pub hits: u64,
+    pub answers: Vec<DnsRecord>,
+}
+
+/// Answers from the upstreams, kept for as long as their TTLs allow so the same question isn't
+/// forwarded again
+#[derive(Debug)]
+pub struct Cache {
+    /// Entries kept before the expired ones are dropped, and then the ones closest to expiring
+    capacity: usize,
+    /// Seconds the TTL of each record is kept between, see [`Cache::with_ttl_limits`]
+    min_ttl: u32,
+    max_ttl: u32,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            min_ttl: 0,
+            max_ttl: u32::MAX,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep the TTL of each record between `min` and `max` seconds, both for how long it is
+    /// cached and what it is answered with
+    pub const fn with_ttl_limits(mut self, min: u32, max: u32) -> Self {
+        self.min_ttl = min;
+        self.max_ttl = max;
+        self
+    }
+
+    /// Keep the TTLs of a response about to be answered between the limits, like the ones of the
+    /// responses answered from the cache
+    pub fn limit_ttls(&self, response: &mut DnsPacket) {
+        for rec in response
+            .answers
+            .iter_mut()
+            .chain(&mut response.authorities)
+            .chain(&mut response.resources)
+            .filter(|rec| !matches!(rec.rdata, RData::OPT { .. }))
+        {
+            rec.set_ttl(rec.ttl().clamp(self.min_ttl, self.max_ttl));
+        }
+    }
+
+    /// The cached answer to a question from the clients of `view` in `subnet`, with its TTLs
+    /// counted down by the time it spent in the cache. The answer tailored to the narrowest network
+    /// the client is in is preferred over the ones for everyone.
+    pub fn get(
+        &self,
+        view: Option<&str>,
+        subnet: Option<ClientSubnet>,
+        qname: &DnsName,
+        qtype: QueryType,
+    ) -> Option<DnsPacket> {
+        let now = Instant::now();
+        let mut entries = self.lock();
+        for key in Key::candidates(view, subnet, qname, qtype) {
+            let Some(entry) = entries.get_mut(&key) else {
+                continue;
+            };
+            if entry.ttl.is_expired_at(now) {
+                entries.remove(&key);
+                continue;
+            }
+
+            entry.hits += 1;
+            return Some(entry.aged(now, self.min_ttl, self.max_ttl));
+        }
+
+        None
+    }
+
+    /// Every cached record owned by `qname` for the clients of `view` in `subnet`, whatever
+    /// question it answered, to answer ANY queries with. `None` if there are none.
+    pub fn get_all(
+        &self,
+        view: Option<&str>,
+        subnet: Option<ClientSubnet>,
+        qname: &DnsName,
+    ) -> Option<DnsPacket> {
+        let now = Instant::now();
+        let mut packet = DnsPacket::new();
+        let (min, max) = (self.min_ttl, self.max_ttl);
+        for (key, entry) in self.lock().iter_mut() {
+            if key.view.as_deref() != view
+                || key.qname != *qname
+                || !key.applies_to(subnet)
+                || entry.ttl.is_expired_at(now)
+            {
+                continue;
+            }
+
+            entry.hits += 1;
+            for rec in entry.aged(now, min, max).answers {
+                if rec.domain() == qname && !packet.answers.contains(&rec) {
+                    packet.answers.push(rec);
+                }
+            }
+        }
+
+        (!packet.answers.is_empty()).then_some(packet)
+    }
+
+    /// Cache an upstream response to a question from the clients of `view`, if it can be. With
+    /// the `subnet` sent upstream, the answer is only for the part of it the scope of the response
+    /// covers, and for everyone when the scope is 0 or the response has none.
+    pub fn insert(
+        &self,
+        view: Option<&str>,
+        subnet: Option<ClientSubnet>,
+        qname: &DnsName,
+        qtype: QueryType,
+        response: &DnsPacket,
+    ) {
+        let Some(original) = cache_ttl(response) else {
+            return;
+        };
+        let ttl = original.clamp(self.min_ttl, self.max_ttl);
+        if self.capacity == 0 || ttl == 0 {
+            return;
+        }
+        if ttl != original {
+            debug!(%qname, ?qtype, original, ttl, "Clamped the TTL of the cached answer");
+        }
+
+        let now = Instant::now();
+        let mut entries = self.lock();
+        if entries.len() >= self.capacity {
+            entries.retain(|_, entry| !entry.ttl.is_expired_at(now));
+        }
+        if entries.len() >= self.capacity {
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.ttl.expires())
+                .map(|(key, _)| key.clone());
+            if let Some(key) = soonest {
+                entries.remove(&key);
+            }
+        }
+
+        // A scope longer than the subnet sent can't be told apart from it, RFC 7871 section 7.3.1
+        let network = subnet.and_then(|subnet| {
+            let scope = response.client_subnet().map_or(0, |upstream| {
+                upstream.scope_prefix.min(subnet.source_prefix)
+            });
+            (scope > 0).then(|| Network::new(subnet.addr, scope))
+        });
+        entries.insert(
+            Key {
+                view: view.map(String::from),
+                network,
+                qname: qname.clone(),
+                qtype,
+            },
+            Entry {
+                response: response.clone(),
+                stored: now,
+                ttl: Ttl::starting_at(ttl, now),
+                hits: 0,
+            },
+        );
+    }
+
+    /// Drop every entry, returning how many there were
+    pub fn flush(&self) -> usize {
+        let mut entries = self.lock();
+        let len = entries.len();
+        entries.clear();
+        len
+    }
+
+    /// Drop the entries for `qname` of every type, returning how many there were
+    pub fn flush_name(&self, qname: &DnsName) -> usize {
+        let mut entries = self.lock();
+        let len = entries.len();
+        entries.retain(|key, _| key.qname != *qname);
+        len - entries.len()
+    }
+
+    /// The answers that haven't expired, for `suffix` and the names below it or all of them,
+    /// sorted by name and type
+    pub fn dump(&self, suffix: Option<&DnsName>) -> Vec<CachedAnswer> {
+        let now = Instant::now();
+        let (min, max) = (self.min_ttl, self.max_ttl);
+        let mut answers: Vec<_> = self
+            .lock()
+            .iter()
+            .filter(|(key, entry)| {
+                !entry.ttl.is_expired_at(now)
+                    && suffix.is_none_or(|suffix| key.qname.is_subdomain_of(suffix))
+            })
+            .map(|(key, entry)| CachedAnswer {
+                view: key.view.clone(),
+                network: key.network,
+                qname: key.qname.clone(),
+                qtype: key.qtype,
+                rescode: entry.response.header.rescode,
+                // Counted down like the TTLs of the records
+                ttl: entry.ttl.remaining_at(now).into(),
+                hits: entry.hits,
+                answers: entry.aged(now, min, max).answers,
+            })
+            .collect();
+        answers.sort_by(|a, b| {
+            let network = |answer: &CachedAnswer| answer.network.map(|n| (n.addr(), n.prefix()));
+            (&a.qname, a.qtype, &a.view, network(a)).cmp(&(&b.qname, b.qtype, &b.view, network(b)))
+        });
+
+        answers
+    }
+
+    /// Entries in the cache, including expired ones that haven't been dropped yet
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Entries are only ever replaced as a whole, so poisoning is ignored
+    fn lock(&self) -> MutexGuard<'_, HashMap<Key, Entry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A resolver answering from a cache before asking the one it wraps, and caching what that one
+/// answers
+#[derive(Debug)]
+pub struct Cached<R> {
+    cache: Cache,
+    inner: R,
+}
+
+impl<R> Cached<R> {
+    pub const fn new(cache: Cache, inner: R) -> Self {
+        Self { cache, inner }
+    }
+
+    pub const fn cache(&self) -> &Cache {
+        &self.cache
+    }
+}
+
+impl<R: Resolver> Resolver for Cached<R> {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        if let Some(cached) = self.cache.get(None, None, &question.name, question.qtype) {
+            return Ok(cached);
+        }
+
+        let mut response = self.inner.resolve(question)?;
+        self.cache
+            .insert(None, None, &question.name, question.qtype, &response);
+        self.cache.limit_ttls(&mut response);
+        Ok(response)
+    }
+}
+
+/// How long a response may be cached: the lowest TTL of its answers, or for names and types
+/// that don't exist the TTL of the SOA record capped by its minimum, RFC 2308. Failures,
+/// truncated responses and responses without either aren't cached.
+fn cache_ttl(response: &DnsPacket) -> Option<u32> {
+    if response.header.truncated_message {
+        return None;
+    }
+    let negative = || {
+        response.authorities.iter().find_map(|rec| match rec.rdata {
+            RData::SOA { minimum, .. } => Some(minimum.min(rec.ttl)),
+            _ => None,
+        })
+    };
+
+    match response.header.rescode {
+        ResultCode::NXDOMAIN => negative(),
+        ResultCode::NOERROR if response.answers.is_empty() => negative(),
+        ResultCode::NOERROR => response.answers.iter().map(DnsRecord::ttl).min(),
+        _ => None,
+    }
+}
@@ -0,0 +1,230 @@
+use std::net::SocketAddr;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::Instant;
+
+use tracing::{info, warn};
+
+use crate::authority::Authority;
+use crate::config::CatalogConfig;
+use crate::error::{DnsError, Result};
+use crate::name::DnsName;
+use crate::question::QueryType;
+use crate::record::RData;
+use crate::secondary::Secondary;
+use crate::tsig::TsigKey;
+use crate::zone::Zone;
+
+/// The schema version of catalog zones from RFC 9432, the only one there is
+const SCHEMA_VERSION: &str = "2";
+
+/// A catalog zone from a primary, RFC 9432. The zones it lists are transferred from the same
+/// primary and served as secondaries, and added or dropped as the catalog changes.
+#[derive(Debug)]
+pub struct Catalog {
+    pub origin: DnsName,
+    pub primary: SocketAddr,
+    key: Option<TsigKey>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The catalog as it was last transferred
+    catalog: Option<Zone>,
+    secondary: Secondary,
+    /// The member zones served, or still to be transferred
+    members: Vec<Secondary>,
+}
+
+impl Catalog {
+    /// The catalog of `config`, signing its transfers with a key from `keys`
+    pub fn new(config: &CatalogConfig, keys: &[TsigKey]) -> Result<Self> {
+        let key = match &config.key {
+            Some(name) => Some(
+                keys.iter()
+                    .find(|key| key.name == *name)
+                    .cloned()
+                    .ok_or_else(|| {
+                        DnsError::Config(format!("No key {name} to transfer {}", config.origin))
+                    })?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            origin: config.origin.clone(),
+            primary: config.primary,
+            key,
+            state: Mutex::new(State {
+                catalog: None,
+                secondary: Secondary::new(config.origin.clone(), Instant::now()),
+                members: Vec::new(),
+            }),
+        })
+    }
+
+    /// Origins of the member zones served, or still to be transferred
+    pub fn members(&self) -> Vec<DnsName> {
+        self.lock()
+            .members
+            .iter()
+            .map(|member| member.origin.clone())
+            .collect()
+    }
+
+    /// Check the catalog and its member zones with the primary as their SOA timers say, see
+    /// [`Catalog::refresh_at`]
+    pub fn refresh(&self, authority: &RwLock<Authority>) -> Instant {
+        self.refresh_at(authority, Instant::now())
+    }
+
+    /// Check the catalog and the zones of `authority` it lists with the primary, those of them
+    /// that are due at `now` by their SOA timers. Members the catalog gained are transferred in
+    /// full, the ones it lost are dropped, and the ones that couldn't be checked for their expire
+    /// interval answer SERVFAIL until they can again. Names already served from elsewhere, like a
+    /// zone file, are left alone. Returns when the next check is due.
+    pub fn refresh_at(&self, authority: &RwLock<Authority>, now: Instant) -> Instant {
+        let read = || authority.read().unwrap_or_else(PoisonError::into_inner);
+        let write = || authority.write().unwrap_or_else(PoisonError::into_inner);
+        let key = self.key.as_ref();
+        let mut state = self.lock();
+        let state = &mut *state;
+
+        if state.secondary.is_due(now) {
+            let catalog = state.catalog.as_ref();
+            match state.secondary.refresh_at(catalog, self.primary, key, now) {
+                Ok(Some(catalog)) => {
+                    match member_zones(&catalog) {
+                        Ok(listed) => {
+                            self.update_members(&mut state.members, &listed, authority, now)
+                        }
+                        Err(e) => warn!("Keeping the members of catalog {}: {e}", self.origin),
+                    }
+                    state.catalog = Some(catalog);
+                }
+                Ok(None) => {}
+                Err(e) => warn!(
+                    "Failed to refresh catalog {} from {}: {e}",
+                    self.origin, self.primary
+                ),
+            }
+        }
+
+        for member in state.members.iter_mut().filter(|member| member.is_due(now)) {
+            // Transferred without holding the lock, so queries are still answered meanwhile
+            let served = read().zone(&member.origin).cloned();
+            match member.refresh_at(served.as_ref(), self.primary, key, now) {
+                Ok(Some(zone)) => {
+                    info!("Transferred {} at serial {:?}", zone.origin, zone.serial());
+                    write().insert(zone);
+                }
+                Ok(None) => write().renew(&member.origin),
+                Err(e) => {
+                    warn!(
+                        "Failed to refresh {} from {}: {e}",
+                        member.origin, self.primary
+                    );
+                    if served.is_some_and(|zone| member.is_expired(&zone, now)) {
+                        warn!("{} expired, answering SERVFAIL for it", member.origin);
+                        write().expire(&member.origin);
+                    }
+                }
+            }
+        }
+
+        state
+            .members
+            .iter()
+            .map(Secondary::next)
+            .fold(state.secondary.next(), Instant::min)
+    }
+
+    /// Drop the members that aren't `listed` any more, and add the ones that are new to be
+    /// transferred right away
+    fn update_members(
+        &self,
+        members: &mut Vec<Secondary>,
+        listed: &[DnsName],
+        authority: &RwLock<Authority>,
+        now: Instant,
+    ) {
+        let mut authority = authority.write().unwrap_or_else(PoisonError::into_inner);
+        members.retain(|member| {
+            let kept = listed.contains(&member.origin);
+            if !kept {
+                authority.remove(&member.origin);
+                info!(
+                    "Dropped {}, removed from catalog {}",
+                    member.origin, self.origin
+                );
+            }
+            kept
+        });
+
+        for origin in listed {
+            if members.iter().any(|member| member.origin == *origin) {
+                continue;
+            }
+            if authority.zone(origin).is_some() {
+                warn!("{origin} from catalog {} is already served", self.origin);
+                continue;
+            }
+            members.push(Secondary::new(origin.clone(), now));
+        }
+    }
+
+    /// The state only changes as a whole on refresh, so poisoning is ignored
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The origins of the zones a catalog lists, from the PTR records of its member nodes:
+/// `<unique-id>.zones.<catalog>`. Catalogs of another schema version than 2 can't be read, and
+/// members with several PTR records are skipped, RFC 9432 section 4.
+pub fn member_zones(catalog: &Zone) -> Result<Vec<DnsName>> {
+    let version = catalog.origin.child("version")?;
+    let versions: Vec<_> = catalog
+        .records
+        .iter()
+        .filter(|rec| *rec.domain() == version)
+        .filter_map(|rec| match &rec.rdata {
+            RData::TXT { data } => Some(String::from_utf8_lossy(&data.concat()).into_owned()),
+            _ => None,
+        })
+        .collect();
+    if versions != [SCHEMA_VERSION] {
+        return Err(DnsError::Transfer(format!(
+            "Catalog {} has schema version {versions:?}, expected {SCHEMA_VERSION}",
+            catalog.origin
+        )));
+    }
+
+    let zones = catalog.origin.child("zones")?;
+    let member_nodes = catalog.records.iter().filter(|rec| {
+        rec.qtype() == QueryType::PTR
+            && rec.domain().label_count() == zones.label_count() + 1
+            && rec.domain().is_subdomain_of(&zones)
+    });
+    let mut members: Vec<DnsName> = Vec::new();
+    for rec in member_nodes.clone() {
+        let RData::PTR { host } = &rec.rdata else {
+            continue;
+        };
+        if member_nodes
+            .clone()
+            .filter(|other| other.domain() == rec.domain())
+            .count()
+            > 1
+        {
+            warn!("Skipping {}, it has several PTR records", rec.domain());
+            continue;
+        }
+        // The same zone may be listed under two IDs while it moves between them
+        if !members.contains(host) {
+            members.push(host.clone());
+        }
+    }
+
+    Ok(members)
+}
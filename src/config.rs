@@ -0,0 +1,901 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::blocklist::BlockPolicy;
+use crate::dns64::Dns64;
+use crate::edns::ClientSubnet;
+use crate::error::{DnsError, Result};
+use crate::hosts::HOSTS_FILE;
+use crate::name::DnsName;
+use crate::network::Network;
+use crate::record::DnsRecord;
+use crate::resolv_conf::ResolvConf;
+use crate::resolver::{parse_server, RetryPolicy, DNS_PORT};
+use crate::socks::Socks5Proxy;
+use crate::tls::DOT_PORT;
+use crate::tsig::TsigKey;
+use crate::upstream::{Transport, Upstreams};
+
+/// Server configuration, usually loaded from a TOML file
+///
+/// ```toml
+/// listen = "0.0.0.0:53"
+/// upstream = ["1.1.1.1", "2606:4700:4700::1111", "192.0.2.53:5353"]
+/// nsid = "ns1.fra"
+/// race = 2
+/// ddr = true
+/// query-timeout = 2000
+/// query-retries = 2
+/// allow-query = ["192.0.2.0/24", "2001:db8::/32"]
+/// allow-recursion = ["192.0.2.0/24"]
+/// round-robin = true
+/// cache-size = 10000
+/// min-ttl = 60
+/// max-ttl = 86400
+/// control = "127.0.0.1:8953"
+/// llmnr = true
+///
+/// [[zones]]
+/// origin = "example.com"
+/// file = "zones/example.com.zone"
+/// allow-transfer = ["192.0.2.53", "198.51.100.0/24"]
+/// allow-update = ["192.0.2.67"]
+/// update-keys = ["dhcp-key"]
+/// persist = true
+/// ksk = "keys/Kexample.com.+015+12345.private"
+///
+/// [hosts]
+/// file = "/etc/hosts"
+/// ttl = 300
+///
+/// [blocklist]
+/// lists = ["https://example.com/ads.txt", "lists/local.txt"]
+/// allow = ["cdn.example.net"]
+/// policy = "nxdomain"
+///
+/// [[forward]]
+/// domain = "corp.internal"
+/// upstream = "10.0.0.2"
+///
+/// [[forward]]
+/// domain = "."
+/// upstream = "1.1.1.1"
+/// transport = "tls"
+/// tls-name = "cloudflare-dns.com"
+///
+/// [[rpz]]
+/// origin = "rpz.local"
+/// file = "zones/rpz.local.zone"
+///
+/// [[catalogs]]
+/// origin = "catalog.example"
+/// primary = "192.0.2.53:53"
+///
+/// [[local-records]]
+/// name = "nas.lan"
+/// type = "A"
+/// value = "192.168.1.10"
+///
+/// [[balanced]]
+/// name = "www.example.com"
+/// check = { tcp = 443 }
+/// targets = [{ addr = "192.0.2.10", weight = 3 }, { addr = "192.0.2.11" }]
+///
+/// [[views]]
+/// name = "internal"
+/// match-clients = ["10.0.0.0/8", "192.168.0.0/16"]
+///
+/// [[views.zones]]
+/// origin = "example.com"
+/// file = "zones/internal/example.com.zone"
+///
+/// [proxy]
+/// addr = "127.0.0.1:9050"
+///
+/// [dns64]
+/// prefix = "64:ff9b::/96"
+///
+/// [rate-limit]
+/// responses-per-second = 5
+/// slip = 2
+///
+/// [client-subnet]
+/// ipv4-prefix = 24
+/// ipv6-prefix = 56
+///
+/// [dnstap]
+/// socket = "/run/dnstap.sock"
+///
+/// [mdns]
+/// names = ["nas.local"]
+///
+/// [query-log]
+/// file = "/var/log/dns-server/queries.log"
+/// format = "json"
+/// max-size = 10485760
+///
+/// [[keys]]
+/// name = "dhcp-key"
+/// algorithm = "hmac-sha256"
+/// secret = "c2VjcmV0IGtleSBmb3IgdXBkYXRlcw=="
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Address the server listens for queries on
+    pub listen: SocketAddr,
+    /// Resolvers that queries for names outside of the configured zones are forwarded to, either
+    /// one address or a list, IPv4 or IPv6 and with an optional port. The fastest one that is up
+    /// gets the queries. `"system"` stands for the nameservers the system is configured with, read
+    /// when the config is loaded, and they are also used without any upstream.
+    #[serde(deserialize_with = "one_or_many")]
+    pub upstream: Vec<SocketAddr>,
+    /// Zones the server is authoritative for
+    pub zones: Vec<ZoneConfig>,
+    /// Shared secrets for signing transfers and updates with TSIG
+    pub keys: Vec<TsigKey>,
+    /// Tell the upstream which network forwarded queries come from with EDNS Client Subnet, so
+    /// geo-targeted answers fit the client. Off by default.
+    pub client_subnet: Option<ClientSubnetConfig>,
+    /// Identifier of this server sent to clients that ask for it with the NSID option, such as
+    /// its hostname and version, to tell instances behind one address apart
+    pub nsid: Option<String>,
+    /// Answer names and addresses from a hosts file before forwarding. Off by default.
+    pub hosts: Option<HostsConfig>,
+    /// Block the domains on lists of ads, trackers and the like. Off by default.
+    pub blocklist: Option<BlocklistConfig>,
+    /// Domains forwarded to upstreams of their own instead of `upstream`
+    pub forward: Vec<ForwardConfig>,
+    /// Response policy zones rewriting the answers to forwarded queries, in order of precedence
+    pub rpz: Vec<RpzConfig>,
+    /// Catalog zones listing more zones to transfer from a primary and serve
+    pub catalogs: Vec<CatalogConfig>,
+    /// Records answered authoritatively before anything else, for small networks that don't need
+    /// zone files
+    pub local_records: Vec<LocalRecordConfig>,
+    /// Names answered with the healthy ones among a set of addresses, picked by weight, for load
+    /// balancing between servers
+    pub balanced: Vec<BalancedConfig>,
+    /// Zones, records and forwarding rules only clients from some networks see, such as private
+    /// addresses for internal clients. The first view matching a client applies.
+    pub views: Vec<ViewConfig>,
+    /// Send the queries to the upstreams through a SOCKS5 proxy, to resolve from networks that
+    /// only allow traffic through it. Off by default.
+    pub proxy: Option<Socks5Proxy>,
+    /// How many upstreams each forwarded query is sent to at once, taking the first answer. More
+    /// than 1 trades load on the upstreams for lower latency on flaky networks.
+    pub race: usize,
+    /// Upgrade the upstreams to DNS over TLS with the resolvers they designate, RFC 9462, where the
+    /// certificate of the designated resolver is valid for the address of the upstream. The others
+    /// keep being asked over plain DNS. Off by default.
+    pub ddr: bool,
+    /// Milliseconds to wait for the upstream before asking again, doubled on every retry
+    pub query_timeout: u64,
+    /// How many times a forwarded query is sent again when the upstream doesn't answer
+    pub query_retries: u32,
+    /// Networks that may query the server at all, everyone by default
+    pub allow_query: Vec<Network>,
+    /// Networks that may have queries forwarded for them, everyone by default. Other clients only
+    /// get answers from the server itself.
+    pub allow_recursion: Vec<Network>,
+    /// Networks that may transfer every zone, on top of the ones allowed by each zone
+    pub allow_transfer: Vec<Network>,
+    /// Limit how fast the same response is sent over UDP to the same network, so the server
+    /// can't be used to amplify floods at spoofed addresses. Off by default.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Synthesize AAAA records from A records for names without any, for IPv6-only networks
+    /// behind NAT64. Off by default.
+    pub dns64: Option<Dns64>,
+    /// Rotate the order of addresses in every response, so clients spread over them. Turning it
+    /// off keeps the order of the zone or upstream, for reproducible answers in tests.
+    pub round_robin: bool,
+    /// How queries for ANY are answered: with a single HINFO record as RFC 8482 recommends, or
+    /// with every record the server has for the name
+    pub any_queries: AnyPolicy,
+    /// Answers from the upstreams kept at most, for as long as their TTL. 0 disables the cache.
+    pub cache_size: usize,
+    /// Seconds the records from the upstreams are cached and answered with at least, to ask the
+    /// upstreams less often. 0 by default, keeping the TTLs as they are.
+    pub min_ttl: u32,
+    /// Seconds the records from the upstreams are cached and answered with at most, so they don't
+    /// stay stale for long. Unlimited by default.
+    pub max_ttl: u32,
+    /// Where to listen for control commands, such as flushing the cache. Only loopback addresses
+    /// are allowed, since anyone who can connect can run them. Off by default.
+    pub control: Option<SocketAddr>,
+    /// Log every message exchanged with clients and upstreams in the dnstap format. Off by
+    /// default.
+    pub dnstap: Option<DnstapConfig>,
+    /// Write a line for every client query to a file. Off by default.
+    pub query_log: Option<QueryLogConfig>,
+    /// Answer multicast DNS queries for names of this host under `.local`. Off by default.
+    pub mdns: Option<MdnsConfig>,
+    /// Answer LLMNR queries for single-label names found in the local records or the hosts file,
+    /// on 224.0.0.252 and ff02::1:3. Off by default.
+    pub llmnr: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ZoneConfig {
+    /// Origin for relative names in the zone file, until it sets its own with `$ORIGIN`
+    #[serde(deserialize_with = "crate::name::deserialize_name")]
+    pub origin: DnsName,
+    /// Path to the zone file, relative paths are resolved from the working directory
+    pub file: PathBuf,
+    /// Secondaries allowed to pull the zone with AXFR, nobody by default
+    #[serde(default)]
+    pub allow_transfer: Vec<Network>,
+    /// Keys that allow pulling the zone from any address when a transfer is signed with them
+    #[serde(default, deserialize_with = "crate::name::deserialize_names")]
+    pub transfer_keys: Vec<DnsName>,
+    /// Clients allowed to change the zone with dynamic updates, nobody by default
+    #[serde(default)]
+    pub allow_update: Vec<Network>,
+    /// Keys that allow updates from any address when they are signed with them
+    #[serde(default, deserialize_with = "crate::name::deserialize_names")]
+    pub update_keys: Vec<DnsName>,
+    /// Write the zone back to `file` after every dynamic update
+    #[serde(default)]
+    pub persist: bool,
+    /// Private key signing the DNSKEY RRset of the zone, which enables DNSSEC. It signs the rest of
+    /// the zone too when there is no `zsk`.
+    #[serde(default)]
+    pub ksk: Option<PathBuf>,
+    /// Private key signing everything but the DNSKEY RRset
+    #[serde(default)]
+    pub zsk: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 2053),
+            upstream: Vec::new(),
+            zones: Vec::new(),
+            keys: Vec::new(),
+            client_subnet: None,
+            nsid: None,
+            hosts: None,
+            blocklist: None,
+            forward: Vec::new(),
+            rpz: Vec::new(),
+            catalogs: Vec::new(),
+            local_records: Vec::new(),
+            balanced: Vec::new(),
+            views: Vec::new(),
+            proxy: None,
+            race: 1,
+            ddr: false,
+            query_timeout: 2000,
+            query_retries: 2,
+            allow_query: everyone(),
+            allow_recursion: everyone(),
+            allow_transfer: Vec::new(),
+            rate_limit: None,
+            dns64: None,
+            round_robin: true,
+            any_queries: AnyPolicy::default(),
+            cache_size: 10_000,
+            min_ttl: 0,
+            max_ttl: u32::MAX,
+            control: None,
+            dnstap: None,
+            query_log: None,
+            mdns: None,
+            llmnr: false,
+        }
+    }
+}
+
+/// How queries for ANY are answered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnyPolicy {
+    /// A synthesized HINFO record, so the query can't be used for amplification and nothing is
+    /// forwarded
+    #[default]
+    Hinfo,
+    /// Every record of the name from the local sources, or else the cache, forwarding only names
+    /// with nothing cached
+    All,
+}
+
+/// Where the hosts file is and how long its answers may be cached
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HostsConfig {
+    pub file: PathBuf,
+    pub ttl: u32,
+}
+
+/// The hosts file of the system, with answers cached for five minutes
+impl Default for HostsConfig {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::from(HOSTS_FILE),
+            ttl: 300,
+        }
+    }
+}
+
+/// Lists of domains to block, each blocking every name below it too
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BlocklistConfig {
+    /// Paths or URLs of lists in hosts format or with one domain per line
+    pub lists: Vec<String>,
+    /// Domains that are never blocked, along with every name below them
+    #[serde(deserialize_with = "crate::name::deserialize_names")]
+    pub allow: Vec<DnsName>,
+    /// How blocked names are answered
+    pub policy: BlockPolicy,
+    /// Seconds between reloading the lists
+    pub refresh: u64,
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        Self {
+            lists: Vec::new(),
+            allow: Vec::new(),
+            policy: BlockPolicy::default(),
+            refresh: 24 * 60 * 60,
+        }
+    }
+}
+
+/// A rule sending queries for a domain, and every name below it, to other upstreams. The rule with
+/// the longest matching domain wins, so a rule for `.` takes over everything the others don't match
+/// from `upstream`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ForwardConfig {
+    #[serde(deserialize_with = "crate::name::deserialize_name")]
+    pub domain: DnsName,
+    /// One address or a list, failing over like `upstream`
+    #[serde(deserialize_with = "one_or_many")]
+    pub upstream: Vec<SocketAddr>,
+    /// How the queries get to the upstreams, plain UDP by default
+    #[serde(default)]
+    pub transport: ForwardTransport,
+    /// The name the certificates of the upstreams are checked against with `tls`
+    #[serde(default, deserialize_with = "crate::name::deserialize_optional_name")]
+    pub tls_name: Option<DnsName>,
+    /// Where the queries are POSTed to with `https`, at the addresses of the upstreams
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl ForwardConfig {
+    /// The upstreams of the rule, over its transport. DNS over TLS goes to port 853 unless the
+    /// upstreams have another one than 53.
+    pub fn upstreams(&self) -> Result<Upstreams> {
+        let error =
+            |message: &str| DnsError::Config(format!("Forwarding {}: {message}", self.domain));
+        let transport = match (self.transport, &self.tls_name, &self.url) {
+            (ForwardTransport::Udp, None, None) => Transport::Udp,
+            (ForwardTransport::Tcp, None, None) => Transport::Tcp,
+            (ForwardTransport::Tls, Some(name), None) => Transport::Tls { name: name.clone() },
+            (ForwardTransport::Tls, None, _) => return Err(error("tls needs a tls-name")),
+            (ForwardTransport::Https, None, Some(url)) if url.starts_with("https://") => {
+                Transport::Https { url: url.clone() }
+            }
+            (ForwardTransport::Https, None, Some(url)) => {
+                return Err(error(&format!("{url} isn't an https URL")))
+            }
+            (ForwardTransport::Https, _, None) => return Err(error("https needs a url")),
+            (_, Some(_), _) => return Err(error("tls-name is only used with tls")),
+            (_, _, Some(_)) => return Err(error("url is only used with https")),
+        };
+        let addrs = self
+            .upstream
+            .iter()
+            .map(|&addr| match transport {
+                Transport::Tls { .. } if addr.port() == DNS_PORT => {
+                    SocketAddr::new(addr.ip(), DOT_PORT)
+                }
+                _ => addr,
+            })
+            .collect();
+
+        Ok(Upstreams::new(addrs).with_transport(transport))
+    }
+}
+
+/// How the queries of a forwarding rule get to its upstreams
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardTransport {
+    #[default]
+    Udp,
+    Tcp,
+    /// DNS over TLS, checking the certificates against `tls-name`
+    Tls,
+    /// DNS over HTTPS to `url`
+    Https,
+}
+
+/// A name balanced between the addresses of several servers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BalancedConfig {
+    #[serde(deserialize_with = "crate::name::deserialize_name")]
+    pub name: DnsName,
+    /// Kept short so clients notice soon when a target goes down
+    #[serde(default = "default_balanced_ttl")]
+    pub ttl: u32,
+    /// Addresses in each answer, all of the healthy ones by default
+    #[serde(default)]
+    pub count: Option<usize>,
+    /// How targets are checked, without which they are always considered up
+    #[serde(default)]
+    pub check: Option<HealthCheck>,
+    /// Seconds between checks
+    #[serde(default = "default_check_interval")]
+    pub check_interval: u64,
+    pub targets: Vec<TargetConfig>,
+}
+
+const fn default_balanced_ttl() -> u32 {
+    30
+}
+
+const fn default_check_interval() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TargetConfig {
+    pub addr: IpAddr,
+    /// Share of the answers the target comes first in, relative to the others. 0 only answers
+    /// with it when no other target is up.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+const fn default_weight() -> u32 {
+    1
+}
+
+/// How to tell whether a target is up
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub enum HealthCheck {
+    /// A TCP connection to this port succeeds
+    Tcp(u16),
+    /// An HTTP GET of this path succeeds, with a status below 400
+    Http {
+        #[serde(default = "default_http_port")]
+        port: u16,
+        #[serde(default = "default_http_path")]
+        path: String,
+    },
+}
+
+const fn default_http_port() -> u16 {
+    80
+}
+
+fn default_http_path() -> String {
+    String::from("/")
+}
+
+/// What the clients of a view see in place of, or on top of, what everyone else does. Names the view
+/// doesn't answer itself are answered like for any other client.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ViewConfig {
+    /// Name of the view, for logging
+    pub name: String,
+    /// Networks the clients of the view query from
+    pub match_clients: Vec<Network>,
+    /// Zones answered to the clients of the view before the zones of the server. They are served
+    /// as they are loaded, without dynamic updates or transfers.
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+    #[serde(default)]
+    pub local_records: Vec<LocalRecordConfig>,
+    /// Upstreams for the clients of the view, in place of the ones of the server
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub upstream: Vec<SocketAddr>,
+    /// Forwarding rules for the clients of the view, in place of the ones of the server
+    #[serde(default)]
+    pub forward: Vec<ForwardConfig>,
+}
+
+impl ViewConfig {
+    /// The records of `local-records`
+    pub fn local_records(&self) -> Result<Vec<DnsRecord>> {
+        local_records(&self.local_records)
+    }
+}
+
+/// A response policy zone, read from a file or pulled from a primary like a feed of malicious
+/// domains
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RpzConfig {
+    #[serde(deserialize_with = "crate::name::deserialize_name")]
+    pub origin: DnsName,
+    /// Path to the zone file
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Server to transfer the zone from when there is no `file`
+    #[serde(default)]
+    pub primary: Option<SocketAddr>,
+    /// Name of the key signing the transfer, from `keys`
+    #[serde(default, deserialize_with = "crate::name::deserialize_optional_name")]
+    pub key: Option<DnsName>,
+}
+
+/// A catalog zone, RFC 9432, transferred from a primary along with every zone it lists
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CatalogConfig {
+    #[serde(deserialize_with = "crate::name::deserialize_name")]
+    pub origin: DnsName,
+    pub primary: SocketAddr,
+    /// Name of the key signing the transfers, from `keys`
+    #[serde(default, deserialize_with = "crate::name::deserialize_optional_name")]
+    pub key: Option<DnsName>,
+}
+
+/// A record defined directly in the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LocalRecordConfig {
+    #[serde(deserialize_with = "crate::name::deserialize_name")]
+    pub name: DnsName,
+    #[serde(rename = "type")]
+    pub rtype: String,
+    /// The data of the record as it is written in zone files, such as `10 mail.lan.` for MX. Names
+    /// in it are absolute, with or without a trailing dot.
+    pub value: String,
+    #[serde(default = "default_local_ttl")]
+    pub ttl: u32,
+}
+
+const fn default_local_ttl() -> u32 {
+    300
+}
+
+impl LocalRecordConfig {
+    pub fn record(&self) -> Result<DnsRecord> {
+        let line = format!(
+            "{} {} IN {} {}",
+            self.name.to_fqdn(),
+            self.ttl,
+            self.rtype,
+            self.value
+        );
+
+        DnsRecord::parse_line(&line, &DnsName::root()).map_err(|e| {
+            DnsError::Config(format!(
+                "Invalid {} record for {}: {e}",
+                self.rtype, self.name
+            ))
+        })
+    }
+}
+
+/// How many of the same response a network gets over UDP
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RateLimitConfig {
+    /// Identical responses allowed each second
+    pub responses_per_second: u32,
+    /// Seconds of excess responses remembered, which the network has to stay under the limit
+    /// for before it is answered again
+    pub window: u64,
+    /// Every this many withheld responses, a truncated one is sent so real clients retry over
+    /// TCP. 0 drops them all.
+    pub slip: u32,
+    /// Leading bits of IPv4 addresses that make up a network
+    pub ipv4_prefix: u8,
+    /// Leading bits of IPv6 addresses that make up a network
+    pub ipv6_prefix: u8,
+    /// Clients that are never limited
+    pub exempt: Vec<Network>,
+}
+
+/// The defaults of BIND
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            responses_per_second: 5,
+            window: 15,
+            slip: 2,
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+            exempt: Vec::new(),
+        }
+    }
+}
+
+/// Where dnstap messages are written, either a unix socket or a file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DnstapConfig {
+    /// Socket of a collector reading Frame Streams
+    pub socket: Option<PathBuf>,
+    /// File to write the messages to, replaced when the server starts
+    pub file: Option<PathBuf>,
+    /// Name of this server in every message, such as its hostname
+    pub identity: Option<String>,
+}
+
+/// Where queries are logged and when the log is rotated
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct QueryLogConfig {
+    pub file: PathBuf,
+    #[serde(default)]
+    pub format: QueryLogFormat,
+    /// Bytes the log may grow to before it is rotated, 0 for no limit
+    #[serde(default)]
+    pub max_size: u64,
+    /// Seconds after which the log is rotated, 0 to never rotate it by age
+    #[serde(default)]
+    pub rotate_interval: u64,
+    /// Rotated logs kept as `file.1`, `file.2` and so on, the oldest being deleted
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+    /// Log the /24 or /48 network of clients rather than their address
+    #[serde(default)]
+    pub anonymize: bool,
+}
+
+const fn default_keep() -> usize {
+    5
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueryLogFormat {
+    /// Space separated fields, for reading
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+/// The `.local` names this host answers for over multicast DNS
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MdnsConfig {
+    #[serde(deserialize_with = "crate::name::deserialize_names")]
+    pub names: Vec<DnsName>,
+    /// Addresses the names resolve to. Without any, the address of the interface that reaches
+    /// the mDNS group is used.
+    #[serde(default)]
+    pub addresses: Vec<IpAddr>,
+    #[serde(default = "default_mdns_ttl")]
+    pub ttl: u32,
+}
+
+/// The TTL RFC 6762 recommends for records with a host name
+const fn default_mdns_ttl() -> u32 {
+    120
+}
+
+/// How much of a client address is revealed to the upstream
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ClientSubnetConfig {
+    /// Leading bits of IPv4 addresses that are sent
+    pub ipv4_prefix: u8,
+    /// Leading bits of IPv6 addresses that are sent
+    pub ipv6_prefix: u8,
+}
+
+/// The prefixes recommended by RFC 7871, which identify a network without its hosts
+impl Default for ClientSubnetConfig {
+    fn default() -> Self {
+        Self {
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+        }
+    }
+}
+
+impl ClientSubnetConfig {
+    /// The subnet to send upstream for a query from `client`, which may have sent a subnet of its
+    /// own in `requested`. Shorter prefixes asked for by the client are honored, including 0 to
+    /// opt out entirely. Addresses that mean nothing outside the local network are never sent.
+    pub fn subnet(&self, client: IpAddr, requested: Option<ClientSubnet>) -> Option<ClientSubnet> {
+        let (addr, prefix) = match requested {
+            Some(requested) => (requested.addr, requested.source_prefix),
+            None => (client, u8::MAX),
+        };
+        let (local, max_prefix) = match addr {
+            IpAddr::V4(v4) => (
+                v4.is_loopback() || v4.is_private() || v4.is_link_local() || v4.is_unspecified(),
+                self.ipv4_prefix,
+            ),
+            IpAddr::V6(v6) => (
+                v6.is_loopback()
+                    || v6.is_unique_local()
+                    || v6.is_unicast_link_local()
+                    || v6.is_unspecified(),
+                self.ipv6_prefix,
+            ),
+        };
+
+        let prefix = prefix.min(max_prefix);
+        if local || prefix == 0 {
+            return None;
+        }
+
+        Some(ClientSubnet::new(addr, prefix))
+    }
+}
+
+impl Config {
+    /// The config of the zone at `origin`
+    pub fn zone(&self, origin: &DnsName) -> Option<&ZoneConfig> {
+        self.zones.iter().find(|zone| zone.origin == *origin)
+    }
+
+    /// Whether `client` may query the server
+    pub fn allows_query(&self, client: IpAddr) -> bool {
+        matches(&self.allow_query, client)
+    }
+
+    /// Whether `client` may have queries forwarded for it
+    pub fn allows_recursion(&self, client: IpAddr) -> bool {
+        matches(&self.allow_recursion, client)
+    }
+
+    /// Whether `client` may transfer the zone at `origin`, with a request signed by `key` if any
+    pub fn allows_transfer(&self, origin: &DnsName, client: IpAddr, key: Option<&DnsName>) -> bool {
+        self.zone(origin).is_some_and(|zone| {
+            matches(&self.allow_transfer, client)
+                || matches(&zone.allow_transfer, client)
+                || key.is_some_and(|key| zone.transfer_keys.contains(key))
+        })
+    }
+
+    /// Whether `client` may send dynamic updates for the zone at `origin`, signed by `key` if any
+    pub fn allows_update(&self, origin: &DnsName, client: IpAddr, key: Option<&DnsName>) -> bool {
+        self.zone(origin).is_some_and(|zone| {
+            matches(&zone.allow_update, client)
+                || key.is_some_and(|key| zone.update_keys.contains(key))
+        })
+    }
+
+    /// The configured upstreams, or else the nameservers in resolv.conf, or else a public resolver
+    pub fn upstreams(&self) -> Vec<SocketAddr> {
+        if !self.upstream.is_empty() {
+            return self.upstream.clone();
+        }
+
+        ResolvConf::system().upstreams()
+    }
+
+    /// The records of `local-records`
+    pub fn local_records(&self) -> Result<Vec<DnsRecord>> {
+        local_records(&self.local_records)
+    }
+
+    /// How long forwarded queries wait for the upstream, and how often they are retried
+    pub const fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::new(
+            Duration::from_millis(self.query_timeout),
+            self.query_retries,
+        )
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+
+        text.parse()
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let config: Self = toml::from_str(s).map_err(|e| DnsError::Config(e.to_string()))?;
+        let rules = config.views.iter().flat_map(|view| &view.forward);
+        for rule in config.forward.iter().chain(rules) {
+            if rule.upstream.is_empty() {
+                return Err(DnsError::Config(format!(
+                    "No upstream to forward {} to",
+                    rule.domain
+                )));
+            }
+            rule.upstreams()?;
+        }
+        config.local_records()?;
+        if config.min_ttl > config.max_ttl {
+            return Err(DnsError::Config(format!(
+                "min-ttl {} is above max-ttl {}",
+                config.min_ttl, config.max_ttl
+            )));
+        }
+        if let Some(balanced) = config.balanced.iter().find(|name| name.targets.is_empty()) {
+            return Err(DnsError::Config(format!(
+                "No targets to balance {} between",
+                balanced.name
+            )));
+        }
+        if let Some(dns64) = &config.dns64 {
+            dns64.validate()?;
+        }
+        if let Some(control) = config.control.filter(|addr| !addr.ip().is_loopback()) {
+            return Err(DnsError::Config(format!(
+                "Control address {control} isn't a loopback address"
+            )));
+        }
+        if let Some(dnstap) = &config.dnstap {
+            if dnstap.socket.is_some() == dnstap.file.is_some() {
+                return Err(DnsError::Config(String::from(
+                    "dnstap needs either a socket or a file",
+                )));
+            }
+        }
+        for view in &config.views {
+            view.local_records()?;
+        }
+        if let Some(mdns) = &config.mdns {
+            let local = DnsName::new("local")?;
+            if let Some(name) = mdns.names.iter().find(|name| !name.is_subdomain_of(&local)) {
+                return Err(DnsError::Config(format!(
+                    "mDNS name {name} isn't under .local"
+                )));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Every IPv4 and IPv6 address
+fn everyone() -> Vec<Network> {
+    vec![
+        Network::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        Network::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    ]
+}
+
+/// Whether `client` is in any of `networks`
+fn matches(networks: &[Network], client: IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(client))
+}
+
+fn local_records(configs: &[LocalRecordConfig]) -> Result<Vec<DnsRecord>> {
+    configs.iter().map(LocalRecordConfig::record).collect()
+}
+
+/// Accept a single server as well as a list of them, each parsed by [`parse_server`], or
+/// `"system"` for the nameservers of the system
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<SocketAddr>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let servers = match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(server) => vec![server],
+        OneOrMany::Many(servers) => servers,
+    };
+    servers
+        .iter()
+        .map(|server| match server.as_str() {
+            "system" => Ok(ResolvConf::system().upstreams()),
+            server => parse_server(server)
+                .map(|server| vec![server])
+                .map_err(serde::de::Error::custom),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|servers| servers.concat())
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::PoisonError;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::authority::Authority;
+use crate::catalog::Catalog;
+use crate::name::DnsName;
+use crate::server::{self, ServerContext};
+use crate::upstream::{Forwarders, Upstreams};
+
+/// How long a control connection may take to send its command
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sets a new log filter, failing with why it can't
+type SetFilter = dyn Fn(&str) -> Result<(), String> + Send + Sync;
+
+/// Changes the verbosity of the logs of a running server. Set up by whoever installed the
+/// subscriber, since only they hold its filter.
+pub struct LogLevel(Box<SetFilter>);
+
+impl LogLevel {
+    /// `set` takes a filter like `RUST_LOG` does, such as `debug` or `dns_server::upstream=trace`
+    pub fn new(set: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static) -> Self {
+        Self(Box::new(set))
+    }
+}
+
+impl fmt::Debug for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LogLevel")
+    }
+}
+
+/// Answer commands on the control listener, one connection at a time. Each connection sends a
+/// single line with a command and its arguments, and gets the output back before it is closed:
+///
+/// - `flush-cache`: drop every cached answer
+/// - `flush-name <name>`: drop the cached answers for a name
+/// - `dump-cache [domain]`: show the cached answers, for a domain and the names below it or all
+/// - `reload`: load the zone files and blocklists again
+/// - `stats`: show the counts of queries and responses, and the state of the cache, upstreams
+///   and blocklist
+/// - `set-log-level <filter>`: change which logs are written, like `RUST_LOG`
+pub fn serve(context: &ServerContext, listener: &TcpListener) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| handle(context, stream));
+        if let Err(e) = result {
+            warn!("Failed to answer control connection: {e}");
+        }
+    }
+}
+
+fn handle(context: &ServerContext, stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+    info!("Control command: {}", words.join(" "));
+    let output = match execute(context, &words) {
+        Ok(output) => output,
+        Err(e) => format!("error: {e}\n"),
+    };
+
+    (&stream).write_all(output.as_bytes())
+}
+
+/// Run a command, returning its output
+fn execute(context: &ServerContext, words: &[&str]) -> Result<String, String> {
+    match words {
+        ["flush-cache"] => Ok(format!("Flushed {} answers\n", context.cache.flush())),
+        ["flush-name", name] => {
+            let name: DnsName = name.parse().map_err(|e| format!("{e}"))?;
+            Ok(format!(
+                "Flushed {} answers\n",
+                context.cache.flush_name(&name)
+            ))
+        }
+        ["dump-cache"] => Ok(dump_cache(context, None)),
+        ["dump-cache", domain] => {
+            let domain: DnsName = domain.parse().map_err(|e| format!("{e}"))?;
+            Ok(dump_cache(context, Some(&domain)))
+        }
+        ["reload"] => reload(context),
+        ["stats"] => Ok(stats(context)),
+        ["set-log-level", filter] => {
+            let log_level = context
+                .log_level
+                .as_ref()
+                .ok_or("The log level can't be changed")?;
+            (log_level.0)(filter)?;
+            Ok(format!("Log level set to {filter}\n"))
+        }
+        [] => Err(String::from("No command")),
+        [command, ..] => Err(format!("Unknown command or arguments for {command}")),
+    }
+}
+
+/// Load the zone files again, replacing the zones with their dynamic updates, and the blocklists.
+/// Zones from catalogs are kept as they are.
+fn reload(context: &ServerContext) -> Result<String, String> {
+    let mut authority = Authority::load(&context.config.zones).map_err(|e| e.to_string())?;
+    let zones = authority.zones().len();
+    let mut served = context
+        .authority
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    for origin in context.catalogs.iter().flat_map(Catalog::members) {
+        if let Some(zone) = served.remove(&origin) {
+            authority.insert(zone);
+        }
+    }
+    *served = authority;
+    drop(served);
+
+    let mut output = format!("Reloaded {zones} zones\n");
+    if let Some(blocked) = server::reload_blocklist(context) {
+        let _ = writeln!(output, "Blocking {blocked} domains");
+    }
+
+    Ok(output)
+}
+
+/// A line for every cached answer with its remaining TTL and hits, followed by its records
+fn dump_cache(context: &ServerContext, domain: Option<&DnsName>) -> String {
+    let mut output = String::new();
+    for answer in context.cache.dump(domain) {
+        let _ = write!(
+            output,
+            "{} {} {:?} ttl={} hits={}",
+            answer.qname, answer.qtype, answer.rescode, answer.ttl, answer.hits
+        );
+        if let Some(view) = &answer.view {
+            let _ = write!(output, " view={view}");
+        }
+        if let Some(network) = &answer.network {
+            let _ = write!(output, " subnet={network}");
+        }
+        output.push('\n');
+        for rec in &answer.answers {
+            let _ = writeln!(output, "\t{rec}");
+        }
+    }
+
+    output
+}
+
+fn stats(context: &ServerContext) -> String {
+    let stats = context.stats();
+    let mut output = String::new();
+    let _ = writeln!(output, "uptime {}", stats.uptime.as_secs());
+    let _ = writeln!(output, "queries {}", stats.queries);
+    for (qtype, count) in &stats.qtypes {
+        let _ = writeln!(output, "queries.{qtype} {count}");
+    }
+    for (rescode, count) in &stats.responses {
+        let _ = writeln!(output, "responses.{rescode:?} {count}");
+    }
+    let _ = writeln!(output, "responses.dropped {}", stats.dropped);
+    let _ = writeln!(output, "timeouts {}", stats.timeouts);
+    let _ = writeln!(output, "cache.hits {}", stats.cache_hits);
+    let _ = writeln!(output, "cache.misses {}", stats.cache_misses);
+    let _ = writeln!(output, "cache.entries {}", context.cache.len());
+    let _ = writeln!(output, "zones {}", context.authority().zones().len());
+    if let Some(blocklist) = &context.blocklist {
+        let blocklist = blocklist.read().unwrap_or_else(PoisonError::into_inner);
+        let _ = writeln!(output, "blocklist.domains {}", blocklist.len());
+    }
+
+    // Views without upstreams of their own share the default ones
+    let views = context
+        .views
+        .iter()
+        .filter_map(|view| view.forwarders.as_ref());
+    let upstreams: BTreeMap<_, _> = views
+        .chain([&context.forwarders])
+        .flat_map(Forwarders::all)
+        .flat_map(Upstreams::health)
+        .collect();
+    for (addr, health) in upstreams {
+        let rtt = health
+            .rtt
+            .map_or_else(|| String::from("-"), |rtt| format!("{}ms", rtt.as_millis()));
+        let state = if health.down { "down" } else { "up" };
+        let _ = writeln!(
+            output,
+            "upstream {addr} {state} rtt={rtt} failures={}",
+            health.failures
+        );
+    }
+
+    output
+}
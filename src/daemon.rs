@@ -0,0 +1,163 @@
+use std::env;
+use std::fs;
+#[cfg(unix)]
+use std::io;
+use std::path::Path;
+
+use socket2::Socket;
+
+use crate::error::{DnsError, Result};
+
+/// The first file descriptor passed with socket activation, after stdin, stdout and stderr
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// The sockets systemd passed to the process with socket activation, sd_listen_fds(3), in the
+/// order of the socket unit, or none when it wasn't activated that way. The variables saying which
+/// are unset, so processes started from here don't take them too.
+pub fn listen_fds() -> Result<Vec<Socket>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    // Meant for another process when the PID isn't ours, like a parent that didn't unset them
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(Vec::new());
+    };
+    if pid.parse() != Ok(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let count: i32 = fds
+        .parse()
+        .map_err(|_| DnsError::Config(format!("Invalid LISTEN_FDS {fds}")))?;
+
+    sockets(count)
+}
+
+#[cfg(unix)]
+fn sockets(count: i32) -> Result<Vec<Socket>> {
+    use std::os::fd::FromRawFd;
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: systemd passed these descriptors to this process, which owns them from here.
+            // They are closed on exec like the ones opened here.
+            unsafe {
+                if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                Ok(Socket::from_raw_fd(fd))
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn sockets(_count: i32) -> Result<Vec<Socket>> {
+    Err(unsupported("Socket activation"))
+}
+
+/// Fork into the background, in a session of its own without a terminal and with the standard
+/// streams going to /dev/null. The parent writes the PID of the child to `pidfile`, if any, and
+/// exits. Has to be called before any thread is started, since only the calling one is forked.
+#[cfg(unix)]
+pub fn daemonize(pidfile: Option<&Path>) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: no other thread is running yet, so the child is a complete copy of the process
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error().into()),
+        0 => {}
+        child => {
+            if let Some(path) = pidfile {
+                if let Err(e) = write_pidfile(path, child.unsigned_abs()) {
+                    // SAFETY: signals the child that was just forked
+                    unsafe { libc::kill(child, libc::SIGTERM) };
+                    return Err(e);
+                }
+            }
+            std::process::exit(0);
+        }
+    }
+
+    // SAFETY: setsid has no preconditions, and fails in a process group leader, which a forked
+    // child can't be
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let null = fs::File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for stream in 0..=2 {
+        // SAFETY: both are open descriptors, and the standard streams are replaced whole
+        if unsafe { libc::dup2(null.as_raw_fd(), stream) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_pidfile: Option<&Path>) -> Result<()> {
+    Err(unsupported("Daemon mode"))
+}
+
+/// Write `pid` to `path` the way init scripts and `PIDFile=` read it, followed by a newline
+pub fn write_pidfile(path: &Path, pid: u32) -> Result<()> {
+    fs::write(path, format!("{pid}\n"))
+        .map_err(|e| DnsError::Config(format!("Failed to write pidfile {}: {e}", path.display())))
+}
+
+/// Switch to `user` and its primary group for good, so the server no longer runs as root once its
+/// sockets are bound. Supplementary groups are dropped.
+#[cfg(unix)]
+pub fn drop_privileges(user: &str) -> Result<()> {
+    use std::ffi::CString;
+    use std::{mem, ptr};
+
+    let name = CString::new(user).map_err(|_| DnsError::Config(format!("Invalid user {user}")))?;
+    // SAFETY: a passwd made of integers and null pointers is valid, and is only read once
+    // getpwnam_r filled it in from `buf`, which outlives it
+    let (uid, gid) = unsafe {
+        let mut entry: libc::passwd = mem::zeroed();
+        let mut buf = vec![0; 16 * 1024];
+        let mut found = ptr::null_mut();
+        let err = libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        );
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err).into());
+        }
+        if found.is_null() {
+            return Err(DnsError::Config(format!("No user {user}")));
+        }
+        (entry.pw_uid, entry.pw_gid)
+    };
+
+    // The groups go first, since changing them takes the privileges the user is about to lose.
+    // SAFETY: plain system calls, on a group list that outlives the call
+    unsafe {
+        if libc::setgroups(1, &gid) == -1 || libc::setgid(gid) == -1 || libc::setuid(uid) == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(_user: &str) -> Result<()> {
+    Err(unsupported("Switching users"))
+}
+
+#[cfg(not(unix))]
+fn unsupported(what: &str) -> DnsError {
+    DnsError::Config(format!("{what} isn't supported on this platform"))
+}
@@ -0,0 +1,183 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::buffer::BytePacketBuffer;
+use crate::error::{DnsError, Result};
+use crate::header::ResultCode;
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::RData;
+use crate::resolver::{lookup_ip, lookup_observed, Forwarder, IpPreference, RetryPolicy};
+use crate::socks::Socks5Proxy;
+use crate::tls::{TlsUpstream, ALPN_DOT, DOT_PORT};
+use crate::upstream::Upstreams;
+
+/// The name a resolver is asked about for the resolvers it designates, RFC 9462 section 4
+pub const RESOLVER_ARPA: &str = "_dns.resolver.arpa";
+
+/// The SVCB record type, RFC 9460, which isn't modeled and read from its rdata here
+pub const SVCB: u16 = 64;
+
+/// How often upstreams are asked again which resolvers they designate
+pub const DISCOVERY_INTERVAL: Duration = Duration::from_secs(3600);
+
+const KEY_ALPN: u16 = 1;
+const KEY_PORT: u16 = 3;
+const KEY_IPV4HINT: u16 = 4;
+const KEY_IPV6HINT: u16 = 6;
+const KEY_DOHPATH: u16 = 7;
+
+/// An encrypted resolver designated by a resolver, from a service mode SVCB record of
+/// `_dns.resolver.arpa`, RFC 9461
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Designation {
+    /// Lower ones are preferred
+    pub priority: u16,
+    pub target: DnsName,
+    /// The protocols it speaks, such as `dot` and `h2`
+    pub alpn: Vec<String>,
+    pub port: Option<u16>,
+    /// Addresses of the target, so it doesn't have to be looked up
+    pub hints: Vec<IpAddr>,
+    /// The URI template for DNS over HTTPS
+    pub dohpath: Option<String>,
+}
+
+impl Designation {
+    /// Parse the rdata of a SVCB record. Parameters other than the ones for DNS servers are
+    /// skipped.
+    pub fn from_rdata(data: &[u8]) -> Result<Self> {
+        let mut buf = BytePacketBuffer {
+            buf: data.to_vec(),
+            pos: 0,
+        };
+        let priority = buf.read_u16()?;
+        let target = buf.read_name()?;
+        let mut designation = Self {
+            priority,
+            target,
+            alpn: Vec::new(),
+            port: None,
+            hints: Vec::new(),
+            dohpath: None,
+        };
+
+        while buf.pos() < data.len() {
+            let key = buf.read_u16()?;
+            let len = buf.read_u16()?;
+            let value = buf.read_range(len.into())?;
+            match key {
+                KEY_ALPN => {
+                    let mut rest = value;
+                    while let Some((&len, ids)) = rest.split_first() {
+                        let id = ids.get(..len.into()).ok_or(DnsError::BufferOverrun)?;
+                        designation
+                            .alpn
+                            .push(String::from_utf8_lossy(id).into_owned());
+                        rest = &ids[len.into()..];
+                    }
+                }
+                KEY_PORT => {
+                    let port = value.try_into().map_err(|_| DnsError::BufferOverrun)?;
+                    designation.port = Some(u16::from_be_bytes(port));
+                }
+                KEY_IPV4HINT => designation.hints.extend(
+                    value
+                        .chunks_exact(4)
+                        .filter_map(|octets| <[u8; 4]>::try_from(octets).ok())
+                        .map(IpAddr::from),
+                ),
+                KEY_IPV6HINT => designation.hints.extend(
+                    value
+                        .chunks_exact(16)
+                        .filter_map(|octets| <[u8; 16]>::try_from(octets).ok())
+                        .map(IpAddr::from),
+                ),
+                KEY_DOHPATH => {
+                    designation.dohpath = Some(String::from_utf8_lossy(value).into_owned());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(designation)
+    }
+
+    pub fn speaks_dot(&self) -> bool {
+        self.alpn.iter().any(|id| id == ALPN_DOT)
+    }
+}
+
+/// The designations in the answers of `response`, by priority. Alias mode records, with a
+/// priority of 0, and ones without a target name to check the certificate for are left out, as
+/// are records that can't be parsed.
+pub fn designations(response: &DnsPacket) -> Vec<Designation> {
+    let mut designations: Vec<_> = response
+        .answers
+        .iter()
+        .filter_map(|rec| match &rec.rdata {
+            RData::UNKNOWN { qtype: SVCB, data } => Designation::from_rdata(data).ok(),
+            _ => None,
+        })
+        .filter(|designation| designation.priority > 0 && !designation.target.is_root())
+        .collect();
+    designations.sort_by_key(|designation| designation.priority);
+
+    designations
+}
+
+/// The DNS over TLS resolver `upstream` designates, RFC 9462 section 4.2, or `None` when it
+/// designates none that could be verified: the certificate of the designated resolver has to be
+/// valid for its own name, and for the address of `upstream` too, so an attacker on the path can't
+/// designate a server of their own. Resolvers that only speak DNS over HTTPS aren't used.
+pub fn discover(
+    upstream: SocketAddr,
+    policy: RetryPolicy,
+    proxy: Option<&Socks5Proxy>,
+) -> Result<Option<TlsUpstream>> {
+    let name = DnsName::new(RESOLVER_ARPA)?;
+    let response = lookup_observed(
+        &name,
+        QueryType::UNKNOWN(SVCB),
+        upstream,
+        None,
+        policy,
+        proxy,
+        &|_| {},
+    )?;
+    if response.header.rescode != ResultCode::NOERROR {
+        return Ok(None);
+    }
+
+    for designation in designations(&response) {
+        if !designation.speaks_dot() {
+            continue;
+        }
+
+        let addrs = if designation.hints.is_empty() {
+            let forwarder = Forwarder::new(Upstreams::new(vec![upstream]), policy);
+            lookup_ip(&forwarder, &designation.target, IpPreference::default())?
+        } else {
+            designation.hints.clone()
+        };
+        let port = designation.port.unwrap_or(DOT_PORT);
+        for addr in addrs {
+            let tls = TlsUpstream {
+                addr: SocketAddr::new(addr, port),
+                name: designation.target.clone(),
+            };
+            let verified = tls
+                .connect(policy.timeout, proxy)
+                .and_then(|stream| tls.verify_ip(&stream, upstream.ip()));
+            match verified {
+                Ok(()) => return Ok(Some(tls)),
+                Err(e) => debug!("Not using {} for {upstream}: {e}", tls.addr),
+            }
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,312 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::buffer::BytePacketBuffer;
+use crate::header::ResultCode;
+use crate::question::QueryType;
+use crate::record::{DnsRecord, RData};
+
+/// Bytes shown on each line of a dump
+const ROW_LEN: usize = 8;
+
+/// A range of bytes of a message and what they mean, or the heading of a part of it when empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub start: usize,
+    pub len: usize,
+    pub note: String,
+}
+
+/// Split a message into its fields: the header, every label and compression pointer of the names,
+/// and the fixed fields and rdata of each record. Unlike parsing it doesn't stop at the first
+/// problem, the fields up to it are returned along with a note of what went wrong.
+pub fn dissect(wire: &[u8]) -> Vec<Field> {
+    let mut dissector = Dissector {
+        wire,
+        fields: Vec::new(),
+    };
+    if let Err(Truncated(pos)) = dissector.message() {
+        dissector.heading(
+            pos,
+            format!("message ends at {pos:#06x}, in the middle of a field"),
+        );
+    }
+
+    let mut fields = dissector.fields;
+    let end = fields.iter().map(|field| field.start + field.len).max();
+    if let Some(end) = end.filter(|&end| end < wire.len()) {
+        fields.push(Field {
+            start: end,
+            len: wire.len() - end,
+            note: String::from("trailing bytes"),
+        });
+    }
+
+    fields
+}
+
+/// A hex dump of a message with the notes of its fields next to their bytes, like
+///
+/// ```text
+/// 0000  12 34                     id: 4660
+/// 0002  01 00                     flags: qr=0 opcode=0 aa=0 tc=0 rd=1 ra=0 z=0 ad=0 cd=0 rcode=NOERROR
+/// ```
+pub fn annotate(wire: &[u8], fields: &[Field]) -> String {
+    let mut output = String::new();
+    for field in fields {
+        if field.len == 0 {
+            let _ = writeln!(output, "\n; {}", field.note);
+            continue;
+        }
+
+        let bytes = &wire[field.start..field.start + field.len];
+        for (i, row) in bytes.chunks(ROW_LEN).enumerate() {
+            let hex: Vec<_> = row.iter().map(|b| format!("{b:02x}")).collect();
+            let note = if i == 0 { field.note.as_str() } else { "" };
+            let line = format!(
+                "{:04x}  {:<width$} {note}",
+                field.start + i * ROW_LEN,
+                hex.join(" "),
+                width = ROW_LEN * 3
+            );
+            let _ = writeln!(output, "{}", line.trim_end());
+        }
+    }
+
+    output
+}
+
+/// A field that would run past the end of the message, at the position it starts
+struct Truncated(usize);
+
+struct Dissector<'a> {
+    wire: &'a [u8],
+    fields: Vec<Field>,
+}
+
+impl Dissector<'_> {
+    fn message(&mut self) -> Result<(), Truncated> {
+        self.heading(0, String::from("header"));
+        let id = self.u16(0)?;
+        self.field(0, 2, format!("id: {id}"))?;
+        let flags = self.u16(2)?;
+        let bit = |n: u16| flags >> n & 1;
+        self.field(
+            2,
+            2,
+            format!(
+                "flags: qr={} opcode={} aa={} tc={} rd={} ra={} z={} ad={} cd={} rcode={:?}",
+                bit(15),
+                flags >> 11 & 0x0f,
+                bit(10),
+                bit(9),
+                bit(8),
+                bit(7),
+                bit(6),
+                bit(5),
+                bit(4),
+                ResultCode::from((flags & 0x0f) as u8)
+            ),
+        )?;
+        let mut counts = [0; 4];
+        let sections = ["question", "answer", "authority", "additional"];
+        for (i, section) in sections.iter().enumerate() {
+            let pos = 4 + i * 2;
+            counts[i] = self.u16(pos)?;
+            self.field(pos, 2, format!("{section} count: {}", counts[i]))?;
+        }
+
+        let mut pos = 12;
+        for n in 1..=counts[0] {
+            self.heading(pos, format!("question {n}: {}", self.read_name(pos)));
+            pos = self.name(pos)?;
+            let qtype = QueryType::from(self.u16(pos)?);
+            self.field(pos, 2, format!("type: {qtype}"))?;
+            let class = self.u16(pos + 2)?;
+            self.field(pos + 2, 2, format!("class: {class}"))?;
+            pos += 4;
+        }
+        for (section, &count) in sections.iter().zip(&counts).skip(1) {
+            for n in 1..=count {
+                self.heading(pos, format!("{section} {n}: {}", self.read_record(pos)));
+                pos = self.record(pos)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The fields of a record starting at `pos`, returning where the next one starts
+    fn record(&mut self, pos: usize) -> Result<usize, Truncated> {
+        let start = pos;
+        let pos = self.name(pos)?;
+        let qtype = QueryType::from(self.u16(pos)?);
+        self.field(pos, 2, format!("type: {qtype}"))?;
+        let class = self.u16(pos + 2)?;
+        let ttl = self.u32(pos + 4)?;
+        if qtype == QueryType::OPT {
+            self.field(pos + 2, 2, format!("udp payload size: {class}"))?;
+            self.field(
+                pos + 4,
+                4,
+                format!(
+                    "extended rcode: {}, version: {}, flags: {:#06x}",
+                    ttl >> 24,
+                    ttl >> 16 & 0xff,
+                    ttl & 0xffff
+                ),
+            )?;
+        } else {
+            self.field(pos + 2, 2, format!("class: {class}"))?;
+            self.field(pos + 4, 4, format!("ttl: {ttl}"))?;
+        }
+        let len = usize::from(self.u16(pos + 8)?);
+        self.field(pos + 8, 2, format!("rdata length: {len}"))?;
+
+        let rdata = pos + 10;
+        let end = rdata + len;
+        if end > self.wire.len() {
+            return Err(Truncated(rdata));
+        }
+        // Names in the rdata get a field for every label, the rest of it one for all of it
+        let names = match qtype {
+            QueryType::NS | QueryType::CNAME | QueryType::PTR => Some((0, 1)),
+            QueryType::MX => Some((2, 1)),
+            QueryType::SRV => Some((6, 1)),
+            QueryType::SOA => Some((0, 2)),
+            _ => None,
+        };
+        match names {
+            Some((offset, count)) if len > offset => {
+                if qtype == QueryType::MX {
+                    let preference = self.u16(rdata)?;
+                    self.field(rdata, 2, format!("preference: {preference}"))?;
+                } else if qtype == QueryType::SRV {
+                    let (priority, weight, port) =
+                        (self.u16(rdata)?, self.u16(rdata + 2)?, self.u16(rdata + 4)?);
+                    let note = format!("priority: {priority}, weight: {weight}, port: {port}");
+                    self.field(rdata, 6, note)?;
+                }
+                let mut name_pos = rdata + offset;
+                for _ in 0..count {
+                    name_pos = self.name(name_pos)?;
+                }
+                if name_pos < end {
+                    let note = if qtype == QueryType::SOA {
+                        "serial, refresh, retry, expire and minimum"
+                    } else {
+                        "rest of rdata"
+                    };
+                    self.field(name_pos, end - name_pos, String::from(note))?;
+                }
+            }
+            _ if len > 0 => self.field(rdata, len, format!("rdata: {}", self.rdata(start)))?,
+            _ => {}
+        }
+
+        Ok(end)
+    }
+
+    /// A field for every label of a name, ending at the root or a pointer, returning the position
+    /// after it
+    fn name(&mut self, mut pos: usize) -> Result<usize, Truncated> {
+        loop {
+            let len = *self.wire.get(pos).ok_or(Truncated(pos))?;
+            match len {
+                0 => {
+                    self.field(pos, 1, String::from("root"))?;
+                    return Ok(pos + 1);
+                }
+                len if len & 0xc0 == 0xc0 => {
+                    let target = usize::from(self.u16(pos)? & 0x3fff);
+                    let note = format!("pointer to {target:#06x}: {}", self.read_name(target));
+                    self.field(pos, 2, note)?;
+                    return Ok(pos + 2);
+                }
+                len => {
+                    let len = usize::from(len);
+                    let label = self
+                        .wire
+                        .get(pos + 1..pos + 1 + len)
+                        .ok_or(Truncated(pos))?;
+                    let note = format!("label: {}", String::from_utf8_lossy(label));
+                    self.field(pos, 1 + len, note)?;
+                    pos += 1 + len;
+                }
+            }
+        }
+    }
+
+    /// The name at `pos` as the parser reads it, or why it can't
+    fn read_name(&self, pos: usize) -> String {
+        let mut buf = BytePacketBuffer {
+            buf: self.wire.to_vec(),
+            pos,
+        };
+        match buf.read_name() {
+            Ok(name) if name.is_root() => String::from("."),
+            Ok(name) => name.to_string(),
+            Err(e) => format!("invalid ({e})"),
+        }
+    }
+
+    /// The record at `pos` in presentation format, as the parser reads it
+    fn read_record(&self, pos: usize) -> String {
+        let mut buf = BytePacketBuffer {
+            buf: self.wire.to_vec(),
+            pos,
+        };
+        match DnsRecord::read(&mut buf) {
+            Ok(DnsRecord {
+                rdata: RData::OPT { options },
+                ..
+            }) => format!("OPT with {} options", options.len()),
+            Ok(rec) => rec.to_string(),
+            Err(e) => format!("invalid ({e})"),
+        }
+    }
+
+    /// Only the rdata of the record at `pos`
+    fn rdata(&self, pos: usize) -> String {
+        let mut buf = BytePacketBuffer {
+            buf: self.wire.to_vec(),
+            pos,
+        };
+        match DnsRecord::read(&mut buf) {
+            Ok(DnsRecord {
+                rdata: RData::OPT { options },
+                ..
+            }) => format!("{} options", options.len()),
+            Ok(rec) => rec.display_rdata().to_string(),
+            Err(e) => format!("invalid ({e})"),
+        }
+    }
+
+    fn heading(&mut self, start: usize, note: String) {
+        self.fields.push(Field {
+            start,
+            len: 0,
+            note,
+        });
+    }
+
+    fn field(&mut self, start: usize, len: usize, note: String) -> Result<(), Truncated> {
+        if start + len > self.wire.len() {
+            return Err(Truncated(start));
+        }
+        self.fields.push(Field { start, len, note });
+        Ok(())
+    }
+
+    fn u16(&self, pos: usize) -> Result<u16, Truncated> {
+        let bytes = self.wire.get(pos..pos + 2).ok_or(Truncated(pos))?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, pos: usize) -> Result<u32, Truncated> {
+        let bytes = self.wire.get(pos..pos + 4).ok_or(Truncated(pos))?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
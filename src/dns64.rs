@@ -0,0 +1,130 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::error::{DnsError, Result};
+use crate::header::ResultCode;
+use crate::network::Network;
+use crate::packet::DnsPacket;
+use crate::record::{DnsRecord, RData};
+
+/// Prefix lengths RFC 6052 defines a way to embed IPv4 addresses in
+const PREFIX_LENGTHS: [u8; 6] = [32, 40, 48, 56, 64, 96];
+
+/// Synthesis of AAAA records from A records for names without any, so clients on IPv6-only
+/// networks reach IPv4-only hosts through a NAT64 gateway, following RFC 6147
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "net", derive(serde::Deserialize))]
+#[cfg_attr(
+    feature = "net",
+    serde(default, deny_unknown_fields, rename_all = "kebab-case")
+)]
+pub struct Dns64 {
+    /// Where the NAT64 gateway maps the IPv4 internet into
+    pub prefix: Network,
+    /// AAAA records with these addresses are treated as missing, like IPv4-mapped addresses that
+    /// IPv6-only clients can't reach
+    pub exclude: Vec<Network>,
+}
+
+/// The well-known prefix of RFC 6052, excluding IPv4-mapped addresses
+impl Default for Dns64 {
+    fn default() -> Self {
+        Self {
+            prefix: Network::new(
+                IpAddr::V6(Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0)),
+                96,
+            ),
+            exclude: vec![Network::new(
+                IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0)),
+                96,
+            )],
+        }
+    }
+}
+
+impl Dns64 {
+    /// Check that the prefix is an IPv6 network of a length addresses can be embedded in
+    pub fn validate(&self) -> Result<()> {
+        if self.prefix.addr().is_ipv6() && PREFIX_LENGTHS.contains(&self.prefix.prefix()) {
+            Ok(())
+        } else {
+            Err(DnsError::Config(format!(
+                "DNS64 prefix {} isn't IPv6 with a length of 32, 40, 48, 56, 64 or 96",
+                self.prefix
+            )))
+        }
+    }
+
+    /// Whether the answer to an AAAA query is empty enough to synthesize one. Names that don't
+    /// exist don't get addresses.
+    pub fn needs_synthesis(&self, response: &DnsPacket) -> bool {
+        response.header.rescode != ResultCode::NXDOMAIN
+            && !response.answers.iter().any(|rec| match rec.rdata {
+                RData::AAAA { addr } => !self.is_excluded(addr),
+                _ => false,
+            })
+    }
+
+    /// The answer to an AAAA query synthesized from the answer to the A query for the same name.
+    /// Without any A records, the AAAA answer is returned as it was.
+    ///
+    /// The records live no longer than the negative AAAA answer may be cached, so real AAAA
+    /// records that show up are found as soon as they would have been.
+    pub fn synthesize(&self, aaaa: DnsPacket, mut a: DnsPacket) -> DnsPacket {
+        if !a
+            .answers
+            .iter()
+            .any(|rec| matches!(rec.rdata, RData::A { .. }))
+        {
+            return aaaa;
+        }
+
+        let max_ttl = aaaa
+            .authorities
+            .iter()
+            .find_map(|rec| match rec.rdata {
+                RData::SOA { minimum, .. } => Some(minimum.min(rec.ttl)),
+                _ => None,
+            })
+            .unwrap_or(u32::MAX);
+
+        a.answers = a
+            .answers
+            .into_iter()
+            .map(|rec| match rec.rdata {
+                RData::A { addr } => DnsRecord::new(
+                    rec.name,
+                    rec.ttl.min(max_ttl),
+                    RData::AAAA {
+                        addr: self.embed(addr),
+                    },
+                ),
+                _ => rec,
+            })
+            .collect();
+        a.header.rescode = ResultCode::NOERROR;
+
+        a
+    }
+
+    fn is_excluded(&self, addr: Ipv6Addr) -> bool {
+        self.exclude
+            .iter()
+            .any(|network| network.contains(IpAddr::V6(addr)))
+    }
+
+    /// Put `addr` into the prefix as described in RFC 6052 section 2.2, skipping bits 64 to 71,
+    /// which are always zero
+    fn embed(&self, addr: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = match self.prefix.addr() {
+            IpAddr::V6(prefix) => prefix.octets(),
+            // Not a valid prefix, see `validate`
+            IpAddr::V4(prefix) => prefix.to_ipv6_mapped().octets(),
+        };
+        let positions = (usize::from(self.prefix.prefix()) / 8..16).filter(|&i| i != 8);
+        for (i, octet) in positions.zip(addr.octets()) {
+            octets[i] = octet;
+        }
+
+        Ipv6Addr::from(octets)
+    }
+}
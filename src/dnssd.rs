@@ -0,0 +1,174 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::buffer::BytePacketBuffer;
+use crate::error::Result;
+use crate::mdns::{MDNS_IPV4, MDNS_MAX_LEN, MDNS_PORT};
+use crate::name::{unescape, DnsName};
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::{DnsRecord, RData};
+use crate::resolver::{lookup, RetryPolicy};
+
+/// A service found by browsing, with where it runs and its metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInstance {
+    /// Full name of the instance, like `Office Printer._ipp._tcp.local`
+    pub name: DnsName,
+    pub host: DnsName,
+    pub port: u16,
+    /// The strings of the TXT record, usually `key=value` pairs with values that may be binary,
+    /// RFC 6763 section 6
+    pub txt: Vec<Vec<u8>>,
+}
+
+impl ServiceInstance {
+    /// The name of the instance without the service type, as it is shown to users: UTF-8 with
+    /// spaces and dots as they are, rather than escaped like in the name
+    pub fn label(&self) -> String {
+        let label = self.name.labels().next().unwrap_or_default();
+        String::from_utf8_lossy(&unescape(label)).into_owned()
+    }
+}
+
+/// Where service discovery queries go
+#[derive(Debug, Clone, Copy)]
+pub enum Transport {
+    /// One-shot queries to the mDNS group, collecting the responses of every responder that
+    /// answers within the duration, RFC 6762 section 5.1
+    Multicast(Duration),
+    /// Queries to a unicast DNS server, for services published in a regular zone
+    Unicast(SocketAddr, RetryPolicy),
+}
+
+impl Transport {
+    /// The answers and additional records of every response to a question
+    fn query(&self, qname: &DnsName, qtype: QueryType) -> Result<Vec<DnsRecord>> {
+        match *self {
+            Self::Multicast(wait) => multicast_query(qname, qtype, wait),
+            Self::Unicast(server, policy) => {
+                let response = lookup(qname, qtype, server, policy)?;
+                Ok(response
+                    .answers
+                    .into_iter()
+                    .chain(response.resources)
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Browse for the instances of a service type like `_http._tcp.local`, with a PTR query for the
+/// type and SRV and TXT queries for any instance the responses didn't already describe
+pub fn browse(service: &DnsName, transport: Transport) -> Result<Vec<ServiceInstance>> {
+    let records = transport.query(service, QueryType::PTR)?;
+    let mut names: Vec<&DnsName> = records
+        .iter()
+        .filter(|rec| rec.domain() == service)
+        .filter_map(|rec| match &rec.rdata {
+            RData::PTR { host } => Some(host),
+            _ => None,
+        })
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+
+    let mut instances = Vec::new();
+    for name in names {
+        if let Some(instance) = resolve_with(name, transport, &records)? {
+            instances.push(instance);
+        }
+    }
+
+    Ok(instances)
+}
+
+/// Look up where a single instance runs, `None` if it has no SRV record
+pub fn resolve(instance: &DnsName, transport: Transport) -> Result<Option<ServiceInstance>> {
+    resolve_with(instance, transport, &[])
+}
+
+/// Resolve an instance from `known` records, only querying for what they lack
+fn resolve_with(
+    name: &DnsName,
+    transport: Transport,
+    known: &[DnsRecord],
+) -> Result<Option<ServiceInstance>> {
+    let srv = |records: &[DnsRecord]| {
+        records.iter().find_map(|rec| match &rec.rdata {
+            RData::SRV { host, port, .. } if rec.name == *name => Some((host.clone(), *port)),
+            _ => None,
+        })
+    };
+    let txt = |records: &[DnsRecord]| {
+        records.iter().find_map(|rec| match &rec.rdata {
+            RData::TXT { data } if rec.name == *name => Some(data.clone()),
+            _ => None,
+        })
+    };
+
+    let srv = match srv(known) {
+        Some(srv) => Some(srv),
+        None => srv(&transport.query(name, QueryType::SRV)?),
+    };
+    let Some((host, port)) = srv else {
+        return Ok(None);
+    };
+    let txt = match txt(known) {
+        Some(txt) => txt,
+        None => txt(&transport.query(name, QueryType::TXT)?).unwrap_or_default(),
+    };
+
+    Ok(Some(ServiceInstance {
+        name: name.clone(),
+        host,
+        port,
+        txt,
+    }))
+}
+
+/// Send a query to the mDNS group from an ephemeral port and collect the records of the responses
+/// arriving within `wait`
+fn multicast_query(qname: &DnsName, qtype: QueryType, wait: Duration) -> Result<Vec<DnsRecord>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_ttl_v4(255)?;
+
+    let mut query = DnsPacket::query(qname, qtype).id(rand::random()).build()?;
+    let mut req_buf = BytePacketBuffer::new();
+    query.write(&mut req_buf)?;
+    socket.send_to(&req_buf.buf[..req_buf.pos()], (MDNS_IPV4, MDNS_PORT))?;
+
+    let deadline = Instant::now() + wait;
+    let mut records = Vec::new();
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(left))?;
+
+        let mut res_buf = BytePacketBuffer::with_len(MDNS_MAX_LEN);
+        match socket.recv_from(&mut res_buf.buf) {
+            Ok((len, _)) => res_buf.buf.truncate(len),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        }
+        // Responders answering other queries, or sending something that can't be read
+        let Ok(response) = DnsPacket::from_buffer(&mut res_buf) else {
+            continue;
+        };
+        if response.header.response && response.header.id == query.header.id {
+            records.extend(response.answers.into_iter().chain(response.resources));
+        }
+    }
+
+    Ok(records)
+}
@@ -0,0 +1,441 @@
+use std::fmt;
+#[cfg(feature = "net")]
+use std::fs;
+#[cfg(feature = "net")]
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::buffer::{BytePacketBuffer, TCP_MAX_LEN};
+use crate::error::{DnsError, Result};
+use crate::journal::serial_gt;
+use crate::name::DnsName;
+use crate::question::QueryType;
+use crate::record::{DnsRecord, RData};
+
+/// Ed25519 from RFC 8080, the only algorithm zones are signed with
+pub const ALGORITHM_ED25519: u8 = 15;
+
+/// DNSKEY flags of a zone signing key
+pub const FLAGS_ZSK: u16 = 256;
+/// DNSKEY flags of a key signing key, a zone key with the secure entry point bit set
+pub const FLAGS_KSK: u16 = 257;
+
+/// How long new signatures are valid, in seconds
+const SIGNATURE_VALIDITY: u32 = 30 * 24 * 60 * 60;
+/// Signatures expiring sooner than this are replaced when the zone is signed again
+const SIGNATURE_REFRESH: u32 = 7 * 24 * 60 * 60;
+/// How far new signatures are backdated, for validators with slow clocks
+const INCEPTION_OFFSET: u32 = 60 * 60;
+
+/// A private key the server signs a zone with
+#[derive(Clone)]
+pub struct ZoneKey {
+    flags: u16,
+    key: SigningKey,
+}
+
+impl ZoneKey {
+    /// A key from its 32 byte Ed25519 seed, with the given DNSKEY `flags`
+    pub fn new(flags: u16, seed: &[u8; 32]) -> Self {
+        Self {
+            flags,
+            key: SigningKey::from_bytes(seed),
+        }
+    }
+
+    /// Read a private key in the format written by `dnssec-keygen -a ED25519`
+    #[cfg(feature = "net")]
+    pub fn load(path: impl AsRef<Path>, flags: u16) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+
+        Self::parse(&text, flags).map_err(|e| match e {
+            DnsError::Dnssec(message) => DnsError::Dnssec(format!("{}: {message}", path.display())),
+            e => e,
+        })
+    }
+
+    /// Parse the text of a private key file written by `dnssec-keygen -a ED25519`
+    pub fn parse(text: &str, flags: u16) -> Result<Self> {
+        let err = |message: &str| DnsError::Dnssec(message.to_string());
+
+        let mut algorithm = None;
+        let mut seed = None;
+        for line in text.lines() {
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            match field.trim() {
+                "Algorithm" => algorithm = value.split_whitespace().next(),
+                "PrivateKey" => seed = Some(value.trim()),
+                _ => {}
+            }
+        }
+
+        if algorithm != Some(&ALGORITHM_ED25519.to_string()) {
+            return Err(err("Only Ed25519 keys (algorithm 15) are supported"));
+        }
+        let seed = seed.ok_or_else(|| err("Missing PrivateKey"))?;
+        let seed = BASE64_STANDARD
+            .decode(seed)
+            .ok()
+            .and_then(|seed| <[u8; 32]>::try_from(seed).ok())
+            .ok_or_else(|| err("Invalid PrivateKey"))?;
+
+        Ok(Self::new(flags, &seed))
+    }
+
+    pub const fn flags(&self) -> u16 {
+        self.flags
+    }
+
+    /// Whether the key signs the DNSKEY RRset, rather than the rest of the zone
+    pub const fn is_ksk(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    /// The DNSKEY record publishing the key at `origin`
+    pub fn dnskey(&self, origin: &DnsName, ttl: u32) -> DnsRecord {
+        DnsRecord::new(
+            origin.clone(),
+            ttl,
+            RData::DNSKEY {
+                flags: self.flags,
+                protocol: 3,
+                algorithm: ALGORITHM_ED25519,
+                public_key: self.key.verifying_key().to_bytes().to_vec(),
+            },
+        )
+    }
+
+    /// The tag that identifies the key in signatures
+    pub fn key_tag(&self) -> u16 {
+        key_tag(&self.dnskey(&DnsName::root(), 0))
+    }
+
+    /// The DS record to publish in the parent zone for the key at `origin`, in presentation format
+    /// with a SHA-256 digest
+    pub fn ds(&self, origin: &DnsName) -> String {
+        let mut digest = Sha256::new();
+        digest.update(origin.canonical_wire());
+        digest.update(self.dnskey(origin, 0).rdata.dnssec_rdata());
+
+        let hex: String = digest
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect();
+        format!(
+            "{origin}.\tIN\tDS\t{} {ALGORITHM_ED25519} 2 {hex}",
+            self.key_tag()
+        )
+    }
+
+    fn sign(&self, rrset: &[DnsRecord], signer: &DnsName, now: u32) -> Option<DnsRecord> {
+        let first = rrset.first()?;
+        let mut rrsig = DnsRecord::new(
+            first.domain().clone(),
+            first.ttl(),
+            RData::RRSIG {
+                type_covered: first.qtype(),
+                algorithm: ALGORITHM_ED25519,
+                labels: rrsig_labels(first.domain()),
+                original_ttl: first.ttl(),
+                expiration: now.wrapping_add(SIGNATURE_VALIDITY),
+                inception: now.wrapping_sub(INCEPTION_OFFSET),
+                key_tag: self.key_tag(),
+                signer: signer.clone(),
+                signature: Vec::new(),
+            },
+        );
+
+        let data = signed_data(&rrsig, rrset);
+        if let RData::RRSIG { signature, .. } = &mut rrsig.rdata {
+            *signature = self.key.sign(&data).to_bytes().to_vec();
+        }
+
+        Some(rrsig)
+    }
+}
+
+/// The secret key is left out
+impl fmt::Debug for ZoneKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZoneKey")
+            .field("flags", &self.flags)
+            .field("key_tag", &self.key_tag())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The key tag of a DNSKEY record, from RFC 4034 appendix B
+pub fn key_tag(dnskey: &DnsRecord) -> u16 {
+    let mut acc: u32 = 0;
+    for (i, &b) in dnskey.rdata.dnssec_rdata().iter().enumerate() {
+        acc += if i % 2 == 0 {
+            u32::from(b) << 8
+        } else {
+            u32::from(b)
+        };
+    }
+    acc += (acc >> 16) & 0xFFFF;
+
+    (acc & 0xFFFF) as u16
+}
+
+/// Whether `rrsig` is a valid signature by `dnskey` over `rrset` at time `now`
+pub fn verify(dnskey: &DnsRecord, rrsig: &DnsRecord, rrset: &[DnsRecord], now: u32) -> bool {
+    let (
+        RData::DNSKEY {
+            algorithm: ALGORITHM_ED25519,
+            public_key,
+            ..
+        },
+        RData::RRSIG {
+            algorithm: ALGORITHM_ED25519,
+            key_tag: tag,
+            inception,
+            expiration,
+            signature,
+            ..
+        },
+    ) = (&dnskey.rdata, &rrsig.rdata)
+    else {
+        return false;
+    };
+    if *tag != key_tag(dnskey) || serial_gt(*inception, now) || serial_gt(now, *expiration) {
+        return false;
+    }
+
+    let Some(key) = <[u8; 32]>::try_from(public_key.as_slice())
+        .ok()
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+    else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+
+    key.verify(&signed_data(rrsig, rrset), &signature).is_ok()
+}
+
+/// Sign the records of the zone at `origin` with `keys`
+///
+/// Any DNSKEY, RRSIG and NSEC records are replaced: the keys are published at the apex, the NSEC
+/// chain is rebuilt, and every authoritative RRset is signed. Signatures from `old` are kept when
+/// they still verify and aren't about to expire, so re-signing an unchanged zone changes nothing.
+/// Records are left as they are without keys, and unsigned without an SOA.
+pub fn sign_zone(
+    origin: &DnsName,
+    records: &mut Vec<DnsRecord>,
+    keys: &[ZoneKey],
+    old: &[DnsRecord],
+) {
+    if keys.is_empty() {
+        return;
+    }
+    records.retain(|rec| !is_dnssec(rec));
+
+    let Some((soa_ttl, minimum)) = records.iter().find_map(|rec| match rec.rdata {
+        RData::SOA { minimum, .. } if rec.name == *origin => Some((rec.ttl, minimum)),
+        _ => None,
+    }) else {
+        return;
+    };
+
+    records.extend(keys.iter().map(|key| key.dnskey(origin, soa_ttl)));
+
+    // Delegation points only have their NSEC signed, and anything below them isn't signed at all
+    let cuts: Vec<DnsName> = records
+        .iter()
+        .filter(|rec| rec.qtype() == QueryType::NS && rec.domain() != origin)
+        .map(|rec| rec.domain().clone())
+        .collect();
+    let below_cut = |name: &DnsName| {
+        cuts.iter()
+            .any(|cut| name != cut && name.is_subdomain_of(cut))
+    };
+    let at_cut = |name: &DnsName| cuts.contains(name);
+
+    let mut owners: Vec<DnsName> = records
+        .iter()
+        .map(|rec| rec.domain().clone())
+        .filter(|name| name.is_subdomain_of(origin) && !below_cut(name))
+        .collect();
+    owners.sort_by(DnsName::canonical_cmp);
+    owners.dedup();
+
+    let nsec_ttl = soa_ttl.min(minimum);
+    let chain: Vec<DnsRecord> = owners
+        .iter()
+        .enumerate()
+        .map(|(i, owner)| {
+            let mut types: Vec<QueryType> = records
+                .iter()
+                .filter(|rec| rec.domain() == owner)
+                .map(DnsRecord::qtype)
+                .collect();
+            types.extend([QueryType::NSEC, QueryType::RRSIG]);
+
+            DnsRecord::new(
+                owner.clone(),
+                nsec_ttl,
+                RData::NSEC {
+                    next: owners[(i + 1) % owners.len()].clone(),
+                    types,
+                },
+            )
+        })
+        .collect();
+    records.extend(chain);
+
+    let mut rrsets: Vec<(&DnsName, QueryType)> = Vec::new();
+    for rec in records.iter() {
+        let name = rec.domain();
+        let signed = name.is_subdomain_of(origin)
+            && !below_cut(name)
+            && (!at_cut(name) || rec.qtype() == QueryType::NSEC);
+        if signed && !rrsets.contains(&(name, rec.qtype())) {
+            rrsets.push((name, rec.qtype()));
+        }
+    }
+
+    let zsks: Vec<&ZoneKey> = keys.iter().filter(|key| !key.is_ksk()).collect();
+    let ksks: Vec<&ZoneKey> = keys.iter().filter(|key| key.is_ksk()).collect();
+    let now = now();
+
+    let mut signatures = Vec::new();
+    for (name, qtype) in rrsets {
+        let rrset: Vec<DnsRecord> = records
+            .iter()
+            .filter(|rec| rec.domain() == name && rec.qtype() == qtype)
+            .cloned()
+            .collect();
+
+        // A lone key signs everything, acting as a combined signing key
+        let signers = match qtype {
+            QueryType::DNSKEY if !ksks.is_empty() => &ksks,
+            _ if !zsks.is_empty() => &zsks,
+            _ => &ksks,
+        };
+        for key in signers {
+            let dnskey = key.dnskey(origin, soa_ttl);
+            let reused = old.iter().find(|rrsig| {
+                matches!(rrsig.rdata, RData::RRSIG { type_covered, original_ttl, expiration, .. }
+                    if type_covered == qtype
+                        && rrsig.domain() == name
+                        && original_ttl == rrset[0].ttl()
+                        && serial_gt(expiration, now.wrapping_add(SIGNATURE_REFRESH)))
+                    && verify(&dnskey, rrsig, &rrset, now)
+            });
+            match reused {
+                Some(rrsig) => signatures.push(rrsig.clone()),
+                None => signatures.extend(key.sign(&rrset, origin, now)),
+            }
+        }
+    }
+    records.extend(signatures);
+}
+
+/// Whether a record is one of those generated by [`sign_zone`]
+pub const fn is_dnssec(rec: &DnsRecord) -> bool {
+    matches!(
+        rec.rdata,
+        RData::DNSKEY { .. } | RData::RRSIG { .. } | RData::NSEC { .. }
+    )
+}
+
+/// The labels field of a signature: the labels of the owner, without the `*` of a wildcard
+fn rrsig_labels(owner: &DnsName) -> u8 {
+    let count = owner.label_count();
+    let wildcard = owner.labels().next() == Some("*");
+
+    (count - usize::from(wildcard)) as u8
+}
+
+/// The data a signature covers, from RFC 4034 section 3.1.8.1: the rdata of the RRSIG without the
+/// signature, then every record of the RRset in canonical form and order
+fn signed_data(rrsig: &DnsRecord, rrset: &[DnsRecord]) -> Vec<u8> {
+    let RData::RRSIG {
+        labels,
+        original_ttl,
+        signature,
+        ..
+    } = &rrsig.rdata
+    else {
+        return Vec::new();
+    };
+    let mut data = rrsig.rdata.dnssec_rdata();
+    data.truncate(data.len() - signature.len());
+
+    let mut buffer = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    let mut wires: Vec<(usize, Vec<u8>)> = rrset
+        .iter()
+        .filter_map(|rec| {
+            let mut rec = canonical(rec);
+            rec.set_ttl(*original_ttl);
+            rec.set_domain(signed_owner(rec.domain(), *labels)?);
+            // The owner is followed by the type, class, TTL and rdata length
+            let rdata_start = rec.domain().canonical_wire().len() + 10;
+
+            buffer.pos = 0;
+            rec.write(&mut buffer).ok()?;
+            Some((rdata_start, buffer.buf[..buffer.pos].to_vec()))
+        })
+        .collect();
+    wires.sort_by(|(a_start, a), (b_start, b)| a[*a_start..].cmp(&b[*b_start..]));
+    wires.dedup();
+
+    for (_, wire) in wires {
+        data.extend(wire);
+    }
+
+    data
+}
+
+/// The owner a signature was made over, which is the wildcard for records synthesized from one:
+/// those have more labels than the signature counts, RFC 4035 section 5.3.2
+fn signed_owner(owner: &DnsName, labels: u8) -> Option<DnsName> {
+    let mut name = owner.clone();
+    if name.label_count() <= labels.into() {
+        return Some(name);
+    }
+    while name.label_count() > labels.into() {
+        name = name.parent()?;
+    }
+
+    name.child("*").ok()
+}
+
+/// A record with its owner and the names in its rdata lowercased, from RFC 4034 section 6.2
+fn canonical(rec: &DnsRecord) -> DnsRecord {
+    let mut rec = rec.clone();
+    match &mut rec.rdata {
+        RData::NS { host }
+        | RData::CNAME { host }
+        | RData::PTR { host }
+        | RData::MX { host, .. }
+        | RData::SRV { host, .. } => *host = host.to_lowercase(),
+        RData::SOA { m_name, r_name, .. } => {
+            *m_name = m_name.to_lowercase();
+            *r_name = r_name.to_lowercase();
+        }
+        _ => {}
+    }
+    let owner = rec.domain().to_lowercase();
+    rec.set_domain(owner);
+
+    rec
+}
+
+/// Seconds since the epoch, in the wrapping 32 bit form signatures use
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
@@ -0,0 +1,311 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::{info, warn};
+
+use crate::config::DnstapConfig;
+use crate::error::{DnsError, Result};
+use crate::resolver::Exchange;
+
+/// What the payload of the frames is, announced when the stream starts
+const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+/// Frame Streams control frame types
+const CONTROL_ACCEPT: u32 = 1;
+const CONTROL_START: u32 = 2;
+const CONTROL_READY: u32 = 4;
+
+/// The only control frame field, carrying [`CONTENT_TYPE`]
+const FIELD_CONTENT_TYPE: u32 = 1;
+
+/// Messages waiting to be written before new ones are dropped, so a slow collector never holds up
+/// answers
+const QUEUE_LEN: usize = 10_000;
+
+/// How long to wait before connecting to the socket again after losing it
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The kinds of dnstap messages the server sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    ResolverQuery = 3,
+    ResolverResponse = 4,
+    ClientQuery = 5,
+    ClientResponse = 6,
+}
+
+/// Where the frames go
+#[derive(Debug, Clone)]
+enum Output {
+    /// Written to a file, which is replaced when the server starts
+    File(PathBuf),
+    /// Sent to a collector listening on a unix socket, like `fstrm_capture` or `dnstap-receiver`
+    Socket(PathBuf),
+}
+
+/// Logs the messages exchanged with clients and upstreams in the dnstap format, as protobuf in a
+/// Frame Streams stream. Messages are queued for a writer thread, and dropped when it can't keep
+/// up. Clones share the writer.
+#[derive(Debug, Clone)]
+pub struct Dnstap {
+    tx: SyncSender<Vec<u8>>,
+    identity: Option<Arc<[u8]>>,
+}
+
+impl Dnstap {
+    /// Start writing to the configured output. A file that can't be created is an error, while a
+    /// socket nobody listens on yet is connected to again later.
+    pub fn open(config: &DnstapConfig) -> Result<Self> {
+        let output = match (&config.socket, &config.file) {
+            (Some(socket), None) => Output::Socket(socket.clone()),
+            (None, Some(file)) => Output::File(file.clone()),
+            _ => {
+                return Err(DnsError::Config(String::from(
+                    "dnstap needs either a socket or a file",
+                )))
+            }
+        };
+
+        let writer = match connect(&output) {
+            Ok(writer) => Some(writer),
+            Err(e) if matches!(output, Output::Socket(_)) => {
+                warn!("Failed to connect to dnstap socket: {e}");
+                None
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        thread::spawn(move || write_frames(&output, writer, &rx));
+
+        Ok(Self {
+            tx,
+            identity: config
+                .identity
+                .as_deref()
+                .map(|identity| identity.as_bytes().into()),
+        })
+    }
+
+    /// Log a query from `client` to the server listening on `local`
+    pub fn client_query(&self, client: SocketAddr, local: SocketAddr, tcp: bool, wire: &[u8]) {
+        self.log(MessageType::ClientQuery, client, local, tcp, wire);
+    }
+
+    /// Log the response sent from `local` to `client`
+    pub fn client_response(&self, client: SocketAddr, local: SocketAddr, tcp: bool, wire: &[u8]) {
+        self.log(MessageType::ClientResponse, client, local, tcp, wire);
+    }
+
+    /// Log a query sent to an upstream or its response
+    pub fn upstream(&self, exchange: Exchange<'_>) {
+        let kind = if exchange.response {
+            MessageType::ResolverResponse
+        } else {
+            MessageType::ResolverQuery
+        };
+        self.log(
+            kind,
+            exchange.local,
+            exchange.server,
+            exchange.tcp,
+            exchange.wire,
+        );
+    }
+
+    fn log(
+        &self,
+        kind: MessageType,
+        query_addr: SocketAddr,
+        response_addr: SocketAddr,
+        tcp: bool,
+        wire: &[u8],
+    ) {
+        let frame = encode(
+            self.identity.as_deref(),
+            kind,
+            query_addr,
+            response_addr,
+            tcp,
+            wire,
+        );
+        match self.tx.try_send(frame) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => warn!("dnstap queue is full, dropping message"),
+        }
+    }
+}
+
+/// Open the output and start a unidirectional stream on a file, or a bidirectional one with the
+/// handshake on a socket
+fn connect(output: &Output) -> io::Result<Box<dyn Write + Send>> {
+    match output {
+        Output::File(path) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            file.write_all(&control_frame(CONTROL_START))?;
+            Ok(Box::new(file))
+        }
+        #[cfg(unix)]
+        Output::Socket(path) => {
+            use std::io::Read;
+            use std::os::unix::net::UnixStream;
+
+            let mut stream = UnixStream::connect(path)?;
+            stream.write_all(&control_frame(CONTROL_READY))?;
+            // An escape, the length of the control frame, then the frame itself
+            let mut header = [0; 8];
+            stream.read_exact(&mut header)?;
+            let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+            let mut accept = vec![0; len as usize];
+            stream.read_exact(&mut accept)?;
+            if header[..4] != [0; 4] || accept.get(..4) != Some(&CONTROL_ACCEPT.to_be_bytes()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "collector didn't accept the stream",
+                ));
+            }
+            stream.write_all(&control_frame(CONTROL_START))?;
+            info!("Connected to dnstap socket {}", path.display());
+            Ok(Box::new(BufWriter::new(stream)))
+        }
+        #[cfg(not(unix))]
+        Output::Socket(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "dnstap sockets need unix domain sockets",
+        )),
+    }
+}
+
+/// Write every queued message as a data frame, flushing whenever the queue runs empty. A socket
+/// that fails is connected to again, dropping the messages in between.
+fn write_frames(
+    output: &Output,
+    mut writer: Option<Box<dyn Write + Send>>,
+    rx: &Receiver<Vec<u8>>,
+) {
+    let mut last_attempt = Instant::now();
+    while let Ok(first) = rx.recv() {
+        if writer.is_none()
+            && matches!(output, Output::Socket(_))
+            && last_attempt.elapsed() >= RECONNECT_INTERVAL
+        {
+            last_attempt = Instant::now();
+            writer = connect(output)
+                .inspect_err(|e| warn!("Failed to connect to dnstap socket: {e}"))
+                .ok();
+        }
+        let Some(out) = &mut writer else {
+            continue;
+        };
+
+        let written = std::iter::once(first)
+            .chain(rx.try_iter())
+            .try_for_each(|frame| {
+                out.write_all(&(frame.len() as u32).to_be_bytes())?;
+                out.write_all(&frame)
+            })
+            .and_then(|()| out.flush());
+        if let Err(e) = written {
+            warn!("Failed to write dnstap: {e}");
+            writer = None;
+            last_attempt = Instant::now();
+        }
+    }
+}
+
+/// A Frame Streams control frame announcing [`CONTENT_TYPE`]
+fn control_frame(kind: u32) -> Vec<u8> {
+    let mut body = kind.to_be_bytes().to_vec();
+    body.extend(FIELD_CONTENT_TYPE.to_be_bytes());
+    body.extend((CONTENT_TYPE.len() as u32).to_be_bytes());
+    body.extend(CONTENT_TYPE);
+
+    // Data frames start with their length, which is never 0, so a 0 marks a control frame
+    let mut frame = vec![0; 4];
+    frame.extend((body.len() as u32).to_be_bytes());
+    frame.extend(body);
+    frame
+}
+
+/// Encode a `Dnstap` protobuf message wrapping a `Message` of `kind`, timestamped now
+fn encode(
+    identity: Option<&[u8]>,
+    kind: MessageType,
+    query_addr: SocketAddr,
+    response_addr: SocketAddr,
+    tcp: bool,
+    wire: &[u8],
+) -> Vec<u8> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let is_query = matches!(kind, MessageType::ClientQuery | MessageType::ResolverQuery);
+
+    let mut message = Vec::with_capacity(wire.len() + 64);
+    varint_field(&mut message, 1, kind as u64);
+    // INET or INET6, and UDP or TCP
+    let family = if query_addr.ip().to_canonical().is_ipv4() {
+        1
+    } else {
+        2
+    };
+    varint_field(&mut message, 2, family);
+    varint_field(&mut message, 3, if tcp { 2 } else { 1 });
+    bytes_field(&mut message, 4, &ip_octets(query_addr));
+    bytes_field(&mut message, 5, &ip_octets(response_addr));
+    varint_field(&mut message, 6, query_addr.port().into());
+    varint_field(&mut message, 7, response_addr.port().into());
+    // Queries carry the time they were sent or received as query_time and the message as
+    // query_message, responses as response_time and response_message
+    let (sec, nsec, msg) = if is_query { (8, 9, 10) } else { (12, 13, 14) };
+    varint_field(&mut message, sec, now.as_secs());
+    fixed32_field(&mut message, nsec, now.subsec_nanos());
+    bytes_field(&mut message, msg, wire);
+
+    let mut dnstap = Vec::with_capacity(message.len() + 32);
+    if let Some(identity) = identity {
+        bytes_field(&mut dnstap, 1, identity);
+    }
+    bytes_field(&mut dnstap, 2, env!("CARGO_PKG_VERSION").as_bytes());
+    bytes_field(&mut dnstap, 14, &message);
+    // Type MESSAGE
+    varint_field(&mut dnstap, 15, 1);
+    dnstap
+}
+
+/// The address of a socket as 4 or 16 bytes, IPv4 clients of dual-stack sockets as IPv4
+fn ip_octets(addr: SocketAddr) -> Vec<u8> {
+    match addr.ip().to_canonical() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    varint(buf, field << 3);
+    varint(buf, value);
+}
+
+fn fixed32_field(buf: &mut Vec<u8>, field: u64, value: u32) {
+    varint(buf, field << 3 | 5);
+    buf.extend(value.to_le_bytes());
+}
+
+fn bytes_field(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    varint(buf, field << 3 | 2);
+    varint(buf, value.len() as u64);
+    buf.extend(value);
+}
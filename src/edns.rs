@@ -0,0 +1,240 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::time::Duration;
+
+use crate::buffer::UDP_MAX_LEN;
+use crate::name::DnsName;
+use crate::record::{DnsRecord, RData};
+
+/// Flag in the TTL field of an OPT record asking for DNSSEC records, from RFC 3225
+pub const FLAG_DNSSEC_OK: u32 = 0x8000;
+
+/// UDP payload size advertised in responses, the size recommended by DNS Flag Day 2020 to avoid
+/// IP fragmentation
+pub const UDP_PAYLOAD_LEN: u16 = 1232;
+
+/// Option code of the name server identifier, RFC 5001
+pub const OPTION_NSID: u16 = 3;
+
+/// Option code of EDNS Client Subnet
+pub const OPTION_CLIENT_SUBNET: u16 = 8;
+
+/// Option code of edns-tcp-keepalive, RFC 7828
+pub const OPTION_TCP_KEEPALIVE: u16 = 11;
+
+/// Option code of EDNS padding, RFC 7830
+pub const OPTION_PADDING: u16 = 12;
+
+/// Option code of Extended DNS Errors, RFC 8914
+pub const OPTION_EXTENDED_ERROR: u16 = 15;
+
+/// Info codes of Extended DNS Errors used here, from RFC 8914 section 4
+pub const EDE_OTHER: u16 = 0;
+pub const EDE_DNSSEC_BOGUS: u16 = 6;
+pub const EDE_NO_REACHABLE_AUTHORITY: u16 = 22;
+pub const EDE_NETWORK_ERROR: u16 = 23;
+
+/// Block sizes messages are padded to on encrypted transports, as recommended by RFC 8467
+pub const QUERY_BLOCK_LEN: usize = 128;
+pub const RESPONSE_BLOCK_LEN: usize = 468;
+
+/// Address families of EDNS Client Subnet, as numbered by IANA
+const FAMILY_IPV4: u16 = 1;
+const FAMILY_IPV6: u16 = 2;
+
+/// An option carried in the rdata of an OPT record (RFC 6891)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+/// The OPT record for a message from this server, setting the DO bit when DNSSEC records were
+/// asked for
+pub const fn opt_record(dnssec_ok: bool, options: Vec<EdnsOption>) -> DnsRecord {
+    DnsRecord {
+        name: DnsName::root(),
+        class: UDP_PAYLOAD_LEN,
+        ttl: if dnssec_ok { FLAG_DNSSEC_OK } else { 0 },
+        rdata: RData::OPT { options },
+    }
+}
+
+/// The largest UDP response a client accepts, given the payload size from its OPT record if any.
+/// Sizes below the plain DNS limit are raised to it, sizes above ours are capped.
+pub fn max_udp_len(opt: Option<&DnsRecord>) -> usize {
+    opt.map_or(UDP_MAX_LEN, |opt| {
+        usize::from(opt.class).clamp(UDP_MAX_LEN, UDP_PAYLOAD_LEN.into())
+    })
+}
+
+/// The EDNS Client Subnet option from RFC 7871: the network a query comes from, so answers can be
+/// tailored to it without revealing the whole client address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientSubnet {
+    /// The network address, with every bit past `source_prefix` cleared
+    pub addr: IpAddr,
+    /// Leading bits of `addr` that identify the network, 0 when the client opts out
+    pub source_prefix: u8,
+    /// Leading bits the answer applies to, set by the server that answers
+    pub scope_prefix: u8,
+}
+
+impl ClientSubnet {
+    /// The network of `addr` made of its first `prefix` bits, capped at the length of the address
+    pub fn new(addr: IpAddr, prefix: u8) -> Self {
+        let (addr, source_prefix) = match addr {
+            IpAddr::V4(v4) => {
+                let prefix = prefix.min(32);
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                (IpAddr::V4((u32::from(v4) & mask).into()), prefix)
+            }
+            IpAddr::V6(v6) => {
+                let prefix = prefix.min(128);
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                (IpAddr::V6((u128::from(v6) & mask).into()), prefix)
+            }
+        };
+
+        Self {
+            addr,
+            source_prefix,
+            scope_prefix: 0,
+        }
+    }
+
+    /// Parse the option, or `None` if it is malformed: an unknown family, a prefix longer than the
+    /// address, or address bits set past the prefix
+    pub fn from_option(option: &EdnsOption) -> Option<Self> {
+        let [family_hi, family_lo, source_prefix, scope_prefix, addr @ ..] = option.data.as_slice()
+        else {
+            return None;
+        };
+        if addr.len() != usize::from(*source_prefix).div_ceil(8) {
+            return None;
+        }
+
+        let addr = match u16::from_be_bytes([*family_hi, *family_lo]) {
+            FAMILY_IPV4 => {
+                let mut octets = [0; 4];
+                octets.get_mut(..addr.len())?.copy_from_slice(addr);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            FAMILY_IPV6 => {
+                let mut octets = [0; 16];
+                octets.get_mut(..addr.len())?.copy_from_slice(addr);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return None,
+        };
+
+        let subnet = Self::new(addr, *source_prefix);
+        if subnet.addr != addr || subnet.source_prefix != *source_prefix {
+            return None;
+        }
+
+        Some(Self {
+            scope_prefix: *scope_prefix,
+            ..subnet
+        })
+    }
+
+    /// The option in wire format, with the address cut to the bytes the prefix covers
+    pub fn to_option(&self) -> EdnsOption {
+        let (family, octets) = match self.addr {
+            IpAddr::V4(v4) => (FAMILY_IPV4, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (FAMILY_IPV6, v6.octets().to_vec()),
+        };
+
+        let mut data = family.to_be_bytes().to_vec();
+        data.push(self.source_prefix);
+        data.push(self.scope_prefix);
+        data.extend_from_slice(&octets[..usize::from(self.source_prefix).div_ceil(8)]);
+
+        EdnsOption {
+            code: OPTION_CLIENT_SUBNET,
+            data,
+        }
+    }
+}
+
+/// The edns-tcp-keepalive option from RFC 7828: sent empty by clients over TCP to ask for the
+/// connection to be kept open, and with how long the server keeps it open while idle in responses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    /// In steps of 100 milliseconds on the wire, `None` in queries
+    pub timeout: Option<Duration>,
+}
+
+impl TcpKeepalive {
+    /// Parse the option, or `None` if it is malformed: anything but empty or a 16-bit timeout
+    pub fn from_option(option: &EdnsOption) -> Option<Self> {
+        let timeout = match option.data.as_slice() {
+            [] => None,
+            [hi, lo] => {
+                let steps = u16::from_be_bytes([*hi, *lo]);
+                Some(Duration::from_millis(u64::from(steps) * 100))
+            }
+            _ => return None,
+        };
+
+        Some(Self { timeout })
+    }
+
+    /// The option in wire format, with the timeout rounded down and capped at what fits
+    pub fn to_option(&self) -> EdnsOption {
+        let data = self.timeout.map_or_else(Vec::new, |timeout| {
+            let steps = u16::try_from(timeout.as_millis() / 100).unwrap_or(u16::MAX);
+            steps.to_be_bytes().to_vec()
+        });
+
+        EdnsOption {
+            code: OPTION_TCP_KEEPALIVE,
+            data,
+        }
+    }
+}
+
+/// An Extended DNS Error from RFC 8914, saying why a response is what it is, mostly why it is a
+/// SERVFAIL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedError {
+    pub info_code: u16,
+    /// Meant for people rather than programs, possibly empty
+    pub extra_text: String,
+}
+
+impl ExtendedError {
+    /// Parse the option, or `None` if it is too short for an info code. Text that isn't UTF-8 is
+    /// kept as far as it can be.
+    pub fn from_option(option: &EdnsOption) -> Option<Self> {
+        let [hi, lo, text @ ..] = option.data.as_slice() else {
+            return None;
+        };
+
+        Some(Self {
+            info_code: u16::from_be_bytes([*hi, *lo]),
+            extra_text: String::from_utf8_lossy(text).into_owned(),
+        })
+    }
+
+    /// The option in wire format
+    pub fn to_option(&self) -> EdnsOption {
+        let mut data = self.info_code.to_be_bytes().to_vec();
+        data.extend_from_slice(self.extra_text.as_bytes());
+
+        EdnsOption {
+            code: OPTION_EXTENDED_ERROR,
+            data,
+        }
+    }
+
+    /// Whether the error is about DNSSEC validation: an unsupported algorithm or digest, a bogus
+    /// or indeterminate answer, or signatures, keys or denials that are missing or out of date
+    pub const fn is_dnssec(&self) -> bool {
+        matches!(self.info_code, 1 | 2 | 5..=12)
+    }
+}
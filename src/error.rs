@@ -0,0 +1,206 @@
+use alloc::string::String;
+use core::fmt;
+use core::net::IpAddr;
+#[cfg(feature = "std")]
+use std::io;
+
+use thiserror::Error;
+
+use crate::edns::{
+    ExtendedError, EDE_DNSSEC_BOGUS, EDE_NETWORK_ERROR, EDE_NO_REACHABLE_AUTHORITY, EDE_OTHER,
+};
+
+pub type Result<T, E = DnsError> = core::result::Result<T, E>;
+
+/// Everything that can go wrong while parsing, writing or resolving
+///
+/// The variants are split so that a server can tell a malformed packet from a client (answer with
+/// FORMERR) apart from a failure talking to an upstream (answer with SERVFAIL), see
+/// [`DnsError::is_malformed`].
+#[derive(Debug, Error)]
+pub enum DnsError {
+    #[error("End of buffer")]
+    BufferOverrun,
+
+    #[error("Limit of {0} jumps exceeded")]
+    TooManyJumps(usize),
+
+    #[error("Record data doesn't take up its length of {0} bytes")]
+    RdataLength(u16),
+
+    #[error("Label exceeds 63 character limit: {0}")]
+    LabelTooLong(String),
+
+    #[error("Name exceeds 255 octet limit: {0}")]
+    NameTooLong(String),
+
+    #[error("Character string of {0} octets exceeds 255 octet limit")]
+    CharacterStringTooLong(usize),
+
+    #[error("Empty label in {0}")]
+    EmptyLabel(String),
+
+    #[error("Invalid internationalized domain name {0}")]
+    InvalidIdn(String),
+
+    #[error("Invalid hostname {0}, expected letters, digits and hyphens")]
+    InvalidHostname(String),
+
+    #[error("Invalid network {0}, expected an address with an optional prefix length")]
+    InvalidNetwork(String),
+
+    #[error("Invalid server address {0}")]
+    InvalidServer(String),
+
+    #[error("Unsupported query type: {0}")]
+    UnsupportedType(String),
+
+    #[error("Zone file line {line}: {message}")]
+    Zone { line: usize, message: String },
+
+    #[error("Invalid config: {0}")]
+    Config(String),
+
+    #[error("Zone transfer failed: {0}")]
+    Transfer(String),
+
+    #[error("TSIG verification failed: {0}")]
+    Tsig(String),
+
+    #[error("DNSSEC signing failed: {0}")]
+    Dnssec(String),
+
+    #[error("Invalid capture: {0}")]
+    Capture(String),
+
+    #[error("Download failed: {0}")]
+    Download(String),
+
+    #[error("SOCKS5 proxy {0}")]
+    Proxy(String),
+
+    #[error("DNS over TLS with {0}")]
+    Tls(String),
+
+    #[error("DNS over HTTPS with {0}")]
+    Https(String),
+
+    #[error("No valid response from {0}")]
+    InvalidResponse(IpAddr),
+
+    #[error("Timed out waiting for a response")]
+    Timeout,
+
+    #[error("Connection refused by {0}")]
+    Refused(IpAddr),
+
+    #[error("No answer for {0}")]
+    NoAnswer(String),
+
+    #[error("Gave up resolving {0}, too many referrals")]
+    TooManyReferrals(String),
+
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl DnsError {
+    /// Whether the error was caused by invalid input data, as opposed to a failure to communicate
+    /// or anything else, which isn't the fault of the message
+    pub const fn is_malformed(&self) -> bool {
+        matches!(
+            self,
+            Self::BufferOverrun
+                | Self::TooManyJumps(_)
+                | Self::RdataLength(_)
+                | Self::LabelTooLong(_)
+                | Self::NameTooLong(_)
+                | Self::EmptyLabel(_)
+                | Self::InvalidIdn(_)
+                | Self::InvalidHostname(_)
+                | Self::UnsupportedType(_)
+        )
+    }
+
+    /// Why a lookup that failed with this error is answered with SERVFAIL
+    pub const fn failure_reason(&self) -> FailureReason {
+        match self {
+            Self::Timeout => FailureReason::Timeout,
+            Self::Refused(_) => FailureReason::Refused,
+            Self::InvalidResponse(_) => FailureReason::InvalidResponse,
+            Self::Proxy(_) | Self::Tls(_) | Self::Https(_) => FailureReason::Network,
+            #[cfg(feature = "std")]
+            Self::Io(_) => FailureReason::Network,
+            _ => FailureReason::Other,
+        }
+    }
+}
+
+/// Why a lookup ended in SERVFAIL, as logged and told to clients with an Extended DNS Error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    /// None of the upstreams answered in time
+    Timeout,
+    /// An upstream refused the query, by closing the port it was sent to
+    Refused,
+    /// Only responses that had to be dropped came back, like spoofed or malformed ones
+    InvalidResponse,
+    /// The connection to the upstreams failed, or the proxy, TLS or HTTPS in between
+    Network,
+    /// The upstream found the answer failed DNSSEC validation
+    ValidationFailed,
+    /// The upstream answered with SERVFAIL itself, without saying it was about DNSSEC
+    Upstream,
+    /// The query came back from an upstream it was forwarded to, and would have gone around forever
+    Loop,
+    /// Anything else, like a plugged in resolver failing
+    Other,
+}
+
+impl FailureReason {
+    /// The Extended DNS Error telling clients about it
+    pub fn extended_error(self) -> ExtendedError {
+        let (info_code, text) = match self {
+            Self::Timeout => (EDE_NO_REACHABLE_AUTHORITY, "No upstream answered in time"),
+            Self::Refused => (EDE_NETWORK_ERROR, "Refused by the upstream"),
+            Self::InvalidResponse => (EDE_NETWORK_ERROR, "No valid response from the upstream"),
+            Self::Network => (EDE_NETWORK_ERROR, "Failed to reach the upstream"),
+            Self::ValidationFailed => (EDE_DNSSEC_BOGUS, "DNSSEC validation failed"),
+            Self::Upstream => (EDE_OTHER, "SERVFAIL from the upstream"),
+            Self::Loop => (EDE_OTHER, "Forwarding loop"),
+            Self::Other => (EDE_OTHER, ""),
+        };
+
+        ExtendedError {
+            info_code,
+            extra_text: text.into(),
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::Timeout => "timeout",
+            Self::Refused => "refused",
+            Self::InvalidResponse => "invalid-response",
+            Self::Network => "network",
+            Self::ValidationFailed => "validation-failed",
+            Self::Upstream => "upstream",
+            Self::Loop => "loop",
+            Self::Other => "other",
+        };
+        f.write_str(reason)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for DnsError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Io(e),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::buffer::BytePacketBuffer;
+use crate::error::Result;
+
+/// Opcode of a standard query
+pub const OPCODE_QUERY: u8 = 0;
+/// Opcode of a dynamic update from RFC 2136
+pub const OPCODE_UPDATE: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[allow(clippy::upper_case_acronyms)]
+pub enum ResultCode {
+    NOERROR = 0,
+    FORMERR = 1,
+    SERVFAIL = 2,
+    NXDOMAIN = 3,
+    NOTIMP = 4,
+    REFUSED = 5,
+    YXDOMAIN = 6,
+    YXRRSET = 7,
+    NXRRSET = 8,
+    NOTAUTH = 9,
+    NOTZONE = 10,
+}
+
+impl From<u8> for ResultCode {
+    fn from(n: u8) -> Self {
+        match n {
+            1 => Self::FORMERR,
+            2 => Self::SERVFAIL,
+            3 => Self::NXDOMAIN,
+            4 => Self::NOTIMP,
+            5 => Self::REFUSED,
+            6 => Self::YXDOMAIN,
+            7 => Self::YXRRSET,
+            8 => Self::NXRRSET,
+            9 => Self::NOTAUTH,
+            10 => Self::NOTZONE,
+            _ => Self::NOERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DnsHeader {
+    pub id: u16, // 16b
+
+    pub recursion_desired: bool,    // 1b
+    pub truncated_message: bool,    // 1b
+    pub authoritative_answer: bool, // 1b
+    pub opcode: u8,                 // 4b
+    pub response: bool,             // 1b
+
+    pub rescode: ResultCode,       // 4b
+    pub checking_disabled: bool,   // 1b
+    pub authed_data: bool,         // 1b
+    pub z: bool,                   // 1b
+    pub recursion_available: bool, // 1b
+
+    pub questions: u16,             // 16b
+    pub answers: u16,               // 16b
+    pub authoritative_entries: u16, // 16b
+    pub resource_entries: u16,      // 16b
+}
+
+impl Default for DnsHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsHeader {
+    pub const fn new() -> Self {
+        Self {
+            id: 0,
+
+            recursion_desired: false,
+            truncated_message: false,
+            authoritative_answer: false,
+            opcode: 0,
+            response: false,
+
+            rescode: ResultCode::NOERROR,
+            checking_disabled: false,
+            authed_data: false,
+            z: false,
+            recursion_available: false,
+
+            questions: 0,
+            answers: 0,
+            authoritative_entries: 0,
+            resource_entries: 0,
+        }
+    }
+
+    pub fn read(&mut self, buf: &mut BytePacketBuffer) -> Result<()> {
+        self.id = buf.read_u16()?;
+
+        let flags = buf.read_u16()?;
+        let a = (flags >> 8) as u8;
+        let b = (flags & 0xFF) as u8;
+        self.recursion_desired = (a & 1) > 0;
+        self.truncated_message = (a & (1 << 1)) > 0;
+        self.authoritative_answer = (a & (1 << 2)) > 0;
+        self.opcode = (a >> 3) & 0x0F;
+        self.response = (a & (1 << 7)) > 0;
+
+        self.rescode = ResultCode::from(b & 0x0F);
+        self.checking_disabled = (b & (1 << 4)) > 0;
+        self.authed_data = (b & (1 << 5)) > 0;
+        self.z = (b & (1 << 6)) > 0;
+        self.recursion_available = (b & (1 << 7)) > 0;
+
+        self.questions = buf.read_u16()?;
+        self.answers = buf.read_u16()?;
+        self.authoritative_entries = buf.read_u16()?;
+        self.resource_entries = buf.read_u16()?;
+
+        // Return the constant header size
+        Ok(())
+    }
+
+    pub fn write(&self, buf: &mut BytePacketBuffer) -> Result<()> {
+        buf.write_u16(self.id)?;
+
+        buf.write_u8(
+            (self.recursion_desired as u8)
+                | ((self.truncated_message as u8) << 1)
+                | ((self.authoritative_answer as u8) << 2)
+                | ((self.opcode & 0x0f) << 3)
+                | ((self.response as u8) << 7),
+        )?;
+
+        buf.write_u8(
+            (self.rescode as u8)
+                | ((self.checking_disabled as u8) << 4)
+                | ((self.authed_data as u8) << 5)
+                | ((self.z as u8) << 6)
+                | ((self.recursion_available as u8) << 7),
+        )?;
+
+        buf.write_u16(self.questions)?;
+        buf.write_u16(self.answers)?;
+        buf.write_u16(self.authoritative_entries)?;
+        buf.write_u16(self.resource_entries)?;
+
+        Ok(())
+    }
+}
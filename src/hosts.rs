@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::error::{DnsError, Result};
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::{DnsRecord, RData};
+use crate::resolver::reverse_name;
+use crate::stack::Resolver;
+
+/// Where the system keeps its static host names on Unix
+pub const HOSTS_FILE: &str = "/etc/hosts";
+
+/// Addresses and names from a hosts file, answered locally instead of forwarding them
+#[derive(Debug, Clone, Default)]
+pub struct Hosts {
+    /// Addresses of each name, in the order they are listed
+    addrs: HashMap<DnsName, Vec<IpAddr>>,
+    /// The first name listed for each address, keyed by its reverse lookup name
+    names: HashMap<DnsName, DnsName>,
+    /// TTL of the records answered
+    ttl: u32,
+}
+
+impl Hosts {
+    pub fn load(path: impl AsRef<Path>, ttl: u32) -> Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?, ttl))
+    }
+
+    /// Parse a hosts file, where each line has an address followed by its canonical name and any
+    /// aliases. Like the C library, lines that can't be understood are skipped.
+    pub fn parse(text: &str, ttl: u32) -> Self {
+        let mut hosts = Self {
+            ttl,
+            ..Self::default()
+        };
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            // Link-local IPv6 addresses may carry a zone, which IpAddr doesn't support
+            let addr = words
+                .next()
+                .and_then(|addr| addr.split('%').next())
+                .and_then(|addr| addr.parse::<IpAddr>().ok());
+            let Some(addr) = addr else {
+                continue;
+            };
+
+            for (i, name) in words.filter_map(|name| DnsName::new(name).ok()).enumerate() {
+                if i == 0 {
+                    hosts
+                        .names
+                        .entry(reverse_name(addr))
+                        .or_insert(name.clone());
+                }
+
+                let addrs = hosts.addrs.entry(name).or_default();
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+
+        hosts
+    }
+
+    /// Answer a question from the hosts file, or `None` if it doesn't know the name. Names it
+    /// knows get their A and AAAA records, which may be none of one family, both for ANY, and
+    /// addresses their PTR record; any other type is left for the upstream.
+    pub fn lookup(&self, qname: &DnsName, qtype: QueryType) -> Option<DnsPacket> {
+        let mut packet = DnsPacket::new();
+
+        match qtype {
+            QueryType::A | QueryType::AAAA | QueryType::ANY => {
+                let addrs = self.addrs.get(qname)?;
+                packet.answers = addrs
+                    .iter()
+                    .filter_map(|addr| match (*addr, qtype) {
+                        (IpAddr::V4(addr), QueryType::A | QueryType::ANY) => {
+                            Some(DnsRecord::new(qname.clone(), self.ttl, RData::A { addr }))
+                        }
+                        (IpAddr::V6(addr), QueryType::AAAA | QueryType::ANY) => Some(
+                            DnsRecord::new(qname.clone(), self.ttl, RData::AAAA { addr }),
+                        ),
+                        _ => None,
+                    })
+                    .collect();
+            }
+            QueryType::PTR => {
+                let host = self.names.get(qname)?;
+                packet.answers.push(DnsRecord::new(
+                    qname.clone(),
+                    self.ttl,
+                    RData::PTR { host: host.clone() },
+                ));
+            }
+            _ => return None,
+        }
+
+        Some(packet)
+    }
+}
+
+impl Resolver for Hosts {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        self.lookup(&question.name, question.qtype)
+            .ok_or_else(|| DnsError::NoAnswer(question.name.to_string()))
+    }
+}
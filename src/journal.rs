@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+
+use crate::record::{DnsRecord, RData};
+
+/// How many changes a zone remembers for incremental transfers
+pub const JOURNAL_LEN: usize = 64;
+
+/// The changes between two versions of a zone, in the shape IXFR sends them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneDiff {
+    /// SOA of the version the changes apply to
+    pub from_soa: DnsRecord,
+    /// SOA of the version after the changes
+    pub to_soa: DnsRecord,
+    pub removed: Vec<DnsRecord>,
+    pub added: Vec<DnsRecord>,
+}
+
+impl ZoneDiff {
+    /// The changes from the `old` records of a zone to the `new` ones. Returns `None` if either
+    /// is missing its SOA.
+    pub fn between(old: &[DnsRecord], new: &[DnsRecord]) -> Option<Self> {
+        let is_soa = |rec: &&DnsRecord| matches!(rec.rdata, RData::SOA { .. });
+        let from_soa = old.iter().find(is_soa)?.clone();
+        let to_soa = new.iter().find(is_soa)?.clone();
+
+        let missing_from = |records: &[DnsRecord], other: &[DnsRecord]| -> Vec<DnsRecord> {
+            records
+                .iter()
+                .filter(|rec| !is_soa(rec) && !other.contains(rec))
+                .cloned()
+                .collect()
+        };
+
+        Some(Self {
+            removed: missing_from(old, new),
+            added: missing_from(new, old),
+            from_soa,
+            to_soa,
+        })
+    }
+
+    /// Apply the changes to the records of a zone, swapping in the new SOA
+    pub fn apply(&self, records: &mut Vec<DnsRecord>) {
+        records
+            .retain(|rec| !matches!(rec.rdata, RData::SOA { .. }) && !self.removed.contains(rec));
+        records.insert(0, self.to_soa.clone());
+        records.extend(self.added.iter().cloned());
+    }
+}
+
+/// The most recent changes to a zone, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    diffs: VecDeque<ZoneDiff>,
+}
+
+impl Journal {
+    /// Remember a change, forgetting the oldest one when the journal is full
+    pub fn record(&mut self, diff: ZoneDiff) {
+        if self.diffs.len() == JOURNAL_LEN {
+            self.diffs.pop_front();
+        }
+        self.diffs.push_back(diff);
+    }
+
+    /// Forget every change, for when the zone was replaced in a way that can't be diffed
+    pub fn clear(&mut self) {
+        self.diffs.clear();
+    }
+
+    /// The changes that bring a zone at `serial` up to date, or `None` if the journal doesn't go
+    /// back that far
+    pub fn since(&self, serial: u32) -> Option<impl Iterator<Item = &ZoneDiff>> {
+        let start = self
+            .diffs
+            .iter()
+            .position(|diff| soa_serial(&diff.from_soa) == Some(serial))?;
+
+        Some(self.diffs.iter().skip(start))
+    }
+}
+
+/// The serial of an SOA record
+pub(crate) const fn soa_serial(rec: &DnsRecord) -> Option<u32> {
+    match rec.rdata {
+        RData::SOA { serial, .. } => Some(serial),
+        _ => None,
+    }
+}
+
+/// Whether serial `a` is newer than `b`, using the wrapping comparison from RFC 1982
+pub(crate) const fn serial_gt(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < 1 << 31
+}
@@ -1,2 +1,103 @@
-pub mod packet_parser;
-pub mod stub_resolver;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "net")]
+pub mod authority;
+#[cfg(feature = "net")]
+pub mod balance;
+#[cfg(feature = "net")]
+pub mod blocklist;
+pub mod buffer;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "net")]
+pub mod catalog;
+#[cfg(feature = "net")]
+pub mod config;
+#[cfg(feature = "net")]
+pub mod control;
+#[cfg(feature = "net")]
+pub mod daemon;
+#[cfg(feature = "net")]
+pub mod ddr;
+pub mod dissect;
+#[cfg(feature = "std")]
+pub mod dns64;
+#[cfg(feature = "net")]
+pub mod dnssd;
+#[cfg(feature = "std")]
+pub mod dnssec;
+#[cfg(feature = "net")]
+pub mod dnstap;
+pub mod edns;
+pub mod error;
+pub mod header;
+#[cfg(feature = "net")]
+pub mod hosts;
+#[cfg(feature = "std")]
+pub mod journal;
+#[cfg(feature = "net")]
+pub mod llmnr;
+#[cfg(feature = "std")]
+pub mod local;
+#[cfg(feature = "net")]
+pub mod mdns;
+#[cfg(feature = "test-util")]
+pub mod mock;
+pub mod name;
+#[cfg(feature = "std")]
+pub mod network;
+pub mod packet;
+#[cfg(feature = "std")]
+pub mod pcap;
+#[cfg(feature = "net")]
+pub mod pool;
+#[cfg(feature = "net")]
+pub mod query_log;
+pub mod question;
+pub mod record;
+#[cfg(feature = "net")]
+pub mod resolv_conf;
+#[cfg(feature = "net")]
+pub mod resolver;
+#[cfg(feature = "net")]
+pub mod rpz;
+#[cfg(feature = "net")]
+pub mod rrl;
+#[cfg(feature = "net")]
+pub mod secondary;
+#[cfg(feature = "net")]
+pub mod server;
+#[cfg(feature = "net")]
+pub mod socks;
+pub mod stack;
+#[cfg(feature = "net")]
+pub mod stats;
+#[cfg(feature = "net")]
+pub mod tls;
+#[cfg(feature = "net")]
+pub mod transfer;
+#[cfg(feature = "std")]
+pub mod tsig;
+#[cfg(feature = "std")]
+pub mod ttl;
+#[cfg(feature = "std")]
+pub mod update;
+#[cfg(feature = "net")]
+pub mod upstream;
+#[cfg(feature = "net")]
+pub mod view;
+#[cfg(feature = "std")]
+pub mod zone;
+
+pub use buffer::BytePacketBuffer;
+pub use error::{DnsError, FailureReason, Result};
+pub use header::{DnsHeader, ResultCode};
+pub use name::DnsName;
+pub use packet::{DnsPacket, QueryBuilder};
+pub use question::{DnsQuestion, QueryType};
+pub use record::{DnsRecord, RData};
+pub use stack::Resolver;
+#[cfg(feature = "std")]
+pub use zone::Zone;
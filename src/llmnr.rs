@@ -0,0 +1,113 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+
+use tracing::{debug, info, warn};
+
+use crate::buffer::{BytePacketBuffer, UDP_MAX_LEN};
+use crate::error::Result;
+use crate::mdns;
+use crate::packet::DnsPacket;
+use crate::question::CLASS_IN;
+use crate::server::ServerContext;
+
+pub const LLMNR_PORT: u16 = 5355;
+pub const LLMNR_IPV4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+pub const LLMNR_IPV6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 3);
+
+/// Join the LLMNR groups and answer queries on them from background threads, the way Windows
+/// hosts resolve each other's single-label names. Failing to join the IPv6 group only leaves it
+/// out.
+pub fn start(context: &Arc<ServerContext>) -> Result<()> {
+    let ipv4 = (LLMNR_IPV4, LLMNR_PORT).into();
+    let ipv6: SocketAddr = (LLMNR_IPV6, LLMNR_PORT).into();
+    let mut sockets = vec![(mdns::join(ipv4)?, ipv4)];
+    match mdns::join(ipv6) {
+        Ok(socket) => sockets.push((socket, ipv6)),
+        Err(e) => warn!("Failed to join the IPv6 LLMNR group: {e}"),
+    }
+
+    for (socket, group) in sockets {
+        info!("Answering LLMNR on {group}");
+        let context = Arc::clone(context);
+        thread::spawn(move || serve(&context, &socket));
+    }
+
+    Ok(())
+}
+
+fn serve(context: &ServerContext, socket: &UdpSocket) {
+    loop {
+        let mut buf = BytePacketBuffer::new();
+        let src = match socket.recv_from(&mut buf.buf) {
+            Ok((len, src)) => {
+                buf.buf.truncate(len);
+                src
+            }
+            Err(e) => {
+                warn!("Failed to receive LLMNR query: {e}");
+                continue;
+            }
+        };
+        if !context.config.allows_query(src.ip().to_canonical()) {
+            continue;
+        }
+
+        let query = match DnsPacket::from_buffer(&mut buf) {
+            Ok(query) => query,
+            Err(e) => {
+                debug!("Ignoring malformed LLMNR message from {src}: {e}");
+                continue;
+            }
+        };
+        let Some(mut response) = answer(context, &query) else {
+            continue;
+        };
+
+        debug!(
+            "Answering LLMNR query from {src} with {} records",
+            response.answers.len()
+        );
+        let mut res_buf = BytePacketBuffer::with_len(UDP_MAX_LEN);
+        let sent = response
+            .write_truncated(&mut res_buf)
+            .and_then(|()| Ok(socket.send_to(&res_buf.buf[..res_buf.pos()], src)?));
+        if let Err(e) = sent {
+            warn!("Failed to answer LLMNR query from {src}: {e}");
+        }
+    }
+}
+
+/// The response to an LLMNR query for a single-label name from the local records or the hosts
+/// file, always sent back to the querier alone. Anything else, including names the server doesn't
+/// know, is left for other hosts to answer, RFC 4795 section 2.1.
+pub fn answer(context: &ServerContext, query: &DnsPacket) -> Option<DnsPacket> {
+    let header = &query.header;
+    let [question] = query.questions.as_slice() else {
+        return None;
+    };
+    if header.response
+        || header.opcode != 0
+        || !query.answers.is_empty()
+        || !query.authorities.is_empty()
+        || question.class != CLASS_IN
+        || question.name.label_count() != 1
+    {
+        return None;
+    }
+
+    let mut response = context
+        .local_records
+        .lookup(&question.name, question.qtype)
+        .or_else(|| {
+            let hosts = context.hosts.as_ref()?;
+            hosts.lookup(&question.name, question.qtype)
+        })?;
+    response.header.id = header.id;
+    response.header.response = true;
+    // The bit is the conflict bit in LLMNR, and the records aren't tentative
+    response.header.authoritative_answer = false;
+    response.questions = vec![question.clone()];
+
+    Some(response)
+}
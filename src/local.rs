@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use crate::error::{DnsError, Result};
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::{DnsRecord, RData};
+use crate::stack::Resolver;
+
+/// Records defined directly in the config, answered authoritatively in place of anything the zones
+/// or upstreams would say about their names
+#[derive(Debug, Clone, Default)]
+pub struct LocalRecords {
+    records: HashMap<DnsName, Vec<DnsRecord>>,
+}
+
+impl LocalRecords {
+    pub fn new(records: impl IntoIterator<Item = DnsRecord>) -> Self {
+        let mut local = Self::default();
+        for rec in records {
+            local
+                .records
+                .entry(rec.domain().clone())
+                .or_default()
+                .push(rec);
+        }
+
+        local
+    }
+
+    /// Answer a question about a name with local records, or `None` if there are none for it.
+    /// Types the name has no records of get an empty answer, and CNAMEs are followed as far as
+    /// the local records go.
+    pub fn lookup(&self, qname: &DnsName, qtype: QueryType) -> Option<DnsPacket> {
+        let mut records = self.records.get(qname)?;
+        let mut packet = DnsPacket::new();
+        packet.header.authoritative_answer = true;
+
+        // Bound the CNAME chain by the number of names so a loop can't spin forever
+        for _ in 0..=self.records.len() {
+            let owned: Vec<_> = records
+                .iter()
+                .filter(|rec| rec.answers(qtype))
+                .cloned()
+                .collect();
+            if !owned.is_empty() {
+                packet.answers.extend(owned);
+                break;
+            }
+
+            let cname = records.iter().find_map(|rec| match &rec.rdata {
+                RData::CNAME { host } => Some((rec, host)),
+                _ => None,
+            });
+            let Some((cname, host)) = cname else {
+                break;
+            };
+            packet.answers.push(cname.clone());
+            match self.records.get(host) {
+                Some(next) => records = next,
+                None => break,
+            }
+        }
+
+        Some(packet)
+    }
+}
+
+impl Resolver for LocalRecords {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        self.lookup(&question.name, question.qtype)
+            .ok_or_else(|| DnsError::NoAnswer(question.name.to_string()))
+    }
+}
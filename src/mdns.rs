@@ -0,0 +1,312 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{debug, info, warn};
+
+use crate::buffer::BytePacketBuffer;
+use crate::config::MdnsConfig;
+use crate::error::Result;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType, CLASS_IN};
+use crate::record::{DnsRecord, RData};
+use crate::resolver::reverse_name;
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_IPV4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_IPV6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Largest message mDNS allows, RFC 6762 section 17
+pub(crate) const MDNS_MAX_LEN: usize = 9000;
+
+/// Top bit of the class, asking for a unicast response in questions and telling caches to replace
+/// what they hold for the name and type in records
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// Highest TTL given to queriers that aren't mDNS responders, RFC 6762 section 6.7
+const LEGACY_MAX_TTL: u32 = 10;
+
+/// Announcements sent on startup, a second apart, RFC 6762 section 8.3
+const ANNOUNCEMENTS: usize = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Length of the header, after which the questions start
+const HEADER_LEN: usize = 12;
+
+/// Answers multicast DNS queries for the `.local` names of this host, on 224.0.0.251 and
+/// ff02::fb. Since the names are configured, they aren't probed for conflicts first.
+#[derive(Debug, Clone)]
+pub struct Responder {
+    records: Vec<DnsRecord>,
+}
+
+impl Responder {
+    /// Records for every name and address, and the reverse names of the addresses pointing to the
+    /// first name
+    pub fn new(config: &MdnsConfig) -> Self {
+        let addrs = if config.addresses.is_empty() {
+            local_addr().into_iter().collect()
+        } else {
+            config.addresses.clone()
+        };
+
+        let mut records = Vec::new();
+        for name in &config.names {
+            for &addr in &addrs {
+                records.push(match addr {
+                    IpAddr::V4(addr) => DnsRecord::new(name.clone(), config.ttl, RData::A { addr }),
+                    IpAddr::V6(addr) => {
+                        DnsRecord::new(name.clone(), config.ttl, RData::AAAA { addr })
+                    }
+                });
+            }
+        }
+        if let Some(name) = config.names.first() {
+            records.extend(addrs.iter().map(|&addr| {
+                DnsRecord::new(
+                    reverse_name(addr),
+                    config.ttl,
+                    RData::PTR { host: name.clone() },
+                )
+            }));
+        }
+
+        Self { records }
+    }
+
+    /// The response to a query, or `None` if none of its questions are about this host or the
+    /// querier already knows the answers. Queries from ports other than 5353 come from plain
+    /// resolvers, which get a conventional response to their `legacy` query.
+    pub fn answer(&self, query: &DnsPacket, legacy: bool) -> Option<DnsPacket> {
+        let mut response = response();
+        if legacy {
+            response.header.id = query.header.id;
+            response.questions.clone_from(&query.questions);
+        }
+
+        for question in &query.questions {
+            for rec in self.records.iter().filter(|rec| matches(question, rec)) {
+                if known(query, rec) {
+                    continue;
+                }
+                let mut rec = rec.clone();
+                if legacy {
+                    rec.set_ttl(rec.ttl().min(LEGACY_MAX_TTL));
+                }
+                if !response.answers.contains(&rec) {
+                    response.answers.push(rec);
+                }
+            }
+        }
+
+        (!response.answers.is_empty()).then_some(response)
+    }
+
+    /// Every record, sent unasked so caches on the link pick up the names
+    pub fn announcement(&self) -> DnsPacket {
+        let mut response = response();
+        response.answers.clone_from(&self.records);
+        response
+    }
+
+    /// Join the mDNS groups, then announce the records and answer queries on each from a
+    /// background thread. Failing to join the IPv6 group only leaves it out.
+    pub fn start(self) -> Result<()> {
+        let responder = Arc::new(self);
+        let ipv4 = (MDNS_IPV4, MDNS_PORT).into();
+        let ipv6 = (MDNS_IPV6, MDNS_PORT).into();
+        let mut groups = vec![(join(ipv4)?, ipv4)];
+        match join(ipv6) {
+            Ok(socket) => groups.push((socket, ipv6)),
+            Err(e) => warn!("Failed to join the IPv6 mDNS group: {e}"),
+        }
+
+        for (socket, group) in groups {
+            info!("Answering mDNS on {group}");
+            let responder = Arc::clone(&responder);
+            thread::spawn(move || {
+                responder.announce(&socket, group);
+                responder.serve(&socket, group);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn announce(&self, socket: &UdpSocket, group: SocketAddr) {
+        for i in 0..ANNOUNCEMENTS {
+            if i > 0 {
+                thread::sleep(ANNOUNCE_INTERVAL);
+            }
+            let sent = write(&mut self.announcement(), true)
+                .and_then(|buf| Ok(socket.send_to(&buf.buf[..buf.pos()], group)?));
+            if let Err(e) = sent {
+                warn!("Failed to announce mDNS records to {group}: {e}");
+            }
+        }
+    }
+
+    fn serve(&self, socket: &UdpSocket, group: SocketAddr) {
+        loop {
+            let mut buf = BytePacketBuffer::with_len(MDNS_MAX_LEN);
+            let src = match socket.recv_from(&mut buf.buf) {
+                Ok((len, src)) => {
+                    buf.buf.truncate(len);
+                    src
+                }
+                Err(e) => {
+                    warn!("Failed to receive mDNS query: {e}");
+                    continue;
+                }
+            };
+
+            let query = match DnsPacket::from_buffer(&mut buf) {
+                Ok(query) => query,
+                Err(e) => {
+                    debug!("Ignoring malformed mDNS message from {src}: {e}");
+                    continue;
+                }
+            };
+            // Other responders announcing their records, and opcodes mDNS doesn't use
+            if query.header.response || query.header.opcode != 0 {
+                continue;
+            }
+
+            match self.respond(&query, src, group) {
+                Ok(Some((buf, dest))) => {
+                    if let Err(e) = socket.send_to(&buf.buf[..buf.pos()], dest) {
+                        warn!("Failed to answer mDNS query from {src}: {e}");
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to answer mDNS query from {src}: {e}"),
+            }
+        }
+    }
+
+    /// The response to a query from `src` to `group` as it is sent, and where to send it: back to
+    /// `src` for legacy queriers and when every question asks for a unicast response, to the
+    /// group otherwise. `None` when there is nothing to answer.
+    pub fn respond(
+        &self,
+        query: &DnsPacket,
+        src: SocketAddr,
+        group: SocketAddr,
+    ) -> Result<Option<(BytePacketBuffer, SocketAddr)>> {
+        let legacy = src.port() != MDNS_PORT;
+        let Some(mut response) = self.answer(query, legacy) else {
+            return Ok(None);
+        };
+        let unicast = legacy
+            || query
+                .questions
+                .iter()
+                .all(|question| question.class & CLASS_TOP_BIT != 0);
+        let dest = if unicast { src } else { group };
+        debug!(
+            "Answering mDNS query from {src} with {} records",
+            response.answers.len()
+        );
+
+        Ok(Some((write(&mut response, !legacy)?, dest)))
+    }
+}
+
+/// An empty authoritative response, with the id of 0 multicast responses have
+fn response() -> DnsPacket {
+    let mut response = DnsPacket::new();
+    response.header.response = true;
+    response.header.authoritative_answer = true;
+    response
+}
+
+/// Whether a question asks for a record, in the Internet class or any
+fn matches(question: &DnsQuestion, rec: &DnsRecord) -> bool {
+    let class = question.class & !CLASS_TOP_BIT;
+    (class == CLASS_IN || class == u16::from(QueryType::ANY))
+        && (question.qtype == QueryType::ANY || question.qtype == rec.qtype())
+        && question.name == *rec.domain()
+}
+
+/// Whether the querier listed the record among the answers it knows, with at least half of its
+/// TTL left, RFC 6762 section 7.1
+fn known(query: &DnsPacket, rec: &DnsRecord) -> bool {
+    query.answers.iter().any(|known| {
+        let mut same = known.clone();
+        same.set_ttl(rec.ttl());
+        same == *rec && known.ttl() >= rec.ttl() / 2
+    })
+}
+
+/// Write a message, with the cache-flush bit set on its records when `cache_flush`
+fn write(packet: &mut DnsPacket, cache_flush: bool) -> Result<BytePacketBuffer> {
+    let mut buf = BytePacketBuffer::with_len(MDNS_MAX_LEN);
+    packet.write(&mut buf)?;
+    if cache_flush {
+        set_cache_flush(&mut buf, packet)?;
+    }
+
+    Ok(buf)
+}
+
+/// Set the top bit of the class of every record in a written message. The records are all unique
+/// to this host, so caches should drop whatever else they hold for them, RFC 6762 section 10.2.
+fn set_cache_flush(buf: &mut BytePacketBuffer, packet: &DnsPacket) -> Result<()> {
+    let end = buf.pos();
+    buf.seek(HEADER_LEN)?;
+    for _ in &packet.questions {
+        buf.read_name()?;
+        // Type and class
+        buf.step(4)?;
+    }
+    let records = packet.answers.len() + packet.authorities.len() + packet.resources.len();
+    for _ in 0..records {
+        buf.read_name()?;
+        buf.set_u16(buf.pos() + 2, CLASS_IN | CLASS_TOP_BIT)?;
+        // Type, class and TTL
+        buf.step(8)?;
+        let len = buf.read_u16()?;
+        buf.step(len.into())?;
+    }
+
+    buf.seek(end)
+}
+
+/// Bind the port of a multicast group, shared with any other responder on the host, and join it
+pub(crate) fn join(group: SocketAddr) -> io::Result<UdpSocket> {
+    let (domain, unspecified) = match group.ip() {
+        IpAddr::V4(_) => (Domain::IPV4, IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        IpAddr::V6(_) => (Domain::IPV6, IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+    };
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    // mDNS responders only accept messages sent with the highest hop limit, which can't have come
+    // from off the link, RFC 6762 section 11
+    match group.ip() {
+        IpAddr::V4(ip) => {
+            socket.set_multicast_ttl_v4(255)?;
+            socket.bind(&SocketAddr::new(unspecified, group.port()).into())?;
+            socket.join_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED)?;
+        }
+        IpAddr::V6(ip) => {
+            socket.set_only_v6(true)?;
+            socket.set_multicast_hops_v6(255)?;
+            socket.bind(&SocketAddr::new(unspecified, group.port()).into())?;
+            socket.join_multicast_v6(&ip, 0)?;
+        }
+    }
+
+    Ok(socket.into())
+}
+
+/// The address of the interface the IPv4 group is reached through, found by connecting a socket,
+/// which sends nothing
+fn local_addr() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((MDNS_IPV4, MDNS_PORT)).ok()?;
+    let addr = socket.local_addr().ok()?.ip();
+    (!addr.is_unspecified()).then_some(addr)
+}
@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::buffer::{BytePacketBuffer, TCP_MAX_LEN};
+use crate::edns::{opt_record, ClientSubnet, TcpKeepalive};
+use crate::error::Result;
+use crate::header::ResultCode;
+use crate::packet::DnsPacket;
+use crate::record::DnsRecord;
+
+/// How often the serving threads check whether the server was dropped
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// What the mock server sends back for a query
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// A response with these answers, echoing the ID and the question in the case it was sent in
+    Answer(Vec<DnsRecord>),
+    /// A response without answers, with these NS records in the authority section and their glue
+    /// in the additional section, referring the query to a zone further down
+    Referral(Vec<DnsRecord>, Vec<DnsRecord>),
+    /// A response with no records and this result code
+    Rcode(ResultCode),
+    /// An empty response with the TC bit set, so the client asks again over TCP
+    Truncated,
+    /// These bytes instead of a DNS message
+    Malformed(Vec<u8>),
+    /// The reply with the ID of the query changed, like a spoofed response
+    WrongId(Box<Reply>),
+    /// The reply sent after waiting, without holding up the queries after it
+    Delayed(Duration, Box<Reply>),
+    /// The reply with the edns-tcp-keepalive option, saying how long the connection is kept open
+    KeepAlive(Duration, Box<Reply>),
+    /// The reply with the client subnet of the query echoed back with this scope prefix
+    Scoped(u8, Box<Reply>),
+    /// Nothing at all
+    Silent,
+}
+
+/// A query the mock server received
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub packet: DnsPacket,
+    pub tcp: bool,
+}
+
+type Handler = dyn Fn(&DnsPacket) -> Reply + Send + Sync;
+
+/// An upstream server on an ephemeral loopback port, answering over UDP and TCP with scripted
+/// replies instead of going to the network. It stops serving when dropped.
+///
+/// ```no_run
+/// use dns_server::mock::{MockServer, Reply};
+///
+/// let server = MockServer::scripted([Reply::Silent, Reply::Answer(vec![])]).unwrap();
+/// // Point a resolver at server.addr(), then check what it sent with server.received()
+/// ```
+pub struct MockServer {
+    addr: SocketAddr,
+    received: Arc<Mutex<Vec<Received>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// Reply to every query with whatever `handler` returns for it
+    pub fn new(handler: impl Fn(&DnsPacket) -> Reply + Send + Sync + 'static) -> Result<Self> {
+        let (udp, tcp) = bind()?;
+        let addr = udp.local_addr()?;
+        udp.set_read_timeout(Some(POLL_INTERVAL))?;
+        tcp.set_nonblocking(true)?;
+
+        let server = Self {
+            addr,
+            received: Arc::default(),
+            stop: Arc::default(),
+        };
+        let handler: Arc<Handler> = Arc::new(handler);
+        let udp_server = Serving {
+            handler: Arc::clone(&handler),
+            received: Arc::clone(&server.received),
+            stop: Arc::clone(&server.stop),
+        };
+        thread::spawn(move || udp_server.udp(&udp));
+        let tcp_server = Serving {
+            handler,
+            received: Arc::clone(&server.received),
+            stop: Arc::clone(&server.stop),
+        };
+        thread::spawn(move || tcp_server.tcp(&tcp));
+
+        Ok(server)
+    }
+
+    /// Reply to the queries in the order they arrive, over either transport, with `replies` one
+    /// after the other. Once they run out, queries get no reply.
+    pub fn scripted(replies: impl IntoIterator<Item = Reply>) -> Result<Self> {
+        let replies = Mutex::new(replies.into_iter().collect::<VecDeque<_>>());
+        Self::new(move |_| {
+            let mut replies = replies.lock().unwrap_or_else(|e| e.into_inner());
+            replies.pop_front().unwrap_or(Reply::Silent)
+        })
+    }
+
+    /// The address to send queries to, the same port for UDP and TCP
+    pub const fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Every query received so far, in order
+    pub fn received(&self) -> Vec<Received> {
+        self.received
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A UDP socket and a TCP listener on the same ephemeral port, which may take a few tries when
+/// the TCP port happens to be taken
+fn bind() -> Result<(UdpSocket, TcpListener)> {
+    let mut attempts = 0;
+    loop {
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        match TcpListener::bind(udp.local_addr()?) {
+            Ok(tcp) => return Ok((udp, tcp)),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && attempts < 10 => attempts += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// What the serving threads share with the [`MockServer`]
+struct Serving {
+    handler: Arc<Handler>,
+    received: Arc<Mutex<Vec<Received>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Serving {
+    fn udp(&self, socket: &UdpSocket) {
+        while !self.stop.load(Ordering::Relaxed) {
+            let mut buf = [0; TCP_MAX_LEN];
+            let Ok((len, src)) = socket.recv_from(&mut buf) else {
+                continue;
+            };
+            let Some(reply) = self.handle(&buf[..len], false) else {
+                continue;
+            };
+
+            let Ok(socket) = socket.try_clone() else {
+                continue;
+            };
+            send_later(reply, move |wire| {
+                let _ = socket.send_to(&wire, src);
+            });
+        }
+    }
+
+    fn tcp(&self, listener: &TcpListener) {
+        while !self.stop.load(Ordering::Relaxed) {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            // A client may send several queries over one connection
+            let _ = self.connection(stream);
+        }
+    }
+
+    fn connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        loop {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            let mut wire = vec![0; usize::from(u16::from_be_bytes(len))];
+            stream.read_exact(&mut wire)?;
+
+            let Some(reply) = self.handle(&wire, true) else {
+                continue;
+            };
+            let mut stream = stream.try_clone()?;
+            send_later(reply, move |wire| {
+                let buf = BytePacketBuffer {
+                    pos: wire.len(),
+                    buf: wire,
+                };
+                let _ = buf.write_to(&mut stream);
+            });
+        }
+    }
+
+    /// Note the query and pick the reply, with nothing to reply to what doesn't parse
+    fn handle(&self, wire: &[u8], tcp: bool) -> Option<Pending> {
+        let mut buf = BytePacketBuffer {
+            buf: wire.to_vec(),
+            pos: 0,
+        };
+        let packet = DnsPacket::from_buffer(&mut buf).ok()?;
+        let reply = (self.handler)(&packet);
+        self.received
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Received {
+                packet: packet.clone(),
+                tcp,
+            });
+
+        Some(Pending {
+            reply,
+            query: packet,
+        })
+    }
+}
+
+/// A reply to send, with the query it answers
+struct Pending {
+    reply: Reply,
+    query: DnsPacket,
+}
+
+/// Send the reply right away, or from another thread once a delay is up
+fn send_later(pending: Pending, send: impl FnOnce(Vec<u8>) + Send + 'static) {
+    let mut reply = pending.reply;
+    let mut delay = Duration::ZERO;
+    let mut id = pending.query.header.id;
+    let mut keepalive = None;
+    let mut scope = None;
+    loop {
+        match reply {
+            Reply::Delayed(wait, inner) => {
+                delay += wait;
+                reply = *inner;
+            }
+            Reply::WrongId(inner) => {
+                id = id.wrapping_add(1);
+                reply = *inner;
+            }
+            Reply::KeepAlive(timeout, inner) => {
+                keepalive = Some(timeout);
+                reply = *inner;
+            }
+            Reply::Scoped(prefix, inner) => {
+                scope = Some(prefix);
+                reply = *inner;
+            }
+            _ => break,
+        }
+    }
+
+    let Some(wire) = response(&reply, &pending.query, id, keepalive, scope) else {
+        return;
+    };
+    if delay.is_zero() {
+        send(wire);
+    } else {
+        thread::spawn(move || {
+            thread::sleep(delay);
+            send(wire);
+        });
+    }
+}
+
+/// The bytes of the response, or `None` when the reply is to stay silent
+fn response(
+    reply: &Reply,
+    query: &DnsPacket,
+    id: u16,
+    keepalive: Option<Duration>,
+    scope: Option<u8>,
+) -> Option<Vec<u8>> {
+    let mut packet = DnsPacket::response_to(query);
+    packet.header.id = id;
+    let mut options = Vec::new();
+    if let Some(timeout) = keepalive {
+        let option = TcpKeepalive {
+            timeout: Some(timeout),
+        };
+        options.push(option.to_option());
+    }
+    if let (Some(scope_prefix), Some(subnet)) = (scope, query.client_subnet()) {
+        let option = ClientSubnet {
+            scope_prefix,
+            ..subnet
+        };
+        options.push(option.to_option());
+    }
+    if !options.is_empty() {
+        packet.resources.push(opt_record(false, options));
+    }
+
+    match reply {
+        Reply::Answer(answers) => packet.answers = answers.clone(),
+        Reply::Referral(authorities, glue) => {
+            packet.authorities = authorities.clone();
+            packet.resources.extend(glue.iter().cloned());
+        }
+        Reply::Rcode(rescode) => packet.header.rescode = *rescode,
+        Reply::Truncated => packet.header.truncated_message = true,
+        Reply::Malformed(bytes) => return Some(bytes.clone()),
+        Reply::Silent => return None,
+        Reply::WrongId(_) | Reply::Delayed(..) | Reply::KeepAlive(..) | Reply::Scoped(..) => {
+            unreachable!("unwrapped by send_later")
+        }
+    }
+
+    let mut buf = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    packet.write(&mut buf).ok()?;
+    Some(buf.buf[..buf.pos()].to_vec())
+}
@@ -0,0 +1,507 @@
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::str::FromStr;
+
+use crate::error::{DnsError, Result};
+
+/// Maximum length of a single label
+pub const MAX_LABEL_LEN: usize = 63;
+/// Maximum length of a whole name in wire format, including the length octets and the root label
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Convert an internationalized name to its ASCII-compatible form, e.g. `bücher.example` becomes
+/// `xn--bcher-kva.example`. Names that are already ASCII are passed through untouched.
+pub fn to_ascii(name: &str) -> Result<Cow<'_, str>> {
+    if name.is_ascii() {
+        return Ok(Cow::Borrowed(name));
+    }
+
+    match idna::domain_to_ascii(name) {
+        Ok(ascii) => Ok(Cow::Owned(ascii)),
+        Err(_) => Err(DnsError::InvalidIdn(name.to_string())),
+    }
+}
+
+/// Check the labels and the wire length of a name in presentation format without the trailing dot
+fn validate(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Ok(());
+    }
+
+    // One length octet per label plus the terminating root label
+    let mut wire_len = 1;
+    for label in Labels::new(name) {
+        if label.is_empty() {
+            return Err(DnsError::EmptyLabel(name.to_string()));
+        }
+        let len = unescape(label).len();
+        if len > MAX_LABEL_LEN {
+            return Err(DnsError::LabelTooLong(label.to_string()));
+        }
+        wire_len += len + 1;
+    }
+    if wire_len > MAX_NAME_LEN {
+        return Err(DnsError::NameTooLong(name.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Whether the dot at `i` of a name in presentation format separates labels, rather than being
+/// escaped as part of one
+fn is_separator(name: &[u8], i: usize) -> bool {
+    name[i] == b'.' && name[..i].iter().rev().take_while(|&&b| b == b'\\').count() % 2 == 0
+}
+
+/// The octets of a label or character string in presentation format, with `\DDD` and `\X`
+/// escapes decoded
+pub(crate) fn unescape(text: &str) -> Cow<'_, [u8]> {
+    if !text.contains('\\') {
+        return Cow::Borrowed(text.as_bytes());
+    }
+
+    let bytes = text.as_bytes();
+    let mut octets = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            octets.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let decimal = bytes
+            .get(i + 1..i + 4)
+            .and_then(|digits| core::str::from_utf8(digits).ok())
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse::<u8>().ok());
+        match decimal {
+            Some(octet) => {
+                octets.push(octet);
+                i += 4;
+            }
+            None => {
+                octets.extend(bytes.get(i + 1));
+                i += 2;
+            }
+        }
+    }
+
+    Cow::Owned(octets)
+}
+
+/// Append a label read from the wire in presentation format, RFC 1035 section 5.1: dots and
+/// backslashes are escaped with a backslash, and octets that aren't printable ASCII as `\DDD`, so
+/// binary labels survive as they are instead of being taken for text
+pub(crate) fn push_label(name: &mut String, label: &[u8]) {
+    for &b in label {
+        match b {
+            b'.' | b'\\' => {
+                name.push('\\');
+                name.push(char::from(b));
+            }
+            0x21..=0x7e => name.push(char::from(b)),
+            _ => name.push_str(&format!("\\{b:03}")),
+        }
+    }
+}
+
+/// The labels of a name in presentation format, split at the dots that aren't escaped
+struct Labels<'a> {
+    rest: Option<&'a str>,
+}
+
+impl<'a> Labels<'a> {
+    fn new(name: &'a str) -> Self {
+        Self {
+            rest: (!name.is_empty()).then_some(name),
+        }
+    }
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.rest?;
+        let bytes = rest.as_bytes();
+        match (0..bytes.len()).find(|&i| is_separator(bytes, i)) {
+            Some(i) => {
+                self.rest = Some(&rest[i + 1..]);
+                Some(&rest[..i])
+            }
+            None => self.rest.take(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Labels<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let rest = self.rest?;
+        let bytes = rest.as_bytes();
+        match (0..bytes.len()).rev().find(|&i| is_separator(bytes, i)) {
+            Some(i) => {
+                self.rest = Some(&rest[..i]);
+                Some(&rest[i + 1..])
+            }
+            None => self.rest.take(),
+        }
+    }
+}
+
+/// A validated domain name
+///
+/// Names are stored in presentation format without the trailing dot, so the root name is the empty
+/// string. Dots and backslashes inside labels and octets that aren't printable ASCII are escaped
+/// like in zone files. Every label is guaranteed to be non-empty and at most 63 octets long, and
+/// the name fits in 255 octets on the wire. Comparisons and hashing ignore ASCII case, like DNS
+/// does.
+#[derive(Clone, Default)]
+pub struct DnsName(String);
+
+impl DnsName {
+    /// The root name `.`
+    pub const fn root() -> Self {
+        Self(String::new())
+    }
+
+    /// Validate and build a name. A single trailing dot is accepted, and internationalized names
+    /// are converted to their ASCII-compatible form.
+    pub fn new(name: &str) -> Result<Self> {
+        let name = to_ascii(name)?;
+        let name = match name.len().checked_sub(1) {
+            Some(last) if is_separator(name.as_bytes(), last) => &name[..last],
+            _ => &name,
+        };
+        validate(name)?;
+
+        Ok(Self(name.to_string()))
+    }
+
+    /// Validate a name read from the wire, which [`push_label`] already escaped, keeping its
+    /// allocation
+    pub(crate) fn from_wire(name: String) -> Result<Self> {
+        validate(&name)?;
+
+        Ok(Self(name))
+    }
+
+    /// Wrap a name that is already known to be valid
+    #[cfg(feature = "std")]
+    pub(crate) const fn from_validated(name: String) -> Self {
+        Self(name)
+    }
+
+    /// The name in presentation format, without the trailing dot
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The name in presentation format with its trailing dot, `.` for the root
+    pub fn to_fqdn(&self) -> String {
+        format!("{}.", self.0)
+    }
+
+    /// Check that the name is a hostname, RFC 952 and RFC 1123 section 2.1: every label is made
+    /// of letters, digits and hyphens, and doesn't start or end with a hyphen. Names of services
+    /// like `_dns.resolver.arpa` and wildcards aren't hostnames, nor is the root.
+    pub fn check_hostname(&self) -> Result<()> {
+        let is_ldh = |label: &str| {
+            label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        };
+        if self.is_root() || !self.labels().all(is_ldh) {
+            return Err(DnsError::InvalidHostname(self.0.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Iterate over the labels from the leftmost (most specific) to the rightmost
+    pub fn labels(&self) -> impl DoubleEndedIterator<Item = &str> {
+        Labels::new(&self.0)
+    }
+
+    /// The labels as they are on the wire, with their escapes decoded
+    pub(crate) fn wire_labels(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        self.labels().map(unescape)
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    /// Octets the name takes on the wire without compression
+    pub fn wire_len(&self) -> usize {
+        if self.is_root() {
+            1
+        } else if !self.0.contains('\\') {
+            self.0.len() + 2
+        } else {
+            self.wire_labels()
+                .map(|label| label.len() + 1)
+                .sum::<usize>()
+                + 1
+        }
+    }
+
+    /// The name with the leftmost label removed, or `None` for the root
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_root() {
+            return None;
+        }
+
+        let bytes = self.0.as_bytes();
+        match (0..bytes.len()).find(|&i| is_separator(bytes, i)) {
+            Some(i) => Some(Self(self.0[i + 1..].to_string())),
+            None => Some(Self::root()),
+        }
+    }
+
+    /// Prepend a label to the name
+    pub fn child(&self, label: &str) -> Result<Self> {
+        if self.is_root() {
+            Self::new(label)
+        } else {
+            Self::new(&format!("{label}.{}", self.0))
+        }
+    }
+
+    /// Decode the punycode labels back to Unicode for display, e.g. `xn--bcher-kva.example`
+    /// becomes `bücher.example`. Labels that fail to decode are left as they are.
+    pub fn to_unicode(&self) -> String {
+        let (unicode, _) = idna::domain_to_unicode(&self.0);
+        unicode
+    }
+
+    /// The name in lowercase, as it is signed with DNSSEC
+    pub fn to_lowercase(&self) -> Self {
+        Self(self.0.to_ascii_lowercase())
+    }
+
+    /// Lowercase labels without compression, as names are covered by TSIG and DNSSEC signatures
+    pub(crate) fn canonical_wire(&self) -> Vec<u8> {
+        let mut wire = Vec::with_capacity(self.0.len() + 2);
+        for label in self.wire_labels() {
+            wire.push(label.len() as u8);
+            wire.extend(label.iter().map(u8::to_ascii_lowercase));
+        }
+        wire.push(0);
+
+        wire
+    }
+
+    /// Compare names in the canonical order of RFC 4034, label by label starting from the root,
+    /// which is the order of the NSEC chain
+    pub fn canonical_cmp(&self, other: &Self) -> Ordering {
+        let lower = |label: Cow<'_, [u8]>| label.to_ascii_lowercase();
+        let a = self.labels().rev().map(unescape).map(lower);
+        let b = other.labels().rev().map(unescape).map(lower);
+        a.cmp(b)
+    }
+
+    /// Whether this name is equal to or below `zone`
+    pub fn is_subdomain_of(&self, zone: &Self) -> bool {
+        if zone.is_root() {
+            return true;
+        }
+
+        if self.0.contains('\\') || zone.0.contains('\\') {
+            let count = zone.label_count();
+            let name = self.labels().rev().map(unescape).take(count);
+            let zone = zone.labels().rev().map(unescape);
+            return self.label_count() >= count && labels_eq(name, zone);
+        }
+
+        let name = self.0.as_bytes();
+        let zone = zone.0.as_bytes();
+        match name.len().cmp(&zone.len()) {
+            Ordering::Less => false,
+            Ordering::Equal => name.eq_ignore_ascii_case(zone),
+            Ordering::Greater => {
+                let split = name.len() - zone.len();
+                is_separator(name, split - 1) && name[split..].eq_ignore_ascii_case(zone)
+            }
+        }
+    }
+}
+
+impl FromStr for DnsName {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+impl AsRef<str> for DnsName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DnsName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for DnsName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+/// Names are equal when their labels are on the wire, in any case, however they're escaped
+impl PartialEq for DnsName {
+    fn eq(&self, other: &Self) -> bool {
+        if !self.0.contains('\\') && !other.0.contains('\\') {
+            return self.0.eq_ignore_ascii_case(&other.0);
+        }
+        labels_eq(self.wire_labels(), other.wire_labels())
+    }
+}
+
+impl Eq for DnsName {}
+
+impl PartialEq<str> for DnsName {
+    fn eq(&self, other: &str) -> bool {
+        let other = other.strip_suffix('.').unwrap_or(other);
+        if !self.0.contains('\\') && !other.contains('\\') {
+            return self.0.eq_ignore_ascii_case(other);
+        }
+        labels_eq(self.wire_labels(), Labels::new(other).map(unescape))
+    }
+}
+
+impl PartialEq<&str> for DnsName {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+/// Hashes the lowercase wire format, so equal names hash the same however they're escaped
+impl Hash for DnsName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in self.wire_labels() {
+            state.write_u8(label.len() as u8);
+            for b in label.iter() {
+                state.write_u8(b.to_ascii_lowercase());
+            }
+        }
+        state.write_u8(0);
+    }
+}
+
+impl PartialOrd for DnsName {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Label by label from the left, see [`DnsName::canonical_cmp`] for the order of RFC 4034
+impl Ord for DnsName {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let lower = |label: Cow<'_, [u8]>| label.to_ascii_lowercase();
+        let a = self.wire_labels().map(lower);
+        let b = other.wire_labels().map(lower);
+        a.cmp(b)
+    }
+}
+
+/// Whether two sequences of wire labels are the same but for case
+fn labels_eq<'a, 'b>(
+    mut a: impl Iterator<Item = Cow<'a, [u8]>>,
+    mut b: impl Iterator<Item = Cow<'b, [u8]>>,
+) -> bool {
+    loop {
+        match (a.next(), b.next()) {
+            (Some(x), Some(y)) if x.eq_ignore_ascii_case(&y) => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for DnsName {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DnsName {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = <Cow<'de, str>>::deserialize(deserializer)?;
+        Self::new(&name).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Names in the config are parsed by [`DnsName::new`] without the `serde` feature, which is about
+/// the wire format types
+#[cfg(feature = "net")]
+pub(crate) fn deserialize_name<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DnsName, D::Error> {
+    let name = <Cow<'de, str> as serde::Deserialize>::deserialize(deserializer)?;
+    DnsName::new(&name).map_err(serde::de::Error::custom)
+}
+
+/// A list of names in the config, see [`deserialize_name`]
+#[cfg(feature = "net")]
+pub(crate) fn deserialize_names<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<DnsName>, D::Error> {
+    let names = <Vec<Cow<'de, str>> as serde::Deserialize>::deserialize(deserializer)?;
+    names
+        .iter()
+        .map(|name| DnsName::new(name).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// An optional name in the config, see [`deserialize_name`]
+#[cfg(feature = "net")]
+pub(crate) fn deserialize_optional_name<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DnsName>, D::Error> {
+    let name = <Option<Cow<'de, str>> as serde::Deserialize>::deserialize(deserializer)?;
+    name.map(|name| DnsName::new(&name).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Valid names of up to four labels in mixed case
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for DnsName {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        const LABEL_CHARS: &[u8] =
+            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_";
+
+        let mut labels = Vec::new();
+        let mut wire_len = 1;
+        for _ in 0..u.int_in_range(0..=4)? {
+            let len = u.int_in_range(1..=MAX_LABEL_LEN)?;
+            if wire_len + len + 1 > MAX_NAME_LEN {
+                break;
+            }
+            wire_len += len + 1;
+            let label = (0..len)
+                .map(|_| u.choose(LABEL_CHARS).map(|&b| char::from(b)))
+                .collect::<arbitrary::Result<String>>()?;
+            labels.push(label);
+        }
+
+        Ok(Self(labels.join(".")))
+    }
+}
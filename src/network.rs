@@ -0,0 +1,107 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::error::DnsError;
+
+/// A range of addresses in CIDR notation, like `10.0.0.0/8` or `2001:db8::/32`. A bare address is
+/// a network of just itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Network {
+    /// The network address, with every bit past `prefix` cleared
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    /// The network of `addr` made of its first `prefix` bits, capped at the length of the address
+    pub fn new(addr: IpAddr, prefix: u8) -> Self {
+        let prefix = prefix.min(max_prefix(addr));
+        Self {
+            addr: mask(addr, prefix),
+            prefix,
+        }
+    }
+
+    pub const fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub const fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether `addr` is in the network. IPv4 addresses mapped into IPv6, as dual-stack sockets
+    /// report them, count as their IPv4 selves in IPv4 networks.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = if self.addr.is_ipv4() {
+            addr.to_canonical()
+        } else {
+            addr
+        };
+        addr.is_ipv4() == self.addr.is_ipv4() && mask(addr, self.prefix) == self.addr
+    }
+}
+
+const fn max_prefix(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4((u32::from(v4) & mask).into())
+        }
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6((u128::from(v6) & mask).into())
+        }
+    }
+}
+
+impl From<IpAddr> for Network {
+    fn from(addr: IpAddr) -> Self {
+        Self::new(addr, max_prefix(addr))
+    }
+}
+
+impl FromStr for Network {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::InvalidNetwork(s.to_string());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => max_prefix(addr),
+        };
+        if prefix > max_prefix(addr) {
+            return Err(invalid());
+        }
+
+        Ok(Self::new(addr, prefix))
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[cfg(feature = "net")]
+impl<'de> serde::Deserialize<'de> for Network {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,550 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+use core::net::{IpAddr, Ipv4Addr};
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "net")]
+use rand::seq::IndexedRandom;
+
+use crate::buffer::{BytePacketBuffer, TCP_MAX_LEN};
+use crate::edns::{
+    opt_record, ClientSubnet, EdnsOption, ExtendedError, TcpKeepalive, FLAG_DNSSEC_OK,
+    OPTION_CLIENT_SUBNET, OPTION_EXTENDED_ERROR, OPTION_NSID, OPTION_PADDING, OPTION_TCP_KEEPALIVE,
+};
+use crate::error::{DnsError, Result};
+use crate::header::DnsHeader;
+use crate::name::DnsName;
+use crate::question::{DnsQuestion, QueryType, CLASS_IN};
+use crate::record::{DnsRecord, RData};
+#[cfg(feature = "std")]
+use crate::tsig::TsigSession;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DnsPacket {
+    pub header: DnsHeader,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub resources: Vec<DnsRecord>,
+}
+
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsPacket {
+    pub const fn new() -> Self {
+        Self {
+            header: DnsHeader::new(),
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            resources: Vec::new(),
+        }
+    }
+
+    /// Start building a query for `name`, like
+    /// `DnsPacket::query("example.com", QueryType::A).recursion_desired(true).build()`
+    pub fn query(name: impl AsRef<str>, qtype: QueryType) -> QueryBuilder {
+        QueryBuilder {
+            name: DnsName::new(name.as_ref()),
+            qtype,
+            class: CLASS_IN,
+            header: DnsHeader::new(),
+            opt: None,
+        }
+    }
+
+    /// An empty response to `query`, with its ID, opcode, question and the RD and CD flags copied
+    /// over. QR and RA are set, servers that don't recurse for the client clear RA again.
+    pub fn response_to(query: &Self) -> Self {
+        let mut response = Self::new();
+        response.header.id = query.header.id;
+        response.header.opcode = query.header.opcode;
+        response.header.recursion_desired = query.header.recursion_desired;
+        response.header.checking_disabled = query.header.checking_disabled;
+        response.header.response = true;
+        response.header.recursion_available = true;
+        response.questions.clone_from(&query.questions);
+        response.header.questions = response.questions.len() as u16;
+
+        response
+    }
+
+    pub fn from_buffer(buf: &mut BytePacketBuffer) -> Result<Self> {
+        let mut res = Self::new();
+        res.header.read(buf)?;
+
+        for _ in 0..res.header.questions {
+            let mut question = DnsQuestion::new(DnsName::root(), QueryType::UNKNOWN(0));
+            question.read(buf)?;
+            res.questions.push(question);
+        }
+        for _ in 0..res.header.answers {
+            let rec = DnsRecord::read(buf)?;
+            res.answers.push(rec);
+        }
+        for _ in 0..res.header.authoritative_entries {
+            let rec = DnsRecord::read(buf)?;
+            res.authorities.push(rec);
+        }
+        for _ in 0..res.header.resource_entries {
+            let rec = DnsRecord::read(buf)?;
+            res.resources.push(rec);
+        }
+
+        Ok(res)
+    }
+
+    pub fn write(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        self.header.questions = self.questions.len() as u16;
+        self.header.answers = self.answers.len() as u16;
+        self.header.authoritative_entries = self.authorities.len() as u16;
+        self.header.resource_entries = self.resources.len() as u16;
+
+        self.header.write(buffer)?;
+
+        for question in &self.questions {
+            question.write(buffer)?;
+        }
+        for rec in &self.answers {
+            rec.write(buffer)?;
+        }
+        for rec in &self.authorities {
+            rec.write(buffer)?;
+        }
+        for rec in &self.resources {
+            rec.write(buffer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the packet into `buffer`, leaving out the records that don't fit the rest of it:
+    /// answers are kept before authorities and those before additional records, each section in
+    /// order, with the OPT record always kept. Leaving out answers or authorities sets the TC flag
+    /// so the client asks again over TCP, records of the additional section are left out without
+    /// it, RFC 2181 section 9.
+    pub fn write_truncated(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        let start = buffer.pos();
+        match self.write(buffer) {
+            Err(DnsError::BufferOverrun) => buffer.seek(start)?,
+            result => return result,
+        }
+
+        // Names are written without compression, so every record takes the same room wherever
+        // it ends up
+        let mut scratch = BytePacketBuffer::with_len(TCP_MAX_LEN);
+        for question in &self.questions {
+            question.write(&mut scratch)?;
+        }
+        for rec in self.resources.iter().filter(|rec| is_opt(rec)) {
+            rec.write(&mut scratch)?;
+        }
+        let room = buffer.buf.len().saturating_sub(start);
+        let mut used = 12 + scratch.pos();
+        let mut fits = |records: &[DnsRecord]| -> Result<usize> {
+            let mut kept = 0;
+            for rec in records {
+                scratch.seek(0)?;
+                used += rec.write(&mut scratch)?;
+                if used > room {
+                    break;
+                }
+                kept += 1;
+            }
+            Ok(kept)
+        };
+
+        let answers = fits(&self.answers)?;
+        let authorities = if answers == self.answers.len() {
+            fits(&self.authorities)?
+        } else {
+            0
+        };
+        let complete = answers == self.answers.len() && authorities == self.authorities.len();
+        let additional: Vec<_> = self
+            .resources
+            .iter()
+            .filter(|rec| !is_opt(rec))
+            .cloned()
+            .collect();
+        let additional = if complete { fits(&additional)? } else { 0 };
+
+        self.header.truncated_message |= !complete;
+        self.answers.truncate(answers);
+        self.authorities.truncate(authorities);
+        let mut kept = 0;
+        self.resources.retain(|rec| {
+            kept += usize::from(!is_opt(rec));
+            is_opt(rec) || kept <= additional
+        });
+
+        self.write(buffer)
+    }
+
+    /// The addresses of the A records in the answer section
+    pub fn a_records(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.answers.iter().filter_map(|rec| match rec.rdata {
+            RData::A { addr } => Some(addr),
+            _ => None,
+        })
+    }
+
+    /// (zone, host) pairs for the NS records in the authority section of zones `qname` is at or
+    /// below, as in a referral
+    pub fn ns<'a>(
+        &'a self,
+        qname: &'a DnsName,
+    ) -> impl Iterator<Item = (&'a DnsName, &'a DnsName)> + 'a {
+        self.authorities
+            .iter()
+            .filter_map(|rec| match &rec.rdata {
+                RData::NS { host } => Some((&rec.name, host)),
+                _ => None,
+            })
+            .filter(move |(domain, _)| qname.is_subdomain_of(domain))
+    }
+
+    /// The address of a nameserver for `qname` that the additional section carries glue for
+    pub fn resolved_ns(&self, qname: &DnsName) -> Option<IpAddr> {
+        self.ns(qname).find_map(|(_, host)| self.glue(host))
+    }
+
+    /// The hostname of a nameserver for `qname`, for when there is no glue for any of them
+    pub fn unresolved_ns<'a>(&'a self, qname: &'a DnsName) -> Option<&'a DnsName> {
+        self.ns(qname).map(|(_, host)| host).next()
+    }
+
+    /// The address of `host` in the additional section, IPv4 if there is one
+    pub fn glue(&self, host: &DnsName) -> Option<IpAddr> {
+        let mut glue = self.resources.iter().filter(|rec| rec.name == *host);
+        glue.clone()
+            .find_map(|rec| match rec.rdata {
+                RData::A { addr } => Some(IpAddr::V4(addr)),
+                _ => None,
+            })
+            .or_else(|| {
+                glue.find_map(|rec| match rec.rdata {
+                    RData::AAAA { addr } => Some(IpAddr::V6(addr)),
+                    _ => None,
+                })
+            })
+    }
+
+    /// One of the addresses of the A records in the answer section, picked at random so load
+    /// spreads over all of them
+    #[cfg(feature = "net")]
+    pub fn random_a(&self) -> Option<Ipv4Addr> {
+        let addrs: Vec<_> = self.a_records().collect();
+        addrs.choose(&mut rand::rng()).copied()
+    }
+
+    /// One of the addresses of the A and AAAA records in the answer section, picked at random
+    #[cfg(feature = "net")]
+    pub fn random_address(&self) -> Option<IpAddr> {
+        let addrs: Vec<_> = self
+            .answers
+            .iter()
+            .filter_map(|rec| match rec.rdata {
+                RData::A { addr } => Some(IpAddr::V4(addr)),
+                RData::AAAA { addr } => Some(IpAddr::V6(addr)),
+                _ => None,
+            })
+            .collect();
+        addrs.choose(&mut rand::rng()).copied()
+    }
+
+    /// The NS records of [`DnsPacket::ns`] that refer `qname` to a zone below `zone`
+    pub fn ns_below<'a>(
+        &'a self,
+        qname: &'a DnsName,
+        zone: &'a DnsName,
+    ) -> impl Iterator<Item = (&'a DnsName, &'a DnsName)> {
+        self.ns(qname)
+            .filter(move |(domain, _)| domain.is_subdomain_of(zone) && *domain != zone)
+    }
+
+    /// The zone and address of a nameserver referred to below `zone` that has glue
+    pub fn glued_referral<'a>(
+        &'a self,
+        qname: &'a DnsName,
+        zone: &'a DnsName,
+    ) -> Option<(&'a DnsName, IpAddr)> {
+        self.ns_below(qname, zone)
+            .find_map(|(cut, host)| Some((cut, self.glue(host)?)))
+    }
+
+    /// The zone and hostname of a nameserver referred to below `zone`, for when there's no glue
+    pub fn referral<'a>(
+        &'a self,
+        qname: &'a DnsName,
+        zone: &'a DnsName,
+    ) -> Option<(&'a DnsName, &'a DnsName)> {
+        self.ns_below(qname, zone).next()
+    }
+
+    /// The name the CNAME chain in the answer section leads to from the question, or `None`
+    /// when the question isn't an alias
+    pub fn cname_target(&self) -> Option<&DnsName> {
+        let mut name = &self.questions.first()?.name;
+        let mut target = None;
+        // Bound the walk by the number of answers so a CNAME loop can't spin forever
+        for _ in 0..self.answers.len() {
+            let host = self.answers.iter().find_map(|rec| match &rec.rdata {
+                RData::CNAME { host } if rec.name == *name => Some(host),
+                _ => None,
+            });
+            match host {
+                Some(host) => {
+                    name = host;
+                    target = Some(host);
+                }
+                None => break,
+            }
+        }
+
+        target
+    }
+
+    /// The OPT record of a message using EDNS
+    pub fn edns(&self) -> Option<&DnsRecord> {
+        self.resources
+            .iter()
+            .find(|rec| matches!(rec.rdata, RData::OPT { .. }))
+    }
+
+    /// Whether the sender asked for DNSSEC records by setting the DO bit
+    pub fn dnssec_ok(&self) -> bool {
+        self.edns()
+            .is_some_and(|opt| opt.ttl() & FLAG_DNSSEC_OK != 0)
+    }
+
+    /// The options of the OPT record with `code`
+    pub fn edns_options(&self, code: u16) -> impl Iterator<Item = &EdnsOption> {
+        let options = match self.edns() {
+            Some(DnsRecord {
+                rdata: RData::OPT { options },
+                ..
+            }) => options.as_slice(),
+            _ => &[],
+        };
+
+        options.iter().filter(move |option| option.code == code)
+    }
+
+    /// The EDNS Client Subnet option, if the message has a well-formed one
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        self.edns_options(OPTION_CLIENT_SUBNET)
+            .next()
+            .and_then(ClientSubnet::from_option)
+    }
+
+    /// The edns-tcp-keepalive option, if the message has a well-formed one
+    pub fn tcp_keepalive(&self) -> Option<TcpKeepalive> {
+        self.edns_options(OPTION_TCP_KEEPALIVE)
+            .next()
+            .and_then(TcpKeepalive::from_option)
+    }
+
+    /// The Extended DNS Errors of a response, the well-formed ones
+    pub fn extended_errors(&self) -> impl Iterator<Item = ExtendedError> + '_ {
+        self.edns_options(OPTION_EXTENDED_ERROR)
+            .filter_map(ExtendedError::from_option)
+    }
+
+    /// The identifier of the server that sent a response, if it was asked for with the NSID option
+    /// and the server has one
+    pub fn nsid(&self) -> Option<&[u8]> {
+        self.edns_options(OPTION_NSID)
+            .next()
+            .map(|option| option.data.as_slice())
+            .filter(|nsid| !nsid.is_empty())
+    }
+
+    /// Pad the message with the EDNS padding option to a multiple of `block_len` bytes, so its
+    /// size reveals less about its contents on an encrypted transport. Messages without an OPT
+    /// record are left alone, as are those that can't be padded without growing past the limit.
+    /// Blocks of 0 bytes are an error.
+    pub fn pad(&mut self, block_len: usize) -> Result<()> {
+        if block_len == 0 {
+            return Err(DnsError::Config(String::from(
+                "Can't pad to blocks of 0 bytes",
+            )));
+        }
+        let Some(options) = self.edns_options_mut() else {
+            return Ok(());
+        };
+        options.retain(|option| option.code != OPTION_PADDING);
+
+        let mut buffer = BytePacketBuffer::with_len(TCP_MAX_LEN);
+        self.write(&mut buffer)?;
+        // The option itself takes four bytes for its code and length
+        let unpadded = buffer.pos() + 4;
+        let padding = (block_len - unpadded % block_len) % block_len;
+        if unpadded + padding > TCP_MAX_LEN {
+            return Ok(());
+        }
+
+        if let Some(options) = self.edns_options_mut() {
+            options.push(EdnsOption {
+                code: OPTION_PADDING,
+                data: vec![0; padding],
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The options of the OPT record, for changing them
+    pub fn edns_options_mut(&mut self) -> Option<&mut Vec<EdnsOption>> {
+        self.resources
+            .iter_mut()
+            .find_map(|rec| match &mut rec.rdata {
+                RData::OPT { options } => Some(options),
+                _ => None,
+            })
+    }
+
+    /// Read a length-prefixed message from a TCP stream
+    #[cfg(feature = "std")]
+    pub fn read_from(stream: &mut impl Read) -> Result<Self> {
+        Self::from_buffer(&mut BytePacketBuffer::read_from(stream)?)
+    }
+
+    /// Write the packet to a TCP stream, prefixed with its length
+    #[cfg(feature = "std")]
+    pub fn write_to(&mut self, stream: &mut impl Write) -> Result<()> {
+        self.write_signed_to(stream, None)
+    }
+
+    /// Same as [`DnsPacket::write_to`], signing the message with TSIG when given a session
+    #[cfg(feature = "std")]
+    pub fn write_signed_to(
+        &mut self,
+        stream: &mut impl Write,
+        tsig: Option<&mut TsigSession<'_>>,
+    ) -> Result<()> {
+        self.write_signed(tsig)?.write_to(stream)
+    }
+
+    /// Write the packet into a buffer as large as TCP allows, signing the message with TSIG when
+    /// given a session
+    #[cfg(feature = "std")]
+    pub fn write_signed(&mut self, tsig: Option<&mut TsigSession<'_>>) -> Result<BytePacketBuffer> {
+        let mut buffer = BytePacketBuffer::with_len(TCP_MAX_LEN);
+        self.write(&mut buffer)?;
+        if let Some(tsig) = tsig {
+            tsig.sign(&mut buffer)?;
+        }
+
+        Ok(buffer)
+    }
+
+    /// The records of every section in zone file presentation format, one per line, so they can
+    /// be saved as a zone file
+    pub fn to_zone_text(&self) -> String {
+        let mut text = String::new();
+        for rec in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.resources)
+        {
+            let _ = writeln!(text, "{rec}");
+        }
+
+        text
+    }
+}
+
+/// A query being built with [`DnsPacket::query`]
+#[derive(Debug)]
+pub struct QueryBuilder {
+    name: Result<DnsName>,
+    qtype: QueryType,
+    class: u16,
+    header: DnsHeader,
+    opt: Option<DnsRecord>,
+}
+
+impl QueryBuilder {
+    pub const fn id(mut self, id: u16) -> Self {
+        self.header.id = id;
+        self
+    }
+
+    pub const fn recursion_desired(mut self, rd: bool) -> Self {
+        self.header.recursion_desired = rd;
+        self
+    }
+
+    pub const fn checking_disabled(mut self, cd: bool) -> Self {
+        self.header.checking_disabled = cd;
+        self
+    }
+
+    /// Ask in another class than the Internet
+    pub const fn class(mut self, class: u16) -> Self {
+        self.class = class;
+        self
+    }
+
+    /// Use EDNS, accepting responses of up to `packet_len` bytes over UDP
+    pub fn edns(mut self, packet_len: u16) -> Self {
+        self.opt_mut().class = packet_len;
+        self
+    }
+
+    /// Use EDNS with the DO bit, asking for DNSSEC records
+    pub fn dnssec_ok(mut self, dnssec_ok: bool) -> Self {
+        let opt = self.opt_mut();
+        if dnssec_ok {
+            opt.ttl |= FLAG_DNSSEC_OK;
+        } else {
+            opt.ttl &= !FLAG_DNSSEC_OK;
+        }
+        self
+    }
+
+    /// Use EDNS and send `option` along
+    pub fn option(mut self, option: EdnsOption) -> Self {
+        if let RData::OPT { options } = &mut self.opt_mut().rdata {
+            options.push(option);
+        }
+        self
+    }
+
+    /// The query, with the counts of the header set, or the error of an invalid name
+    pub fn build(self) -> Result<DnsPacket> {
+        let mut packet = DnsPacket::new();
+        packet.header = self.header;
+        packet.questions.push(DnsQuestion {
+            name: self.name?,
+            qtype: self.qtype,
+            class: self.class,
+        });
+        packet.resources.extend(self.opt);
+        packet.header.questions = 1;
+        packet.header.resource_entries = packet.resources.len() as u16;
+
+        Ok(packet)
+    }
+
+    /// The OPT record, added with the defaults of this server the first time
+    fn opt_mut(&mut self) -> &mut DnsRecord {
+        self.opt
+            .get_or_insert_with(|| opt_record(false, Vec::new()))
+    }
+}
+
+const fn is_opt(rec: &DnsRecord) -> bool {
+    matches!(rec.rdata, RData::OPT { .. })
+}
@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::error::{DnsError, Result};
+
+/// Ports whose traffic is taken for DNS messages: DNS, mDNS and LLMNR
+const PORTS: [u16; 3] = [53, 5353, 5355];
+
+/// Magic numbers at the start of pcap files, with microsecond or nanosecond timestamps
+const PCAP_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_NANOS: u32 = 0xa1b2_3c4d;
+
+/// pcapng block types
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const SIMPLE_PACKET: u32 = 3;
+const ENHANCED_PACKET: u32 = 6;
+/// Written in the byte order of the section, telling which one it is
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+/// Interface option with the resolution of the timestamps
+const OPTION_TSRESOL: u16 = 9;
+
+/// Link layers the packets can be captured on
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+/// A DNS message found in a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedMessage {
+    /// When the packet carrying the message, or its end for TCP, was captured, since the Unix
+    /// epoch
+    pub time: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub tcp: bool,
+    pub wire: Vec<u8>,
+}
+
+/// Whether `data` starts like a pcap or pcapng file
+pub fn is_capture(data: &[u8]) -> bool {
+    let Some(magic) = data.get(..4) else {
+        return false;
+    };
+    let magic = u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]);
+    [PCAP_MICROS, PCAP_NANOS, SECTION_HEADER].contains(&magic)
+        || [PCAP_MICROS, PCAP_NANOS].contains(&magic.swap_bytes())
+}
+
+/// The DNS messages sent over UDP and TCP in a pcap or pcapng capture, in the order they were
+/// captured. Messages over TCP are taken from the stream of each connection, skipping what was
+/// retransmitted and starting over after what wasn't captured.
+pub fn read_messages(data: &[u8]) -> Result<Vec<CapturedMessage>> {
+    let mut messages = Messages::default();
+    if data.get(..4) == Some(&SECTION_HEADER.to_le_bytes()) {
+        read_pcapng(data, &mut messages)?;
+    } else {
+        read_pcap(data, &mut messages)?;
+    }
+
+    Ok(messages.found)
+}
+
+/// Integers read in the byte order of the capture
+#[derive(Debug, Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn u16(&self, pos: usize) -> Result<u16> {
+        let bytes = self.bytes(pos, 2)?;
+        let bytes = [bytes[0], bytes[1]];
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Result<u32> {
+        let bytes = self.bytes(pos, 4)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn bytes(&self, pos: usize, len: usize) -> Result<&[u8]> {
+        self.data
+            .get(pos..pos.saturating_add(len))
+            .ok_or_else(|| invalid("file ends in the middle of a packet"))
+    }
+}
+
+fn read_pcap(data: &[u8], messages: &mut Messages) -> Result<()> {
+    let magic = data
+        .get(..4)
+        .map(|magic| u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]))
+        .ok_or_else(|| invalid("not a pcap file"))?;
+    let big_endian = magic.swap_bytes() == PCAP_MICROS || magic.swap_bytes() == PCAP_NANOS;
+    let nanos = if big_endian {
+        magic.swap_bytes() == PCAP_NANOS
+    } else if magic == PCAP_NANOS || magic == PCAP_MICROS {
+        magic == PCAP_NANOS
+    } else {
+        return Err(invalid("not a pcap file"));
+    };
+    let reader = Reader { data, big_endian };
+    // The upper bits hold the length of the FCS some captures add
+    let linktype = reader.u32(20)? & 0x0fff_ffff;
+
+    let mut pos = 24;
+    while pos < data.len() {
+        let secs = reader.u32(pos)?;
+        let frac = reader.u32(pos + 4)?;
+        let captured = reader.u32(pos + 8)? as usize;
+        let packet = reader.bytes(pos + 16, captured)?;
+        let time = if nanos {
+            Duration::new(secs.into(), frac)
+        } else {
+            Duration::from_secs(secs.into()) + Duration::from_micros(frac.into())
+        };
+
+        messages.link(linktype, time, packet);
+        pos += 16 + captured;
+    }
+
+    Ok(())
+}
+
+/// An interface packets of a pcapng section were captured on
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    linktype: u32,
+    /// Timestamp units per second
+    units: u64,
+}
+
+fn read_pcapng(data: &[u8], messages: &mut Messages) -> Result<()> {
+    let mut reader = Reader {
+        data,
+        big_endian: false,
+    };
+    let mut interfaces = Vec::new();
+
+    let mut pos = 0;
+    while pos < data.len() {
+        // The block type of section headers reads the same in both byte orders
+        if reader.u32(pos)? == SECTION_HEADER {
+            let magic = reader.u32(pos + 8)?;
+            reader.big_endian = if magic == BYTE_ORDER_MAGIC {
+                reader.big_endian
+            } else if magic.swap_bytes() == BYTE_ORDER_MAGIC {
+                !reader.big_endian
+            } else {
+                return Err(invalid("invalid pcapng byte order"));
+            };
+            interfaces.clear();
+        }
+
+        let kind = reader.u32(pos)?;
+        let len = reader.u32(pos + 4)? as usize;
+        if len < 12 {
+            return Err(invalid("pcapng block too short"));
+        }
+        let block = Reader {
+            data: reader.bytes(pos, len)?,
+            ..reader
+        };
+
+        match kind {
+            INTERFACE_DESCRIPTION => interfaces.push(Interface {
+                linktype: block.u16(8)?.into(),
+                units: timestamp_units(&block, len)?,
+            }),
+            ENHANCED_PACKET => {
+                let interface = interfaces
+                    .get(block.u32(8)? as usize)
+                    .ok_or_else(|| invalid("packet of an undescribed interface"))?;
+                let timestamp = u64::from(block.u32(12)?) << 32 | u64::from(block.u32(16)?);
+                let captured = block.u32(20)? as usize;
+                let packet = block.bytes(28, captured)?;
+                let nanos = u128::from(timestamp % interface.units) * 1_000_000_000
+                    / u128::from(interface.units);
+                let time = Duration::from_secs(timestamp / interface.units)
+                    + Duration::from_nanos(nanos as u64);
+                messages.link(interface.linktype, time, packet);
+            }
+            // Simple packets have no timestamp, and belong to the first interface
+            SIMPLE_PACKET => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| invalid("packet of an undescribed interface"))?;
+                let captured = (block.u32(8)? as usize).min(len.saturating_sub(16));
+                let packet = block.bytes(12, captured)?;
+                messages.link(interface.linktype, Duration::ZERO, packet);
+            }
+            _ => {}
+        }
+        pos += len;
+    }
+
+    Ok(())
+}
+
+/// Timestamp units per second of an interface, from its `if_tsresol` option: a power of 10, or of
+/// 2 with the top bit set. Microseconds without it.
+fn timestamp_units(block: &Reader<'_>, len: usize) -> Result<u64> {
+    let mut pos = 16;
+    // Options up to the trailing length of the block
+    while pos + 4 <= len - 4 {
+        let code = block.u16(pos)?;
+        let option_len = block.u16(pos + 2)? as usize;
+        if code == 0 {
+            break;
+        }
+        if code == OPTION_TSRESOL && option_len >= 1 {
+            let resolution = block.bytes(pos + 4, 1)?[0];
+            let exponent = u32::from(resolution & 0x7f);
+            let units = if resolution & 0x80 == 0 {
+                10_u64.checked_pow(exponent)
+            } else {
+                2_u64.checked_pow(exponent)
+            };
+            return units
+                .filter(|&units| units > 0)
+                .ok_or_else(|| invalid("unsupported timestamp resolution"));
+        }
+        // Options are padded to 32 bits
+        pos += 4 + option_len.next_multiple_of(4);
+    }
+
+    Ok(1_000_000)
+}
+
+/// One direction of a TCP connection
+type Flow = (SocketAddr, SocketAddr);
+
+/// The bytes of a TCP stream not yet taken as messages
+#[derive(Debug, Default)]
+struct Stream {
+    /// Sequence number of the next byte expected
+    next_seq: u32,
+    buf: Vec<u8>,
+}
+
+/// DNS messages found so far, and the state of the TCP streams carrying more
+#[derive(Debug, Default)]
+struct Messages {
+    found: Vec<CapturedMessage>,
+    streams: HashMap<Flow, Stream>,
+}
+
+impl Messages {
+    /// Take the IP packet out of a frame of the link layer
+    fn link(&mut self, linktype: u32, time: Duration, frame: &[u8]) {
+        let ip = match linktype {
+            LINKTYPE_ETHERNET => {
+                let mut pos = 12;
+                let mut ethertype = be_u16(frame, pos);
+                // 802.1Q tags, possibly stacked
+                while ethertype == Some(ETHERTYPE_VLAN) {
+                    pos += 4;
+                    ethertype = be_u16(frame, pos);
+                }
+                match ethertype {
+                    Some(ETHERTYPE_IPV4 | ETHERTYPE_IPV6) => frame.get(pos + 2..),
+                    _ => None,
+                }
+            }
+            // The address family of loopback captures, in the byte order of the capturing host
+            LINKTYPE_NULL => frame.get(4..),
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(frame),
+            LINKTYPE_LINUX_SLL => match be_u16(frame, 14) {
+                Some(ETHERTYPE_IPV4 | ETHERTYPE_IPV6) => frame.get(16..),
+                _ => None,
+            },
+            LINKTYPE_LINUX_SLL2 => match be_u16(frame, 0) {
+                Some(ETHERTYPE_IPV4 | ETHERTYPE_IPV6) => frame.get(20..),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some(ip) = ip {
+            self.ip(time, ip);
+        }
+    }
+
+    /// Take the UDP datagram or TCP segment out of an IPv4 or IPv6 packet. Fragments and IPv6
+    /// extension headers are skipped.
+    fn ip(&mut self, time: Duration, packet: &[u8]) {
+        let parsed = match packet.first().map(|first| first >> 4) {
+            Some(4) => ipv4(packet),
+            Some(6) => ipv6(packet),
+            _ => None,
+        };
+        let Some((protocol, src, dst, payload)) = parsed else {
+            return;
+        };
+
+        match protocol {
+            PROTOCOL_UDP => self.udp(time, src, dst, payload),
+            PROTOCOL_TCP => self.tcp(time, src, dst, payload),
+            _ => {}
+        }
+    }
+
+    fn udp(&mut self, time: Duration, src: IpAddr, dst: IpAddr, datagram: &[u8]) {
+        let (Some(src_port), Some(dst_port), Some(payload)) =
+            (be_u16(datagram, 0), be_u16(datagram, 2), datagram.get(8..))
+        else {
+            return;
+        };
+        if !PORTS.contains(&src_port) && !PORTS.contains(&dst_port) {
+            return;
+        }
+
+        self.found.push(CapturedMessage {
+            time,
+            src: SocketAddr::new(src, src_port),
+            dst: SocketAddr::new(dst, dst_port),
+            tcp: false,
+            wire: payload.to_vec(),
+        });
+    }
+
+    /// Add a segment to the stream of its connection, then take every complete message from it,
+    /// each prefixed with its length
+    fn tcp(&mut self, time: Duration, src: IpAddr, dst: IpAddr, segment: &[u8]) {
+        let Some((src_port, dst_port, seq, flags, payload)) = tcp_segment(segment) else {
+            return;
+        };
+        if !PORTS.contains(&src_port) && !PORTS.contains(&dst_port) {
+            return;
+        }
+        let src = SocketAddr::new(src, src_port);
+        let dst = SocketAddr::new(dst, dst_port);
+
+        const SYN: u8 = 0x02;
+        const FIN: u8 = 0x01;
+        const RST: u8 = 0x04;
+        if flags & SYN != 0 {
+            // The SYN takes up a sequence number of its own
+            self.streams.insert(
+                (src, dst),
+                Stream {
+                    next_seq: seq.wrapping_add(1),
+                    buf: Vec::new(),
+                },
+            );
+        }
+        if payload.is_empty() {
+            if flags & (FIN | RST) != 0 {
+                self.streams.remove(&(src, dst));
+            }
+            return;
+        }
+
+        let stream = self.streams.entry((src, dst)).or_insert_with(|| Stream {
+            next_seq: seq,
+            buf: Vec::new(),
+        });
+        // How far the segment starts past the next expected byte, negative for retransmissions
+        let ahead = seq.wrapping_sub(stream.next_seq) as i32;
+        if ahead > 0 {
+            // Bytes weren't captured, so whatever was buffered can't be completed
+            stream.buf.clear();
+            stream.buf.extend_from_slice(payload);
+        } else if let Some(new) = payload.get(ahead.unsigned_abs() as usize..) {
+            stream.buf.extend_from_slice(new);
+        }
+        let end = seq.wrapping_add(payload.len() as u32);
+        if end.wrapping_sub(stream.next_seq) as i32 > 0 {
+            stream.next_seq = end;
+        }
+
+        while let Some(len) = be_u16(&stream.buf, 0).map(usize::from) {
+            if stream.buf.len() < 2 + len {
+                break;
+            }
+            let wire = stream.buf[2..2 + len].to_vec();
+            stream.buf.drain(..2 + len);
+            self.found.push(CapturedMessage {
+                time,
+                src,
+                dst,
+                tcp: true,
+                wire,
+            });
+        }
+
+        if flags & (FIN | RST) != 0 {
+            self.streams.remove(&(src, dst));
+        }
+    }
+}
+
+/// The protocol, addresses and payload of an IPv4 packet that isn't a fragment
+fn ipv4(packet: &[u8]) -> Option<(u8, IpAddr, IpAddr, &[u8])> {
+    let header_len = usize::from(packet.first()? & 0x0f) * 4;
+    let total_len = usize::from(be_u16(packet, 2)?);
+    // More fragments, or an offset
+    if be_u16(packet, 6)? & 0x3fff != 0 {
+        return None;
+    }
+    let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+    let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+    let payload = packet.get(header_len..total_len.min(packet.len()))?;
+
+    Some((*packet.get(9)?, src.into(), dst.into(), payload))
+}
+
+/// The next header, addresses and payload of an IPv6 packet
+fn ipv6(packet: &[u8]) -> Option<(u8, IpAddr, IpAddr, &[u8])> {
+    let payload_len = usize::from(be_u16(packet, 4)?);
+    let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+    let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+    let payload = packet.get(40..(40 + payload_len).min(packet.len()))?;
+
+    Some((*packet.get(6)?, src.into(), dst.into(), payload))
+}
+
+/// The ports, sequence number, flags and payload of a TCP segment
+fn tcp_segment(segment: &[u8]) -> Option<(u16, u16, u32, u8, &[u8])> {
+    let offset = usize::from(*segment.get(12)? >> 4) * 4;
+    Some((
+        be_u16(segment, 0)?,
+        be_u16(segment, 2)?,
+        u32::from_be_bytes(segment.get(4..8)?.try_into().ok()?),
+        *segment.get(13)?,
+        segment.get(offset..)?,
+    ))
+}
+
+/// A big-endian integer as found in network headers
+fn be_u16(data: &[u8], pos: usize) -> Option<u16> {
+    let bytes = data.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn invalid(message: &str) -> DnsError {
+    DnsError::Capture(message.to_string())
+}
@@ -0,0 +1,72 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How many idle connections are kept to each server
+const MAX_IDLE: usize = 4;
+
+/// How long before the server closes an idle connection it stops being reused, so the server
+/// doesn't close it while a query is on the way
+const MARGIN: Duration = Duration::from_millis(500);
+
+/// Connections to servers that said how long they keep them open while idle with the
+/// edns-tcp-keepalive option, RFC 7828, kept for later queries instead of connecting for each one
+pub struct ConnectionPool<K, S> {
+    idle: Mutex<Vec<Idle<K, S>>>,
+}
+
+struct Idle<K, S> {
+    key: K,
+    stream: S,
+    expires: Instant,
+}
+
+impl<K: PartialEq, S> ConnectionPool<K, S> {
+    pub const fn new() -> Self {
+        Self {
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// An idle connection to `key` that the server still keeps open, the one used last first
+    pub fn take(&self, key: &K) -> Option<S> {
+        let mut idle = self.lock();
+        let now = Instant::now();
+        idle.retain(|conn| conn.expires > now);
+        let index = idle.iter().rposition(|conn| conn.key == *key)?;
+
+        Some(idle.remove(index).stream)
+    }
+
+    /// Keep `stream` for the `timeout` the server said it keeps it open while idle. Connections
+    /// the server closes right away are dropped, as is the oldest one to the same server when there
+    /// are too many.
+    pub fn put(&self, key: K, stream: S, timeout: Duration) {
+        let timeout = timeout.saturating_sub(MARGIN);
+        if timeout.is_zero() {
+            return;
+        }
+
+        let mut idle = self.lock();
+        if idle.iter().filter(|conn| conn.key == key).count() >= MAX_IDLE {
+            if let Some(oldest) = idle.iter().position(|conn| conn.key == key) {
+                idle.remove(oldest);
+            }
+        }
+        idle.push(Idle {
+            key,
+            stream,
+            expires: Instant::now() + timeout,
+        });
+    }
+
+    /// Connections are only ever added or removed whole, so poisoning is ignored
+    fn lock(&self) -> MutexGuard<'_, Vec<Idle<K, S>>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<K: PartialEq, S> Default for ConnectionPool<K, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,279 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Serializer};
+use tracing::warn;
+
+use crate::config::{QueryLogConfig, QueryLogFormat};
+use crate::error::{FailureReason, Result};
+use crate::network::Network;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+/// Leading bits of client addresses kept when they are anonymized
+const ANONYMIZED_IPV4_PREFIX: u8 = 24;
+const ANONYMIZED_IPV6_PREFIX: u8 = 48;
+
+/// How a query was answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// From a view, the local records, the balanced names, the zones, the hosts file or the
+    /// plugged in resolver
+    Local,
+    /// By the blocklist
+    Blocked,
+    /// By a response policy zone
+    Policy,
+    /// By an upstream
+    Forwarded,
+    /// From an earlier answer of an upstream
+    Cached,
+    /// With SERVFAIL, for this reason
+    Failed(FailureReason),
+    /// Refused without looking the name up, by the access control lists
+    Refused,
+    /// Answered with FORMERR without looking the name up
+    Malformed,
+    /// Not at all
+    Dropped,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Local => "local",
+            Self::Blocked => "blocked",
+            Self::Policy => "policy",
+            Self::Forwarded => "forwarded",
+            Self::Cached => "cached",
+            Self::Failed(_) => "failed",
+            Self::Refused => "refused",
+            Self::Malformed => "malformed",
+            Self::Dropped => "dropped",
+        };
+        f.write_str(status)
+    }
+}
+
+impl Status {
+    /// Why the query failed, if it did
+    pub const fn reason(self) -> Option<FailureReason> {
+        match self {
+            Self::Failed(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// Written the way it is displayed, with the reason of failures in a field of its own
+impl Serialize for Status {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// One line of the log
+#[derive(Debug, Serialize)]
+struct Entry {
+    timestamp: String,
+    client: IpAddr,
+    qname: String,
+    qtype: String,
+    /// Missing when the query was dropped
+    rcode: Option<String>,
+    status: Status,
+    /// Why the query failed, only there when it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// The answer section, each record as its type and data
+    answers: Vec<String>,
+    latency_ms: f64,
+}
+
+/// The open log file, with what decides when it is rotated
+#[derive(Debug)]
+struct LogFile {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+/// A log of every client query, one line each, rotated by size or age
+#[derive(Debug)]
+pub struct QueryLog {
+    config: QueryLogConfig,
+    file: Mutex<LogFile>,
+}
+
+impl QueryLog {
+    /// Open the log file for appending, creating it if needed
+    pub fn open(config: QueryLogConfig) -> Result<Self> {
+        let file = open(&config.file)?;
+
+        Ok(Self {
+            config,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Log a query from `client` with its response, or `None` if it was dropped. A log that
+    /// can't be written is reported without failing the query.
+    pub fn log(
+        &self,
+        client: IpAddr,
+        question: &DnsQuestion,
+        response: Option<&DnsPacket>,
+        status: Status,
+        latency: Duration,
+    ) {
+        let client = if self.config.anonymize {
+            anonymize(client.to_canonical())
+        } else {
+            client.to_canonical()
+        };
+        let entry = Entry {
+            timestamp: timestamp(SystemTime::now()),
+            client,
+            qname: question.name.to_lowercase().to_string(),
+            qtype: question.qtype.to_string(),
+            rcode: response.map(|response| format!("{:?}", response.header.rescode)),
+            status,
+            reason: status.reason().map(|reason| reason.to_string()),
+            answers: response
+                .map(|response| {
+                    response
+                        .answers
+                        .iter()
+                        .map(|rec| format!("{} {}", rec.qtype(), rec.display_rdata()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            latency_ms: latency.as_secs_f64() * 1000.0,
+        };
+
+        let mut line = match self.config.format {
+            // Failed queries have no answers, the reason takes their place
+            QueryLogFormat::Text => format!(
+                "{} {} {} {} {} {} {:.1}ms {}",
+                entry.timestamp,
+                entry.client,
+                entry.qname,
+                entry.qtype,
+                entry.rcode.as_deref().unwrap_or("-"),
+                entry.status,
+                entry.latency_ms,
+                entry
+                    .reason
+                    .clone()
+                    .unwrap_or_else(|| entry.answers.join(", ")),
+            )
+            .trim_end()
+            .to_string(),
+            QueryLogFormat::Json => match serde_json::to_string(&entry) {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Failed to encode query log entry: {e}");
+                    return;
+                }
+            },
+        };
+        line.push('\n');
+
+        if let Err(e) = self.write(line.as_bytes()) {
+            warn!(
+                "Failed to write query log {}: {e}",
+                self.config.file.display()
+            );
+        }
+    }
+
+    /// Append a line, first rotating the file if it grew too large or old
+    fn write(&self, line: &[u8]) -> std::io::Result<()> {
+        let mut log = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let too_large =
+            self.config.max_size > 0 && log.size + line.len() as u64 > self.config.max_size;
+        let too_old = self.config.rotate_interval > 0
+            && log.opened.elapsed() >= Duration::from_secs(self.config.rotate_interval);
+        if (too_large && log.size > 0) || too_old {
+            rotate(&self.config.file, self.config.keep)?;
+            *log = open(&self.config.file)?;
+        }
+
+        log.file.write_all(line)?;
+        log.size += line.len() as u64;
+
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> std::io::Result<LogFile> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+
+    Ok(LogFile {
+        file,
+        size,
+        opened: Instant::now(),
+    })
+}
+
+/// Shift `path` to `path.1`, `path.1` to `path.2` and so on, dropping the ones past `keep`
+fn rotate(path: &Path, keep: usize) -> std::io::Result<()> {
+    let rotated = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    // The oldest may not exist yet
+    let _ = fs::remove_file(rotated(keep));
+    for n in (1..keep).rev() {
+        let _ = fs::rename(rotated(n), rotated(n + 1));
+    }
+
+    fs::rename(path, rotated(1))
+}
+
+/// The network of `client`, so the log can't tell hosts apart
+fn anonymize(client: IpAddr) -> IpAddr {
+    let prefix = match client {
+        IpAddr::V4(_) => ANONYMIZED_IPV4_PREFIX,
+        IpAddr::V6(_) => ANONYMIZED_IPV6_PREFIX,
+    };
+
+    Network::new(client, prefix).addr()
+}
+
+/// `time` in RFC 3339 format in UTC with milliseconds, like `2024-05-01T12:30:00.125Z`
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Days to a civil date, from Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
@@ -0,0 +1,164 @@
+use alloc::string::ToString;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::buffer::BytePacketBuffer;
+use crate::error::{DnsError, Result};
+use crate::name::DnsName;
+
+/// The Internet class, the only one served
+pub const CLASS_IN: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[allow(clippy::upper_case_acronyms)]
+pub enum QueryType {
+    UNKNOWN(u16),
+    A,      // 1
+    NS,     // 2
+    CNAME,  // 5
+    SOA,    // 6
+    PTR,    // 12
+    HINFO,  // 13
+    MX,     // 15
+    TXT,    // 16
+    AAAA,   // 28
+    SRV,    // 33
+    OPT,    // 41
+    RRSIG,  // 46
+    NSEC,   // 47
+    DNSKEY, // 48
+    IXFR,   // 251
+    AXFR,   // 252
+    ANY,    // 255
+}
+
+impl From<u16> for QueryType {
+    fn from(n: u16) -> Self {
+        match n {
+            1 => Self::A,
+            2 => Self::NS,
+            5 => Self::CNAME,
+            6 => Self::SOA,
+            12 => Self::PTR,
+            13 => Self::HINFO,
+            15 => Self::MX,
+            16 => Self::TXT,
+            28 => Self::AAAA,
+            33 => Self::SRV,
+            41 => Self::OPT,
+            46 => Self::RRSIG,
+            47 => Self::NSEC,
+            48 => Self::DNSKEY,
+            251 => Self::IXFR,
+            252 => Self::AXFR,
+            255 => Self::ANY,
+            _ => Self::UNKNOWN(n),
+        }
+    }
+}
+
+impl From<QueryType> for u16 {
+    fn from(t: QueryType) -> Self {
+        match t {
+            QueryType::A => 1,
+            QueryType::NS => 2,
+            QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::HINFO => 13,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::OPT => 41,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
+            QueryType::ANY => 255,
+            QueryType::UNKNOWN(n) => n,
+        }
+    }
+}
+
+impl FromStr for QueryType {
+    type Err = DnsError;
+
+    /// Parse a type mnemonic like `A` or `cname`, or the generic `TYPE<n>` form from RFC 3597
+    fn from_str(s: &str) -> Result<Self> {
+        let upper = s.to_ascii_uppercase();
+        match upper.as_str() {
+            "A" => Ok(Self::A),
+            "NS" => Ok(Self::NS),
+            "CNAME" => Ok(Self::CNAME),
+            "SOA" => Ok(Self::SOA),
+            "PTR" => Ok(Self::PTR),
+            "HINFO" => Ok(Self::HINFO),
+            "MX" => Ok(Self::MX),
+            "TXT" => Ok(Self::TXT),
+            "AAAA" => Ok(Self::AAAA),
+            "SRV" => Ok(Self::SRV),
+            "OPT" => Ok(Self::OPT),
+            "RRSIG" => Ok(Self::RRSIG),
+            "NSEC" => Ok(Self::NSEC),
+            "DNSKEY" => Ok(Self::DNSKEY),
+            "IXFR" => Ok(Self::IXFR),
+            "AXFR" => Ok(Self::AXFR),
+            "ANY" => Ok(Self::ANY),
+            _ => upper
+                .strip_prefix("TYPE")
+                .and_then(|n| n.parse::<u16>().ok())
+                .map(Self::from)
+                .ok_or_else(|| DnsError::UnsupportedType(s.to_string())),
+        }
+    }
+}
+
+/// The type mnemonic, or the generic `TYPE<n>` form for unknown types
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UNKNOWN(n) => write!(f, "TYPE{n}"),
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DnsQuestion {
+    pub name: DnsName,
+    pub qtype: QueryType,
+    pub class: u16,
+}
+
+impl DnsQuestion {
+    /// A question in the Internet class
+    pub const fn new(name: DnsName, qtype: QueryType) -> Self {
+        Self {
+            name,
+            qtype,
+            class: CLASS_IN,
+        }
+    }
+
+    pub fn read(&mut self, buf: &mut BytePacketBuffer) -> Result<()> {
+        self.name = buf.read_name()?;
+        self.qtype = QueryType::from(buf.read_u16()?); // qtype
+        self.class = buf.read_u16()?;
+
+        Ok(())
+    }
+
+    pub fn write(&self, buf: &mut BytePacketBuffer) -> Result<()> {
+        buf.write_qname(&self.name)?;
+        buf.write_u16(self.qtype.into())?;
+        buf.write_u16(self.class)?;
+
+        Ok(())
+    }
+}
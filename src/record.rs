@@ -0,0 +1,774 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{Ipv4Addr, Ipv6Addr};
+#[cfg(feature = "std")]
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use crate::buffer::BytePacketBuffer;
+use crate::edns::EdnsOption;
+use crate::error::{DnsError, Result};
+use crate::name::DnsName;
+use crate::question::{QueryType, CLASS_IN};
+#[cfg(feature = "std")]
+use crate::ttl::Ttl;
+#[cfg(feature = "std")]
+use crate::zone;
+
+/// A resource record: the name that owns it, its class and TTL, and the data of its type
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DnsRecord {
+    pub name: DnsName,
+    /// The Internet class for everything but OPT, which keeps the largest UDP payload the sender
+    /// can receive here
+    pub class: u16,
+    /// Seconds the record may be cached, or the extended rcode, EDNS version and flags like DO
+    /// for OPT
+    pub ttl: u32,
+    pub rdata: RData,
+}
+
+/// The data of a record, which decides its type
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RData {
+    /// A type that isn't modeled, kept as it was on the wire so it can be passed on intact,
+    /// RFC 3597
+    UNKNOWN {
+        qtype: u16,
+        data: Vec<u8>,
+    }, // 0
+    A {
+        addr: Ipv4Addr,
+    }, // 1
+    NS {
+        host: DnsName,
+    }, // 2
+    CNAME {
+        host: DnsName,
+    }, // 5
+    SOA {
+        m_name: DnsName,
+        r_name: DnsName,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    }, // 6
+    PTR {
+        host: DnsName,
+    }, // 12
+    /// What a host runs, nowadays only answered to ANY queries, RFC 8482
+    HINFO {
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    }, // 13
+    MX {
+        priority: u16,
+        host: DnsName,
+    }, // 15
+    /// Character strings are octets, which are usually but not always text
+    TXT {
+        data: Vec<Vec<u8>>,
+    }, // 16
+    AAAA {
+        addr: Ipv6Addr,
+    }, // 28
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: DnsName,
+    }, // 33
+    /// The EDNS pseudo-record, whose class and TTL fields are used for its own purposes
+    OPT {
+        options: Vec<EdnsOption>,
+    }, // 41
+    RRSIG {
+        type_covered: QueryType,
+        algorithm: u8,
+        /// Labels of the owner name, not counting a leading `*` of a wildcard
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: DnsName,
+        signature: Vec<u8>,
+    }, // 46
+    NSEC {
+        /// The next owner name of the zone in canonical order
+        next: DnsName,
+        /// The types present at the owner name
+        types: Vec<QueryType>,
+    }, // 47
+    DNSKEY {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    }, // 48
+}
+
+impl DnsRecord {
+    /// A record in the Internet class
+    pub const fn new(name: DnsName, ttl: u32, rdata: RData) -> Self {
+        Self {
+            name,
+            class: CLASS_IN,
+            ttl,
+            rdata,
+        }
+    }
+
+    pub fn read(buf: &mut BytePacketBuffer) -> Result<Self> {
+        let name = buf.read_name()?;
+        let qtype = buf.read_u16()?;
+        let class = buf.read_u16()?;
+        let ttl = buf.read_u32()?;
+        let data_len = buf.read_u16()?;
+        let rdata = RData::read(buf, qtype, data_len)?;
+
+        Ok(Self {
+            name,
+            class,
+            ttl,
+            rdata,
+        })
+    }
+
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<usize> {
+        let start_pos = buffer.pos();
+
+        buffer.write_qname(&self.name)?;
+        buffer.write_u16(self.rdata.type_number())?;
+        buffer.write_u16(self.class)?;
+        buffer.write_u32(self.ttl)?;
+
+        let pos = buffer.pos();
+        buffer.write_u16(0)?;
+        self.rdata.write(buffer)?;
+
+        let size = buffer.pos() - (pos + 2);
+        buffer.set_u16(pos, size as u16)?;
+
+        Ok(buffer.pos() - start_pos)
+    }
+
+    /// The type of the record
+    pub const fn qtype(&self) -> QueryType {
+        self.rdata.qtype()
+    }
+
+    /// Whether the record answers a question for `qtype`: one of its type, or ANY
+    pub fn answers(&self, qtype: QueryType) -> bool {
+        qtype == QueryType::ANY || self.qtype() == qtype
+    }
+
+    /// Parse a single record in master file format, like `www 300 IN CNAME host.example.com.`
+    ///
+    /// Relative names are completed with `origin`. The TTL is required since there is no `$TTL` to
+    /// fall back on, the class is optional.
+    #[cfg(feature = "std")]
+    pub fn parse_line(line: &str, origin: &DnsName) -> Result<Self> {
+        zone::parse_record(line, origin)
+    }
+
+    /// The TTL of the record in seconds
+    pub const fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    /// The TTL of the record counting down from `received`, when it arrived
+    #[cfg(feature = "std")]
+    pub fn ttl_from(&self, received: Instant) -> Ttl {
+        Ttl::starting_at(self.ttl, received)
+    }
+
+    /// Display only the rdata in presentation format, like `10 mail.example.com.` for an MX record
+    pub const fn display_rdata(&self) -> &RData {
+        &self.rdata
+    }
+
+    /// The owner name of the record
+    pub const fn domain(&self) -> &DnsName {
+        &self.name
+    }
+
+    /// Change the owner name of the record, e.g. when synthesizing it from a wildcard
+    pub fn set_domain(&mut self, name: DnsName) {
+        self.name = name;
+    }
+
+    /// Change the TTL of the record, e.g. when comparing records regardless of it
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl;
+    }
+}
+
+impl RData {
+    /// Read rdata of type `qtype` taking up `data_len` bytes
+    ///
+    /// # Errors
+    ///
+    /// [`DnsError::RdataLength`] when the fields of the type end before or after `data_len`,
+    /// which would leave the records after it misread.
+    pub fn read(buf: &mut BytePacketBuffer, qtype: u16, data_len: u16) -> Result<Self> {
+        let end = buf.pos() + data_len as usize;
+        let rdata = Self::read_fields(buf, qtype, data_len, end)?;
+        if buf.pos() != end {
+            return Err(DnsError::RdataLength(data_len));
+        }
+
+        Ok(rdata)
+    }
+
+    /// The fields of rdata of type `qtype`, which should end at `end`
+    fn read_fields(
+        buf: &mut BytePacketBuffer,
+        qtype: u16,
+        data_len: u16,
+        end: usize,
+    ) -> Result<Self> {
+        match QueryType::from(qtype) {
+            QueryType::A => {
+                let raw_addr = buf.read_u32()?;
+
+                Ok(Self::A {
+                    addr: Ipv4Addr::from(raw_addr),
+                })
+            }
+            QueryType::NS => Ok(Self::NS {
+                host: buf.read_name()?,
+            }),
+            QueryType::CNAME => Ok(Self::CNAME {
+                host: buf.read_name()?,
+            }),
+            QueryType::SOA => {
+                let m_name = buf.read_name()?;
+                let r_name = buf.read_name()?;
+                let serial = buf.read_u32()?;
+                let refresh = buf.read_u32()?;
+                let retry = buf.read_u32()?;
+                let expire = buf.read_u32()?;
+                let minimum = buf.read_u32()?;
+
+                Ok(Self::SOA {
+                    m_name,
+                    r_name,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                })
+            }
+            QueryType::PTR => Ok(Self::PTR {
+                host: buf.read_name()?,
+            }),
+            QueryType::MX => {
+                let priority = buf.read_u16()?;
+                let host = buf.read_name()?;
+
+                Ok(Self::MX { priority, host })
+            }
+            QueryType::SRV => {
+                let priority = buf.read_u16()?;
+                let weight = buf.read_u16()?;
+                let port = buf.read_u16()?;
+                let host = buf.read_name()?;
+
+                Ok(Self::SRV {
+                    priority,
+                    weight,
+                    port,
+                    host,
+                })
+            }
+            QueryType::HINFO => {
+                let cpu = read_character_string(buf)?;
+                let os = read_character_string(buf)?;
+
+                Ok(Self::HINFO { cpu, os })
+            }
+            QueryType::TXT => {
+                // A sequence of character strings filling the whole rdata
+                let mut data = Vec::new();
+                while buf.pos() < end {
+                    data.push(read_character_string(buf)?);
+                }
+
+                Ok(Self::TXT { data })
+            }
+            QueryType::AAAA => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(buf.read_range(16)?);
+
+                Ok(Self::AAAA {
+                    addr: Ipv6Addr::from(octets),
+                })
+            }
+            QueryType::OPT => {
+                let mut options = Vec::new();
+                while buf.pos() < end {
+                    let code = buf.read_u16()?;
+                    let len = buf.read_u16()?;
+                    let data = buf.read_range(len as usize)?.to_vec();
+                    options.push(EdnsOption { code, data });
+                }
+
+                Ok(Self::OPT { options })
+            }
+            QueryType::RRSIG => {
+                let type_covered = QueryType::from(buf.read_u16()?);
+                let algorithm = buf.read_range(1)?[0];
+                let labels = buf.read_range(1)?[0];
+                let original_ttl = buf.read_u32()?;
+                let expiration = buf.read_u32()?;
+                let inception = buf.read_u32()?;
+                let key_tag = buf.read_u16()?;
+                let signer = buf.read_name()?;
+                let len = end.checked_sub(buf.pos()).ok_or(DnsError::BufferOverrun)?;
+                let signature = buf.read_range(len)?.to_vec();
+
+                Ok(Self::RRSIG {
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer,
+                    signature,
+                })
+            }
+            QueryType::NSEC => {
+                let next = buf.read_name()?;
+                let len = end.checked_sub(buf.pos()).ok_or(DnsError::BufferOverrun)?;
+                let types = read_type_bitmap(buf.read_range(len)?)?;
+
+                Ok(Self::NSEC { next, types })
+            }
+            QueryType::DNSKEY => {
+                let flags = buf.read_u16()?;
+                let protocol = buf.read_range(1)?[0];
+                let algorithm = buf.read_range(1)?[0];
+                let len = (data_len as usize)
+                    .checked_sub(4)
+                    .ok_or(DnsError::BufferOverrun)?;
+                let public_key = buf.read_range(len)?.to_vec();
+
+                Ok(Self::DNSKEY {
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                })
+            }
+            // Meta types only appear in questions, keep anything else claiming them opaque
+            QueryType::UNKNOWN(_) | QueryType::IXFR | QueryType::AXFR | QueryType::ANY => {
+                let data = buf.read_range(data_len as usize)?.to_vec();
+
+                Ok(Self::UNKNOWN { qtype, data })
+            }
+        }
+    }
+
+    /// Write the rdata, without the length in front of it
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        match self {
+            Self::A { addr } => {
+                for b in addr.octets() {
+                    buffer.write_u8(b)?;
+                }
+            }
+            Self::NS { host } | Self::CNAME { host } | Self::PTR { host } => {
+                buffer.write_qname(host)?;
+            }
+            Self::SOA {
+                m_name,
+                r_name,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                buffer.write_qname(m_name)?;
+                buffer.write_qname(r_name)?;
+                buffer.write_u32(*serial)?;
+                buffer.write_u32(*refresh)?;
+                buffer.write_u32(*retry)?;
+                buffer.write_u32(*expire)?;
+                buffer.write_u32(*minimum)?;
+            }
+            Self::MX { priority, host } => {
+                buffer.write_u16(*priority)?;
+                buffer.write_qname(host)?;
+            }
+            Self::SRV {
+                priority,
+                weight,
+                port,
+                host,
+            } => {
+                buffer.write_u16(*priority)?;
+                buffer.write_u16(*weight)?;
+                buffer.write_u16(*port)?;
+                buffer.write_qname(host)?;
+            }
+            Self::TXT { data } => {
+                for s in data {
+                    write_character_string(buffer, s)?;
+                }
+            }
+            Self::HINFO { cpu, os } => {
+                write_character_string(buffer, cpu)?;
+                write_character_string(buffer, os)?;
+            }
+            Self::AAAA { addr } => {
+                for segment in addr.segments() {
+                    buffer.write_u16(segment)?;
+                }
+            }
+            Self::OPT { options } => {
+                for option in options {
+                    buffer.write_u16(option.code)?;
+                    buffer.write_u16(option.data.len() as u16)?;
+                    for &b in &option.data {
+                        buffer.write_u8(b)?;
+                    }
+                }
+            }
+            Self::RRSIG { .. } | Self::NSEC { .. } | Self::DNSKEY { .. } => {
+                for b in self.dnssec_rdata() {
+                    buffer.write_u8(b)?;
+                }
+            }
+            Self::UNKNOWN { data, .. } => {
+                for &b in data {
+                    buffer.write_u8(b)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The type of record the data is for
+    pub const fn qtype(&self) -> QueryType {
+        match self {
+            Self::UNKNOWN { qtype, .. } => QueryType::UNKNOWN(*qtype),
+            Self::A { .. } => QueryType::A,
+            Self::NS { .. } => QueryType::NS,
+            Self::CNAME { .. } => QueryType::CNAME,
+            Self::SOA { .. } => QueryType::SOA,
+            Self::PTR { .. } => QueryType::PTR,
+            Self::HINFO { .. } => QueryType::HINFO,
+            Self::MX { .. } => QueryType::MX,
+            Self::TXT { .. } => QueryType::TXT,
+            Self::AAAA { .. } => QueryType::AAAA,
+            Self::SRV { .. } => QueryType::SRV,
+            Self::OPT { .. } => QueryType::OPT,
+            Self::RRSIG { .. } => QueryType::RRSIG,
+            Self::NSEC { .. } => QueryType::NSEC,
+            Self::DNSKEY { .. } => QueryType::DNSKEY,
+        }
+    }
+
+    /// The type number on the wire, which unknown data keeps even if it claims a meta type
+    fn type_number(&self) -> u16 {
+        match self {
+            Self::UNKNOWN { qtype, .. } => *qtype,
+            _ => self.qtype().into(),
+        }
+    }
+
+    /// The rdata of a DNSSEC record in canonical form, without compression and with names in
+    /// lowercase, as it is both written and signed
+    pub(crate) fn dnssec_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        match self {
+            Self::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+            } => {
+                rdata.extend_from_slice(&u16::from(*type_covered).to_be_bytes());
+                rdata.push(*algorithm);
+                rdata.push(*labels);
+                rdata.extend_from_slice(&original_ttl.to_be_bytes());
+                rdata.extend_from_slice(&expiration.to_be_bytes());
+                rdata.extend_from_slice(&inception.to_be_bytes());
+                rdata.extend_from_slice(&key_tag.to_be_bytes());
+                rdata.extend(signer.canonical_wire());
+                rdata.extend_from_slice(signature);
+            }
+            Self::NSEC { next, types } => {
+                // The next name keeps its case on the wire, RFC 6840 section 5.1
+                for label in next.wire_labels() {
+                    rdata.push(label.len() as u8);
+                    rdata.extend_from_slice(&label);
+                }
+                rdata.push(0);
+                write_type_bitmap(types, &mut rdata);
+            }
+            Self::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                rdata.extend_from_slice(&flags.to_be_bytes());
+                rdata.push(*protocol);
+                rdata.push(*algorithm);
+                rdata.extend_from_slice(public_key);
+            }
+            _ => {}
+        }
+
+        rdata
+    }
+}
+
+/// Presentation format as used in zone files, e.g. `example.com. 3600 IN A 1.2.3.4`. The
+/// alternate form `{:#}` shows internationalized names in Unicode instead of punycode.
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_name(&self.name, f)?;
+        write!(f, "\t{}\t", self.ttl)?;
+        fmt_class(self.class, f)?;
+        write!(f, "\t{}\t", self.qtype())?;
+        self.rdata.fmt(f)
+    }
+}
+
+/// The mnemonic of a class, or the generic `CLASS<n>` form from RFC 3597 for classes without one
+fn fmt_class(class: u16, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match class {
+        CLASS_IN => f.write_str("IN"),
+        3 => f.write_str("CH"),
+        4 => f.write_str("HS"),
+        254 => f.write_str("NONE"),
+        255 => f.write_str("ANY"),
+        n => write!(f, "CLASS{n}"),
+    }
+}
+
+/// Presentation format of the rdata alone, like `10 mail.example.com.` for an MX record
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::A { addr } => write!(f, "{addr}"),
+            Self::AAAA { addr } => write!(f, "{addr}"),
+            Self::NS { host } | Self::CNAME { host } | Self::PTR { host } => fmt_name(host, f),
+            Self::MX { priority, host } => {
+                write!(f, "{priority} ")?;
+                fmt_name(host, f)
+            }
+            Self::SRV {
+                priority,
+                weight,
+                port,
+                host,
+            } => {
+                write!(f, "{priority} {weight} {port} ")?;
+                fmt_name(host, f)
+            }
+            Self::SOA {
+                m_name,
+                r_name,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                fmt_name(m_name, f)?;
+                f.write_str(" ")?;
+                fmt_name(r_name, f)?;
+                write!(f, " {serial} {refresh} {retry} {expire} {minimum}")
+            }
+            Self::HINFO { cpu, os } => {
+                fmt_character_string(cpu, f)?;
+                f.write_str(" ")?;
+                fmt_character_string(os, f)
+            }
+            Self::TXT { data } => {
+                for (i, s) in data.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    fmt_character_string(s, f)?;
+                }
+                Ok(())
+            }
+            Self::OPT { options } => {
+                for (i, option) in options.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{}:", option.code)?;
+                    for b in &option.data {
+                        write!(f, "{b:02x}")?;
+                    }
+                }
+                Ok(())
+            }
+            Self::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+            } => {
+                write!(
+                    f,
+                    "{type_covered} {algorithm} {labels} {original_ttl} {expiration} {inception} \
+                     {key_tag} "
+                )?;
+                fmt_name(signer, f)?;
+                write!(f, " {}", BASE64_STANDARD.encode(signature))
+            }
+            Self::NSEC { next, types } => {
+                fmt_name(next, f)?;
+                for qtype in types {
+                    write!(f, " {qtype}")?;
+                }
+                Ok(())
+            }
+            Self::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => write!(
+                f,
+                "{flags} {protocol} {algorithm} {}",
+                BASE64_STANDARD.encode(public_key)
+            ),
+            // The generic form of RFC 3597 section 5
+            Self::UNKNOWN { data, .. } => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    f.write_str(" ")?;
+                }
+                data.iter().try_for_each(|b| write!(f, "{b:02x}"))
+            }
+        }
+    }
+}
+
+/// Decode the type bitmap of an NSEC record: windows of up to 256 types, each a window number,
+/// a length and a bitmap with the most significant bit first
+fn read_type_bitmap(mut bytes: &[u8]) -> Result<Vec<QueryType>> {
+    let mut types = Vec::new();
+    while let [window, len, rest @ ..] = bytes {
+        let len = usize::from(*len);
+        let bitmap = rest.get(..len).ok_or(DnsError::BufferOverrun)?;
+        for (i, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    let n = u16::from(*window) << 8 | (i * 8 + bit) as u16;
+                    types.push(QueryType::from(n));
+                }
+            }
+        }
+        bytes = &rest[len..];
+    }
+    if !bytes.is_empty() {
+        return Err(DnsError::BufferOverrun);
+    }
+
+    Ok(types)
+}
+
+/// Encode the type bitmap of an NSEC record, see [`read_type_bitmap`]
+fn write_type_bitmap(types: &[QueryType], out: &mut Vec<u8>) {
+    let mut numbers: Vec<u16> = types.iter().map(|&qtype| qtype.into()).collect();
+    numbers.sort_unstable();
+    numbers.dedup();
+
+    for window in numbers.chunk_by(|a, b| a >> 8 == b >> 8) {
+        let mut bitmap = [0u8; 32];
+        for n in window {
+            let low = usize::from(n & 0xFF);
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+        }
+        let len = bitmap.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        out.push((window[0] >> 8) as u8);
+        out.push(len as u8);
+        out.extend_from_slice(&bitmap[..len]);
+    }
+}
+
+/// Write a fully qualified name with its trailing dot
+fn fmt_name(name: &DnsName, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if f.alternate() {
+        write!(f, "{}.", name.to_unicode())
+    } else {
+        f.write_str(&name.to_fqdn())
+    }
+}
+
+/// A character string on the wire: a length octet and that many bytes
+fn read_character_string(buf: &mut BytePacketBuffer) -> Result<Vec<u8>> {
+    let len = buf.read_range(1)?[0] as usize;
+
+    Ok(buf.read_range(len)?.to_vec())
+}
+
+/// Write a character string, which has a single octet for its length
+fn write_character_string(buffer: &mut BytePacketBuffer, s: &[u8]) -> Result<()> {
+    let len = u8::try_from(s.len()).map_err(|_| DnsError::CharacterStringTooLong(s.len()))?;
+    buffer.write_u8(len)?;
+    for &b in s {
+        buffer.write_u8(b)?;
+    }
+
+    Ok(())
+}
+
+/// Write a quoted character string, escaping quotes, backslashes and bytes that aren't printable
+/// ASCII
+fn fmt_character_string(s: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("\"")?;
+    for &b in s {
+        match b {
+            b'"' | b'\\' => write!(f, "\\{}", char::from(b))?,
+            0x20..=0x7e => write!(f, "{}", char::from(b))?,
+            b => write!(f, "\\{b:03}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Parse a record in master file format with every name taken as absolute, see
+/// [`DnsRecord::parse_line`]
+#[cfg(feature = "std")]
+impl FromStr for DnsRecord {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse_line(s, &DnsName::root())
+    }
+}
@@ -0,0 +1,294 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+#[cfg(any(windows, target_os = "macos"))]
+use std::process::Command;
+
+use crate::error::Result;
+use crate::name::DnsName;
+use crate::resolver::DNS_PORT;
+
+/// Where the system resolver is configured on Unix
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Public resolver used when the system doesn't have any nameservers configured
+pub const FALLBACK_NAMESERVER: IpAddr = IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8));
+
+/// Registry keys holding the DNS settings of Windows, for itself and for each interface
+#[cfg(windows)]
+const WINDOWS_KEYS: [&str; 2] = [
+    r"HKLM\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters",
+    r"HKLM\SYSTEM\CurrentControlSet\Services\Tcpip6\Parameters",
+];
+
+/// The parts of a resolv.conf file that matter to a stub resolver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvConf {
+    /// Servers to send queries to, in the order they are listed
+    pub nameservers: Vec<IpAddr>,
+    /// Domains appended to names that may be relative, from `search` or `domain`
+    pub search: Vec<DnsName>,
+    /// Names with fewer dots than this are tried with the search domains first
+    pub ndots: usize,
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        Self {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+        }
+    }
+}
+
+impl ResolvConf {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// The config of the system resolver, or the defaults when there is none: resolv.conf on
+    /// Unix, the resolvers `scutil` reports on macOS, where resolv.conf is only kept for
+    /// compatibility, and the TCP/IP parameters in the registry on Windows
+    pub fn system() -> Self {
+        #[cfg(target_os = "macos")]
+        if let Some(conf) =
+            command_output("scutil", &["--dns"]).map(|text| Self::parse_scutil(&text))
+        {
+            if !conf.nameservers.is_empty() {
+                return conf;
+            }
+        }
+
+        #[cfg(windows)]
+        return Self::windows();
+
+        #[cfg(not(windows))]
+        Self::load(RESOLV_CONF).unwrap_or_default()
+    }
+
+    #[cfg(windows)]
+    fn windows() -> Self {
+        let text: String = WINDOWS_KEYS
+            .iter()
+            .filter_map(|key| command_output("reg", &["query", key, "/s"]))
+            .collect();
+        Self::parse_windows_registry(&text)
+    }
+
+    /// Parse the text of a resolv.conf file. Like the C library, lines that can't be understood
+    /// are skipped rather than failing the whole file.
+    pub fn parse(text: &str) -> Self {
+        let mut conf = Self::default();
+
+        for line in text.lines() {
+            let line = line.split(['#', ';']).next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    // Link-local IPv6 servers may carry a zone, which IpAddr doesn't support
+                    let addr = words.next().and_then(|addr| addr.split('%').next());
+                    if let Some(addr) = addr.and_then(|addr| addr.parse().ok()) {
+                        conf.nameservers.push(addr);
+                    }
+                }
+                // Whichever of `domain` and `search` comes last wins
+                Some("domain" | "search") => {
+                    conf.search = words.filter_map(|domain| domain.parse().ok()).collect();
+                }
+                Some("options") => {
+                    let ndots = words.find_map(|option| option.strip_prefix("ndots:"));
+                    if let Some(ndots) = ndots.and_then(|ndots| ndots.parse::<usize>().ok()) {
+                        // Capped like the C library does
+                        conf.ndots = ndots.min(15);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        conf
+    }
+
+    /// Parse the output of `scutil --dns` on macOS. Only the resolvers for every domain count,
+    /// not the ones for a `domain` of their own like `local` for mDNS, nor the scoped ones of each
+    /// interface listed after them.
+    pub fn parse_scutil(text: &str) -> Self {
+        let mut conf = Self::default();
+        let text = text
+            .split("DNS configuration (for scoped queries)")
+            .next()
+            .unwrap_or_default();
+
+        for resolver in text.split("resolver #").skip(1) {
+            let entries: Vec<(&str, &str)> = resolver
+                .lines()
+                .filter_map(|line| line.split_once(" : "))
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .collect();
+            if entries.iter().any(|&(key, _)| key == "domain") {
+                continue;
+            }
+
+            for (key, value) in entries {
+                let key = key.split('[').next().unwrap_or_default().trim();
+                match key {
+                    "nameserver" => {
+                        let addr = value.split('%').next().unwrap_or_default();
+                        if let Ok(addr) = addr.parse() {
+                            if !conf.nameservers.contains(&addr) {
+                                conf.nameservers.push(addr);
+                            }
+                        }
+                    }
+                    "search domain" => {
+                        if let Ok(domain) = value.parse() {
+                            if !conf.search.contains(&domain) {
+                                conf.search.push(domain);
+                            }
+                        }
+                    }
+                    "options" => {
+                        let ndots = value
+                            .split_whitespace()
+                            .find_map(|option| option.strip_prefix("ndots:"));
+                        if let Some(ndots) = ndots.and_then(|ndots| ndots.parse::<usize>().ok()) {
+                            conf.ndots = ndots.min(15);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        conf
+    }
+
+    /// Parse the output of `reg query <key> /s` for the TCP/IP parameters of Windows. Each
+    /// interface takes the nameservers set by hand over the ones from DHCP, and the search
+    /// domains come from the search list of the system, or else from the domains of the system
+    /// and the interfaces.
+    pub fn parse_windows_registry(text: &str) -> Self {
+        let mut conf = Self::default();
+        let mut search_list = Vec::new();
+        let mut domains = Vec::new();
+
+        // Values are listed under the key they belong to, one key after the other
+        for key in text.split("HKEY_").skip(1) {
+            let values: Vec<(&str, String)> = key
+                .lines()
+                .skip(1)
+                .filter_map(|line| {
+                    let mut columns = line.split_whitespace();
+                    let name = columns.next()?;
+                    columns.next().filter(|kind| kind.starts_with("REG_"))?;
+                    Some((name, columns.collect::<Vec<_>>().join(" ")))
+                })
+                .filter(|(_, value)| !value.is_empty())
+                .collect();
+            let value = |name: &str| {
+                values
+                    .iter()
+                    .find(|(other, _)| other.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.as_str())
+            };
+            // Lists are separated by commas or spaces depending on what wrote them
+            let list = |value: &str| {
+                value
+                    .split([',', ' '])
+                    .filter(|item| !item.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            };
+
+            let nameservers = value("NameServer").or_else(|| value("DhcpNameServer"));
+            for addr in nameservers.map(list).unwrap_or_default() {
+                if let Ok(addr) = addr.split('%').next().unwrap_or_default().parse() {
+                    if !conf.nameservers.contains(&addr) {
+                        conf.nameservers.push(addr);
+                    }
+                }
+            }
+            if let Some(searches) = value("SearchList") {
+                search_list.extend(list(searches));
+            }
+            if let Some(domain) = value("Domain").or_else(|| value("DhcpDomain")) {
+                domains.push(domain.to_string());
+            }
+        }
+
+        let search = if search_list.is_empty() {
+            domains
+        } else {
+            search_list
+        };
+        for domain in search {
+            if let Ok(domain) = domain.parse() {
+                if !conf.search.contains(&domain) {
+                    conf.search.push(domain);
+                }
+            }
+        }
+
+        conf
+    }
+
+    /// The nameservers on the DNS port to send queries to, or [`FALLBACK_NAMESERVER`] without
+    /// any
+    pub fn upstreams(&self) -> Vec<SocketAddr> {
+        if self.nameservers.is_empty() {
+            return vec![SocketAddr::new(FALLBACK_NAMESERVER, DNS_PORT)];
+        }
+
+        self.nameservers
+            .iter()
+            .map(|&addr| SocketAddr::new(addr, DNS_PORT))
+            .collect()
+    }
+
+    /// The first nameserver, or [`FALLBACK_NAMESERVER`] without any
+    pub fn nameserver(&self) -> IpAddr {
+        self.nameservers
+            .first()
+            .copied()
+            .unwrap_or(FALLBACK_NAMESERVER)
+    }
+
+    /// The names to try for `name` in order, like getaddrinfo: names with a trailing dot are only
+    /// tried as they are, names with fewer than `ndots` dots with every search domain first, and
+    /// other names as they are first. Expansions that would be too long are left out.
+    pub fn search_names(&self, name: &str) -> Result<Vec<DnsName>> {
+        if let Some(absolute) = name.strip_suffix('.') {
+            return Ok(vec![DnsName::new(absolute)?]);
+        }
+
+        let as_is = DnsName::new(name)?;
+        let relative = name.matches('.').count() < self.ndots;
+        let expanded = self
+            .search
+            .iter()
+            .filter(|domain| !domain.is_root())
+            .filter_map(|domain| DnsName::new(&format!("{as_is}.{domain}")).ok());
+
+        let mut names = Vec::new();
+        if !relative {
+            names.push(as_is.clone());
+        }
+        names.extend(expanded);
+        if relative {
+            names.push(as_is);
+        }
+
+        Ok(names)
+    }
+}
+
+/// What a command printed, if it could be run and succeeded
+#[cfg(any(windows, target_os = "macos"))]
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
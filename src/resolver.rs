@@ -0,0 +1,1044 @@
+use std::fmt::Write;
+use std::io::{self, Read, Write as _};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use tracing::debug;
+use ureq::unversioned::resolver::ResolvedSocketAddrs;
+use ureq::unversioned::transport::{
+    Buffers, ConnectionDetails, Connector, DefaultConnector, LazyBuffers, NextTimeout,
+    RustlsConnector, Transport as HttpTransport,
+};
+
+use crate::buffer::BytePacketBuffer;
+use crate::edns::{max_udp_len, opt_record, EdnsOption, TcpKeepalive, QUERY_BLOCK_LEN};
+use crate::error::{DnsError, Result};
+use crate::header::ResultCode;
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::pool::ConnectionPool;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::{DnsRecord, RData};
+use crate::socks::{Socks5Proxy, UdpRelay};
+use crate::stack::Resolver;
+use crate::tls::{TlsStream, TlsUpstream};
+use crate::upstream::{Transport, Upstreams};
+
+/// How long to wait on a TCP connection when a query is retried over it
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections to upstreams queried over TCP and DNS over TLS that they keep open
+static TCP_POOL: ConnectionPool<SocketAddr, TcpStream> = ConnectionPool::new();
+static TLS_POOL: ConnectionPool<TlsUpstream, TlsStream> = ConnectionPool::new();
+
+/// The port DNS servers listen on
+pub const DNS_PORT: u16 = 53;
+
+const HTTPS_PORT: u16 = 443;
+
+/// The media type of DNS messages sent over HTTPS
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// a.root-servers.net, where iterative resolution starts
+pub const ROOT_SERVER: Ipv4Addr = Ipv4Addr::new(198, 41, 0, 4);
+
+/// Most queries a single recursive lookup sends, including those resolving nameservers without
+/// glue, so referral chains and loops between zones end
+pub const MAX_ITERATIVE_QUERIES: usize = 64;
+
+/// Most lookups of nameservers without glue nested in each other, since the nameserver for one
+/// can itself be without glue
+pub const MAX_GLUELESS_DEPTH: usize = 4;
+
+/// Parse the address of a server, with or without a port: `192.0.2.53`, `192.0.2.53:5353`,
+/// `2001:db8::53` or `[2001:db8::53]:5353`. The port is 53 when it is left out.
+pub fn parse_server(text: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = text.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    let addr = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'));
+    addr.unwrap_or(text)
+        .parse::<IpAddr>()
+        .map(|addr| SocketAddr::new(addr, DNS_PORT))
+        .map_err(|_| DnsError::InvalidServer(text.to_string()))
+}
+
+/// Build the reverse lookup name for an address, e.g. `4.3.2.1.in-addr.arpa` for `1.2.3.4`, or the
+/// nibble-reversed `ip6.arpa` name for an IPv6 address.
+pub fn reverse_name(addr: IpAddr) -> DnsName {
+    let name = match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::with_capacity(72);
+            for byte in v6.octets().iter().rev() {
+                let _ = write!(name, "{:x}.{:x}.", byte & 0x0F, byte >> 4);
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    };
+
+    // At most 32 single-character labels plus `ip6.arpa`, well within the limits
+    DnsName::from_validated(name)
+}
+
+/// How long to wait for a response from an upstream server, and how many times to ask again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How long the first attempt waits, doubled for every retry
+    pub timeout: Duration,
+    /// Attempts after the first one
+    pub retries: u32,
+}
+
+impl RetryPolicy {
+    pub const fn new(timeout: Duration, retries: u32) -> Self {
+        Self { timeout, retries }
+    }
+
+    /// How long to wait on the given attempt, counting from 0, with exponential backoff
+    fn timeout_for(&self, attempt: u32) -> Duration {
+        self.timeout.saturating_mul(1 << attempt.min(16))
+    }
+}
+
+/// Two seconds, then four and eight on the retries
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(2), 2)
+    }
+}
+
+/// A message sent to or received from an upstream server, as it was on the wire
+#[derive(Debug, Clone, Copy)]
+pub struct Exchange<'a> {
+    /// The address the query was sent from
+    pub local: SocketAddr,
+    pub server: SocketAddr,
+    pub tcp: bool,
+    /// Whether the message is the response rather than the query
+    pub response: bool,
+    pub wire: &'a [u8],
+}
+
+/// Send a single recursive query to `server` and wait for the response.
+pub fn lookup(
+    qname: &DnsName,
+    qtype: QueryType,
+    server: SocketAddr,
+    policy: RetryPolicy,
+) -> Result<DnsPacket> {
+    let question = DnsQuestion::new(qname.clone(), qtype);
+    query(&question, true, server, None, policy, None, &|_| {})
+}
+
+/// Same as [`lookup`], but the query uses EDNS and carries `options`
+pub fn lookup_with_options(
+    qname: &DnsName,
+    qtype: QueryType,
+    server: SocketAddr,
+    options: Vec<EdnsOption>,
+    policy: RetryPolicy,
+) -> Result<DnsPacket> {
+    let question = DnsQuestion::new(qname.clone(), qtype);
+    let opt = opt_record(false, options);
+    query(&question, true, server, Some(opt), policy, None, &|_| {})
+}
+
+/// Same as [`lookup_with_options`], without EDNS when `options` is `None`, going through `proxy`
+/// if any, and calling `observe` with every message sent to and received from the server,
+/// including retries
+pub fn lookup_observed(
+    qname: &DnsName,
+    qtype: QueryType,
+    server: SocketAddr,
+    options: Option<Vec<EdnsOption>>,
+    policy: RetryPolicy,
+    proxy: Option<&Socks5Proxy>,
+    observe: &dyn Fn(Exchange<'_>),
+) -> Result<DnsPacket> {
+    let question = DnsQuestion::new(qname.clone(), qtype);
+    let opt = options.map(|options| opt_record(false, options));
+
+    query(&question, true, server, opt, policy, proxy, observe)
+}
+
+/// Same as [`lookup_observed`] with DNS over TLS to `upstream`, through `proxy` if any. The query
+/// is sent once, on a connection the upstream keeps open from an earlier query or else on a new one
+/// that waits for as long as the first attempt of `policy`.
+pub fn lookup_tls(
+    qname: &DnsName,
+    qtype: QueryType,
+    upstream: &TlsUpstream,
+    options: Option<Vec<EdnsOption>>,
+    policy: RetryPolicy,
+    proxy: Option<&Socks5Proxy>,
+) -> Result<DnsPacket> {
+    let opt = options.map(|options| opt_record(false, options));
+    let mut packet = new_query(qname, qtype, opt)?;
+    request_keepalive(&mut packet);
+    packet.pad(QUERY_BLOCK_LEN)?;
+    if let Some(stream) = TLS_POOL.take(upstream) {
+        match exchange_tls(&mut packet, stream, upstream) {
+            Ok(response) => return Ok(response),
+            // The upstream may have closed it in the meantime
+            Err(e) => debug!(server = %upstream.addr, "Reused connection failed: {e}"),
+        }
+    }
+
+    let stream = upstream.connect(policy.timeout, proxy)?;
+    exchange_tls(&mut packet, stream, upstream)
+}
+
+/// Send `packet` over `stream` and read the response, keeping the connection for later queries
+/// when the upstream says it keeps it open
+fn exchange_tls(
+    packet: &mut DnsPacket,
+    mut stream: TlsStream,
+    upstream: &TlsUpstream,
+) -> Result<DnsPacket> {
+    packet.write_to(&mut stream)?;
+
+    let response = DnsPacket::read_from(&mut stream)?;
+    if !is_response_to(&response, packet) {
+        return Err(DnsError::InvalidResponse(upstream.addr.ip()));
+    }
+    if let Some(timeout) = response
+        .tcp_keepalive()
+        .and_then(|keepalive| keepalive.timeout)
+    {
+        TLS_POOL.put(upstream.clone(), stream, timeout);
+    }
+
+    Ok(response)
+}
+
+/// Same as [`lookup_observed`] with DNS over HTTPS, RFC 8484: the query is POSTed to `url`,
+/// connecting to the address of `server` whatever the host of the URL resolves to, so the upstream
+/// doesn't have to be looked up first. The connection goes through `proxy` if any, and fails
+/// rather than going around it.
+pub fn lookup_https(
+    qname: &DnsName,
+    qtype: QueryType,
+    url: &str,
+    server: SocketAddr,
+    options: Option<Vec<EdnsOption>>,
+    policy: RetryPolicy,
+    proxy: Option<&Socks5Proxy>,
+) -> Result<DnsPacket> {
+    let error = |e: ureq::Error| DnsError::Https(format!("{url} at {server}: {e}"));
+    // Always with EDNS, for the padding option
+    let opt = opt_record(false, options.unwrap_or_default());
+    let mut packet = new_query(qname, qtype, Some(opt))?;
+    // Section 4.1: an ID of 0 keeps the same query cacheable by HTTP caches
+    packet.header.id = 0;
+    packet.pad(QUERY_BLOCK_LEN)?;
+    let req_buf = packet.write_signed(None)?;
+
+    let config = ureq::Agent::config_builder()
+        .timeout_global(Some(policy.timeout))
+        .build();
+    let agent = match proxy {
+        Some(proxy) => {
+            let tunnel = Tunnel {
+                proxy: proxy.clone(),
+                timeout: policy.timeout,
+            };
+            let connector = tunnel.chain(RustlsConnector::default());
+            ureq::Agent::with_parts(config, connector, Pinned(server.ip()))
+        }
+        None => ureq::Agent::with_parts(config, DefaultConnector::new(), Pinned(server.ip())),
+    };
+    let mut response = agent
+        .post(url)
+        .header("content-type", DNS_MESSAGE)
+        .header("accept", DNS_MESSAGE)
+        .send(&req_buf.buf[..req_buf.pos()])
+        .map_err(error)?;
+    let body = response
+        .body_mut()
+        .with_config()
+        .limit(u16::MAX.into())
+        .read_to_vec()
+        .map_err(error)?;
+
+    let response = DnsPacket::from_buffer(&mut BytePacketBuffer { buf: body, pos: 0 })?;
+    if !is_response_to(&response, &packet) {
+        return Err(DnsError::InvalidResponse(server.ip()));
+    }
+
+    Ok(response)
+}
+
+/// Resolves the host of every URL to the same address, on the port of the URL
+#[derive(Debug)]
+struct Pinned(IpAddr);
+
+impl ureq::unversioned::resolver::Resolver for Pinned {
+    fn resolve(
+        &self,
+        uri: &ureq::http::Uri,
+        _config: &ureq::config::Config,
+        _timeout: NextTimeout,
+    ) -> std::result::Result<ResolvedSocketAddrs, ureq::Error> {
+        let port = uri.port_u16().unwrap_or(HTTPS_PORT);
+        let mut addrs = self.empty();
+        addrs.push(SocketAddr::new(self.0, port));
+
+        Ok(addrs)
+    }
+}
+
+/// Opens the connections of DNS over HTTPS through a SOCKS5 proxy, for TLS to be set up over
+#[derive(Debug)]
+struct Tunnel {
+    proxy: Socks5Proxy,
+    timeout: Duration,
+}
+
+impl Connector for Tunnel {
+    type Out = Tunneled;
+
+    fn connect(
+        &self,
+        details: &ConnectionDetails,
+        _chained: Option<()>,
+    ) -> std::result::Result<Option<Tunneled>, ureq::Error> {
+        let target = *details.addrs.first().ok_or(ureq::Error::HostNotFound)?;
+        let stream = self
+            .proxy
+            .connect(target, self.timeout)
+            .map_err(|e| ureq::Error::Io(io::Error::other(e)))?;
+        let config = details.config;
+        let buffers = LazyBuffers::new(config.input_buffer_size(), config.output_buffer_size());
+
+        Ok(Some(Tunneled { stream, buffers }))
+    }
+}
+
+/// A connection through the proxy, used for a single request
+#[derive(Debug)]
+struct Tunneled {
+    stream: TcpStream,
+    buffers: LazyBuffers,
+}
+
+impl HttpTransport for Tunneled {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        &mut self.buffers
+    }
+
+    fn transmit_output(
+        &mut self,
+        amount: usize,
+        timeout: NextTimeout,
+    ) -> std::result::Result<(), ureq::Error> {
+        self.stream
+            .set_write_timeout(timeout.not_zero().map(|t| *t))?;
+        self.stream.write_all(&self.buffers.output()[..amount])?;
+
+        Ok(())
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> std::result::Result<bool, ureq::Error> {
+        self.stream
+            .set_read_timeout(timeout.not_zero().map(|t| *t))?;
+        let amount = self.stream.read(self.buffers.input_append_buf())?;
+        self.buffers.input_appended(amount);
+
+        Ok(amount > 0)
+    }
+
+    /// Open while nothing is waiting to be read, which would be the server closing it or sending
+    /// what wasn't asked for
+    fn is_open(&mut self) -> bool {
+        let Ok(()) = self.stream.set_nonblocking(true) else {
+            return false;
+        };
+        let idle = matches!(
+            self.stream.peek(&mut [0]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock
+        );
+
+        self.stream.set_nonblocking(false).is_ok() && idle
+    }
+}
+
+/// Same as [`lookup_observed`] over `transport`, where only the messages of plain DNS are
+/// observed. Queries over TCP wait on the connection for as long as when UDP is retried over it,
+/// while the encrypted ones only make a single attempt, see [`lookup_tls`] and [`lookup_https`].
+pub fn lookup_over(
+    transport: &Transport,
+    question: &DnsQuestion,
+    server: SocketAddr,
+    options: Option<Vec<EdnsOption>>,
+    policy: RetryPolicy,
+    proxy: Option<&Socks5Proxy>,
+    observe: &dyn Fn(Exchange<'_>),
+) -> Result<DnsPacket> {
+    let (qname, qtype) = (&question.name, question.qtype);
+    match transport {
+        Transport::Udp => lookup_observed(qname, qtype, server, options, policy, proxy, observe),
+        Transport::Tcp => {
+            let opt = options.map(|options| opt_record(false, options));
+            let mut packet = new_query(qname, qtype, opt)?;
+            query_tcp(&mut packet, server, proxy, observe)
+        }
+        Transport::Tls { name } => {
+            let upstream = TlsUpstream {
+                addr: server,
+                name: name.clone(),
+            };
+            lookup_tls(qname, qtype, &upstream, options, policy, proxy)
+        }
+        Transport::Https { url } => lookup_https(qname, qtype, url, server, options, policy, proxy),
+    }
+}
+
+/// How many times [`lookup_ip`] asks again for the target of a CNAME chain that came back without
+/// the addresses it leads to
+const MAX_CNAME_QUERIES: usize = 8;
+
+/// Which addresses [`lookup_ip`] puts first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// IPv6 before IPv4, like the default policy table of RFC 6724
+    #[default]
+    Ipv6First,
+    Ipv4First,
+}
+
+/// The addresses of `name`, from an A and an AAAA query sent to `resolver` at once. CNAMEs are
+/// followed, the addresses of both families are merged without duplicates, and the ones of the
+/// `preferred` family come first, each in the order of the answers.
+///
+/// Names that don't exist or have no addresses give an empty list. It fails only when neither
+/// query could be answered, with the error of the A query.
+///
+/// ```no_run
+/// use dns_server::resolv_conf::ResolvConf;
+/// use dns_server::resolver::{lookup_ip, Forwarder, IpPreference, RetryPolicy};
+/// use dns_server::upstream::Upstreams;
+///
+/// let upstreams = Upstreams::new(ResolvConf::system().upstreams());
+/// let resolver = Forwarder::new(upstreams, RetryPolicy::default());
+/// let name = "example.com".parse().unwrap();
+/// let addrs = lookup_ip(&resolver, &name, IpPreference::default()).unwrap();
+/// ```
+pub fn lookup_ip(
+    resolver: &(impl Resolver + Sync),
+    name: &DnsName,
+    preferred: IpPreference,
+) -> Result<Vec<IpAddr>> {
+    let (v4, v6) = thread::scope(|s| {
+        let v6 = s.spawn(|| lookup_addrs(resolver, name, QueryType::AAAA));
+        let v4 = lookup_addrs(resolver, name, QueryType::A);
+        (v4, v6.join().expect("AAAA lookup panicked"))
+    });
+
+    let (first, second) = match (v4, v6) {
+        (Err(e), Err(_)) => return Err(e),
+        (v4, v6) => (v4.unwrap_or_default(), v6.unwrap_or_default()),
+    };
+    let (first, second) = match preferred {
+        IpPreference::Ipv6First => (second, first),
+        IpPreference::Ipv4First => (first, second),
+    };
+    let mut addrs = Vec::with_capacity(first.len() + second.len());
+    for addr in first.into_iter().chain(second) {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    Ok(addrs)
+}
+
+/// The addresses of type `qtype` that `name` leads to, asking again for the end of the CNAME chain
+/// when the response stops short of it. Responses other than NOERROR and NXDOMAIN are failures.
+fn lookup_addrs(resolver: &impl Resolver, name: &DnsName, qtype: QueryType) -> Result<Vec<IpAddr>> {
+    let mut name = name.clone();
+    for _ in 0..=MAX_CNAME_QUERIES {
+        let response = resolver.resolve(&DnsQuestion::new(name.clone(), qtype))?;
+        match response.header.rescode {
+            ResultCode::NOERROR => {}
+            ResultCode::NXDOMAIN => return Ok(Vec::new()),
+            rescode => return Err(DnsError::NoAnswer(format!("{name} {qtype:?}: {rescode:?}"))),
+        }
+
+        // Bound the walk by the number of answers so a CNAME loop can't spin forever
+        let mut aliased = false;
+        for _ in 0..response.answers.len() {
+            let host = response.answers.iter().find_map(|rec| match &rec.rdata {
+                RData::CNAME { host } if rec.name == name => Some(host),
+                _ => None,
+            });
+            match host {
+                Some(host) => {
+                    name = host.clone();
+                    aliased = true;
+                }
+                None => break,
+            }
+        }
+
+        let addrs: Vec<IpAddr> = response
+            .answers
+            .iter()
+            .filter(|rec| rec.name == name)
+            .filter_map(|rec| match rec.rdata {
+                RData::A { addr } => Some(addr.into()),
+                RData::AAAA { addr } => Some(addr.into()),
+                _ => None,
+            })
+            .collect();
+        if !addrs.is_empty() || !aliased {
+            return Ok(addrs);
+        }
+    }
+
+    Err(DnsError::NoAnswer(format!(
+        "{name} {qtype:?}: CNAME chain longer than {MAX_CNAME_QUERIES} queries"
+    )))
+}
+
+/// A resolver forwarding questions to upstream resolvers, failing over between them
+#[derive(Debug, Clone)]
+pub struct Forwarder {
+    upstreams: Upstreams,
+    policy: RetryPolicy,
+}
+
+impl Forwarder {
+    pub const fn new(upstreams: Upstreams, policy: RetryPolicy) -> Self {
+        Self { upstreams, policy }
+    }
+}
+
+impl Resolver for Forwarder {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        self.upstreams
+            .query(|server| lookup(&question.name, question.qtype, server, self.policy))
+    }
+}
+
+/// Send the query until a valid response arrives, failing with [`DnsError::Timeout`] when the
+/// server stays silent, [`DnsError::Refused`] when nothing listens on the port, or
+/// [`DnsError::InvalidResponse`] when only responses that had to be dropped came back. Iterative
+/// queries to authoritative servers don't set `recursion_desired`.
+fn query(
+    question: &DnsQuestion,
+    recursion_desired: bool,
+    server: SocketAddr,
+    opt: Option<DnsRecord>,
+    policy: RetryPolicy,
+    proxy: Option<&Socks5Proxy>,
+    observe: &dyn Fn(Exchange<'_>),
+) -> Result<DnsPacket> {
+    let (qname, qtype) = (&question.name, question.qtype);
+    let new_query = |qname: &DnsName, opt: Option<DnsRecord>| {
+        let mut packet = new_query(qname, qtype, opt)?;
+        packet.header.recursion_desired = recursion_desired;
+        Ok::<_, DnsError>(packet)
+    };
+    let socket = match proxy {
+        None => {
+            // Bound to the family of the server, which may be IPv4 or IPv6
+            let local: IpAddr = match server {
+                SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+            };
+            let socket = UdpSocket::bind((local, 0))?;
+            // Connected, so the error for a closed port is reported back
+            socket.connect(server)?;
+            Datagram::Direct(socket)
+        }
+        Some(proxy) if proxy.udp => match proxy.udp_associate(TCP_TIMEOUT)? {
+            Some(relay) => Datagram::Relayed(relay),
+            None => {
+                debug!(proxy = %proxy.addr, "UDP isn't relayed, querying over TCP");
+                let mut packet = new_query(qname, opt)?;
+                return query_tcp(&mut packet, server, Some(proxy), observe);
+            }
+        },
+        Some(proxy) => {
+            let mut packet = new_query(qname, opt)?;
+            return query_tcp(&mut packet, server, Some(proxy), observe);
+        }
+    };
+    let local = socket.local_addr()?;
+    let exchange = Exchange {
+        local,
+        server,
+        tcp: false,
+        response: false,
+        wire: &[],
+    };
+    // The response can be as large as the OPT record says we accept
+    let max_len = max_udp_len(opt.as_ref());
+    let mut dropped = false;
+
+    for attempt in 0..=policy.retries {
+        // The name is sent with random case, which a spoofed response would have to guess (0x20
+        // encoding)
+        let sent_name = randomize_case(qname);
+        let mut packet = new_query(&sent_name, opt.clone())?;
+
+        let mut req_buf = BytePacketBuffer::new();
+        packet.write(&mut req_buf)?;
+
+        // Observed before it is sent, so whatever comes of it can't be seen first
+        observe(Exchange {
+            wire: &req_buf.buf[0..req_buf.pos],
+            ..exchange
+        });
+        socket
+            .send_to(&req_buf.buf[0..req_buf.pos], server)
+            .map_err(|e| classify(e, server.ip()))?;
+
+        let deadline = Instant::now() + policy.timeout_for(attempt);
+        dropped = false;
+        let Some((mut response, res_buf)) =
+            receive(&socket, &packet, server, deadline, max_len, &mut dropped)?
+        else {
+            debug!(%server, attempt, dropped, "No response in time");
+            continue;
+        };
+        observe(Exchange {
+            response: true,
+            wire: &res_buf.buf,
+            ..exchange
+        });
+
+        // A response that doesn't echo the case may have been forged, so ask again over TCP where
+        // that takes more than winning a race
+        let echoed = response
+            .questions
+            .first()
+            .is_some_and(|question| question.name.as_str() == sent_name.as_str());
+        if !echoed {
+            debug!(%server, "Case not echoed, retrying over TCP");
+            response = query_tcp(&mut packet, server, proxy, observe)?;
+        } else if response.header.truncated_message {
+            // The whole response only fits over TCP, RFC 7766 section 5
+            debug!(%server, "Response truncated, retrying over TCP");
+            response = query_tcp(&mut packet, server, proxy, observe)?;
+        }
+        restore_case(&mut response, qname);
+
+        return Ok(response);
+    }
+
+    if dropped {
+        Err(DnsError::InvalidResponse(server.ip()))
+    } else {
+        Err(DnsError::Timeout)
+    }
+}
+
+/// Wait until `deadline` for the response to `query`, or `None` if it doesn't arrive in time.
+/// Anything that doesn't come from the server or doesn't answer the query is dropped without
+/// looking further, and noted in `dropped`.
+fn receive(
+    socket: &Datagram,
+    query: &DnsPacket,
+    server: SocketAddr,
+    deadline: Instant,
+    max_len: usize,
+    dropped: &mut bool,
+) -> Result<Option<(DnsPacket, BytePacketBuffer)>> {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let mut res_buf = BytePacketBuffer::with_len(max_len);
+        let (len, src) = match socket.recv_from(&mut res_buf.buf) {
+            Ok(received) => received,
+            Err(e) => match classify(e, server.ip()) {
+                DnsError::Timeout => return Ok(None),
+                e => return Err(e),
+            },
+        };
+        res_buf.buf.truncate(len);
+
+        if src != server {
+            *dropped = true;
+            continue;
+        }
+        match DnsPacket::from_buffer(&mut res_buf) {
+            Ok(response) if is_response_to(&response, query) => {
+                return Ok(Some((response, res_buf)));
+            }
+            _ => *dropped = true,
+        }
+    }
+}
+
+/// A recursive query for `qname`, with EDNS when there is an `opt` record
+fn new_query(qname: &DnsName, qtype: QueryType, opt: Option<DnsRecord>) -> Result<DnsPacket> {
+    let mut packet = DnsPacket::query(qname, qtype)
+        .id(rand::random())
+        .recursion_desired(true)
+        .build()?;
+    packet.resources.extend(opt);
+
+    Ok(packet)
+}
+
+/// Where UDP queries are sent from: a socket of our own connected to the server, or the relay of
+/// a SOCKS5 proxy
+enum Datagram {
+    Direct(UdpSocket),
+    Relayed(UdpRelay),
+}
+
+impl Datagram {
+    fn send_to(&self, buf: &[u8], server: SocketAddr) -> io::Result<usize> {
+        match self {
+            Self::Direct(socket) => socket.send(buf),
+            Self::Relayed(relay) => relay.send_to(buf, server),
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self {
+            Self::Direct(socket) => socket.recv_from(buf),
+            Self::Relayed(relay) => relay.recv_from(buf),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Direct(socket) => socket.set_read_timeout(timeout),
+            Self::Relayed(relay) => relay.set_read_timeout(timeout),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Direct(socket) => socket.local_addr(),
+            Self::Relayed(relay) => relay.local_addr(),
+        }
+    }
+}
+
+/// Tell a server that refused the connection apart from other network errors
+fn classify(e: io::Error, server: IpAddr) -> DnsError {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused => DnsError::Refused(server),
+        _ => e.into(),
+    }
+}
+
+/// Send `packet` over a TCP connection to `server`, through `proxy` if any, and read the response.
+/// The connection is one the server keeps open from an earlier query if there is one.
+fn query_tcp(
+    packet: &mut DnsPacket,
+    server: SocketAddr,
+    proxy: Option<&Socks5Proxy>,
+    observe: &dyn Fn(Exchange<'_>),
+) -> Result<DnsPacket> {
+    request_keepalive(packet);
+    if let Some(stream) = TCP_POOL.take(&server) {
+        match exchange_tcp(packet, stream, server, observe) {
+            Ok(response) => return Ok(response),
+            // The server may have closed it in the meantime
+            Err(e) => debug!(%server, "Reused connection failed: {e}"),
+        }
+    }
+
+    let stream = match proxy {
+        Some(proxy) => proxy.connect(server, TCP_TIMEOUT)?,
+        None => TcpStream::connect_timeout(&server, TCP_TIMEOUT)
+            .map_err(|e| classify(e, server.ip()))?,
+    };
+    exchange_tcp(packet, stream, server, observe)
+}
+
+/// Send `packet` over `stream` and read the response, keeping the connection for later queries
+/// when the server says it keeps it open
+fn exchange_tcp(
+    packet: &mut DnsPacket,
+    mut stream: TcpStream,
+    server: SocketAddr,
+    observe: &dyn Fn(Exchange<'_>),
+) -> Result<DnsPacket> {
+    stream.set_read_timeout(Some(TCP_TIMEOUT))?;
+    let local = stream.local_addr()?;
+    let exchange = Exchange {
+        local,
+        server,
+        tcp: true,
+        response: false,
+        wire: &[],
+    };
+
+    let req_buf = packet.write_signed(None)?;
+    observe(Exchange {
+        wire: &req_buf.buf[..req_buf.pos()],
+        ..exchange
+    });
+    req_buf.write_to(&mut stream)?;
+
+    let mut res_buf = BytePacketBuffer::read_from(&mut stream)?;
+    observe(Exchange {
+        response: true,
+        wire: &res_buf.buf,
+        ..exchange
+    });
+    let response = DnsPacket::from_buffer(&mut res_buf)?;
+    if !is_response_to(&response, packet) {
+        return Err(DnsError::InvalidResponse(server.ip()));
+    }
+    if let Some(timeout) = response
+        .tcp_keepalive()
+        .and_then(|keepalive| keepalive.timeout)
+    {
+        TCP_POOL.put(server, stream, timeout);
+    }
+
+    Ok(response)
+}
+
+/// Ask the server to keep the connection open with the edns-tcp-keepalive option, RFC 7828, using
+/// EDNS for it if the query doesn't already
+fn request_keepalive(packet: &mut DnsPacket) {
+    let option = TcpKeepalive::default().to_option();
+    match packet.edns_options_mut() {
+        Some(options) => {
+            if !options.contains(&option) {
+                options.push(option);
+            }
+        }
+        None => packet.resources.push(opt_record(false, vec![option])),
+    }
+}
+
+/// Whether `response` is a response with the ID of `query` and echoes its question, ignoring the
+/// case of the name
+fn is_response_to(response: &DnsPacket, query: &DnsPacket) -> bool {
+    let question = |packet: &DnsPacket| {
+        packet
+            .questions
+            .first()
+            .map(|q| (q.name.clone(), q.qtype, q.class))
+    };
+
+    response.header.response
+        && response.header.id == query.header.id
+        && response.questions.len() == 1
+        && question(response) == question(query)
+}
+
+/// `name` with the case of every letter picked at random
+fn randomize_case(name: &DnsName) -> DnsName {
+    let mut rng = rand::rng();
+    let name = name
+        .as_str()
+        .chars()
+        .map(|c| {
+            if rng.random() {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect();
+
+    DnsName::from_validated(name)
+}
+
+/// Give the names of `response` that stand for `qname` its case back, so the mixed case sent
+/// upstream doesn't leak into answers
+fn restore_case(response: &mut DnsPacket, qname: &DnsName) {
+    for question in &mut response.questions {
+        if question.name == *qname {
+            question.name = qname.clone();
+        }
+    }
+
+    let records = response
+        .answers
+        .iter_mut()
+        .chain(&mut response.authorities)
+        .chain(&mut response.resources);
+    for rec in records {
+        if rec.domain() == qname {
+            rec.set_domain(qname.clone());
+        }
+    }
+}
+
+/// Resolve a name iteratively, starting at the root servers and following referrals.
+pub fn recursive_lookup(
+    qname: &DnsName,
+    qtype: QueryType,
+    policy: RetryPolicy,
+) -> Result<DnsPacket> {
+    recursive_lookup_traced(qname, qtype, policy, &mut |_, _| {})
+}
+
+/// Same as [`recursive_lookup`], but calls `trace` with the nameserver queried and the response
+/// it sent back for every step of the resolution, including lookups of nameservers that were
+/// referred to without glue.
+///
+/// Names are minimized following RFC 9156: each nameserver is only asked about one label more than
+/// the zone it was referred to for, so the root servers see `com` rather than the whole name.
+///
+/// # Errors
+///
+/// [`DnsError::TooManyReferrals`] when the resolution takes more than [`MAX_ITERATIVE_QUERIES`]
+/// queries, or nameservers without glue lead to more than [`MAX_GLUELESS_DEPTH`] nested lookups.
+pub fn recursive_lookup_traced(
+    qname: &DnsName,
+    qtype: QueryType,
+    policy: RetryPolicy,
+    trace: &mut dyn FnMut(IpAddr, &DnsPacket),
+) -> Result<DnsPacket> {
+    let root = SocketAddr::new(IpAddr::V4(ROOT_SERVER), DNS_PORT);
+    recursive_lookup_from(qname, qtype, root, policy, trace)
+}
+
+/// Same as [`recursive_lookup_traced`], but starting at `root` instead of the root servers. Every
+/// nameserver referred to is asked on the port of `root`, so a private hierarchy can be served
+/// from one host.
+pub fn recursive_lookup_from(
+    qname: &DnsName,
+    qtype: QueryType,
+    root: SocketAddr,
+    policy: RetryPolicy,
+    trace: &mut dyn FnMut(IpAddr, &DnsPacket),
+) -> Result<DnsPacket> {
+    let mut budget = MAX_ITERATIVE_QUERIES;
+    iterate(qname, qtype, root, policy, trace, &mut budget, 0)
+}
+
+/// One resolution of [`recursive_lookup_traced`], with the queries left to send shared with the
+/// lookups of nameservers it nests, `nested` deep
+fn iterate(
+    qname: &DnsName,
+    qtype: QueryType,
+    root: SocketAddr,
+    policy: RetryPolicy,
+    trace: &mut dyn FnMut(IpAddr, &DnsPacket),
+    budget: &mut usize,
+    nested: usize,
+) -> Result<DnsPacket> {
+    let mut ns = root.ip();
+    // The deepest zone cut found so far, which `ns` is a nameserver for
+    let mut zone = DnsName::root();
+    // How many labels of `qname` are revealed to `ns`
+    let mut depth = 1;
+
+    loop {
+        *budget = budget
+            .checked_sub(1)
+            .ok_or_else(|| DnsError::TooManyReferrals(qname.to_string()))?;
+        let server = SocketAddr::new(ns, root.port());
+        // Minimized questions ask for A records, which nameservers handle best, RFC 9156 section 3
+        let minimized = (depth < qname.label_count()).then(|| ancestor(qname, depth));
+        let question = match &minimized {
+            Some(name) => DnsQuestion::new(name.clone(), QueryType::A),
+            None => DnsQuestion::new(qname.clone(), qtype),
+        };
+        // Authoritative servers are asked without recursion desired, RFC 1034 section 5.3.1
+        let response = query(&question, false, server, None, policy, None, &|_| {})?;
+        trace(ns, &response);
+        let asked = minimized.as_ref().unwrap_or(qname);
+
+        if minimized.is_none() {
+            // Done when there are answers, or when the authoritative server tells us the name
+            // doesn't exist.
+            if (!response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR)
+                || response.header.rescode == ResultCode::NXDOMAIN
+            {
+                return Ok(response);
+            }
+        } else if response.header.rescode == ResultCode::NXDOMAIN {
+            // Nothing exists below a name that doesn't exist, but the answer has to be for the
+            // question that was asked
+            depth = qname.label_count();
+            continue;
+        }
+
+        // Follow a referral to a zone further down, preferring a nameserver that came with glue.
+        let next = match response.glued_referral(asked, &zone) {
+            Some((cut, new_ns)) => Some((cut.clone(), new_ns)),
+            None => match response.referral(asked, &zone) {
+                // No glue, so the nameserver itself needs to be resolved first, over IPv6 if it
+                // has no IPv4 address. If that's not possible, the last response is the best
+                // we've got.
+                Some((cut, host)) => {
+                    if nested == MAX_GLUELESS_DEPTH {
+                        return Err(DnsError::TooManyReferrals(qname.to_string()));
+                    }
+                    let mut new_ns = None;
+                    for qtype in [QueryType::A, QueryType::AAAA] {
+                        let ns_response =
+                            iterate(host, qtype, root, policy, trace, budget, nested + 1)?;
+                        new_ns = ns_response.random_address();
+                        if new_ns.is_some() {
+                            break;
+                        }
+                    }
+                    match new_ns {
+                        Some(new_ns) => Some((cut.clone(), new_ns)),
+                        None => return Ok(response),
+                    }
+                }
+                None => None,
+            },
+        };
+
+        match next {
+            Some((cut, new_ns)) => {
+                ns = new_ns;
+                depth = cut.label_count() + 1;
+                zone = cut;
+            }
+            // No zone cut at the minimized name, so the same nameserver is asked about one more
+            // label
+            None if minimized.is_some() => depth += 1,
+            None => return Ok(response),
+        }
+    }
+}
+
+/// The ancestor of `name` made of its last `depth` labels
+fn ancestor(name: &DnsName, depth: usize) -> DnsName {
+    let mut ancestor = name.clone();
+    for _ in depth..name.label_count() {
+        ancestor = ancestor.parent().unwrap_or_default();
+    }
+
+    ancestor
+}
+
+/// A resolver resolving questions iteratively from the root servers, see [`recursive_lookup`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Recursive {
+    policy: RetryPolicy,
+}
+
+impl Recursive {
+    pub const fn new(policy: RetryPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl Resolver for Recursive {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        recursive_lookup(&question.name, question.qtype, self.policy)
+    }
+}
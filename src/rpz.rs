@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use tracing::warn;
+
+use crate::config::{Config, RpzConfig};
+use crate::error::{DnsError, Result};
+use crate::header::ResultCode;
+use crate::name::DnsName;
+use crate::network::Network;
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::{DnsRecord, RData};
+use crate::transfer::axfr_zone;
+use crate::zone::Zone;
+
+/// Label under which triggers on the addresses in answers are kept
+const IP_TRIGGER: &str = "rpz-ip";
+
+/// What a policy zone says to do with a query that triggers it, following the encoding of
+/// draft-vixie-dnsop-dns-rpz
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// `CNAME .`: the name doesn't exist
+    Nxdomain,
+    /// `CNAME *.`: the name exists without records of any type
+    Nodata,
+    /// `CNAME rpz-passthru.`: answer as usual, ignoring any later policy
+    Passthru,
+    /// `CNAME rpz-drop.`: don't answer at all
+    Drop,
+    /// Any other CNAME: answer with an alias to the target, which may start with `*` to be
+    /// replaced by the query name
+    Rewrite { target: DnsName, ttl: u32 },
+    /// Any other records: answer with these in place of the real ones
+    LocalData(Vec<DnsRecord>),
+}
+
+impl Action {
+    fn from_records(records: Vec<DnsRecord>) -> Self {
+        let (target, ttl) = match records.as_slice() {
+            [DnsRecord {
+                ttl,
+                rdata: RData::CNAME { host },
+                ..
+            }] => (host.clone(), *ttl),
+            _ => return Self::LocalData(records),
+        };
+
+        match target.as_str().to_ascii_lowercase().as_str() {
+            "" => Self::Nxdomain,
+            "*" => Self::Nodata,
+            // TCP-only answers are left to the transport, which isn't known here
+            "rpz-passthru" | "rpz-tcp-only" => Self::Passthru,
+            "rpz-drop" => Self::Drop,
+            _ => Self::Rewrite { target, ttl },
+        }
+    }
+
+    /// The target a rewrite sends `qname` to, with a leading `*` replaced by the name itself
+    pub fn rewrite_target(&self, qname: &DnsName) -> Option<DnsName> {
+        let Self::Rewrite { target, .. } = self else {
+            return None;
+        };
+
+        match target.as_str().strip_prefix("*.") {
+            // Too long to rewrite, so the name is answered as if it doesn't exist
+            Some(suffix) => DnsName::new(&format!("{qname}.{suffix}")).ok(),
+            None => Some(target.clone()),
+        }
+    }
+
+    /// The answer to a question that triggered the action. PASSTHRU and DROP don't have one, and
+    /// rewrites only get their alias, which is left to the caller to follow.
+    pub fn response(&self, qname: &DnsName, qtype: QueryType) -> Option<DnsPacket> {
+        let mut packet = DnsPacket::new();
+        match self {
+            Self::Passthru | Self::Drop => return None,
+            Self::Nxdomain => packet.header.rescode = ResultCode::NXDOMAIN,
+            Self::Nodata => {}
+            Self::Rewrite { ttl, .. } => match self.rewrite_target(qname) {
+                Some(host) => {
+                    packet
+                        .answers
+                        .push(DnsRecord::new(qname.clone(), *ttl, RData::CNAME { host }))
+                }
+                None => packet.header.rescode = ResultCode::NXDOMAIN,
+            },
+            Self::LocalData(records) => {
+                // Wildcard rules answer with the query name as the owner
+                packet.answers = records
+                    .iter()
+                    .filter(|rec| rec.qtype() == qtype)
+                    .map(|rec| {
+                        let mut rec = rec.clone();
+                        rec.set_domain(qname.clone());
+                        rec
+                    })
+                    .collect();
+            }
+        }
+
+        Some(packet)
+    }
+}
+
+/// A policy rule that matched, for logging
+#[derive(Debug, Clone, Copy)]
+pub struct Hit<'a> {
+    /// Origin of the policy zone the rule is in
+    pub zone: &'a DnsName,
+    /// Owner of the rule, relative to the origin
+    pub trigger: &'a DnsName,
+    pub action: &'a Action,
+}
+
+impl fmt::Display for Hit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.trigger, self.zone)
+    }
+}
+
+/// The rules of one response policy zone
+#[derive(Debug, Clone)]
+struct PolicyZone {
+    origin: DnsName,
+    /// Rules on query names, by the name relative to the origin, which may be a wildcard
+    qnames: HashMap<DnsName, Action>,
+    /// Rules on the addresses in answers, with the network they cover
+    ips: Vec<(Network, DnsName, Action)>,
+}
+
+impl PolicyZone {
+    /// Sort the records of a zone into rules. The SOA and NS at the apex only make it a zone, and
+    /// rules of kinds that aren't supported, on name servers or clients, are skipped.
+    fn new(zone: Zone) -> Self {
+        let origin = zone.origin;
+        let mut owned: HashMap<DnsName, Vec<DnsRecord>> = HashMap::new();
+        for rec in zone.records {
+            let Some(trigger) = relative(rec.domain(), &origin) else {
+                continue;
+            };
+            owned.entry(trigger).or_default().push(rec);
+        }
+
+        let mut policy = Self {
+            origin,
+            qnames: HashMap::new(),
+            ips: Vec::new(),
+        };
+        for (trigger, records) in owned {
+            let action = Action::from_records(records);
+            let kind = trigger.labels().next_back().unwrap_or_default();
+            let kind = kind.to_ascii_lowercase();
+            if kind == IP_TRIGGER {
+                match ip_network(&trigger) {
+                    Some(network) => policy.ips.push((network, trigger, action)),
+                    None => warn!("Skipping invalid RPZ trigger {trigger}.{}", policy.origin),
+                }
+            } else if !kind.starts_with("rpz-") {
+                policy.qnames.insert(trigger, action);
+            }
+        }
+
+        policy
+    }
+
+    /// The rule for `qname`, either on the name itself or the closest wildcard above it
+    fn qname_hit(&self, qname: &DnsName) -> Option<Hit<'_>> {
+        let hit = |(trigger, action)| Hit {
+            zone: &self.origin,
+            trigger,
+            action,
+        };
+        if let Some(exact) = self.qnames.get_key_value(qname) {
+            return Some(hit(exact));
+        }
+
+        let mut name = qname.parent();
+        while let Some(current) = name {
+            let wildcard = current.child("*").ok()?;
+            if let Some(found) = self.qnames.get_key_value(&wildcard) {
+                return Some(hit(found));
+            }
+            name = current.parent();
+        }
+
+        None
+    }
+
+    /// The rule with the longest network containing `addr`
+    fn ip_hit(&self, addr: IpAddr) -> Option<Hit<'_>> {
+        self.ips
+            .iter()
+            .filter(|(network, _, _)| network.contains(addr))
+            .max_by_key(|(network, _, _)| network.prefix())
+            .map(|(_, trigger, action)| Hit {
+                zone: &self.origin,
+                trigger,
+                action,
+            })
+    }
+}
+
+/// Response policy zones, consulted in the order they are configured. The first zone with a rule
+/// that matches decides.
+#[derive(Debug, Clone, Default)]
+pub struct Rpz {
+    zones: Vec<PolicyZone>,
+}
+
+impl Rpz {
+    /// Load every configured policy zone, from its file or by transfer from its primary
+    pub fn load(config: &Config) -> Result<Self> {
+        let zones = config
+            .rpz
+            .iter()
+            .map(|rpz| Ok(PolicyZone::new(load_zone(config, rpz)?)))
+            .collect::<Result<_>>()?;
+
+        Ok(Self { zones })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    /// The rule on a query name, if any zone has one
+    pub fn qname_hit(&self, qname: &DnsName) -> Option<Hit<'_>> {
+        self.zones.iter().find_map(|zone| zone.qname_hit(qname))
+    }
+
+    /// The rule on any address in `answers`, if any zone has one
+    pub fn ip_hit(&self, answers: &[DnsRecord]) -> Option<Hit<'_>> {
+        let addrs: Vec<IpAddr> = answers
+            .iter()
+            .filter_map(|rec| match rec.rdata {
+                RData::A { addr } => Some(IpAddr::V4(addr)),
+                RData::AAAA { addr } => Some(IpAddr::V6(addr)),
+                _ => None,
+            })
+            .collect();
+
+        self.zones
+            .iter()
+            .find_map(|zone| addrs.iter().find_map(|addr| zone.ip_hit(*addr)))
+    }
+}
+
+fn load_zone(config: &Config, rpz: &RpzConfig) -> Result<Zone> {
+    match (&rpz.file, rpz.primary) {
+        (Some(file), _) => Zone::load(file, &rpz.origin),
+        (None, Some(primary)) => {
+            let key = match &rpz.key {
+                Some(name) => Some(
+                    config
+                        .keys
+                        .iter()
+                        .find(|key| key.name == *name)
+                        .ok_or_else(|| {
+                            DnsError::Config(format!("No key {name} to transfer {}", rpz.origin))
+                        })?,
+                ),
+                None => None,
+            };
+            axfr_zone(&rpz.origin, primary, key)
+        }
+        (None, None) => Err(DnsError::Config(format!(
+            "No file or primary for policy zone {}",
+            rpz.origin
+        ))),
+    }
+}
+
+/// `name` relative to `origin`, or `None` if it isn't below it
+fn relative(name: &DnsName, origin: &DnsName) -> Option<DnsName> {
+    if name == origin || !name.is_subdomain_of(origin) {
+        return None;
+    }
+
+    let labels: Vec<_> = name
+        .labels()
+        .take(name.label_count() - origin.label_count())
+        .collect();
+    DnsName::new(&labels.join(".")).ok()
+}
+
+/// The network of a response IP trigger such as `24.0.2.0.192.rpz-ip` for 192.0.2.0/24, or
+/// `48.zz.db8.2001.rpz-ip` for 2001:db8::/48, where `zz` stands for a run of zeros
+fn ip_network(trigger: &DnsName) -> Option<Network> {
+    let mut labels = trigger.labels();
+    let prefix: u8 = labels.next()?.parse().ok()?;
+    // The last label is the kind of trigger
+    let mut parts: Vec<&str> = labels.collect();
+    parts.pop();
+    parts.reverse();
+
+    let addr = if parts.len() == 4 && prefix <= 32 {
+        IpAddr::V4(parts.join(".").parse::<Ipv4Addr>().ok()?)
+    } else if prefix <= 128 {
+        let mut text = parts
+            .iter()
+            .map(|part| {
+                if part.eq_ignore_ascii_case("zz") {
+                    ""
+                } else {
+                    part
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(":");
+        if text.starts_with(':') {
+            text.insert(0, ':');
+        }
+        if text.ends_with(':') {
+            text.push(':');
+        }
+        IpAddr::V6(text.parse::<Ipv6Addr>().ok()?)
+    } else {
+        return None;
+    };
+
+    Some(Network::new(addr, prefix))
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+use crate::header::ResultCode;
+use crate::name::DnsName;
+use crate::network::Network;
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::record::{DnsRecord, RData};
+
+/// Buckets kept before the ones that have been idle for a whole window are forgotten
+const MAX_BUCKETS: usize = 10_000;
+
+/// What to do with a response over UDP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Send,
+    /// Don't answer at all
+    Drop,
+    /// Answer with an empty truncated response, so legitimate clients retry over TCP where their
+    /// address can't be spoofed
+    Slip,
+}
+
+/// Responses that count against the same limit: the same answer to clients in the same network
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    network: Network,
+    name: DnsName,
+    qtype: QueryType,
+    rescode: ResultCode,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// Responses that may still be sent, negative when over the limit
+    balance: f64,
+    last: Instant,
+    /// Responses withheld since the bucket went over the limit
+    limited: u32,
+}
+
+/// Response Rate Limiting, which keeps the server from being used to reflect and amplify floods of
+/// responses at spoofed addresses
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<Key, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a response to `client` and decide whether it is sent
+    pub fn check(&self, client: IpAddr, response: &DnsPacket) -> Verdict {
+        let client = client.to_canonical();
+        if self
+            .config
+            .exempt
+            .iter()
+            .any(|network| network.contains(client))
+        {
+            return Verdict::Send;
+        }
+        let Some(key) = self.key(client, response) else {
+            return Verdict::Send;
+        };
+
+        let rate = f64::from(self.config.responses_per_second);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() >= MAX_BUCKETS {
+            let window = Duration::from_secs(self.config.window);
+            buckets.retain(|_, bucket| now.duration_since(bucket.last) < window);
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            balance: rate,
+            last: now,
+            limited: 0,
+        });
+        // Credit comes back at the allowed rate, up to one second worth, and debt is capped at a
+        // window worth so a flood that stopped is forgiven in time
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        let debt = -rate * self.config.window as f64;
+        bucket.balance = (bucket.balance + elapsed * rate).min(rate) - 1.0;
+        bucket.balance = bucket.balance.max(debt);
+        bucket.last = now;
+
+        if bucket.balance >= 0.0 {
+            bucket.limited = 0;
+            return Verdict::Send;
+        }
+
+        bucket.limited += 1;
+        let slip = self.config.slip;
+        if slip > 0 && bucket.limited.is_multiple_of(slip) {
+            Verdict::Slip
+        } else {
+            Verdict::Drop
+        }
+    }
+
+    /// The bucket of a response. Negative answers count by the zone they come from, so that
+    /// random names below it don't each get a limit of their own.
+    fn key(&self, client: IpAddr, response: &DnsPacket) -> Option<Key> {
+        let question = response.questions.first()?;
+        let prefix = match client {
+            IpAddr::V4(_) => self.config.ipv4_prefix,
+            IpAddr::V6(_) => self.config.ipv6_prefix,
+        };
+        let zone = response
+            .authorities
+            .iter()
+            .find(|rec| matches!(rec.rdata, RData::SOA { .. }))
+            .map(DnsRecord::domain);
+        let name = match (response.header.rescode, zone) {
+            (ResultCode::NXDOMAIN, Some(zone)) => zone.clone(),
+            _ => question.name.clone(),
+        };
+
+        Some(Key {
+            network: Network::new(client, prefix),
+            name,
+            qtype: question.qtype,
+            rescode: response.header.rescode,
+        })
+    }
+}
+
+/// The response sent in place of one that slipped: just the question, truncated
+pub fn truncated(response: &DnsPacket) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header = response.header;
+    packet.header.truncated_message = true;
+    packet.questions.clone_from(&response.questions);
+
+    packet
+}
@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::name::DnsName;
+use crate::record::RData;
+use crate::transfer::{axfr_zone, ixfr};
+use crate::tsig::TsigKey;
+use crate::zone::Zone;
+
+/// Shortest time between checks, so a primary with timers of 0 isn't asked in a loop. It is also
+/// the retry interval of zones that were never transferred, which have no SOA to take it from.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A zone transferred from a primary and kept up to date with the timers of its SOA, RFC 1034
+/// section 4.3.5: the primary is asked for a newer serial every refresh interval, every retry
+/// interval after that fails, and the zone expires when it can't be checked for the expire
+/// interval.
+#[derive(Debug, Clone)]
+pub struct Secondary {
+    pub origin: DnsName,
+    /// When the zone was last found to be current, `None` until it was first transferred
+    refreshed: Option<Instant>,
+    /// When to ask the primary again
+    next: Instant,
+}
+
+impl Secondary {
+    /// A zone to transfer at `now`
+    pub const fn new(origin: DnsName, now: Instant) -> Self {
+        Self {
+            origin,
+            refreshed: None,
+            next: now,
+        }
+    }
+
+    /// When the primary is to be asked again
+    pub const fn next(&self) -> Instant {
+        self.next
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next <= now
+    }
+
+    /// Whether `zone`, this version of the secondary, couldn't be checked with the primary for
+    /// longer than its expire interval at `now`
+    pub fn is_expired(&self, zone: &Zone, now: Instant) -> bool {
+        match (self.refreshed, timers(zone)) {
+            (Some(refreshed), Some(Timers { expire, .. })) => refreshed + expire <= now,
+            _ => false,
+        }
+    }
+
+    /// Bring `zone`, the version served so far, up to date with `primary`, or transfer it in full
+    /// when there is none yet. Returns the new version when it changed, and schedules the next
+    /// check at the refresh interval of the zone, or the retry interval if this one failed.
+    pub fn refresh_at(
+        &mut self,
+        zone: Option<&Zone>,
+        primary: SocketAddr,
+        key: Option<&TsigKey>,
+        now: Instant,
+    ) -> Result<Option<Zone>> {
+        let result = match zone {
+            Some(zone) => {
+                let mut zone = zone.clone();
+                ixfr(&mut zone, primary, key).map(|changed| changed.then_some(zone))
+            }
+            None => axfr_zone(&self.origin, primary, key).map(Some),
+        };
+
+        match &result {
+            Ok(updated) => {
+                let refresh = updated
+                    .as_ref()
+                    .or(zone)
+                    .and_then(timers)
+                    .map_or(MIN_INTERVAL, |timers| timers.refresh);
+                self.refreshed = Some(now);
+                self.next = now + refresh;
+            }
+            Err(_) => {
+                let retry = zone
+                    .and_then(timers)
+                    .map_or(MIN_INTERVAL, |timers| timers.retry);
+                self.next = now + retry;
+            }
+        }
+
+        result
+    }
+}
+
+/// The intervals of a zone SOA, no shorter than [`MIN_INTERVAL`] apart from the expire interval
+struct Timers {
+    refresh: Duration,
+    retry: Duration,
+    expire: Duration,
+}
+
+fn timers(zone: &Zone) -> Option<Timers> {
+    let RData::SOA {
+        refresh,
+        retry,
+        expire,
+        ..
+    } = zone.soa()?.rdata
+    else {
+        return None;
+    };
+    let secs = |secs: u32| Duration::from_secs(secs.into());
+
+    Some(Timers {
+        refresh: secs(refresh).max(MIN_INTERVAL),
+        retry: secs(retry).max(MIN_INTERVAL),
+        expire: secs(expire),
+    })
+}
@@ -0,0 +1,1322 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use socket2::Type;
+use tracing::{debug, debug_span, info, info_span, warn};
+
+use crate::authority::Authority;
+use crate::balance::Balancer;
+use crate::blocklist::Blocklist;
+use crate::buffer::{BytePacketBuffer, UDP_MAX_LEN};
+use crate::cache::Cache;
+use crate::catalog::Catalog;
+use crate::config::{AnyPolicy, Config};
+use crate::control::{self, LogLevel};
+use crate::daemon;
+use crate::ddr;
+use crate::dnstap::Dnstap;
+use crate::edns::{
+    max_udp_len, opt_record, ClientSubnet, EdnsOption, TcpKeepalive, OPTION_CLIENT_SUBNET,
+    OPTION_EXTENDED_ERROR, OPTION_NSID, OPTION_PADDING, RESPONSE_BLOCK_LEN,
+};
+use crate::error::{DnsError, FailureReason, Result};
+use crate::header::{DnsHeader, ResultCode, OPCODE_QUERY, OPCODE_UPDATE};
+use crate::hosts::Hosts;
+use crate::journal::soa_serial;
+use crate::llmnr;
+use crate::local::LocalRecords;
+use crate::mdns::Responder;
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::query_log::{QueryLog, Status};
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::{DnsRecord, RData};
+use crate::resolver::{lookup_over, lookup_tls, RetryPolicy};
+use crate::rpz::{Action, Rpz};
+use crate::rrl::{self, RateLimiter, Verdict};
+use crate::stack::Resolver;
+use crate::stats::{Counters, Stats};
+use crate::tls::TlsUpstream;
+use crate::transfer::write_transfer;
+use crate::tsig::{TsigKey, TsigSession};
+use crate::update::{self, UpdateMessage};
+use crate::upstream::{Forwarders, Transport, Upstreams};
+use crate::view::View;
+use crate::zone::Zone;
+
+/// How long an idle TCP connection is kept open waiting for the next query
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often signed zones are checked for signatures that are about to expire
+const RESIGN_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often upstreams that failed are probed
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// TTL of the HINFO record answered to ANY queries, long since it never changes
+const ANY_HINFO_TTL: u32 = 3600;
+
+/// How long a forwarded query is looked out for coming back, when its forward never finished
+const IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Shared state for every query the server handles
+#[derive(Debug)]
+pub struct ServerContext {
+    pub config: Config,
+    /// Locked so dynamic updates can change the zones while queries are answered from them
+    pub authority: RwLock<Authority>,
+    /// Records from the config, answered before the zones
+    pub local_records: LocalRecords,
+    /// Policies applied to forwarded queries and their answers
+    pub rpz: Rpz,
+    /// Catalogs of zones transferred from a primary, which are served along with the others
+    pub catalogs: Vec<Catalog>,
+    /// Names balanced between the healthy ones of their targets
+    pub balancer: Balancer,
+    /// Names answered from a hosts file, when one is configured
+    pub hosts: Option<Hosts>,
+    /// Blocked domains, when a blocklist is configured. Replaced as a whole when the lists are
+    /// refreshed.
+    pub blocklist: Option<RwLock<Blocklist>>,
+    /// Where forwarded queries go, with how well each upstream has been answering
+    pub forwarders: Forwarders,
+    /// The encrypted resolvers upstreams designated, which their queries go to instead with `ddr`
+    pub designated: Arc<RwLock<HashMap<SocketAddr, TlsUpstream>>>,
+    /// What clients from some networks see on top of the rest, the first one matching applies
+    pub views: Vec<View>,
+    /// Limits on responses over UDP, when configured
+    pub rate_limiter: Option<RateLimiter>,
+    /// Where messages with clients and upstreams are logged, when configured
+    pub dnstap: Option<Dnstap>,
+    /// Where every client query is logged, when configured
+    pub query_log: Option<QueryLog>,
+    /// Answers from the upstreams
+    pub cache: Cache,
+    /// Changes the log level for the `set-log-level` control command, when the logs allow it
+    pub log_level: Option<LogLevel>,
+    /// Asked before the upstreams about what the server doesn't know itself, for embedders with
+    /// backends of their own. Questions it has no answer for are forwarded.
+    pub resolver: Option<Box<dyn Resolver + Send + Sync>>,
+    /// What has been answered so far, see [`ServerContext::stats`]
+    counters: Counters,
+    /// Counts responses, to rotate addresses by
+    rotation: AtomicUsize,
+    /// The queries sent to the upstreams, to notice one of them coming back
+    in_flight: Arc<InFlight>,
+}
+
+impl ServerContext {
+    /// Build the context for a config, loading all of its zone files
+    pub fn new(config: Config) -> Result<Self> {
+        let authority = RwLock::new(Authority::load(&config.zones)?);
+        let local_records = LocalRecords::new(config.local_records()?);
+        let rpz = Rpz::load(&config)?;
+        let catalogs = config
+            .catalogs
+            .iter()
+            .map(|catalog| Catalog::new(catalog, &config.keys))
+            .collect::<Result<_>>()?;
+        let balancer = Balancer::new(&config.balanced);
+        let hosts = match &config.hosts {
+            Some(hosts) => Some(Hosts::load(&hosts.file, hosts.ttl)?),
+            None => None,
+        };
+        let blocklist = config.blocklist.as_ref().map(|blocklist| {
+            let loaded = Blocklist::load(&blocklist.lists, &blocklist.allow, None);
+            info!("Blocking {} domains", loaded.len());
+            RwLock::new(loaded)
+        });
+        let rules = config
+            .forward
+            .iter()
+            .map(|rule| Ok((rule.domain.clone(), rule.upstreams()?)))
+            .collect::<Result<_>>()?;
+        let upstreams = Upstreams::new(config.upstreams());
+        let views = config
+            .views
+            .iter()
+            .map(|view| View::load(view, &upstreams))
+            .collect::<Result<_>>()?;
+        let forwarders = Forwarders::new(upstreams, rules);
+        let rate_limiter = config.rate_limit.clone().map(RateLimiter::new);
+        let dnstap = config.dnstap.as_ref().map(Dnstap::open).transpose()?;
+        let query_log = config.query_log.clone().map(QueryLog::open).transpose()?;
+        let cache = Cache::new(config.cache_size).with_ttl_limits(config.min_ttl, config.max_ttl);
+
+        Ok(Self {
+            config,
+            authority,
+            local_records,
+            rpz,
+            catalogs,
+            balancer,
+            hosts,
+            blocklist,
+            forwarders,
+            designated: Arc::default(),
+            views,
+            rate_limiter,
+            dnstap,
+            query_log,
+            cache,
+            log_level: None,
+            resolver: None,
+            counters: Counters::default(),
+            rotation: AtomicUsize::new(0),
+            in_flight: Arc::default(),
+        })
+    }
+
+    /// The view for queries from `client`, if any
+    pub fn view(&self, client: IpAddr) -> Option<&View> {
+        self.views.iter().find(|view| view.matches(client))
+    }
+
+    /// Where queries from `client` are forwarded, by its view if that has rules of its own
+    pub fn forwarders(&self, client: IpAddr) -> &Forwarders {
+        self.view(client)
+            .and_then(|view| view.forwarders.as_ref())
+            .unwrap_or(&self.forwarders)
+    }
+
+    /// Counts of the queries, responses and cache hits since the server started
+    pub fn stats(&self) -> Stats {
+        self.counters.snapshot()
+    }
+
+    /// The served zones, for reading. A panic while they were being updated leaves them as
+    /// consistent as a failed update does, so poisoning is ignored.
+    pub fn authority(&self) -> RwLockReadGuard<'_, Authority> {
+        self.authority
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A client request, parsed according to its opcode
+enum Request {
+    Query(DnsPacket),
+    Update(UpdateMessage),
+    /// A message with QR set, which is never answered so two servers can't keep bouncing messages
+    /// between them
+    Response,
+    /// A request that can't be answered, by the header it had and the result code to answer with:
+    /// FORMERR when the rest of it can't be parsed, NOTIMP for opcodes other than QUERY and UPDATE
+    /// like IQUERY and STATUS
+    Error(DnsHeader, ResultCode),
+}
+
+impl Request {
+    /// Fails only when not even the ID can be read
+    fn from_buffer(buf: &mut BytePacketBuffer) -> Result<Self> {
+        let mut header = DnsHeader::new();
+        if header.read(buf).is_err() {
+            let header = partial_header(&buf.buf).ok_or(DnsError::BufferOverrun)?;
+            debug!("Request cut short in its header");
+            return Ok(if header.response {
+                Self::Response
+            } else {
+                Self::Error(header, ResultCode::FORMERR)
+            });
+        }
+        buf.seek(0)?;
+
+        let request = match header.opcode {
+            _ if header.response => return Ok(Self::Response),
+            OPCODE_QUERY => DnsPacket::from_buffer(buf).map(Self::Query),
+            OPCODE_UPDATE => UpdateMessage::from_buffer(buf).map(Self::Update),
+            _ => return Ok(Self::Error(header, ResultCode::NOTIMP)),
+        };
+        match request {
+            Err(e) if e.is_malformed() => {
+                debug!("Malformed request: {e}");
+                Ok(Self::Error(header, ResultCode::FORMERR))
+            }
+            request => request,
+        }
+    }
+}
+
+/// What can be read of a header that was cut short: the ID from the first two octets, so the client
+/// can tell which of its requests was malformed, and the QR bit, opcode and RD flag from the third
+fn partial_header(buf: &[u8]) -> Option<DnsHeader> {
+    let [id_high, id_low, rest @ ..] = buf else {
+        return None;
+    };
+    let mut header = DnsHeader::new();
+    header.id = u16::from_be_bytes([*id_high, *id_low]);
+    if let Some(&flags) = rest.first() {
+        header.recursion_desired = flags & 1 > 0;
+        header.opcode = (flags >> 3) & 0x0F;
+        header.response = flags & (1 << 7) > 0;
+    }
+
+    Some(header)
+}
+
+/// Build the response to a query from `src`, answered by [`resolve`], and log it to the query log.
+/// Returns `None` when a response policy drops the query.
+pub fn handle_query(
+    context: &ServerContext,
+    request: &DnsPacket,
+    src: IpAddr,
+) -> Option<DnsPacket> {
+    let start = Instant::now();
+    let (response, status) = answer(context, request, src);
+    context
+        .counters
+        .query(request.questions.first().map(|question| question.qtype));
+    context
+        .counters
+        .response(response.as_ref().map(|response| response.header.rescode));
+    if let (Some(query_log), Some(question)) = (&context.query_log, request.questions.first()) {
+        query_log.log(src, question, response.as_ref(), status, start.elapsed());
+    }
+
+    response
+}
+
+/// The response to a query with how it was answered, see [`handle_query`]
+fn answer(
+    context: &ServerContext,
+    request: &DnsPacket,
+    src: IpAddr,
+) -> (Option<DnsPacket>, Status) {
+    let mut packet = DnsPacket::new();
+    packet.header.id = request.header.id;
+    packet.header.recursion_desired = request.header.recursion_desired;
+    packet.header.recursion_available = context.config.allows_recursion(src);
+    packet.header.response = true;
+
+    // Nothing says how to answer several questions at once, RFC 9619
+    let [question] = request.questions.as_slice() else {
+        packet.header.rescode = ResultCode::FORMERR;
+        return (Some(packet), Status::Malformed);
+    };
+    packet.questions.push(question.clone());
+    let _span = info_span!(
+        "query",
+        qname = %question.name,
+        qtype = ?question.qtype,
+        client = %src,
+    )
+    .entered();
+
+    if !context.config.allows_query(src) {
+        packet.header.rescode = ResultCode::REFUSED;
+        return (Some(packet), Status::Refused);
+    }
+
+    // Zone transfers are only served over TCP, to secondaries that are allowed them
+    if matches!(question.qtype, QueryType::AXFR | QueryType::IXFR) {
+        packet.header.rescode = ResultCode::REFUSED;
+        return (Some(packet), Status::Refused);
+    }
+
+    // A query this server forwarded that came back, the upstreams forward to it in turn
+    if context.in_flight.contains(request) {
+        return failed(request, packet, FailureReason::Loop);
+    }
+
+    // A client subnet that can't be parsed is an error rather than ignored, RFC 7871 section 7.1.1
+    let client_subnet = request.client_subnet();
+    if client_subnet.is_none() && request.edns_options(OPTION_CLIENT_SUBNET).next().is_some() {
+        packet.header.rescode = ResultCode::FORMERR;
+        return (Some(packet), Status::Malformed);
+    }
+    let subnet = context
+        .config
+        .client_subnet
+        .and_then(|config| config.subnet(src, client_subnet));
+
+    let dnssec_ok = request.dnssec_ok();
+    let resolved = match (
+        &context.config.dns64,
+        resolve(context, question, dnssec_ok, subnet, src),
+    ) {
+        // Validating clients check the signatures themselves, which synthesized records don't have
+        (Some(dns64), Ok(Some((result, status))))
+            if question.qtype == QueryType::AAAA
+                && dns64.needs_synthesis(&result)
+                && !(dnssec_ok && request.header.checking_disabled) =>
+        {
+            let question = DnsQuestion::new(question.name.clone(), QueryType::A);
+            match resolve(context, &question, dnssec_ok, subnet, src) {
+                Ok(Some((ipv4, _))) => Ok(Some((dns64.synthesize(result, ipv4), status))),
+                // Without addresses to synthesize from, the client gets the answer it asked for
+                _ => Ok(Some((result, status))),
+            }
+        }
+        (_, resolved) => resolved,
+    };
+    let (result, status) = match resolved {
+        Ok(Some(resolved)) => resolved,
+        Ok(None) => {
+            debug!("Dropped");
+            return (None, Status::Dropped);
+        }
+        Err(e) => {
+            warn!("Failed to forward: {e}");
+            return failed(request, packet, e.failure_reason());
+        }
+    };
+    // What the upstream said about its answer goes on to the client, as do its failures
+    let extended_errors: Vec<EdnsOption> = result
+        .edns_options(OPTION_EXTENDED_ERROR)
+        .cloned()
+        .collect();
+    let status = match status {
+        Status::Forwarded if result.header.rescode == ResultCode::SERVFAIL => {
+            let dnssec = result.extended_errors().any(|error| error.is_dnssec());
+            Status::Failed(if dnssec {
+                FailureReason::ValidationFailed
+            } else {
+                FailureReason::Upstream
+            })
+        }
+        status => status,
+    };
+
+    // The answer applies to as much of the client subnet as the upstream says it used, and to
+    // everyone when it is answered here or the subnet wasn't forwarded
+    let scope_prefix = subnet
+        .and_then(|_| result.client_subnet())
+        .map_or(0, |upstream| upstream.scope_prefix);
+
+    packet.header.rescode = result.header.rescode;
+    packet.header.authoritative_answer = result.header.authoritative_answer;
+    packet.answers = result.answers;
+    // Balanced names are already in an order weighted by their targets
+    if context.config.round_robin && !context.balancer.contains(&question.name) {
+        rotate(
+            &mut packet.answers,
+            context.rotation.fetch_add(1, Ordering::Relaxed),
+        );
+    }
+    packet.authorities = result.authorities;
+    // The EDNS options of the upstream were for us, the client gets its own
+    packet.resources = result
+        .resources
+        .into_iter()
+        .filter(|rec| !matches!(rec.rdata, RData::OPT { .. }))
+        .collect();
+    if request.edns().is_some() {
+        let mut options: Vec<EdnsOption> = client_subnet
+            .map(|client_subnet| {
+                ClientSubnet {
+                    scope_prefix,
+                    ..client_subnet
+                }
+                .to_option()
+            })
+            .into_iter()
+            .collect();
+        if let (Some(nsid), Some(_)) = (
+            &context.config.nsid,
+            request.edns_options(OPTION_NSID).next(),
+        ) {
+            options.push(EdnsOption {
+                code: OPTION_NSID,
+                data: nsid.as_bytes().to_vec(),
+            });
+        }
+        options.extend(extended_errors);
+        packet
+            .resources
+            .push(opt_record(request.dnssec_ok(), options));
+    }
+
+    debug!(
+        rescode = ?packet.header.rescode,
+        answers = packet.answers.len(),
+        "Answered"
+    );
+    (Some(packet), status)
+}
+
+/// Answer with SERVFAIL for `reason`, told to clients that use EDNS with an Extended DNS Error
+fn failed(
+    request: &DnsPacket,
+    mut packet: DnsPacket,
+    reason: FailureReason,
+) -> (Option<DnsPacket>, Status) {
+    debug!(%reason, "Failed");
+    packet.header.rescode = ResultCode::SERVFAIL;
+    if request.edns().is_some() {
+        let options = vec![reason.extended_error().to_option()];
+        packet
+            .resources
+            .push(opt_record(request.dnssec_ok(), options));
+    }
+
+    (Some(packet), Status::Failed(reason))
+}
+
+/// Rotate every set of A and AAAA records in `answers` by `offset`, so clients that take the first
+/// address spread over all of them
+fn rotate(answers: &mut [DnsRecord], offset: usize) {
+    let mut sets: Vec<Vec<usize>> = Vec::new();
+    for (i, rec) in answers.iter().enumerate() {
+        if !matches!(rec.qtype(), QueryType::A | QueryType::AAAA) {
+            continue;
+        }
+        let set = sets.iter_mut().find(|set| {
+            let first = &answers[set[0]];
+            first.qtype() == rec.qtype() && first.domain() == rec.domain()
+        });
+        match set {
+            Some(set) => set.push(i),
+            None => sets.push(vec![i]),
+        }
+    }
+
+    for set in sets.iter().filter(|set| set.len() > 1) {
+        let mut records: Vec<_> = set.iter().map(|&i| answers[i].clone()).collect();
+        records.rotate_left(offset % set.len());
+        for (&i, rec) in set.iter().zip(records) {
+            answers[i] = rec;
+        }
+    }
+}
+
+/// Answer a question from wherever the name is known, in order: the view of the client, the local
+/// records, the balanced names, the zones, the hosts file and the blocklist. Everything else goes
+/// to the plugged in resolver and then the upstream resolvers, for clients that may recurse. ANY
+/// queries are answered as the `any-queries` policy says. `None` means the query is dropped.
+fn resolve(
+    context: &ServerContext,
+    question: &DnsQuestion,
+    dnssec_ok: bool,
+    subnet: Option<ClientSubnet>,
+    src: IpAddr,
+) -> Result<Option<(DnsPacket, Status)>> {
+    // Bound first so the zones aren't locked while waiting on the upstream
+    let local_span = debug_span!("local").entered();
+    let local = context
+        .view(src)
+        .and_then(|view| view.lookup(&question.name, question.qtype, dnssec_ok))
+        .or_else(|| context.local_records.lookup(&question.name, question.qtype))
+        .or_else(|| context.balancer.lookup(&question.name, question.qtype))
+        .or_else(|| {
+            context
+                .authority()
+                .lookup(&question.name, question.qtype, dnssec_ok)
+        })
+        .or_else(|| {
+            let hosts = context.hosts.as_ref()?;
+            hosts.lookup(&question.name, question.qtype)
+        })
+        .map(|result| (result, Status::Local))
+        .or_else(|| blocked(context, question, src).map(|result| (result, Status::Blocked)));
+    debug!(found = local.is_some());
+    drop(local_span);
+
+    let minimal_any =
+        question.qtype == QueryType::ANY && context.config.any_queries == AnyPolicy::Hinfo;
+    match local {
+        // Names that exist get the HINFO record instead of what they have, RFC 8482 section 4.2
+        Some((result, Status::Local))
+            if minimal_any && result.header.rescode == ResultCode::NOERROR =>
+        {
+            let mut packet = any_hinfo(&question.name);
+            packet.header.authoritative_answer = result.header.authoritative_answer;
+            Ok(Some((packet, Status::Local)))
+        }
+        Some(result) => Ok(Some(result)),
+        // Clients that may not recurse only get what the server knows itself
+        None if !context.config.allows_recursion(src) => {
+            let mut packet = DnsPacket::new();
+            packet.header.rescode = ResultCode::REFUSED;
+            Ok(Some((packet, Status::Refused)))
+        }
+        None if minimal_any => Ok(Some((any_hinfo(&question.name), Status::Local))),
+        None => {
+            let plugged = context
+                .resolver
+                .as_ref()
+                .map(|resolver| debug_span!("plugged").in_scope(|| resolver.resolve(question)));
+            match plugged {
+                Some(Err(DnsError::NoAnswer(_))) | None => {
+                    forward_with_policy(context, question, subnet, src)
+                }
+                Some(result) => Ok(Some((result?, Status::Local))),
+            }
+        }
+    }
+}
+
+/// The answer to an ANY query for `qname` under the `hinfo` policy, RFC 8482 section 4.2
+fn any_hinfo(qname: &DnsName) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.answers.push(DnsRecord::new(
+        qname.clone(),
+        ANY_HINFO_TTL,
+        RData::HINFO {
+            cpu: b"RFC8482".to_vec(),
+            os: Vec::new(),
+        },
+    ));
+    packet
+}
+
+/// The answer for a question about a blocked name, following the blocklist policy. Blocks and
+/// exceptions made for allowed domains are logged.
+fn blocked(context: &ServerContext, question: &DnsQuestion, src: IpAddr) -> Option<DnsPacket> {
+    let config = context.config.blocklist.as_ref()?;
+    let blocklist = context
+        .blocklist
+        .as_ref()?
+        .read()
+        .unwrap_or_else(PoisonError::into_inner);
+    let qname = &question.name;
+    let listed = blocklist.blocked_by(qname)?;
+
+    if let Some(allowed) = blocklist.allowed_by(qname) {
+        info!("Allowed {qname} for {src}: blocked by {listed}, allowed by {allowed}");
+        return None;
+    }
+
+    info!("Blocked {qname} for {src}: listed as {listed}");
+    Some(config.policy.response(qname, question.qtype))
+}
+
+/// Forward a question, applying the response policy zones to its name before and to the addresses
+/// in the answer after. `None` means the query is dropped without an answer.
+fn forward_with_policy(
+    context: &ServerContext,
+    question: &DnsQuestion,
+    subnet: Option<ClientSubnet>,
+    src: IpAddr,
+) -> Result<Option<(DnsPacket, Status)>> {
+    let qname = &question.name;
+    let hit = context.rpz.qname_hit(qname);
+    if let Some(hit) = hit {
+        info!("Policy {hit} matched {qname} for {src}");
+        if *hit.action != Action::Passthru {
+            let result = apply_policy(context, question, subnet, src, hit.action)?;
+            return Ok(result.map(|result| (result, Status::Policy)));
+        }
+    }
+
+    let (result, status) = forward(context, question, subnet, src)?;
+    if hit.is_some() {
+        return Ok(Some((result, status)));
+    }
+    match context.rpz.ip_hit(&result.answers) {
+        Some(hit) if *hit.action != Action::Passthru => {
+            info!("Policy {hit} matched the answer for {qname} for {src}");
+            let result = apply_policy(context, question, subnet, src, hit.action)?;
+            Ok(result.map(|result| (result, Status::Policy)))
+        }
+        _ => Ok(Some((result, status))),
+    }
+}
+
+/// The answer a policy gives to a question, following rewrites with the upstream
+fn apply_policy(
+    context: &ServerContext,
+    question: &DnsQuestion,
+    subnet: Option<ClientSubnet>,
+    src: IpAddr,
+    action: &Action,
+) -> Result<Option<DnsPacket>> {
+    let Some(mut packet) = action.response(&question.name, question.qtype) else {
+        return Ok(None);
+    };
+
+    if let Some(target) = action.rewrite_target(&question.name) {
+        if question.qtype != QueryType::CNAME {
+            let (rewritten, _) = forward(
+                context,
+                &DnsQuestion::new(target, question.qtype),
+                subnet,
+                src,
+            )?;
+            packet.header.rescode = rewritten.header.rescode;
+            packet.answers.extend(rewritten.answers);
+            packet.authorities = rewritten.authorities;
+        }
+    }
+
+    Ok(Some(packet))
+}
+
+/// Forward a question to the upstream resolvers for its name, along with the network of the
+/// client if any, unless the answer is cached
+fn forward(
+    context: &ServerContext,
+    question: &DnsQuestion,
+    subnet: Option<ClientSubnet>,
+    src: IpAddr,
+) -> Result<(DnsPacket, Status)> {
+    let view = context.view(src).map(|view| view.name.as_str());
+    let cache = &context.cache;
+    let cached = debug_span!("cache").in_scope(|| {
+        let mut cached = cache.get(view, subnet, &question.name, question.qtype);
+        // ANY is answered with whatever was cached for the name, RFC 8482 section 4.1
+        if question.qtype == QueryType::ANY {
+            cached = cached.or_else(|| cache.get_all(view, subnet, &question.name));
+        }
+        debug!(hit = cached.is_some());
+        context.counters.cache(cached.is_some());
+        cached
+    });
+    if let Some(cached) = cached {
+        return Ok((cached, Status::Cached));
+    }
+
+    let policy = context.config.retry_policy();
+    let query = question.clone();
+    let dnstap = context.dnstap.clone();
+    let proxy = context.config.proxy.clone();
+    let designated = Arc::clone(&context.designated);
+    let upstreams = context.forwarders(src).route(&question.name);
+    let transport = upstreams.transport().clone();
+    let in_flight = Arc::clone(&context.in_flight);
+    let forwarded = Arc::new(Mutex::new(Vec::new()));
+    let noted = Arc::clone(&forwarded);
+    let send = move |upstream| {
+        // Always with EDNS, which upstreams need to say why they fail with Extended DNS Errors
+        let options = Some(
+            subnet
+                .map(|subnet| subnet.to_option())
+                .into_iter()
+                .collect(),
+        );
+        let tls = designated
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&upstream)
+            .filter(|_| transport == Transport::Udp)
+            .cloned();
+        if let Some(tls) = tls {
+            let (qname, qtype) = (&query.name, query.qtype);
+            return lookup_tls(qname, qtype, &tls, options, policy, proxy.as_ref());
+        }
+        lookup_over(
+            &transport,
+            &query,
+            upstream,
+            options,
+            policy,
+            proxy.as_ref(),
+            &|exchange| {
+                if !exchange.response {
+                    if let Some(key) = in_flight.insert(exchange.wire) {
+                        lock(&noted).push(key);
+                    }
+                }
+                if let Some(dnstap) = &dnstap {
+                    dnstap.upstream(exchange);
+                }
+            },
+        )
+    };
+
+    let result = if context.config.race > 1 {
+        upstreams.race(context.config.race, send)
+    } else {
+        upstreams.query(send)
+    };
+    context.in_flight.remove(&lock(&forwarded));
+    let mut result = result.inspect_err(|e| {
+        if matches!(e, DnsError::Timeout) {
+            context.counters.timeout();
+        }
+    })?;
+    cache.insert(view, subnet, &question.name, question.qtype, &result);
+    // Answered with the TTLs it would have from the cache, whether or not it was cached
+    context.cache.limit_ttls(&mut result);
+
+    Ok((result, Status::Forwarded))
+}
+
+/// The queries forwarded to the upstreams and not answered yet, by their ID and their name in the
+/// case it was sent in, which a query coming back from an upstream has both of
+#[derive(Debug, Default)]
+struct InFlight(Mutex<HashMap<(u16, String), Instant>>);
+
+impl InFlight {
+    /// Note a query about to be sent to an upstream as `wire`, returning what it is known by
+    fn insert(&self, wire: &[u8]) -> Option<(u16, String)> {
+        let mut buf = BytePacketBuffer {
+            buf: wire.to_vec(),
+            pos: 0,
+        };
+        let query = DnsPacket::from_buffer(&mut buf).ok()?;
+        let key = (query.header.id, query.questions.first()?.name.to_string());
+
+        let mut queries = lock(&self.0);
+        // Queries whose forward is left to finish in the background, like the ones that lose a
+        // race, are only forgotten in time
+        let now = Instant::now();
+        queries.retain(|_, sent| now.duration_since(*sent) < IN_FLIGHT_TIMEOUT);
+        queries.insert(key.clone(), now);
+
+        Some(key)
+    }
+
+    fn remove(&self, keys: &[(u16, String)]) {
+        let mut queries = lock(&self.0);
+        for key in keys {
+            queries.remove(key);
+        }
+    }
+
+    /// Whether `request` is one of the queries sent to an upstream
+    fn contains(&self, request: &DnsPacket) -> bool {
+        let Some(question) = request.questions.first() else {
+            return false;
+        };
+        lock(&self.0).contains_key(&(request.header.id, question.name.to_string()))
+    }
+}
+
+/// The queries in flight are only ever added or removed whole, so poisoning is ignored
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Apply a dynamic update from `src` to one of the served zones, if the client is allowed to
+/// change it by its address or by the `key` the update was signed with
+pub fn handle_update(
+    context: &ServerContext,
+    update: &UpdateMessage,
+    src: IpAddr,
+    key: Option<&DnsName>,
+) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = update.header.id;
+    packet.header.opcode = OPCODE_UPDATE;
+    packet.header.response = true;
+    packet.questions.clone_from(&update.zones);
+
+    let [zone] = update.zones.as_slice() else {
+        packet.header.rescode = ResultCode::FORMERR;
+        return packet;
+    };
+    if zone.qtype != QueryType::SOA {
+        packet.header.rescode = ResultCode::FORMERR;
+        return packet;
+    }
+    if !context.config.allows_update(&zone.name, src, key) {
+        packet.header.rescode = ResultCode::REFUSED;
+        return packet;
+    }
+
+    let mut authority = context
+        .authority
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    let Some(target) = authority.zone_mut(&zone.name) else {
+        packet.header.rescode = ResultCode::NOTAUTH;
+        return packet;
+    };
+
+    match update::apply(target, update) {
+        Ok(true) => persist(context, target),
+        Ok(false) => {}
+        Err(rescode) => packet.header.rescode = rescode,
+    }
+
+    packet
+}
+
+/// Listen for queries on the configured address over both UDP and TCP, answering each UDP query
+/// and each TCP connection on its own thread
+pub fn run(context: Arc<ServerContext>) -> Result<()> {
+    let listeners = Listeners::bind(context.config.listen)?;
+    serve(context, listeners)
+}
+
+/// The sockets queries arrive on, bound apart from the rest of the server so it can give up root
+/// once they are, or have them passed by systemd
+#[derive(Debug)]
+pub struct Listeners {
+    pub udp: UdpSocket,
+    pub tcp: TcpListener,
+}
+
+impl Listeners {
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        Ok(Self {
+            udp: UdpSocket::bind(addr)?,
+            tcp: TcpListener::bind(addr)?,
+        })
+    }
+
+    /// The UDP socket and TCP listener systemd passed with socket activation, from
+    /// `ListenDatagram=` and `ListenStream=` in either order, or `None` when it passed none
+    pub fn from_systemd() -> Result<Option<Self>> {
+        let (mut udp, mut tcp) = (None, None);
+        for socket in daemon::listen_fds()? {
+            match socket.r#type()? {
+                Type::DGRAM if udp.is_none() => udp = Some(UdpSocket::from(socket)),
+                Type::STREAM if tcp.is_none() => tcp = Some(TcpListener::from(socket)),
+                _ => {
+                    return Err(DnsError::Config(String::from(
+                        "systemd passed other sockets than one UDP socket and one TCP listener",
+                    )))
+                }
+            }
+        }
+
+        match (udp, tcp) {
+            (Some(udp), Some(tcp)) => Ok(Some(Self { udp, tcp })),
+            (None, None) => Ok(None),
+            _ => Err(DnsError::Config(String::from(
+                "systemd has to pass both a UDP socket and a TCP listener",
+            ))),
+        }
+    }
+}
+
+/// Same as [`run`] on sockets that are already bound
+pub fn serve(context: Arc<ServerContext>, listeners: Listeners) -> Result<()> {
+    let Listeners {
+        udp: socket,
+        tcp: listener,
+    } = listeners;
+    info!("Listening on {}", socket.local_addr()?);
+
+    let tcp_context = Arc::clone(&context);
+    thread::spawn(move || run_tcp(&tcp_context, &listener));
+    let resign_context = Arc::clone(&context);
+    thread::spawn(move || resign(&resign_context));
+    let probe_context = Arc::clone(&context);
+    thread::spawn(move || probe(&probe_context));
+    if context.config.ddr {
+        let ddr_context = Arc::clone(&context);
+        thread::spawn(move || discover_designated(&ddr_context));
+    }
+    for (index, interval) in context.balancer.checked() {
+        let check_context = Arc::clone(&context);
+        thread::spawn(move || loop {
+            check_context.balancer.check(index);
+            thread::sleep(interval);
+        });
+    }
+    if context.blocklist.is_some() {
+        let refresh_context = Arc::clone(&context);
+        thread::spawn(move || refresh_blocklist(&refresh_context));
+    }
+    for index in 0..context.catalogs.len() {
+        let catalog_context = Arc::clone(&context);
+        thread::spawn(move || refresh_catalog(&catalog_context, index));
+    }
+    if let Some(config) = &context.config.mdns {
+        Responder::new(config).start()?;
+    }
+    if context.config.llmnr {
+        llmnr::start(&context)?;
+    }
+    if let Some(addr) = context.config.control {
+        let control_listener = TcpListener::bind(addr)?;
+        info!("Control listening on {addr}");
+        let control_context = Arc::clone(&context);
+        thread::spawn(move || control::serve(&control_context, &control_listener));
+    }
+
+    loop {
+        let mut req_buf = BytePacketBuffer::new();
+        let src = match socket.recv_from(&mut req_buf.buf) {
+            Ok((len, src)) => {
+                req_buf.buf.truncate(len);
+                src
+            }
+            Err(e) => {
+                warn!("Failed to receive query: {e}");
+                continue;
+            }
+        };
+
+        let socket = socket.try_clone()?;
+        let context = Arc::clone(&context);
+        thread::spawn(move || {
+            if let Err(e) = respond(&context, &socket, req_buf, src) {
+                warn!("Failed to answer {src}: {e}");
+            }
+        });
+    }
+}
+
+/// Write a changed zone back to its file, if it is configured to be
+fn persist(context: &ServerContext, zone: &Zone) {
+    let persist = context
+        .config
+        .zone(&zone.origin)
+        .filter(|config| config.persist);
+    if let Some(config) = persist {
+        if let Err(e) = fs::write(&config.file, zone.to_text()) {
+            warn!("Failed to write {}: {e}", config.file.display());
+        }
+    }
+}
+
+/// Refresh the signatures of the signed zones before they expire
+fn resign(context: &ServerContext) {
+    loop {
+        thread::sleep(RESIGN_INTERVAL);
+
+        let mut authority = context
+            .authority
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        for origin in authority.resign() {
+            if let Some(zone) = authority.zone_mut(&origin) {
+                persist(context, zone);
+            }
+        }
+    }
+}
+
+/// Periodically check whether upstreams that failed answer again, with a single query
+/// for the root nameservers
+fn probe(context: &ServerContext) {
+    let policy = RetryPolicy {
+        retries: 0,
+        ..context.config.retry_policy()
+    };
+    let question = DnsQuestion::new(DnsName::root(), QueryType::NS);
+    loop {
+        thread::sleep(PROBE_INTERVAL);
+
+        let views = context
+            .views
+            .iter()
+            .filter_map(|view| view.forwarders.as_ref());
+        for upstreams in views.chain([&context.forwarders]).flat_map(Forwarders::all) {
+            upstreams.probe(|upstream| {
+                let proxy = context.config.proxy.as_ref();
+                lookup_over(
+                    upstreams.transport(),
+                    &question,
+                    upstream,
+                    None,
+                    policy,
+                    proxy,
+                    &|_| {},
+                )
+            });
+        }
+    }
+}
+
+/// Ask every upstream for the encrypted resolver it designates, and again every
+/// [`ddr::DISCOVERY_INTERVAL`]. Upstreams that stop designating one are asked over plain DNS again,
+/// while the ones that can't be asked keep what they had.
+fn discover_designated(context: &ServerContext) {
+    let policy = context.config.retry_policy();
+    loop {
+        let views = context
+            .views
+            .iter()
+            .filter_map(|view| view.forwarders.as_ref());
+        let mut upstreams: Vec<SocketAddr> = views
+            .chain([&context.forwarders])
+            .flat_map(Forwarders::all)
+            .filter(|upstreams| *upstreams.transport() == Transport::Udp)
+            .flat_map(Upstreams::order)
+            .collect();
+        upstreams.sort_unstable();
+        upstreams.dedup();
+
+        for upstream in upstreams {
+            let discovered = ddr::discover(upstream, policy, context.config.proxy.as_ref());
+            let mut designated = context
+                .designated
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            match discovered {
+                Ok(Some(tls)) => {
+                    if designated.get(&upstream) != Some(&tls) {
+                        info!(
+                            "Sending the queries for {upstream} to {} at {} over TLS",
+                            tls.name, tls.addr
+                        );
+                    }
+                    designated.insert(upstream, tls);
+                }
+                Ok(None) => {
+                    if designated.remove(&upstream).is_some() {
+                        info!("{upstream} no longer designates a resolver, using plain DNS");
+                    }
+                }
+                Err(e) => warn!("Failed to discover the resolvers {upstream} designates: {e}"),
+            }
+        }
+
+        thread::sleep(ddr::DISCOVERY_INTERVAL);
+    }
+}
+
+/// Reload the blocklists on the configured interval. The lists are downloaded while queries are
+/// still answered from the old ones, which are then swapped out.
+fn refresh_blocklist(context: &ServerContext) {
+    let Some(config) = &context.config.blocklist else {
+        return;
+    };
+
+    loop {
+        thread::sleep(Duration::from_secs(config.refresh));
+        reload_blocklist(context);
+    }
+}
+
+/// Keep a catalog and its member zones up to date, checking each when its SOA timers say
+fn refresh_catalog(context: &ServerContext, index: usize) {
+    let catalog = &context.catalogs[index];
+    loop {
+        let next = catalog.refresh(&context.authority);
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Load the blocklists again, keeping the old version of the ones that fail. Returns how many
+/// domains are blocked now, or `None` without a blocklist.
+pub fn reload_blocklist(context: &ServerContext) -> Option<usize> {
+    let (Some(config), Some(blocklist)) = (&context.config.blocklist, &context.blocklist) else {
+        return None;
+    };
+
+    let refreshed = {
+        let current = blocklist.read().unwrap_or_else(PoisonError::into_inner);
+        Blocklist::load(&config.lists, &config.allow, Some(&current))
+    };
+    let len = refreshed.len();
+    info!("Blocking {len} domains");
+    *blocklist.write().unwrap_or_else(PoisonError::into_inner) = refreshed;
+
+    Some(len)
+}
+
+fn respond(
+    context: &ServerContext,
+    socket: &UdpSocket,
+    mut req_buf: BytePacketBuffer,
+    src: SocketAddr,
+) -> Result<()> {
+    let local = socket.local_addr()?;
+    if let Some(dnstap) = &context.dnstap {
+        dnstap.client_query(src, local, false, &req_buf.buf);
+    }
+
+    let mut tsig = match TsigSession::verify_request(&mut req_buf, &context.config.keys) {
+        Ok(tsig) => tsig,
+        // Messages that can't be read far enough to find a TSIG record are answered with FORMERR
+        Err(e) if e.is_malformed() => None,
+        Err(e) => {
+            warn!("Rejected request from {src}: {e}");
+            let res_buf = not_authorized(&mut req_buf, &context.config.keys)?;
+            socket.send_to(&res_buf.buf[0..res_buf.pos()], src)?;
+            return Ok(());
+        }
+    };
+    let key = tsig.as_ref().map(|tsig| tsig.key().name.clone());
+    // Dual-stack sockets see IPv4 clients as mapped IPv6 addresses
+    let client = src.ip().to_canonical();
+
+    let (mut response, max_len) = match Request::from_buffer(&mut req_buf)? {
+        Request::Query(request) => match handle_query(context, &request, client) {
+            Some(response) => (response, max_udp_len(request.edns())),
+            None => return Ok(()),
+        },
+        Request::Update(update) => (
+            handle_update(context, &update, client, key.as_ref()),
+            UDP_MAX_LEN,
+        ),
+        Request::Response => {
+            debug!("Ignoring response from {src}");
+            return Ok(());
+        }
+        Request::Error(header, rescode) => (error_response(&header, rescode), UDP_MAX_LEN),
+    };
+
+    // Signed requests come from clients holding a key, which can't be spoofed
+    if let (Some(limiter), None) = (&context.rate_limiter, &tsig) {
+        match limiter.check(client, &response) {
+            Verdict::Send => {}
+            Verdict::Drop => return Ok(()),
+            Verdict::Slip => response = rrl::truncated(&response),
+        }
+    }
+
+    // What doesn't fit is left out for the client to ask again over TCP, leaving room for the TSIG
+    // record of signed responses
+    let reserved = tsig.as_ref().map_or(0, TsigSession::record_len);
+    let mut res_buf = BytePacketBuffer::with_len(max_len.saturating_sub(reserved));
+    response.write_truncated(&mut res_buf)?;
+    if let Some(tsig) = &mut tsig {
+        res_buf.buf.resize(max_len, 0);
+        tsig.sign(&mut res_buf)?;
+    }
+    socket.send_to(&res_buf.buf[0..res_buf.pos()], src)?;
+    if let Some(dnstap) = &context.dnstap {
+        dnstap.client_response(src, local, false, &res_buf.buf[0..res_buf.pos()]);
+    }
+
+    Ok(())
+}
+
+fn run_tcp(context: &Arc<ServerContext>, listener: &TcpListener) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+
+        let context = Arc::clone(context);
+        thread::spawn(move || {
+            let src = stream.peer_addr();
+            if let Err(e) = respond_tcp(&context, stream) {
+                match src {
+                    Ok(src) => warn!("Failed to answer {src}: {e}"),
+                    Err(_) => warn!("Failed to answer connection: {e}"),
+                }
+            }
+        });
+    }
+}
+
+/// Answer queries on a TCP connection until the client closes it or goes idle
+fn respond_tcp(context: &ServerContext, mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+    let src = stream.peer_addr()?;
+    let local = stream.local_addr()?;
+    let client = src.ip().to_canonical();
+
+    // Any read error, including the client closing the connection, ends it
+    while let Ok(mut req_buf) = BytePacketBuffer::read_from(&mut stream) {
+        if let Some(dnstap) = &context.dnstap {
+            dnstap.client_query(src, local, true, &req_buf.buf);
+        }
+        let mut tsig = match TsigSession::verify_request(&mut req_buf, &context.config.keys) {
+            Ok(tsig) => tsig,
+            Err(e) if e.is_malformed() => None,
+            Err(e) => {
+                warn!("Rejected request from {src}: {e}");
+                not_authorized(&mut req_buf, &context.config.keys)?.write_to(&mut stream)?;
+                continue;
+            }
+        };
+        let key = tsig.as_ref().map(|tsig| tsig.key().name.clone());
+
+        let mut response = match Request::from_buffer(&mut req_buf)? {
+            Request::Query(request) => {
+                if let Some(records) = transfer_records(context, &request, src, key.as_ref()) {
+                    write_transfer(&mut stream, &request, records, tsig.as_mut())?;
+                    continue;
+                }
+                match handle_query(context, &request, client) {
+                    Some(mut response) => {
+                        advertise_keepalive(&request, &mut response);
+                        pad_like(&request, &mut response)?;
+                        response
+                    }
+                    None => continue,
+                }
+            }
+            Request::Update(update) => handle_update(context, &update, client, key.as_ref()),
+            Request::Response => {
+                debug!("Ignoring response from {src}");
+                continue;
+            }
+            Request::Error(header, rescode) => error_response(&header, rescode),
+        };
+
+        let res_buf = response.write_signed(tsig.as_mut())?;
+        res_buf.write_to(&mut stream)?;
+        if let Some(dnstap) = &context.dnstap {
+            dnstap.client_response(src, local, true, &res_buf.buf[..res_buf.pos()]);
+        }
+    }
+
+    Ok(())
+}
+
+/// Tell a client that asked with the edns-tcp-keepalive option how long its connection is kept
+/// open while idle, RFC 7828 section 3.3.2
+fn advertise_keepalive(request: &DnsPacket, response: &mut DnsPacket) {
+    if request.tcp_keepalive().is_none() {
+        return;
+    }
+    if let Some(options) = response.edns_options_mut() {
+        let keepalive = TcpKeepalive {
+            timeout: Some(TCP_IDLE_TIMEOUT),
+        };
+        options.push(keepalive.to_option());
+    }
+}
+
+/// Pad `response` when the client padded `request`, RFC 8467 section 4.1. Clients only pad on an
+/// encrypted transport, so here that is TLS terminated in front of the server.
+fn pad_like(request: &DnsPacket, response: &mut DnsPacket) -> Result<()> {
+    if request.edns_options(OPTION_PADDING).next().is_none() {
+        return Ok(());
+    }
+
+    response.pad(RESPONSE_BLOCK_LEN)
+}
+
+/// The records to stream for a zone transfer request from `src`, if it is one the client is
+/// allowed to make for a zone served here, by its address or the `key` the request was signed with
+fn transfer_records(
+    context: &ServerContext,
+    request: &DnsPacket,
+    src: SocketAddr,
+    key: Option<&DnsName>,
+) -> Option<Vec<DnsRecord>> {
+    let question = request.questions.first()?;
+    if !context
+        .config
+        .allows_transfer(&question.name, src.ip().to_canonical(), key)
+    {
+        return None;
+    }
+
+    match question.qtype {
+        QueryType::AXFR => context.authority().transfer(&question.name),
+        // The client sends the SOA of the version it has along with the question
+        QueryType::IXFR => {
+            let serial = request.authorities.first().and_then(soa_serial)?;
+            context
+                .authority()
+                .incremental_transfer(&question.name, serial)
+        }
+        _ => None,
+    }
+}
+
+/// The response to a request with a TSIG record that doesn't verify, with a TSIG record of its own
+/// saying why, see [`TsigSession::reject`]
+fn not_authorized(req_buf: &mut BytePacketBuffer, keys: &[TsigKey]) -> Result<BytePacketBuffer> {
+    let mut header = DnsHeader::new();
+    req_buf.seek(0)?;
+    header.read(req_buf)?;
+
+    let mut res_buf = BytePacketBuffer::new();
+    error_response(&header, ResultCode::NOTAUTH).write(&mut res_buf)?;
+    TsigSession::reject(req_buf, keys, &mut res_buf)?;
+
+    Ok(res_buf)
+}
+
+/// A response to a request that is only its header, with the result code saying what's wrong
+fn error_response(request: &DnsHeader, rescode: ResultCode) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.id = request.id;
+    packet.header.opcode = request.opcode;
+    packet.header.recursion_desired = request.recursion_desired;
+    packet.header.response = true;
+    packet.header.rescode = rescode;
+
+    packet
+}
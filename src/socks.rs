@@ -0,0 +1,265 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::{DnsError, Result};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USER_PASS: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xFF;
+/// Version of the username and password subnegotiation, RFC 1929
+const USER_PASS_VERSION: u8 = 1;
+
+const CONNECT: u8 = 1;
+const UDP_ASSOCIATE: u8 = 3;
+const COMMAND_NOT_SUPPORTED: u8 = 7;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// A SOCKS5 proxy the queries to the upstreams go through, RFC 1928, such as Tor or `ssh -D`
+///
+/// ```toml
+/// [proxy]
+/// addr = "127.0.0.1:1080"
+/// username = "dns"
+/// password = "secret"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Socks5Proxy {
+    pub addr: SocketAddr,
+    /// Credentials for proxies that ask for them, RFC 1929
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Relay UDP queries with UDP ASSOCIATE where the proxy supports it. Without it, or when the
+    /// proxy doesn't, queries are sent over TCP.
+    #[serde(default = "default_udp")]
+    pub udp: bool,
+}
+
+const fn default_udp() -> bool {
+    true
+}
+
+impl Socks5Proxy {
+    /// A proxy at `addr` that doesn't ask for credentials
+    pub const fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            username: None,
+            password: None,
+            udp: true,
+        }
+    }
+
+    /// A TCP connection to `target` through the proxy
+    pub fn connect(&self, target: SocketAddr, timeout: Duration) -> Result<TcpStream> {
+        let mut stream = self.open(timeout)?;
+        self.request(&mut stream, CONNECT, target)?
+            .ok_or_else(|| self.error("CONNECT isn't supported"))?;
+
+        Ok(stream)
+    }
+
+    /// A relay for UDP datagrams through the proxy, or `None` if it doesn't support UDP
+    pub fn udp_associate(&self, timeout: Duration) -> Result<Option<UdpRelay>> {
+        let mut control = self.open(timeout)?;
+        let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        let Some(mut relay) = self.request(&mut control, UDP_ASSOCIATE, unspecified)? else {
+            return Ok(None);
+        };
+        // Proxies answer with an unspecified address for the one they were reached at
+        if relay.ip().is_unspecified() {
+            relay.set_ip(self.addr.ip());
+        }
+
+        let local: IpAddr = match relay {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0))?;
+        socket.connect(relay)?;
+
+        Ok(Some(UdpRelay {
+            _control: control,
+            socket,
+        }))
+    }
+
+    /// Connect to the proxy and authenticate
+    fn open(&self, timeout: Duration) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect_timeout(&self.addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+
+        let method = if self.username.is_some() {
+            USER_PASS
+        } else {
+            NO_AUTH
+        };
+        stream.write_all(&[VERSION, 1, method])?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply)?;
+        match reply {
+            [VERSION, NO_ACCEPTABLE_METHOD] => {
+                return Err(self.error("no acceptable authentication method"))
+            }
+            [VERSION, chosen] if chosen == method => {}
+            _ => return Err(self.error("invalid reply to the greeting")),
+        }
+
+        if method == USER_PASS {
+            let username = self.username.as_deref().unwrap_or_default();
+            let password = self.password.as_deref().unwrap_or_default();
+            let (Ok(username_len), Ok(password_len)) =
+                (u8::try_from(username.len()), u8::try_from(password.len()))
+            else {
+                return Err(self.error("username or password longer than 255 bytes"));
+            };
+            let mut auth = vec![USER_PASS_VERSION, username_len];
+            auth.extend_from_slice(username.as_bytes());
+            auth.push(password_len);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth)?;
+
+            stream.read_exact(&mut reply)?;
+            if reply[1] != 0 {
+                return Err(self.error("username or password rejected"));
+            }
+        }
+
+        Ok(stream)
+    }
+
+    /// Send a command for `target` and read the address the proxy bound for it, or `None` when
+    /// the proxy doesn't support the command
+    fn request(
+        &self,
+        stream: &mut TcpStream,
+        command: u8,
+        target: SocketAddr,
+    ) -> Result<Option<SocketAddr>> {
+        let mut request = vec![VERSION, command, 0];
+        write_addr(&mut request, target);
+        stream.write_all(&request)?;
+
+        let mut reply = [0; 3];
+        stream.read_exact(&mut reply)?;
+        match reply {
+            [VERSION, 0, _] => {}
+            [VERSION, COMMAND_NOT_SUPPORTED, _] => return Ok(None),
+            [VERSION, code, _] => return Err(self.error(&format!("request failed with {code}"))),
+            _ => return Err(self.error("invalid reply to the request")),
+        }
+
+        read_addr(stream)
+            .map(Some)
+            .map_err(|_| self.error("invalid address in the reply"))
+    }
+
+    fn error(&self, message: &str) -> DnsError {
+        DnsError::Proxy(format!("{}: {message}", self.addr))
+    }
+}
+
+/// UDP datagrams relayed through a SOCKS5 proxy, for as long as the connection that set the relay
+/// up stays open
+#[derive(Debug)]
+pub struct UdpRelay {
+    /// The relay is torn down when this is closed
+    _control: TcpStream,
+    socket: UdpSocket,
+}
+
+impl UdpRelay {
+    /// Send `buf` to `target` through the relay
+    pub fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        // No fragmentation
+        let mut datagram = vec![0, 0, 0];
+        write_addr(&mut datagram, target);
+        let header = datagram.len();
+        datagram.extend_from_slice(buf);
+
+        Ok(self.socket.send(&datagram)? - header)
+    }
+
+    /// Receive a datagram into `buf`, with the address it came from through the relay
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut datagram = vec![0; buf.len() + 22];
+        loop {
+            let len = self.socket.recv(&mut datagram)?;
+            let mut received = &datagram[..len];
+            // Fragments aren't reassembled, so they are dropped
+            let Some(([0, 0, 0], rest)) = received.split_first_chunk() else {
+                continue;
+            };
+            received = rest;
+            let Ok(src) = read_addr(&mut received) else {
+                continue;
+            };
+
+            let len = received.len().min(buf.len());
+            buf[..len].copy_from_slice(&received[..len]);
+            return Ok((len, src));
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+/// Append an address as ATYP, address and port
+fn write_addr(buf: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(v4) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&v4.octets());
+        }
+        IpAddr::V6(v6) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&v6.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Read an address written as ATYP, address and port. Domain names can't be resolved here, so
+/// they are read past and stand for the unspecified address.
+fn read_addr(reader: &mut impl Read) -> io::Result<SocketAddr> {
+    let mut atyp = [0; 1];
+    reader.read_exact(&mut atyp)?;
+    let ip = match atyp[0] {
+        ATYP_IPV4 => {
+            let mut octets = [0; 4];
+            reader.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0; 16];
+            reader.read_exact(&mut octets)?;
+            IpAddr::from(octets)
+        }
+        ATYP_DOMAIN => {
+            let mut len = [0; 1];
+            reader.read_exact(&mut len)?;
+            io::copy(&mut reader.take(len[0].into()), &mut io::sink())?;
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        }
+        _ => return Err(io::ErrorKind::InvalidData.into()),
+    };
+    let mut port = [0; 2];
+    reader.read_exact(&mut port)?;
+
+    Ok(SocketAddr::new(ip, u16::from_be_bytes(port)))
+}
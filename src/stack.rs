@@ -0,0 +1,100 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::fmt;
+#[cfg(feature = "async")]
+use core::future::Future;
+
+use crate::error::{DnsError, Result};
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+/// Something that answers questions, like the forwarding and recursive resolvers, the cache, the
+/// hosts file or the served zones. Resolvers stack: layers like the cache and the blocklist wrap
+/// the one below them, and backends that only know some names fail with [`DnsError::NoAnswer`] for
+/// the rest, so the next one in [`Resolver::or`] gets asked.
+///
+/// ```no_run
+/// use dns_server::cache::{Cache, Cached};
+/// use dns_server::hosts::Hosts;
+/// use dns_server::resolver::{Forwarder, RetryPolicy};
+/// use dns_server::stack::Resolver;
+/// use dns_server::upstream::Upstreams;
+/// use dns_server::{DnsQuestion, QueryType};
+///
+/// let hosts = Hosts::load("/etc/hosts", 300).unwrap();
+/// let upstreams = Upstreams::new(vec!["192.0.2.53:53".parse().unwrap()]);
+/// let forwarder = Forwarder::new(upstreams, RetryPolicy::default());
+/// let stack = hosts.or(Cached::new(Cache::new(1000), forwarder));
+///
+/// let question = DnsQuestion::new("example.com".parse().unwrap(), QueryType::A);
+/// let response = stack.resolve(&question).unwrap();
+/// ```
+pub trait Resolver {
+    /// The answer to `question`, with the records and result code of a response. The header is
+    /// filled in for the client by whoever sends it, apart from the result code and AA flag.
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket>;
+
+    /// Ask `next` what this resolver has no answer for
+    fn or<R: Resolver>(self, next: R) -> Fallback<Self, R>
+    where
+        Self: Sized,
+    {
+        Fallback { first: self, next }
+    }
+}
+
+/// Two resolvers asked in order, see [`Resolver::or`]
+#[derive(Debug, Clone)]
+pub struct Fallback<A, B> {
+    first: A,
+    next: B,
+}
+
+impl<A: Resolver, B: Resolver> Resolver for Fallback<A, B> {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        match self.first.resolve(question) {
+            Err(DnsError::NoAnswer(_)) => self.next.resolve(question),
+            result => result,
+        }
+    }
+}
+
+impl<R: Resolver + ?Sized> Resolver for &R {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        (**self).resolve(question)
+    }
+}
+
+impl<R: Resolver + ?Sized> Resolver for Box<R> {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        (**self).resolve(question)
+    }
+}
+
+impl<R: Resolver + ?Sized> Resolver for Arc<R> {
+    fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        (**self).resolve(question)
+    }
+}
+
+/// So resolvers plugged into the server can be part of its state
+impl fmt::Debug for dyn Resolver + Send + Sync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Resolver")
+    }
+}
+
+/// A [`Resolver`] for async runtimes. Every resolver is one, but the ones of this crate block the
+/// task that awaits them while they wait on the network.
+#[cfg(feature = "async")]
+pub trait AsyncResolver {
+    /// The answer to `question`, see [`Resolver::resolve`]
+    fn resolve(&self, question: &DnsQuestion) -> impl Future<Output = Result<DnsPacket>> + Send;
+}
+
+#[cfg(feature = "async")]
+impl<R: Resolver + Sync> AsyncResolver for R {
+    async fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        Resolver::resolve(self, question)
+    }
+}
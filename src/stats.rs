@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::header::ResultCode;
+use crate::question::QueryType;
+
+/// Result codes fit in the 4 bits of the header
+const RCODES: usize = 16;
+
+/// Counters updated as queries are answered, shared by every thread
+#[derive(Debug)]
+pub struct Counters {
+    started: Instant,
+    queries: AtomicU64,
+    /// Responses by the value of their result code
+    rcodes: [AtomicU64; RCODES],
+    /// Queries answered with nothing at all
+    dropped: AtomicU64,
+    /// Query types vary too much for a fixed set of counters
+    qtypes: Mutex<HashMap<QueryType, u64>>,
+    /// Forwarded queries that no upstream answered in time
+    timeouts: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            queries: AtomicU64::new(0),
+            rcodes: Default::default(),
+            dropped: AtomicU64::new(0),
+            qtypes: Mutex::new(HashMap::new()),
+            timeouts: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Counters {
+    /// Count a query, of `qtype` unless it had no question
+    pub fn query(&self, qtype: Option<QueryType>) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        if let Some(qtype) = qtype {
+            *self
+                .qtypes
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(qtype)
+                .or_default() += 1;
+        }
+    }
+
+    /// Count a response with `rescode`, or a query that was dropped for `None`
+    pub fn response(&self, rescode: Option<ResultCode>) {
+        match rescode {
+            Some(rescode) => self.rcodes[rescode as usize].fetch_add(1, Ordering::Relaxed),
+            None => self.dropped.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters as they are now
+    pub fn snapshot(&self) -> Stats {
+        // Only the values of known result codes are ever counted, the others stay 0
+        let responses = self
+            .rcodes
+            .iter()
+            .enumerate()
+            .map(|(value, count)| (ResultCode::from(value as u8), count.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+
+        let mut qtypes: Vec<_> = self
+            .qtypes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(&qtype, &count)| (qtype, count))
+            .collect();
+        qtypes.sort_unstable();
+
+        Stats {
+            uptime: self.started.elapsed(),
+            queries: self.queries.load(Ordering::Relaxed),
+            responses,
+            dropped: self.dropped.load(Ordering::Relaxed),
+            qtypes,
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// What the server has done since it started, see [`crate::server::ServerContext::stats`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub uptime: Duration,
+    /// Client queries, including the ones refused or dropped
+    pub queries: u64,
+    /// Responses by result code, leaving out the ones never sent
+    pub responses: Vec<(ResultCode, u64)>,
+    /// Queries dropped without a response, by a response policy
+    pub dropped: u64,
+    /// Queries by type, sorted by type
+    pub qtypes: Vec<(QueryType, u64)>,
+    /// Forwarded queries that no upstream answered in time
+    pub timeouts: u64,
+    /// Forwarded queries answered from the cache
+    pub cache_hits: u64,
+    /// Forwarded queries that had to go to an upstream
+    pub cache_misses: u64,
+}
@@ -0,0 +1,115 @@
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::error::{DnsError, Result};
+use crate::name::DnsName;
+use crate::socks::Socks5Proxy;
+
+/// The port DNS over TLS servers listen on, RFC 7858
+pub const DOT_PORT: u16 = 853;
+
+/// The ALPN protocol ID of DNS over TLS, RFC 7858 section 3.1
+pub const ALPN_DOT: &str = "dot";
+
+/// A connection to a DNS over TLS server, which messages are written to and read from like a TCP
+/// stream
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// An upstream that queries are sent to with DNS over TLS, RFC 7858
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsUpstream {
+    pub addr: SocketAddr,
+    /// The name the certificate of the server is checked against
+    pub name: DnsName,
+}
+
+impl TlsUpstream {
+    /// A TLS connection to the server, through `proxy` if any, once the handshake is done and its
+    /// certificate was found valid for its name by the web PKI
+    pub fn connect(&self, timeout: Duration, proxy: Option<&Socks5Proxy>) -> Result<TlsStream> {
+        let mut tcp = match proxy {
+            Some(proxy) => proxy.connect(self.addr, timeout)?,
+            None => TcpStream::connect_timeout(&self.addr, timeout)?,
+        };
+        tcp.set_read_timeout(Some(timeout))?;
+        tcp.set_write_timeout(Some(timeout))?;
+
+        let name = ServerName::try_from(self.name.as_str().to_string())
+            .map_err(|e| self.error(&e.to_string()))?;
+        let mut conn = ClientConnection::new(Arc::clone(client_config()), name)
+            .map_err(|e| self.error(&e.to_string()))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut tcp)
+                .map_err(|e| self.error(&format!("handshake failed: {e}")))?;
+        }
+
+        Ok(StreamOwned::new(conn, tcp))
+    }
+
+    /// Check that the certificate the server sent over `stream` is also valid for `addr`, which a
+    /// designated resolver has to show for the address of the resolver that designated it
+    pub fn verify_ip(&self, stream: &TlsStream, addr: IpAddr) -> Result<()> {
+        let Some((end_entity, intermediates)) = stream
+            .conn
+            .peer_certificates()
+            .and_then(|certs| certs.split_first())
+        else {
+            return Err(self.error("no certificate"));
+        };
+
+        verifier()
+            .verify_server_cert(
+                end_entity,
+                intermediates,
+                &ServerName::IpAddress(addr.into()),
+                &[],
+                UnixTime::now(),
+            )
+            .map(|_| ())
+            .map_err(|e| self.error(&format!("certificate isn't valid for {addr}: {e}")))
+    }
+
+    fn error(&self, message: &str) -> DnsError {
+        DnsError::Tls(format!("{} ({}): {message}", self.addr, self.name))
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn roots() -> Arc<RootCertStore> {
+    Arc::new(RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    })
+}
+
+/// Trusting the roots of the web PKI and offering only the `dot` protocol
+fn client_config() -> &'static Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let mut config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .expect("the ring provider supports the default protocol versions")
+            .with_root_certificates(roots())
+            .with_no_client_auth();
+        config.alpn_protocols = vec![ALPN_DOT.as_bytes().to_vec()];
+        Arc::new(config)
+    })
+}
+
+fn verifier() -> &'static Arc<WebPkiServerVerifier> {
+    static VERIFIER: OnceLock<Arc<WebPkiServerVerifier>> = OnceLock::new();
+    VERIFIER.get_or_init(|| {
+        WebPkiServerVerifier::builder_with_provider(roots(), provider())
+            .build()
+            .expect("the web PKI roots aren't empty")
+    })
+}
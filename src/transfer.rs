@@ -0,0 +1,227 @@
+use std::io::Write;
+use std::iter;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::buffer::{BytePacketBuffer, TCP_MAX_LEN};
+use crate::error::{DnsError, Result};
+use crate::header::ResultCode;
+use crate::journal::{soa_serial, ZoneDiff};
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+use crate::question::{DnsQuestion, QueryType};
+use crate::record::{DnsRecord, RData};
+use crate::tsig::{self, TsigKey, TsigSession};
+use crate::zone::Zone;
+
+/// How long to wait for the primary to connect or send the next message
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pull every record of `zone` from `server` with an AXFR over TCP
+///
+/// The records are returned in the order the server sent them, starting with the SOA. The copy of
+/// the SOA that closes the transfer is dropped.
+///
+/// With a `key` the query is signed with TSIG, and every response has to be signed with it too.
+pub fn axfr(zone: &DnsName, server: SocketAddr, key: Option<&TsigKey>) -> Result<Vec<DnsRecord>> {
+    let mut tsig = key.map(TsigSession::new);
+    let (mut stream, query) = send_query(zone, QueryType::AXFR, None, server, tsig.as_mut())?;
+
+    let mut records: Vec<DnsRecord> = Vec::new();
+    loop {
+        for rec in read_answers(&mut stream, &query, server, tsig.as_mut())? {
+            let is_soa = matches!(rec.rdata, RData::SOA { .. });
+            match records.first() {
+                None if !is_soa || rec.domain() != zone => {
+                    return Err(DnsError::Transfer(format!(
+                        "Transfer from {server} doesn't start with the SOA of {zone}"
+                    )));
+                }
+                // The zone is complete when its SOA comes around again
+                Some(_) if is_soa => return Ok(records),
+                _ => records.push(rec),
+            }
+        }
+    }
+}
+
+/// Pull a zone from `server` with [`axfr`], ready to be served
+pub fn axfr_zone(origin: &DnsName, server: SocketAddr, key: Option<&TsigKey>) -> Result<Zone> {
+    Ok(Zone::new(origin.clone(), axfr(origin, server, key)?))
+}
+
+/// Bring `zone` up to date from `server` with an IXFR over TCP
+///
+/// The primary answers with the changes since the serial of `zone` when it still has them, which
+/// are applied one by one and kept in the zone journal, or with the whole zone otherwise. Returns
+/// whether the zone changed. A `key` signs the exchange like for [`axfr`].
+pub fn ixfr(zone: &mut Zone, server: SocketAddr, key: Option<&TsigKey>) -> Result<bool> {
+    let origin = zone.origin.clone();
+    let soa = zone
+        .soa()
+        .cloned()
+        .ok_or_else(|| DnsError::Transfer(format!("No SOA to transfer {origin} from")))?;
+    let mut tsig = key.map(TsigSession::new);
+    let (mut stream, query) =
+        send_query(&origin, QueryType::IXFR, Some(soa), server, tsig.as_mut())?;
+
+    let mut records: Vec<DnsRecord> = Vec::new();
+    loop {
+        let first_message = records.is_empty();
+        records.extend(read_answers(&mut stream, &query, server, tsig.as_mut())?);
+
+        let newest = &records[0];
+        if !is_soa(newest) || *newest.domain() != origin {
+            return Err(DnsError::Transfer(format!(
+                "Transfer from {server} doesn't start with the SOA of {origin}"
+            )));
+        }
+        if first_message && records.len() == 1 {
+            break;
+        }
+
+        // An incremental transfer has the newest SOA once more at the end of the last change
+        let incremental = is_soa(&records[1]);
+        let closing = if incremental { 3 } else { 2 };
+        if records.iter().filter(|rec| *rec == newest).count() >= closing {
+            break;
+        }
+    }
+
+    if records.len() == 1 {
+        if soa_serial(&records[0]) == zone.serial() {
+            return Ok(false);
+        }
+        // Only the SOA of a newer version, the primary wants a full transfer instead
+        zone.update(axfr(&origin, server, key)?);
+        return Ok(true);
+    }
+
+    records.pop();
+    if !is_soa(&records[1]) {
+        zone.update(records);
+        return Ok(true);
+    }
+
+    let mut changes = records.into_iter().skip(1).peekable();
+    while let Some(from_soa) = changes.next() {
+        if soa_serial(&from_soa) != zone.serial() {
+            return Err(DnsError::Transfer(format!(
+                "Changes from {server} don't apply to the current version of {origin}"
+            )));
+        }
+        let removed = iter::from_fn(|| changes.next_if(|rec| !is_soa(rec))).collect();
+        let to_soa = changes
+            .next()
+            .ok_or_else(|| DnsError::Transfer(format!("Incomplete change from {server}")))?;
+        let added = iter::from_fn(|| changes.next_if(|rec| !is_soa(rec))).collect();
+
+        zone.apply(ZoneDiff {
+            from_soa,
+            to_soa,
+            removed,
+            added,
+        });
+    }
+
+    Ok(true)
+}
+
+const fn is_soa(rec: &DnsRecord) -> bool {
+    matches!(rec.rdata, RData::SOA { .. })
+}
+
+/// Connect to `server` and ask for a transfer of `zone`. IXFR queries carry the SOA of the
+/// version the client has in the authority section.
+fn send_query(
+    zone: &DnsName,
+    qtype: QueryType,
+    soa: Option<DnsRecord>,
+    server: SocketAddr,
+    tsig: Option<&mut TsigSession<'_>>,
+) -> Result<(TcpStream, DnsPacket)> {
+    let mut stream = TcpStream::connect_timeout(&server, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut query = DnsPacket::new();
+    query.header.id = 666;
+    query.questions.push(DnsQuestion::new(zone.clone(), qtype));
+    query.authorities.extend(soa);
+    query.write_signed_to(&mut stream, tsig)?;
+
+    Ok((stream, query))
+}
+
+/// Read the next message of a transfer and check that it belongs to it
+fn read_answers(
+    stream: &mut TcpStream,
+    query: &DnsPacket,
+    server: SocketAddr,
+    tsig: Option<&mut TsigSession<'_>>,
+) -> Result<Vec<DnsRecord>> {
+    let mut buffer = BytePacketBuffer::read_from(stream)?;
+    if let Some(tsig) = tsig {
+        tsig.verify(&mut buffer)?;
+    }
+    let response = DnsPacket::from_buffer(&mut buffer)?;
+    if !response.header.response || response.header.id != query.header.id {
+        return Err(DnsError::Transfer(format!(
+            "Unexpected message from {server}"
+        )));
+    }
+    if response.header.rescode != ResultCode::NOERROR {
+        return Err(DnsError::Transfer(format!(
+            "{:?} from {server}",
+            response.header.rescode
+        )));
+    }
+    if response.answers.is_empty() {
+        return Err(DnsError::Transfer(format!("Empty message from {server}")));
+    }
+
+    Ok(response.answers)
+}
+
+/// Answer an AXFR or IXFR `request` by streaming `records` as a series of messages, each filled as
+/// far as the TCP message size allows. `records` should start and end with the zone SOA. Every
+/// message is signed when the request was.
+pub fn write_transfer(
+    stream: &mut impl Write,
+    request: &DnsPacket,
+    records: Vec<DnsRecord>,
+    mut tsig: Option<&mut TsigSession<'_>>,
+) -> Result<()> {
+    let new_message = || {
+        let mut packet = DnsPacket::new();
+        packet.header.id = request.header.id;
+        packet.header.response = true;
+        packet.header.authoritative_answer = true;
+        packet
+    };
+
+    // Names aren't compressed, so a message is as long as its parts written on their own
+    let limit = TCP_MAX_LEN - tsig.as_ref().map_or(0, |_| tsig::MAX_RECORD_LEN);
+    let mut scratch = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    let mut packet = new_message();
+    packet.questions.clone_from(&request.questions);
+    let mut len = 12;
+    for question in &packet.questions {
+        scratch.pos = 0;
+        question.write(&mut scratch)?;
+        len += scratch.pos();
+    }
+
+    for rec in records {
+        scratch.pos = 0;
+        let rec_len = rec.write(&mut scratch)?;
+        if len + rec_len > limit && !packet.answers.is_empty() {
+            packet.write_signed_to(stream, tsig.as_deref_mut())?;
+            packet = new_message();
+            len = 12;
+        }
+        len += rec_len;
+        packet.answers.push(rec);
+    }
+
+    packet.write_signed_to(stream, tsig)
+}
@@ -0,0 +1,485 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::{Sha256, Sha512};
+
+use crate::buffer::BytePacketBuffer;
+use crate::error::{DnsError, Result};
+use crate::header::DnsHeader;
+use crate::name::DnsName;
+
+/// Type code of the TSIG meta record
+const TSIG_TYPE: u16 = 250;
+const CLASS_ANY: u16 = 255;
+
+/// Largest TSIG record this server writes, to leave room for in a message
+pub const MAX_RECORD_LEN: usize = 255 + 10 + 255 + 16 + 64;
+
+/// Clock difference allowed between the signer and the verifier, in seconds
+pub const FUDGE: u16 = 300;
+
+/// The MAC didn't verify, RFC 8945 section 3
+pub const BADSIG: u16 = 16;
+/// The key isn't known, or doesn't go with the algorithm
+pub const BADKEY: u16 = 17;
+/// The time signed is further from the current time than the fudge allows
+pub const BADTIME: u16 = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "net", derive(serde::Deserialize))]
+#[cfg_attr(feature = "net", serde(rename_all = "kebab-case"))]
+pub enum TsigAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    const fn mnemonic(self) -> &'static str {
+        match self {
+            Self::HmacSha256 => "hmac-sha256",
+            Self::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    /// The algorithm name used on the wire
+    fn name(self) -> DnsName {
+        DnsName::from_validated(self.mnemonic().to_string())
+    }
+
+    /// Octets of the MACs of the algorithm
+    const fn mac_len(self) -> usize {
+        match self {
+            Self::HmacSha256 => 32,
+            Self::HmacSha512 => 64,
+        }
+    }
+
+    fn mac(self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("any key size");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Self::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(secret).expect("any key size");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+/// A named shared secret for signing messages with TSIG (RFC 8945)
+///
+/// ```toml
+/// [[keys]]
+/// name = "transfer-key"
+/// algorithm = "hmac-sha256"
+/// secret = "c2VjcmV0IGtleSBmb3IgdHJhbnNmZXJz"
+/// ```
+#[derive(Clone)]
+#[cfg_attr(feature = "net", derive(serde::Deserialize))]
+#[cfg_attr(feature = "net", serde(deny_unknown_fields))]
+pub struct TsigKey {
+    #[cfg_attr(
+        feature = "net",
+        serde(deserialize_with = "crate::name::deserialize_name")
+    )]
+    pub name: DnsName,
+    pub algorithm: TsigAlgorithm,
+    /// The secret, base64 encoded in the config
+    #[cfg_attr(feature = "net", serde(deserialize_with = "deserialize_secret"))]
+    pub secret: Vec<u8>,
+}
+
+/// Keeps the secret out of logs
+impl fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TsigKey")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "net")]
+fn deserialize_secret<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<u8>, D::Error> {
+    use base64::prelude::{Engine, BASE64_STANDARD};
+
+    let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+
+    BASE64_STANDARD
+        .decode(s)
+        .map_err(|e| serde::de::Error::custom(format!("Invalid base64 secret: {e}")))
+}
+
+/// The TSIG record at the end of a message
+#[derive(Debug)]
+struct TsigRecord {
+    /// Where the record starts, the signed part of the message ends there
+    start: usize,
+    key_name: DnsName,
+    algorithm: DnsName,
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl TsigRecord {
+    /// Find the TSIG record, which has to be the last record of the message. The buffer is left at
+    /// the start of the message for parsing it afterwards.
+    fn find(buf: &mut BytePacketBuffer) -> Result<Option<Self>> {
+        buf.seek(0)?;
+        let tsig = Self::read(buf);
+        buf.seek(0)?;
+
+        tsig
+    }
+
+    fn read(buf: &mut BytePacketBuffer) -> Result<Option<Self>> {
+        let mut header = DnsHeader::new();
+        header.read(buf)?;
+        if header.resource_entries == 0 {
+            return Ok(None);
+        }
+
+        for _ in 0..header.questions {
+            buf.read_name()?;
+            buf.step(4)?;
+        }
+
+        let records = u32::from(header.answers)
+            + u32::from(header.authoritative_entries)
+            + u32::from(header.resource_entries);
+        let mut start = 0;
+        let mut qtype = 0;
+        for _ in 0..records {
+            start = buf.pos();
+            buf.read_name()?;
+            qtype = buf.read_u16()?;
+            buf.step(6)?;
+            let data_len = buf.read_u16()?;
+            buf.read_range(data_len.into())?;
+        }
+        if qtype != TSIG_TYPE {
+            return Ok(None);
+        }
+
+        buf.seek(start)?;
+        let key_name = buf.read_name()?;
+        buf.step(10)?;
+        let algorithm = buf.read_name()?;
+        let time_signed = (u64::from(buf.read_u16()?) << 32) | u64::from(buf.read_u32()?);
+        let fudge = buf.read_u16()?;
+        let mac_len = buf.read_u16()?;
+        let mac = buf.read_range(mac_len.into())?.to_vec();
+        let original_id = buf.read_u16()?;
+        let error = buf.read_u16()?;
+        let other_len = buf.read_u16()?;
+        let other = buf.read_range(other_len.into())?.to_vec();
+
+        Ok(Some(Self {
+            start,
+            key_name,
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        }))
+    }
+
+    /// Append the record to the message written to `buf` so far, counting it in the header
+    fn write(&self, buf: &mut BytePacketBuffer) -> Result<()> {
+        buf.write_qname(&self.key_name)?;
+        buf.write_u16(TSIG_TYPE)?;
+        buf.write_u16(CLASS_ANY)?;
+        buf.write_u32(0)?;
+        let len_pos = buf.pos();
+        buf.write_u16(0)?;
+
+        buf.write_qname(&self.algorithm)?;
+        buf.write_u16((self.time_signed >> 32) as u16)?;
+        buf.write_u32(self.time_signed as u32)?;
+        buf.write_u16(self.fudge)?;
+        buf.write_u16(self.mac.len() as u16)?;
+        for &b in &self.mac {
+            buf.write_u8(b)?;
+        }
+        buf.write_u16(self.original_id)?;
+        buf.write_u16(self.error)?;
+        buf.write_u16(self.other.len() as u16)?;
+        for &b in &self.other {
+            buf.write_u8(b)?;
+        }
+
+        let size = buf.pos() - (len_pos + 2);
+        buf.set_u16(len_pos, size as u16)?;
+
+        let arcount = u16::from_be_bytes([buf.buf[10], buf.buf[11]]);
+        buf.set_u16(10, arcount + 1)?;
+
+        Ok(())
+    }
+}
+
+/// Signs or verifies the messages of one exchange with a key
+///
+/// A reply is tied to the request it answers, and every message of a multi-message response to the
+/// ones before it, by covering the previous MAC.
+#[derive(Debug)]
+pub struct TsigSession<'a> {
+    key: &'a TsigKey,
+    /// MAC of the last signed message of the exchange
+    prior_mac: Option<Vec<u8>>,
+    /// Whether the next message continues a response, in which case only the timers are covered
+    continued: bool,
+    /// Unsigned messages since the last signed one, covered by the next MAC
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+}
+
+impl<'a> TsigSession<'a> {
+    pub const fn new(key: &'a TsigKey) -> Self {
+        Self {
+            key,
+            prior_mac: None,
+            continued: false,
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+        }
+    }
+
+    /// Verify a signed request against the configured keys. Returns `None` for unsigned requests,
+    /// or the session to sign the replies with.
+    pub fn verify_request(buf: &mut BytePacketBuffer, keys: &'a [TsigKey]) -> Result<Option<Self>> {
+        let Some(tsig) = TsigRecord::find(buf)? else {
+            return Ok(None);
+        };
+        let key = keys
+            .iter()
+            .find(|key| key.name == tsig.key_name)
+            .ok_or_else(|| DnsError::Tsig(format!("Unknown key {}", tsig.key_name)))?;
+
+        let mut session = Self::new(key);
+        session.check(buf, &tsig)?;
+
+        Ok(Some(session))
+    }
+
+    /// Append the TSIG record telling why a request failed [`TsigSession::verify_request`] to its
+    /// NOTAUTH response in `buf`, RFC 8945 section 5.3.2. The record names the key and algorithm
+    /// of the request with an empty MAC, since an unknown key or a bad signature leave nothing to
+    /// sign with. Only a response to a request signed at a bad time is signed, with the current
+    /// time of the server for the client to see how far off it is.
+    pub fn reject(
+        request: &mut BytePacketBuffer,
+        keys: &[TsigKey],
+        buf: &mut BytePacketBuffer,
+    ) -> Result<()> {
+        let Some(tsig) = TsigRecord::find(request)? else {
+            return Ok(());
+        };
+        let key = keys.iter().find(|key| key.name == tsig.key_name);
+        let error = match key {
+            Some(key) => match TsigSession::new(key).authenticate(request, &tsig) {
+                Err((error, _)) => error,
+                Ok(_) => return Ok(()),
+            },
+            None => BADKEY,
+        };
+
+        let mut record = TsigRecord {
+            start: buf.pos(),
+            key_name: tsig.key_name.clone(),
+            algorithm: tsig.algorithm.clone(),
+            time_signed: now(),
+            fudge: FUDGE,
+            mac: Vec::new(),
+            original_id: tsig.original_id,
+            error,
+            other: Vec::new(),
+        };
+        if let (BADTIME, Some(key)) = (error, key) {
+            // Signed like any response to the request, covering its MAC, but with the time it was
+            // signed at
+            let mut session = TsigSession::new(key);
+            session.prior_mac = Some(tsig.mac);
+            record.time_signed = tsig.time_signed;
+            record.other = now().to_be_bytes()[2..].to_vec();
+            record.mac = session.mac(
+                &buf.buf[..buf.pos()],
+                record.time_signed,
+                record.fudge,
+                error,
+                &record.other,
+            );
+        }
+
+        record.write(buf)
+    }
+
+    /// The key messages are signed with
+    pub const fn key(&self) -> &TsigKey {
+        self.key
+    }
+
+    /// Octets of the TSIG record [`TsigSession::sign`] appends, to leave room for in a message
+    pub fn record_len(&self) -> usize {
+        let algorithm = self.key.algorithm;
+        self.key.name.wire_len() + 10 + algorithm.name().wire_len() + 16 + algorithm.mac_len()
+    }
+
+    /// Sign the message written to `buf` so far, appending the TSIG record
+    pub fn sign(&mut self, buf: &mut BytePacketBuffer) -> Result<()> {
+        let end = buf.pos();
+        let message = buf.buf[..end].to_vec();
+        let time_signed = now();
+        let record = TsigRecord {
+            start: end,
+            key_name: self.key.name.clone(),
+            algorithm: self.key.algorithm.name(),
+            time_signed,
+            fudge: FUDGE,
+            mac: self.mac(&message, time_signed, FUDGE, 0, &[]),
+            original_id: u16::from_be_bytes([message[0], message[1]]),
+            error: 0,
+            other: Vec::new(),
+        };
+        record.write(buf)?;
+
+        self.advance(&message, record.mac);
+
+        Ok(())
+    }
+
+    /// Verify a message of the exchange. Only the messages following the first one of a response
+    /// may be left unsigned, which are then covered by the next signed one.
+    pub fn verify(&mut self, buf: &mut BytePacketBuffer) -> Result<()> {
+        match TsigRecord::find(buf)? {
+            Some(tsig) => {
+                if tsig.key_name != self.key.name {
+                    return Err(DnsError::Tsig(format!("Unexpected key {}", tsig.key_name)));
+                }
+                self.check(buf, &tsig)
+            }
+            // At most 99 messages in a row may be left unsigned
+            None if self.continued && self.unsigned_count < 99 => {
+                self.unsigned.extend_from_slice(&buf.buf);
+                self.unsigned_count += 1;
+                Ok(())
+            }
+            None => Err(DnsError::Tsig("Message isn't signed".to_string())),
+        }
+    }
+
+    fn check(&mut self, buf: &BytePacketBuffer, tsig: &TsigRecord) -> Result<()> {
+        let (message, mac) = self
+            .authenticate(buf, tsig)
+            .map_err(|(_, reason)| DnsError::Tsig(reason))?;
+        self.advance(&message, mac);
+
+        Ok(())
+    }
+
+    /// The message the TSIG record covers and its MAC, or the TSIG error code saying why it
+    /// doesn't verify along with the reason
+    fn authenticate(
+        &self,
+        buf: &BytePacketBuffer,
+        tsig: &TsigRecord,
+    ) -> core::result::Result<(Vec<u8>, Vec<u8>), (u16, String)> {
+        if tsig.algorithm != self.key.algorithm.name() {
+            let reason = format!("Unexpected algorithm {}", tsig.algorithm);
+            return Err((BADKEY, reason));
+        }
+        if tsig.error != 0 {
+            return Err((BADSIG, format!("Peer reported error {}", tsig.error)));
+        }
+
+        // The MAC covers the message as it was before the TSIG record was added
+        let mut message = buf.buf[..tsig.start].to_vec();
+        message[..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+        let arcount = u16::from_be_bytes([message[10], message[11]]) - 1;
+        message[10..12].copy_from_slice(&arcount.to_be_bytes());
+
+        let mac = self.mac(
+            &message,
+            tsig.time_signed,
+            tsig.fudge,
+            tsig.error,
+            &tsig.other,
+        );
+        if !constant_time_eq(&mac, &tsig.mac) {
+            return Err((BADSIG, "Bad signature".to_string()));
+        }
+        if now().abs_diff(tsig.time_signed) > tsig.fudge.into() {
+            let reason = "Signature time outside of the allowed fudge".to_string();
+            return Err((BADTIME, reason));
+        }
+
+        Ok((message, mac))
+    }
+
+    /// The MAC for a message, covering the previous MAC and any unsigned messages before it
+    fn mac(
+        &self,
+        message: &[u8],
+        time_signed: u64,
+        fudge: u16,
+        error: u16,
+        other: &[u8],
+    ) -> Vec<u8> {
+        let mut data = Vec::with_capacity(message.len() + 128);
+        if let Some(prior) = &self.prior_mac {
+            data.extend_from_slice(&(prior.len() as u16).to_be_bytes());
+            data.extend_from_slice(prior);
+        }
+        data.extend_from_slice(&self.unsigned);
+        data.extend_from_slice(message);
+
+        if !self.continued {
+            data.extend(self.key.name.canonical_wire());
+            data.extend_from_slice(&CLASS_ANY.to_be_bytes());
+            data.extend_from_slice(&0u32.to_be_bytes());
+            data.extend(self.key.algorithm.name().canonical_wire());
+        }
+        data.extend_from_slice(&time_signed.to_be_bytes()[2..]);
+        data.extend_from_slice(&fudge.to_be_bytes());
+        if !self.continued {
+            data.extend_from_slice(&error.to_be_bytes());
+            data.extend_from_slice(&(other.len() as u16).to_be_bytes());
+            data.extend_from_slice(other);
+        }
+
+        self.key.algorithm.mac(&self.key.secret, &data)
+    }
+
+    fn advance(&mut self, message: &[u8], mac: Vec<u8>) {
+        // Every message after the first of a response only covers the timers
+        let is_response = message[2] & 0x80 != 0;
+        self.continued = is_response;
+        self.prior_mac = Some(mac);
+        self.unsigned.clear();
+        self.unsigned_count = 0;
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
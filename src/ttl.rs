@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+
+/// A TTL counting down from when its record was received: the seconds it started with and the
+/// moment they run out, so it can be sent on with the time it has left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ttl {
+    original: u32,
+    expires: Instant,
+}
+
+impl Ttl {
+    /// A TTL of `secs` starting now
+    pub fn new(secs: u32) -> Self {
+        Self::starting_at(secs, Instant::now())
+    }
+
+    /// A TTL of `secs` starting at `start`
+    pub fn starting_at(secs: u32, start: Instant) -> Self {
+        Self {
+            original: secs,
+            expires: start + Duration::from_secs(secs.into()),
+        }
+    }
+
+    /// The seconds the TTL started with
+    pub const fn original(&self) -> u32 {
+        self.original
+    }
+
+    pub const fn expires(&self) -> Instant {
+        self.expires
+    }
+
+    /// Seconds left, counting the one it is in, and 0 once it expired
+    pub fn remaining(&self) -> u32 {
+        self.remaining_at(Instant::now())
+    }
+
+    /// Seconds left at `now`, counting the one it is in, and 0 once it expired
+    pub fn remaining_at(&self, now: Instant) -> u32 {
+        let left = self.expires.saturating_duration_since(now);
+        (left.as_secs() + u64::from(left.subsec_nanos() > 0)) as u32
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(Instant::now())
+    }
+
+    pub fn is_expired_at(&self, now: Instant) -> bool {
+        self.expires <= now
+    }
+
+    /// The same TTL kept between `min` and `max` seconds, counting from when it started
+    pub fn clamp(self, min: u32, max: u32) -> Self {
+        let start = self.expires - Duration::from_secs(self.original.into());
+        Self::starting_at(self.original.clamp(min, max), start)
+    }
+}
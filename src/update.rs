@@ -0,0 +1,299 @@
+use crate::buffer::BytePacketBuffer;
+use crate::error::Result;
+use crate::header::{DnsHeader, ResultCode};
+use crate::journal::serial_gt;
+use crate::name::DnsName;
+use crate::question::{DnsQuestion, QueryType, CLASS_IN};
+use crate::record::{DnsRecord, RData};
+use crate::zone::Zone;
+
+/// The class of a record in an UPDATE message, which decides what the record means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum UpdateClass {
+    UNKNOWN(u16),
+    IN,   // 1
+    NONE, // 254
+    ANY,  // 255
+}
+
+impl From<u16> for UpdateClass {
+    fn from(n: u16) -> Self {
+        match n {
+            1 => Self::IN,
+            254 => Self::NONE,
+            255 => Self::ANY,
+            _ => Self::UNKNOWN(n),
+        }
+    }
+}
+
+/// A record from the prerequisite or update section of an UPDATE message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateRecord {
+    pub name: DnsName,
+    pub qtype: QueryType,
+    pub class: UpdateClass,
+    pub ttl: u32,
+    /// The record itself, for entries that carry rdata
+    pub record: Option<DnsRecord>,
+}
+
+impl UpdateRecord {
+    pub fn read(buf: &mut BytePacketBuffer) -> Result<Self> {
+        let start = buf.pos();
+        let name = buf.read_name()?;
+        let qtype = QueryType::from(buf.read_u16()?);
+        let class = UpdateClass::from(buf.read_u16()?);
+        let ttl = buf.read_u32()?;
+        let data_len = buf.read_u16()?;
+
+        // Deletions of whole RRsets and most prerequisites have no rdata at all. The class says
+        // what the entry means, the record itself is in the Internet class like the zone.
+        let record = if data_len == 0 {
+            None
+        } else {
+            buf.seek(start)?;
+            let mut record = DnsRecord::read(buf)?;
+            record.class = CLASS_IN;
+            Some(record)
+        };
+
+        Ok(Self {
+            name,
+            qtype,
+            class,
+            ttl,
+            record,
+        })
+    }
+}
+
+/// A dynamic update message from RFC 2136
+///
+/// It shares the layout of a query, but the sections hold the zone, the prerequisites and the
+/// updates, and the class of each record changes its meaning.
+#[derive(Debug, Clone)]
+pub struct UpdateMessage {
+    pub header: DnsHeader,
+    pub zones: Vec<DnsQuestion>,
+    pub prerequisites: Vec<UpdateRecord>,
+    pub updates: Vec<UpdateRecord>,
+}
+
+impl UpdateMessage {
+    pub fn from_buffer(buf: &mut BytePacketBuffer) -> Result<Self> {
+        let mut header = DnsHeader::new();
+        header.read(buf)?;
+
+        let mut zones = Vec::with_capacity(header.questions.into());
+        for _ in 0..header.questions {
+            let mut zone = DnsQuestion::new(DnsName::root(), QueryType::UNKNOWN(0));
+            zone.read(buf)?;
+            zones.push(zone);
+        }
+        let prerequisites = (0..header.answers)
+            .map(|_| UpdateRecord::read(buf))
+            .collect::<Result<_>>()?;
+        let updates = (0..header.authoritative_entries)
+            .map(|_| UpdateRecord::read(buf))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            header,
+            zones,
+            prerequisites,
+            updates,
+        })
+    }
+}
+
+/// Check the prerequisites of `update` against `zone` and apply its changes, bumping the SOA serial
+/// when anything changed. Returns whether the zone changed, or the result code to refuse the update
+/// with.
+pub fn apply(zone: &mut Zone, update: &UpdateMessage) -> Result<bool, ResultCode> {
+    check_prerequisites(zone, &update.prerequisites)?;
+    prescan(zone, &update.updates)?;
+
+    let mut records = zone.records.clone();
+    for rr in &update.updates {
+        apply_one(&mut records, &zone.origin, rr);
+    }
+    if records == zone.records {
+        return Ok(false);
+    }
+
+    // Secondaries only notice the change when the serial moves, so bump it unless the update did
+    let old_serial = zone.serial();
+    if let Some(RData::SOA { serial, .. }) = records
+        .iter_mut()
+        .map(|rec| &mut rec.rdata)
+        .find(|rdata| matches!(rdata, RData::SOA { .. }))
+    {
+        if Some(*serial) == old_serial {
+            *serial = serial.wrapping_add(1);
+        }
+    }
+    zone.update(records);
+
+    Ok(true)
+}
+
+/// Whether two records are the same apart from their TTL
+fn same_rr(a: &DnsRecord, b: &DnsRecord) -> bool {
+    let mut b = b.clone();
+    b.set_ttl(a.ttl());
+
+    *a == b
+}
+
+const fn is_meta(qtype: QueryType) -> bool {
+    matches!(qtype, QueryType::ANY | QueryType::AXFR | QueryType::IXFR)
+}
+
+fn check_prerequisites(zone: &Zone, prerequisites: &[UpdateRecord]) -> Result<(), ResultCode> {
+    let mut rrsets = Vec::new();
+    for pr in prerequisites {
+        if pr.ttl != 0 {
+            return Err(ResultCode::FORMERR);
+        }
+        if !pr.name.is_subdomain_of(&zone.origin) {
+            return Err(ResultCode::NOTZONE);
+        }
+
+        let mut at_name = zone.records.iter().filter(|rec| *rec.domain() == pr.name);
+        match (pr.class, &pr.record) {
+            // The name, or an RRset at it, is in use
+            (UpdateClass::ANY, None) if pr.qtype == QueryType::ANY => {
+                if at_name.next().is_none() {
+                    return Err(ResultCode::NXDOMAIN);
+                }
+            }
+            (UpdateClass::ANY, None) => {
+                if !at_name.any(|rec| rec.qtype() == pr.qtype) {
+                    return Err(ResultCode::NXRRSET);
+                }
+            }
+            // The name, or an RRset at it, is not in use
+            (UpdateClass::NONE, None) if pr.qtype == QueryType::ANY => {
+                if at_name.next().is_some() {
+                    return Err(ResultCode::YXDOMAIN);
+                }
+            }
+            (UpdateClass::NONE, None) => {
+                if at_name.any(|rec| rec.qtype() == pr.qtype) {
+                    return Err(ResultCode::YXRRSET);
+                }
+            }
+            // An RRset exists with exactly these records, checked once they are all collected
+            (UpdateClass::IN, Some(rec)) if !is_meta(pr.qtype) => rrsets.push(rec),
+            _ => return Err(ResultCode::FORMERR),
+        }
+    }
+
+    for rec in &rrsets {
+        let same_set =
+            |other: &&DnsRecord| other.domain() == rec.domain() && other.qtype() == rec.qtype();
+        let wanted: Vec<&DnsRecord> = rrsets.iter().copied().filter(same_set).collect();
+        let have: Vec<&DnsRecord> = zone.records.iter().filter(same_set).collect();
+
+        let covers =
+            |a: &[&DnsRecord], b: &[&DnsRecord]| a.iter().all(|x| b.iter().any(|y| same_rr(x, y)));
+        if !covers(&wanted, &have) || !covers(&have, &wanted) {
+            return Err(ResultCode::NXRRSET);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reject the whole update before changing anything if any part of it is invalid
+fn prescan(zone: &Zone, updates: &[UpdateRecord]) -> Result<(), ResultCode> {
+    for rr in updates {
+        if !rr.name.is_subdomain_of(&zone.origin) {
+            return Err(ResultCode::NOTZONE);
+        }
+
+        let valid = match rr.class {
+            UpdateClass::IN => rr.record.is_some() && !is_meta(rr.qtype),
+            UpdateClass::ANY => {
+                rr.ttl == 0
+                    && rr.record.is_none()
+                    && !matches!(rr.qtype, QueryType::AXFR | QueryType::IXFR)
+            }
+            UpdateClass::NONE => rr.ttl == 0 && rr.record.is_some() && !is_meta(rr.qtype),
+            UpdateClass::UNKNOWN(_) => false,
+        };
+        if !valid {
+            return Err(ResultCode::FORMERR);
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_one(records: &mut Vec<DnsRecord>, origin: &DnsName, rr: &UpdateRecord) {
+    let at_apex = rr.name == *origin;
+    // The SOA and NS records at the apex can be replaced but never removed
+    let protected =
+        |rec: &DnsRecord| at_apex && matches!(rec.qtype(), QueryType::SOA | QueryType::NS);
+
+    match (rr.class, &rr.record) {
+        (UpdateClass::IN, Some(new)) => add(records, at_apex, new),
+        (UpdateClass::ANY, None) => records.retain(|rec| {
+            *rec.domain() != rr.name
+                || protected(rec)
+                || (rr.qtype != QueryType::ANY && rec.qtype() != rr.qtype)
+        }),
+        (UpdateClass::NONE, Some(old)) => {
+            let apex_ns = records
+                .iter()
+                .filter(|rec| at_apex && rec.qtype() == QueryType::NS && *rec.domain() == rr.name)
+                .count();
+            let keep = old.qtype() == QueryType::SOA
+                || (old.qtype() == QueryType::NS && at_apex && apex_ns <= 1);
+            if !keep {
+                records.retain(|rec| !same_rr(rec, old));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn add(records: &mut Vec<DnsRecord>, at_apex: bool, new: &DnsRecord) {
+    let name = new.domain();
+    let mut at_name = records.iter().filter(|rec| rec.domain() == name);
+
+    match new.qtype() {
+        QueryType::SOA => {
+            let newer = records.iter().position(|rec| {
+                matches!(
+                    (&rec.rdata, &new.rdata),
+                    (RData::SOA { serial: old, .. }, RData::SOA { serial, .. })
+                        if serial_gt(*serial, *old)
+                )
+            });
+            if let (true, Some(i)) = (at_apex, newer) {
+                records[i] = new.clone();
+            }
+            return;
+        }
+        // A CNAME can't share its name with other data
+        QueryType::CNAME => {
+            if at_name.any(|rec| rec.qtype() != QueryType::CNAME) {
+                return;
+            }
+            records.retain(|rec| !(rec.domain() == name && rec.qtype() == QueryType::CNAME));
+        }
+        _ => {
+            if at_name.any(|rec| rec.qtype() == QueryType::CNAME) {
+                return;
+            }
+        }
+    }
+
+    match records.iter().position(|rec| same_rr(rec, new)) {
+        Some(i) => records[i] = new.clone(),
+        None => records.push(new.clone()),
+    }
+}
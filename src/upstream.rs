@@ -0,0 +1,266 @@
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, debug_span, info, warn, Span};
+
+use crate::error::{DnsError, Result};
+use crate::name::DnsName;
+use crate::packet::DnsPacket;
+
+/// Consecutive failures after which an upstream is marked down
+const MAX_FAILURES: u32 = 3;
+
+/// What is known about how an upstream has been doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Health {
+    /// Smoothed round trip time of successful queries, `None` until one succeeds
+    pub rtt: Option<Duration>,
+    /// Failed queries since the last one that succeeded
+    pub failures: u32,
+    /// Marked down after too many failures, until a probe gets an answer again
+    pub down: bool,
+}
+
+impl Health {
+    fn record_success(&mut self, rtt: Duration) {
+        // Weighted like the smoothed RTT of TCP, so one slow answer doesn't reorder everything
+        self.rtt = Some(match self.rtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+            None => rtt,
+        });
+        self.failures = 0;
+        self.down = false;
+    }
+
+    fn record_failure(&mut self) {
+        self.failures += 1;
+        self.down |= self.failures >= MAX_FAILURES;
+    }
+}
+
+/// How queries get to a set of upstreams
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// Plain DNS over UDP, or DNS over TLS to the resolver an upstream designates with `ddr`
+    #[default]
+    Udp,
+    /// Plain DNS over TCP
+    Tcp,
+    /// DNS over TLS, RFC 7858, with the certificates of the upstreams checked against `name`
+    Tls { name: DnsName },
+    /// DNS over HTTPS, RFC 8484, with the queries POSTed to `url` at the address of each upstream
+    Https { url: String },
+}
+
+/// A set of upstream resolvers that queries are forwarded to, tried from the fastest healthy one
+/// down and failing over to the next when one doesn't answer. Clones share the health of the
+/// upstreams.
+#[derive(Debug, Clone)]
+pub struct Upstreams {
+    addrs: Arc<[SocketAddr]>,
+    /// Health of each address in `addrs`, at the same index
+    health: Arc<Mutex<Vec<Health>>>,
+    transport: Transport,
+}
+
+impl Upstreams {
+    /// Upstreams queried over UDP
+    pub fn new(addrs: Vec<SocketAddr>) -> Self {
+        let health = Arc::new(Mutex::new(vec![Health::default(); addrs.len()]));
+
+        Self {
+            addrs: addrs.into(),
+            health,
+            transport: Transport::default(),
+        }
+    }
+
+    /// The same upstreams queried over `transport`
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub const fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    /// The upstreams with their health, in the order they were configured
+    pub fn health(&self) -> Vec<(SocketAddr, Health)> {
+        self.addrs
+            .iter()
+            .copied()
+            .zip(self.lock().clone())
+            .collect()
+    }
+
+    /// The order to try upstreams in: the healthy ones by recent failures then round trip time,
+    /// those that haven't answered yet first, and the ones marked down last, in case nothing else
+    /// answers either
+    pub fn order(&self) -> Vec<SocketAddr> {
+        let health = self.lock();
+        let mut order: Vec<_> = self.addrs.iter().copied().zip(health.iter()).collect();
+        order.sort_by_key(|(_, health)| (health.down, health.failures, health.rtt));
+
+        order.into_iter().map(|(addr, _)| addr).collect()
+    }
+
+    /// Send a query with `send` to each upstream in [`Upstreams::order`] until one answers,
+    /// keeping track of how each of them did. Fails with the error of the last upstream tried.
+    pub fn query(&self, send: impl FnMut(SocketAddr) -> Result<DnsPacket>) -> Result<DnsPacket> {
+        self.query_each(self.order(), send, None)
+    }
+
+    /// Send a query with `send` to the first `count` upstreams in [`Upstreams::order`] at once and
+    /// return the first response, for when answering fast matters more than the load on the
+    /// upstreams. The queries that lose the race are left to finish in the background, where they
+    /// still count towards the health of their upstream. When all of them fail, the remaining
+    /// upstreams are tried one at a time.
+    pub fn race(
+        &self,
+        count: usize,
+        send: impl Fn(SocketAddr) -> Result<DnsPacket> + Send + Sync + 'static,
+    ) -> Result<DnsPacket> {
+        let mut order = self.order();
+        let rest = order.split_off(count.clamp(1, order.len().max(1)));
+
+        let send = Arc::new(send);
+        let (tx, rx) = mpsc::channel();
+        for addr in order {
+            let upstreams = self.clone();
+            let send = Arc::clone(&send);
+            let tx = tx.clone();
+            // Spans don't follow threads on their own
+            let span = Span::current();
+            thread::spawn(move || {
+                let _entered = span.enter();
+                let result = upstreams.send_to(addr, &*send);
+                // Nobody listens anymore once another upstream won
+                let _ = tx.send(result);
+            });
+        }
+        drop(tx);
+
+        let mut last_err = None;
+        for result in rx {
+            match result {
+                Ok(response) => return Ok(response),
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        self.query_each(rest, |addr| send(addr), last_err)
+    }
+
+    /// Try each of `addrs` in turn, failing with the error of the last one or `last_err` when
+    /// there is none
+    fn query_each(
+        &self,
+        addrs: Vec<SocketAddr>,
+        mut send: impl FnMut(SocketAddr) -> Result<DnsPacket>,
+        mut last_err: Option<DnsError>,
+    ) -> Result<DnsPacket> {
+        for addr in addrs {
+            match self.send_to(addr, &mut send) {
+                Ok(response) => return Ok(response),
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| DnsError::Config("No upstream configured".to_string())))
+    }
+
+    /// Send a query to `addr` with `send`, keeping track of how it did
+    fn send_to(
+        &self,
+        addr: SocketAddr,
+        send: impl FnOnce(SocketAddr) -> Result<DnsPacket>,
+    ) -> Result<DnsPacket> {
+        let _span = debug_span!("upstream", %addr).entered();
+        let start = Instant::now();
+        let result = send(addr);
+        match &result {
+            Ok(_) => {
+                debug!(rtt = ?start.elapsed(), "Answered");
+                self.record(addr, |health| health.record_success(start.elapsed()));
+            }
+            Err(e) => {
+                warn!("Upstream {addr} failed: {e}");
+                self.record(addr, Health::record_failure);
+            }
+        }
+
+        result
+    }
+
+    /// Send a query with `send` to each upstream that failed recently, whether or not it is marked
+    /// down yet, bringing back the ones that answer
+    pub fn probe(&self, mut send: impl FnMut(SocketAddr) -> Result<DnsPacket>) {
+        let failing: Vec<_> = self
+            .health()
+            .into_iter()
+            .filter(|(_, health)| health.failures > 0)
+            .map(|(addr, _)| addr)
+            .collect();
+
+        for addr in failing {
+            let start = Instant::now();
+            if send(addr).is_ok() {
+                self.record(addr, |health| health.record_success(start.elapsed()));
+            }
+        }
+    }
+
+    /// Update the health of `addr`, logging when it goes down or comes back
+    fn record(&self, addr: SocketAddr, update: impl FnOnce(&mut Health)) {
+        let mut health = self.lock();
+        let Some(index) = self.addrs.iter().position(|a| *a == addr) else {
+            return;
+        };
+
+        let was_down = health[index].down;
+        update(&mut health[index]);
+        match (was_down, health[index].down) {
+            (false, true) => warn!("Upstream {addr} is down"),
+            (true, false) => info!("Upstream {addr} is back up"),
+            _ => {}
+        }
+    }
+
+    /// Health is only ever updated as a whole, so poisoning is ignored
+    fn lock(&self) -> MutexGuard<'_, Vec<Health>> {
+        self.health.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Upstreams chosen by the domain of the query, for split DNS where some domains are only known to
+/// resolvers of their own
+#[derive(Debug, Clone)]
+pub struct Forwarders {
+    /// Where everything goes that no rule matches
+    default: Upstreams,
+    /// Domains with their own upstreams, each covering the domain and everything below it
+    rules: Vec<(DnsName, Upstreams)>,
+}
+
+impl Forwarders {
+    pub fn new(default: Upstreams, rules: Vec<(DnsName, Upstreams)>) -> Self {
+        Self { default, rules }
+    }
+
+    /// The upstreams of the rule with the longest domain that `qname` is in, or the default ones
+    pub fn route(&self, qname: &DnsName) -> &Upstreams {
+        self.rules
+            .iter()
+            .filter(|(domain, _)| qname.is_subdomain_of(domain))
+            .max_by_key(|(domain, _)| domain.label_count())
+            .map_or(&self.default, |(_, upstreams)| upstreams)
+    }
+
+    /// Every set of upstreams, the default one first
+    pub fn all(&self) -> impl Iterator<Item = &Upstreams> {
+        std::iter::once(&self.default).chain(self.rules.iter().map(|(_, upstreams)| upstreams))
+    }
+}
@@ -0,0 +1,66 @@
+use std::net::IpAddr;
+
+use crate::authority::Authority;
+use crate::config::ViewConfig;
+use crate::error::Result;
+use crate::local::LocalRecords;
+use crate::name::DnsName;
+use crate::network::Network;
+use crate::packet::DnsPacket;
+use crate::question::QueryType;
+use crate::upstream::{Forwarders, Upstreams};
+
+/// What the clients from some networks see on top of everything the server answers
+#[derive(Debug)]
+pub struct View {
+    pub name: String,
+    clients: Vec<Network>,
+    pub authority: Authority,
+    pub local_records: LocalRecords,
+    /// Where queries from the clients of the view are forwarded, when it has upstreams or rules
+    /// of its own
+    pub forwarders: Option<Forwarders>,
+}
+
+impl View {
+    /// Load the zones of a view. Without upstreams of its own, its forwarding rules send
+    /// everything else to `default`.
+    pub fn load(config: &ViewConfig, default: &Upstreams) -> Result<Self> {
+        let forwarders = if config.upstream.is_empty() && config.forward.is_empty() {
+            None
+        } else {
+            let upstreams = if config.upstream.is_empty() {
+                default.clone()
+            } else {
+                Upstreams::new(config.upstream.clone())
+            };
+            let rules = config
+                .forward
+                .iter()
+                .map(|rule| Ok((rule.domain.clone(), rule.upstreams()?)))
+                .collect::<Result<_>>()?;
+            Some(Forwarders::new(upstreams, rules))
+        };
+
+        Ok(Self {
+            name: config.name.clone(),
+            clients: config.match_clients.clone(),
+            authority: Authority::load(&config.zones)?,
+            local_records: LocalRecords::new(config.local_records()?),
+            forwarders,
+        })
+    }
+
+    /// Whether the view applies to queries from `client`
+    pub fn matches(&self, client: IpAddr) -> bool {
+        self.clients.iter().any(|network| network.contains(client))
+    }
+
+    /// Answer a question from the records and zones of the view, or `None` if it doesn't have
+    /// the name
+    pub fn lookup(&self, qname: &DnsName, qtype: QueryType, dnssec_ok: bool) -> Option<DnsPacket> {
+        self.local_records
+            .lookup(qname, qtype)
+            .or_else(|| self.authority.lookup(qname, qtype, dnssec_ok))
+    }
+}
@@ -0,0 +1,674 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+#[cfg(feature = "net")]
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+#[cfg(feature = "net")]
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::buffer::BytePacketBuffer;
+use crate::dnssec::{self, ZoneKey};
+use crate::error::{DnsError, Result};
+use crate::journal::{soa_serial, Journal, ZoneDiff};
+use crate::name::{unescape, DnsName};
+use crate::question::QueryType;
+use crate::record::{DnsRecord, RData};
+
+/// The records of a zone, loaded from an RFC 1035 master file
+#[derive(Debug, Clone)]
+pub struct Zone {
+    /// The apex of the zone, taken from the SOA owner when the file has one
+    pub origin: DnsName,
+    /// The records, changed through [`Zone::update`] and [`Zone::apply`] so the names in the zone
+    /// are indexed again
+    pub records: Vec<DnsRecord>,
+    /// Recent changes, for serving incremental transfers
+    pub journal: Journal,
+    /// Keys the zone is signed with, again whenever it changes
+    pub keys: Vec<ZoneKey>,
+    /// Every owner name with the empty non-terminals between it and the apex
+    names: HashSet<DnsName>,
+}
+
+impl Zone {
+    /// Read and parse a zone file. `origin` is used for relative names until the file sets its own
+    /// with `$ORIGIN`.
+    #[cfg(feature = "net")]
+    pub fn load(path: impl AsRef<Path>, origin: &DnsName) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+
+        Self::parse(&text, origin)
+    }
+
+    /// Parse master file text into records
+    pub fn parse(text: &str, origin: &DnsName) -> Result<Self> {
+        let mut parser = Parser::new(origin);
+        for entry in tokenize(text)? {
+            parser.entry(&entry)?;
+        }
+
+        let origin = parser
+            .records
+            .iter()
+            .find(|rec| matches!(rec.rdata, RData::SOA { .. }))
+            .map_or_else(|| origin.clone(), |soa| soa.domain().clone());
+
+        Ok(Self::new(origin, parser.records))
+    }
+
+    /// An unsigned zone with `records` and nothing in its journal yet
+    pub fn new(origin: DnsName, records: Vec<DnsRecord>) -> Self {
+        let mut zone = Self {
+            origin,
+            records,
+            journal: Journal::default(),
+            keys: Vec::new(),
+            names: HashSet::new(),
+        };
+        zone.index();
+
+        zone
+    }
+
+    /// Whether `name` exists in the zone, either with records of its own or as an empty
+    /// non-terminal with records below it
+    pub fn exists(&self, name: &DnsName) -> bool {
+        self.names.contains(name)
+    }
+
+    /// Collect the names that exist from the owners of the records
+    fn index(&mut self) {
+        self.names.clear();
+        for rec in &self.records {
+            let mut name = rec.domain().clone();
+            // Once a name is in, so are the ones above it
+            while name != self.origin && self.names.insert(name.clone()) {
+                let Some(parent) = name.parent() else {
+                    break;
+                };
+                name = parent;
+            }
+        }
+        self.names.insert(self.origin.clone());
+    }
+
+    /// The SOA record at the apex of the zone
+    pub fn soa(&self) -> Option<&DnsRecord> {
+        self.records
+            .iter()
+            .find(|rec| matches!(rec.rdata, RData::SOA { .. }) && *rec.domain() == self.origin)
+    }
+
+    /// The serial of the zone SOA
+    pub fn serial(&self) -> Option<u32> {
+        self.soa().and_then(soa_serial)
+    }
+
+    /// Whether the zone is signed with DNSSEC
+    pub fn is_signed(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Sign the zone with `keys`, see [`dnssec::sign_zone`]
+    pub fn sign(&mut self, keys: Vec<ZoneKey>) {
+        self.keys = keys;
+        let records = self.records.clone();
+        self.update(records);
+    }
+
+    /// Replace signatures that are about to expire, bumping the serial if there were any so
+    /// secondaries pick them up
+    pub fn resign(&mut self) {
+        let mut records = self.records.clone();
+        dnssec::sign_zone(&self.origin, &mut records, &self.keys, &self.records);
+        if records == self.records {
+            return;
+        }
+
+        if let Some(RData::SOA { serial, .. }) = records
+            .iter_mut()
+            .map(|rec| &mut rec.rdata)
+            .find(|rdata| matches!(rdata, RData::SOA { .. }))
+        {
+            *serial = serial.wrapping_add(1);
+        }
+        self.update(records);
+    }
+
+    /// Replace the records with a new version of the zone, keeping the difference in the journal
+    /// when the serial changed. Signed zones are signed again first.
+    pub fn update(&mut self, mut records: Vec<DnsRecord>) {
+        dnssec::sign_zone(&self.origin, &mut records, &self.keys, &self.records);
+        match ZoneDiff::between(&self.records, &records) {
+            Some(diff) if diff.from_soa != diff.to_soa => self.journal.record(diff),
+            _ => self.journal.clear(),
+        }
+        self.records = records;
+        self.index();
+    }
+
+    /// Apply a change to the zone and keep it in the journal
+    pub fn apply(&mut self, diff: ZoneDiff) {
+        diff.apply(&mut self.records);
+        self.index();
+        self.journal.record(diff);
+    }
+
+    /// The zone in master file format, e.g. to write it back to its file after an update. The
+    /// DNSSEC records of a signed zone are left out, since they are generated when it is loaded.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("$ORIGIN {}.\n", self.origin);
+        let signed = self.is_signed();
+        for rec in self
+            .records
+            .iter()
+            .filter(|rec| !signed || !dnssec::is_dnssec(rec))
+        {
+            let _ = writeln!(text, "{rec}");
+        }
+
+        text
+    }
+
+    /// Every record of the zone bracketed by its SOA, as sent in an AXFR
+    pub fn transfer_records(&self) -> Option<Vec<DnsRecord>> {
+        let soa = self.soa()?;
+
+        let mut records = Vec::with_capacity(self.records.len() + 2);
+        records.push(soa.clone());
+        records.extend(self.records.iter().filter(|rec| *rec != soa).cloned());
+        records.push(soa.clone());
+
+        Some(records)
+    }
+
+    /// The changes since `serial` as sent in an IXFR, or `None` if the journal doesn't go back that
+    /// far. Only the SOA is sent when the zone hasn't changed.
+    pub fn incremental_records(&self, serial: u32) -> Option<Vec<DnsRecord>> {
+        let soa = self.soa()?;
+        if soa_serial(soa) == Some(serial) {
+            return Some(vec![soa.clone()]);
+        }
+
+        let mut records = vec![soa.clone()];
+        for diff in self.journal.since(serial)? {
+            records.push(diff.from_soa.clone());
+            records.extend(diff.removed.iter().cloned());
+            records.push(diff.to_soa.clone());
+            records.extend(diff.added.iter().cloned());
+        }
+        records.push(soa.clone());
+
+        Some(records)
+    }
+}
+
+/// Parse a single record in master file format, see [`DnsRecord::parse_line`]
+pub(crate) fn parse_record(text: &str, origin: &DnsName) -> Result<DnsRecord> {
+    let entries = tokenize(text)?;
+    let entry = match entries.as_slice() {
+        [entry] => entry,
+        [] => return Err(zone_err(1, "Missing record")),
+        [_, extra, ..] => return Err(zone_err(extra.line, "Expected a single record")),
+    };
+    if entry.blank_owner {
+        return Err(zone_err(entry.line, "Missing owner name"));
+    }
+    if entry.tokens[0].text.starts_with('$') {
+        return Err(zone_err(entry.line, "Expected a record, not a directive"));
+    }
+
+    let mut parser = Parser::new(origin);
+    parser.entry(entry)?;
+
+    Ok(parser.records.remove(0))
+}
+
+/// A logical line of a master file, with parenthesized continuations joined
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    /// Entries starting with whitespace reuse the owner of the previous record
+    blank_owner: bool,
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug)]
+struct Token {
+    text: String,
+    /// The octets of the token as a character string, with its escapes decoded
+    octets: Vec<u8>,
+}
+
+fn zone_err(line: usize, message: impl Into<String>) -> DnsError {
+    DnsError::Zone {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Split master file text into entries, dropping comments and joining lines inside parentheses
+fn tokenize(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0;
+
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let entry = current.get_or_insert_with(|| Entry {
+            line: line_no,
+            blank_owner: line.starts_with([' ', '\t']),
+            tokens: Vec::new(),
+        });
+
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            match c {
+                ';' => break,
+                '(' => {
+                    depth += 1;
+                    chars.next();
+                }
+                ')' => {
+                    if depth == 0 {
+                        return Err(zone_err(line_no, "Unbalanced closing parenthesis"));
+                    }
+                    depth -= 1;
+                    chars.next();
+                }
+                '"' => {
+                    chars.next();
+                    let mut escaped = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            // Kept for `unescape`, with the character after it so an escaped quote
+                            // doesn't end the string
+                            Some('\\') => {
+                                escaped.push('\\');
+                                escaped.extend(chars.next());
+                            }
+                            Some(c) => escaped.push(c),
+                            None => return Err(zone_err(line_no, "Unterminated quoted string")),
+                        }
+                    }
+                    // `\DDD` is a decimal byte, anything else is taken literally
+                    let octets = unescape(&escaped).into_owned();
+                    entry.tokens.push(Token {
+                        text: String::from_utf8_lossy(&octets).into_owned(),
+                        octets,
+                    });
+                }
+                c if c.is_whitespace() => {
+                    chars.next();
+                }
+                _ => {
+                    let mut text = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                            break;
+                        }
+                        chars.next();
+                        if c == '\\' {
+                            text.push(c);
+                            text.extend(chars.next());
+                        } else {
+                            text.push(c);
+                        }
+                    }
+                    entry.tokens.push(Token {
+                        octets: unescape(&text).into_owned(),
+                        text,
+                    });
+                }
+            }
+        }
+
+        if depth == 0 {
+            if let Some(entry) = current.take() {
+                if !entry.tokens.is_empty() {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+
+    if let Some(entry) = current {
+        return Err(zone_err(entry.line, "Unbalanced opening parenthesis"));
+    }
+
+    Ok(entries)
+}
+
+/// Parse a TTL, either as plain seconds or with BIND style units like `1h30m`
+fn parse_ttl(s: &str) -> Option<u32> {
+    if let Ok(ttl) = s.parse() {
+        return Some(ttl);
+    }
+
+    let mut total: u32 = 0;
+    let mut num: Option<u32> = None;
+    for c in s.chars() {
+        if let Some(d) = c.to_digit(10) {
+            num = Some(num.unwrap_or(0).checked_mul(10)?.checked_add(d)?);
+            continue;
+        }
+
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 60 * 60 * 24,
+            'w' => 60 * 60 * 24 * 7,
+            _ => return None,
+        };
+        total = total.checked_add(num.take()?.checked_mul(unit)?)?;
+    }
+
+    match num {
+        Some(_) => None,
+        None => Some(total),
+    }
+}
+
+struct Parser {
+    origin: DnsName,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<DnsName>,
+    records: Vec<DnsRecord>,
+}
+
+impl Parser {
+    fn new(origin: &DnsName) -> Self {
+        Self {
+            origin: origin.clone(),
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+            records: Vec::new(),
+        }
+    }
+
+    fn entry(&mut self, entry: &Entry) -> Result<()> {
+        let line = entry.line;
+        let mut tokens = entry.tokens.iter().peekable();
+
+        if !entry.blank_owner && entry.tokens[0].text.starts_with('$') {
+            return self.directive(line, &entry.tokens);
+        }
+
+        let owner = if entry.blank_owner {
+            self.last_owner
+                .clone()
+                .ok_or_else(|| zone_err(line, "No previous owner name to inherit"))?
+        } else {
+            let token = tokens.next().expect("entries are never empty");
+            self.name(line, &token.text)?
+        };
+
+        // The TTL and class are both optional and may come in either order
+        let mut ttl = None;
+        while let Some(token) = tokens.peek() {
+            if let Some(t) = parse_ttl(&token.text) {
+                ttl = Some(t);
+            } else if token.text.eq_ignore_ascii_case("IN") {
+                // The only class supported
+            } else if ["CH", "HS", "CS"]
+                .iter()
+                .any(|class| token.text.eq_ignore_ascii_case(class))
+            {
+                return Err(zone_err(line, format!("Unsupported class {}", token.text)));
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+
+        let ttl = match ttl {
+            Some(ttl) => {
+                self.last_ttl = Some(ttl);
+                ttl
+            }
+            None => self
+                .default_ttl
+                .or(self.last_ttl)
+                .ok_or_else(|| zone_err(line, "No TTL given and no $TTL default set"))?,
+        };
+
+        let qtype = tokens
+            .next()
+            .ok_or_else(|| zone_err(line, "Missing record type"))?;
+        let qtype = QueryType::from_str(&qtype.text).map_err(|e| zone_err(line, e.to_string()))?;
+        let rdata: Vec<&Token> = tokens.collect();
+
+        let record = self.record(line, owner.clone(), qtype, ttl, &rdata)?;
+        self.records.push(record);
+        self.last_owner = Some(owner);
+
+        Ok(())
+    }
+
+    fn directive(&mut self, line: usize, tokens: &[Token]) -> Result<()> {
+        let arg = tokens
+            .get(1)
+            .ok_or_else(|| zone_err(line, format!("Missing argument to {}", tokens[0].text)))?;
+
+        match tokens[0].text.to_ascii_uppercase().as_str() {
+            "$ORIGIN" => self.origin = self.name(line, &arg.text)?,
+            "$TTL" => {
+                let ttl = parse_ttl(&arg.text)
+                    .ok_or_else(|| zone_err(line, format!("Invalid TTL {}", arg.text)))?;
+                self.default_ttl = Some(ttl);
+            }
+            directive => return Err(zone_err(line, format!("Unsupported directive {directive}"))),
+        }
+
+        Ok(())
+    }
+
+    /// Resolve a possibly relative name against the current origin
+    fn name(&self, line: usize, s: &str) -> Result<DnsName> {
+        let name = if s == "@" {
+            Ok(self.origin.clone())
+        } else if s.ends_with('.') || self.origin.is_root() {
+            DnsName::new(s)
+        } else {
+            DnsName::new(&format!("{s}.{}", self.origin))
+        };
+
+        name.map_err(|e| zone_err(line, e.to_string()))
+    }
+
+    fn record(
+        &self,
+        line: usize,
+        domain: DnsName,
+        qtype: QueryType,
+        ttl: u32,
+        rdata: &[&Token],
+    ) -> Result<DnsRecord> {
+        let field = |i: usize| -> Result<&str> {
+            rdata
+                .get(i)
+                .map(|token| token.text.as_str())
+                .ok_or_else(|| zone_err(line, format!("Missing rdata for {qtype:?} record")))
+        };
+        let number = |i: usize| -> Result<u32> {
+            let s = field(i)?;
+            parse_ttl(s).ok_or_else(|| zone_err(line, format!("Invalid number {s}")))
+        };
+        let name = |i: usize| self.name(line, field(i)?);
+        let octets = |token: &Token| {
+            if token.octets.len() > 255 {
+                return Err(zone_err(
+                    line,
+                    format!("Character string longer than 255 octets: {}", token.text),
+                ));
+            }
+            Ok(token.octets.clone())
+        };
+        let character_string = |i: usize| field(i).and_then(|_| octets(rdata[i]));
+
+        if rdata.first().is_some_and(|token| token.text == "\\#") {
+            let data = generic_rdata(line, &rdata[1..])?;
+            return Ok(DnsRecord::new(domain, ttl, data_of(line, qtype, data)?));
+        }
+
+        let expected = match qtype {
+            QueryType::SOA => Some(7),
+            QueryType::MX | QueryType::HINFO => Some(2),
+            QueryType::SRV => Some(4),
+            QueryType::TXT => None,
+            _ => Some(1),
+        };
+        if let Some(expected) = expected {
+            if rdata.len() > expected {
+                return Err(zone_err(
+                    line,
+                    format!("Unexpected rdata {}", rdata[expected].text),
+                ));
+            }
+        }
+
+        let data = match qtype {
+            QueryType::A => RData::A {
+                addr: Ipv4Addr::from_str(field(0)?)
+                    .map_err(|e| zone_err(line, format!("Invalid IPv4 address: {e}")))?,
+            },
+            QueryType::AAAA => RData::AAAA {
+                addr: Ipv6Addr::from_str(field(0)?)
+                    .map_err(|e| zone_err(line, format!("Invalid IPv6 address: {e}")))?,
+            },
+            QueryType::NS => RData::NS { host: name(0)? },
+            QueryType::CNAME => RData::CNAME { host: name(0)? },
+            QueryType::PTR => RData::PTR { host: name(0)? },
+            QueryType::MX => RData::MX {
+                priority: field(0)?
+                    .parse()
+                    .map_err(|_| zone_err(line, "Invalid MX priority"))?,
+                host: name(1)?,
+            },
+            QueryType::SRV => {
+                let short = |i: usize, what: &str| {
+                    field(i)?
+                        .parse()
+                        .map_err(|_| zone_err(line, format!("Invalid SRV {what}")))
+                };
+                RData::SRV {
+                    priority: short(0, "priority")?,
+                    weight: short(1, "weight")?,
+                    port: short(2, "port")?,
+                    host: name(3)?,
+                }
+            }
+            QueryType::SOA => RData::SOA {
+                m_name: name(0)?,
+                r_name: name(1)?,
+                serial: field(2)?
+                    .parse()
+                    .map_err(|_| zone_err(line, "Invalid SOA serial"))?,
+                refresh: number(3)?,
+                retry: number(4)?,
+                expire: number(5)?,
+                minimum: number(6)?,
+            },
+            QueryType::HINFO => RData::HINFO {
+                cpu: character_string(0)?,
+                os: character_string(1)?,
+            },
+            QueryType::TXT => {
+                if rdata.is_empty() {
+                    return Err(zone_err(line, "Missing rdata for TXT record"));
+                }
+                RData::TXT {
+                    data: rdata
+                        .iter()
+                        .map(|token| octets(token))
+                        .collect::<Result<_>>()?,
+                }
+            }
+            QueryType::UNKNOWN(_) => {
+                return Err(zone_err(
+                    line,
+                    format!("Records of type {qtype} need the generic rdata form"),
+                ))
+            }
+            // DNSSEC records are generated when the zone is signed rather than loaded
+            QueryType::OPT
+            | QueryType::RRSIG
+            | QueryType::NSEC
+            | QueryType::DNSKEY
+            | QueryType::IXFR
+            | QueryType::AXFR
+            | QueryType::ANY => {
+                return Err(zone_err(line, format!("Unsupported record type {qtype:?}")))
+            }
+        };
+
+        Ok(DnsRecord::new(domain, ttl, data))
+    }
+}
+
+/// The octets of rdata in the generic form of RFC 3597 section 5, `\# <length> <hex>...` with the
+/// `\#` already taken off
+fn generic_rdata(line: usize, tokens: &[&Token]) -> Result<Vec<u8>> {
+    let (len, hex) = tokens
+        .split_first()
+        .ok_or_else(|| zone_err(line, "Missing length of generic rdata"))?;
+    let len = len
+        .text
+        .parse::<u16>()
+        .map_err(|_| zone_err(line, format!("Invalid rdata length {}", len.text)))?;
+    let len = usize::from(len);
+
+    // The hex may be split into any number of tokens, but not in the middle of an octet
+    let mut data = Vec::with_capacity(len);
+    for token in hex {
+        let digits = token.text.as_bytes();
+        if digits.len() % 2 != 0 {
+            return Err(zone_err(
+                line,
+                format!("Odd number of hex digits in {}", token.text),
+            ));
+        }
+        for pair in digits.chunks(2) {
+            let octet = std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| zone_err(line, format!("Invalid hex {}", token.text)))?;
+            data.push(octet);
+        }
+    }
+    if data.len() != len {
+        return Err(zone_err(
+            line,
+            format!("Generic rdata is {} octets, not {len}", data.len()),
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Rdata of `qtype` given in the generic form, read like it came off the wire so a known type ends
+/// up the same as in its own presentation format
+fn data_of(line: usize, qtype: QueryType, data: Vec<u8>) -> Result<RData> {
+    match qtype {
+        QueryType::UNKNOWN(qtype) => Ok(RData::UNKNOWN { qtype, data }),
+        // DNSSEC records are generated when the zone is signed rather than loaded
+        QueryType::OPT
+        | QueryType::RRSIG
+        | QueryType::NSEC
+        | QueryType::DNSKEY
+        | QueryType::IXFR
+        | QueryType::AXFR
+        | QueryType::ANY => Err(zone_err(line, format!("Unsupported record type {qtype:?}"))),
+        _ => {
+            let len = data.len() as u16;
+            let mut buf = BytePacketBuffer { buf: data, pos: 0 };
+            RData::read(&mut buf, qtype.into(), len)
+                .map_err(|e| zone_err(line, format!("Invalid {qtype} rdata: {e}")))
+        }
+    }
+}
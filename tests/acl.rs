@@ -0,0 +1,168 @@
+//! Who may query, recurse, transfer and update, by the address the request comes from
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_server::config::Config;
+use dns_server::header::OPCODE_UPDATE;
+use dns_server::server::{self, ServerContext};
+use dns_server::{BytePacketBuffer, DnsName, DnsPacket, QueryType, ResultCode};
+
+fn config(text: &str) -> Config {
+    format!("upstream = [\"127.0.0.1:9\"]\n{text}")
+        .parse()
+        .unwrap()
+}
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+/// Start a server with `config`, answering `local.lan` itself and forwarding the rest to an
+/// upstream that isn't there
+fn start_with(config: &str) -> SocketAddr {
+    let addr = loop {
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = udp.local_addr().unwrap();
+        if TcpListener::bind(addr).is_ok() {
+            break addr;
+        }
+    };
+    let config = format!(
+        "listen = \"{addr}\"\n\
+         upstream = [\"127.0.0.1:9\"]\n\
+         {config}\n\
+         [[local-records]]\n\
+         name = \"local.lan\"\n\
+         type = \"A\"\n\
+         value = \"192.0.2.1\""
+    );
+    let context = ServerContext::new(config.parse().unwrap()).unwrap();
+    thread::spawn(move || server::run(Arc::new(context)));
+    thread::sleep(Duration::from_millis(100));
+
+    addr
+}
+
+/// Send a message from the loopback address and wait for the response
+fn exchange(server: SocketAddr, mut packet: DnsPacket) -> DnsPacket {
+    let mut buf = BytePacketBuffer::new();
+    packet.write(&mut buf).unwrap();
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    socket.send_to(&buf.buf[..buf.pos()], server).unwrap();
+    let mut buf = BytePacketBuffer::new();
+    let len = socket.recv(&mut buf.buf).unwrap();
+    buf.buf.truncate(len);
+
+    DnsPacket::from_buffer(&mut buf).unwrap()
+}
+
+fn query(qname: &str, qtype: QueryType) -> DnsPacket {
+    DnsPacket::query(qname, qtype)
+        .id(0x1234)
+        .recursion_desired(true)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn everyone_may_query_and_recurse_by_default() {
+    let config = config("");
+    for client in ["192.0.2.1", "2001:db8::1"] {
+        assert!(config.allows_query(ip(client)), "{client}");
+        assert!(config.allows_recursion(ip(client)), "{client}");
+    }
+}
+
+#[test]
+fn query_and_recursion_follow_their_lists() {
+    let config = config(
+        "allow-query = [\"192.0.2.0/24\", \"2001:db8::/32\"]\n\
+         allow-recursion = [\"192.0.2.0/25\"]",
+    );
+
+    assert!(config.allows_query(ip("192.0.2.200")));
+    assert!(config.allows_query(ip("2001:db8:1::1")));
+    assert!(!config.allows_query(ip("198.51.100.1")));
+    assert!(!config.allows_query(ip("2001:db9::1")));
+
+    assert!(config.allows_recursion(ip("192.0.2.1")));
+    assert!(!config.allows_recursion(ip("192.0.2.200")));
+}
+
+#[test]
+fn transfers_and_updates_are_per_zone_and_nobody_by_default() {
+    let config = config(
+        "allow-transfer = [\"198.51.100.0/24\"]\n\
+         [[zones]]\n\
+         origin = \"example.com\"\n\
+         file = \"example.com.zone\"\n\
+         allow-transfer = [\"192.0.2.53\"]\n\
+         allow-update = [\"192.0.2.67\"]\n\
+         update-keys = [\"dhcp-key\"]\n\
+         [[zones]]\n\
+         origin = \"example.org\"\n\
+         file = \"example.org.zone\"",
+    );
+    let (com, org) = (name("example.com"), name("example.org"));
+
+    assert!(config.allows_transfer(&com, ip("192.0.2.53"), None));
+    assert!(!config.allows_transfer(&com, ip("192.0.2.54"), None));
+    // The server-wide list counts for every zone, but not for zones it doesn't serve
+    assert!(config.allows_transfer(&org, ip("198.51.100.7"), None));
+    assert!(!config.allows_transfer(&name("example.net"), ip("198.51.100.7"), None));
+
+    assert!(config.allows_update(&com, ip("192.0.2.67"), None));
+    assert!(!config.allows_update(&com, ip("192.0.2.68"), None));
+    assert!(config.allows_update(&com, ip("203.0.113.1"), Some(&name("dhcp-key"))));
+    assert!(!config.allows_update(&org, ip("192.0.2.67"), None));
+}
+
+#[test]
+fn clients_that_may_not_query_are_refused() {
+    let server = start_with("allow-query = [\"192.0.2.0/24\"]");
+
+    let response = exchange(server, query("local.lan", QueryType::A));
+    assert_eq!(response.header.id, 0x1234);
+    assert_eq!(response.header.rescode, ResultCode::REFUSED);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn clients_that_may_not_recurse_only_get_local_answers() {
+    let server = start_with("allow-recursion = [\"192.0.2.0/24\"]");
+
+    let local = exchange(server, query("local.lan", QueryType::A));
+    assert_eq!(local.header.rescode, ResultCode::NOERROR);
+    assert_eq!(local.answers.len(), 1);
+
+    let forwarded = exchange(server, query("www.example.com", QueryType::A));
+    assert_eq!(forwarded.header.rescode, ResultCode::REFUSED);
+    assert!(forwarded.answers.is_empty());
+}
+
+#[test]
+fn updates_and_transfers_not_allowed_are_refused() {
+    // Nobody may update a zone without being listed for it, and transfers need TCP on top
+    let server = start_with("");
+
+    let mut update = query("local.lan", QueryType::SOA);
+    update.header.opcode = OPCODE_UPDATE;
+    update.header.recursion_desired = false;
+    let response = exchange(server, update);
+    assert_eq!(response.header.opcode, OPCODE_UPDATE);
+    assert_eq!(response.header.rescode, ResultCode::REFUSED);
+
+    let response = exchange(server, query("local.lan", QueryType::AXFR));
+    assert_eq!(response.header.rescode, ResultCode::REFUSED);
+}
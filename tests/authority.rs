@@ -0,0 +1,177 @@
+//! Answers from the zones the server is authoritative for
+
+use std::net::Ipv4Addr;
+
+use dns_server::authority::Authority;
+use dns_server::zone::Zone;
+use dns_server::{DnsName, DnsPacket, QueryType, RData, ResultCode};
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+/// A zone with a wildcard at the apex, and `deep` only existing as the parent of `sub.deep`
+fn authority() -> Authority {
+    let text = "$ORIGIN example.com.\n\
+                @ 3600 IN SOA ns1 admin 1 3600 600 86400 300\n\
+                @ 3600 IN NS ns1\n\
+                ns1 3600 IN A 192.0.2.53\n\
+                * 3600 IN A 192.0.2.99\n\
+                www 3600 IN A 192.0.2.1\n\
+                sub.deep 3600 IN A 192.0.2.2\n";
+    let mut authority = Authority::default();
+    authority.insert(Zone::parse(text, &DnsName::root()).unwrap());
+    authority
+}
+
+fn lookup(qname: &str, qtype: QueryType) -> DnsPacket {
+    authority().lookup(&name(qname), qtype, false).unwrap()
+}
+
+fn addresses(packet: &DnsPacket) -> Vec<(DnsName, Ipv4Addr)> {
+    packet
+        .answers
+        .iter()
+        .filter_map(|rec| match rec.rdata {
+            RData::A { addr } => Some((rec.domain().clone(), addr)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks a negative answer carries the SOA with the negative caching TTL, RFC 2308
+fn assert_negative(packet: &DnsPacket, rcode: ResultCode) {
+    assert_eq!(packet.header.rescode, rcode);
+    assert!(packet.header.authoritative_answer);
+    assert!(packet.answers.is_empty());
+    let [soa] = packet.authorities.as_slice() else {
+        panic!("No SOA alone in {packet:?}");
+    };
+    assert_eq!(soa.domain(), &name("example.com"));
+    assert!(matches!(soa.rdata, RData::SOA { .. }));
+    assert_eq!(soa.ttl, 300);
+}
+
+#[test]
+fn wildcard_answers_for_missing_names() {
+    let other = name("other.example.com");
+    let response = lookup("other.example.com", QueryType::A);
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(
+        addresses(&response),
+        [(other, Ipv4Addr::new(192, 0, 2, 99))]
+    );
+
+    // Further down too, as long as nothing exists in between
+    let response = lookup("a.b.example.com", QueryType::A);
+    assert_eq!(addresses(&response).len(), 1);
+}
+
+#[test]
+fn wildcard_doesnt_answer_for_existing_names() {
+    let response = lookup("www.example.com", QueryType::A);
+    assert_eq!(
+        addresses(&response),
+        [(name("www.example.com"), Ipv4Addr::new(192, 0, 2, 1))]
+    );
+
+    // Nor for other types at them, or names below them
+    assert_negative(
+        &lookup("www.example.com", QueryType::TXT),
+        ResultCode::NOERROR,
+    );
+    assert_negative(
+        &lookup("a.www.example.com", QueryType::A),
+        ResultCode::NXDOMAIN,
+    );
+}
+
+#[test]
+fn empty_non_terminals_are_nodata() {
+    // `deep` exists through `sub.deep`, so the wildcard doesn't answer for it
+    assert_negative(
+        &lookup("deep.example.com", QueryType::A),
+        ResultCode::NOERROR,
+    );
+    assert_negative(
+        &lookup("deep.example.com", QueryType::ANY),
+        ResultCode::NOERROR,
+    );
+
+    let response = lookup("sub.deep.example.com", QueryType::A);
+    assert_eq!(addresses(&response).len(), 1);
+    assert_negative(
+        &lookup("other.deep.example.com", QueryType::A),
+        ResultCode::NXDOMAIN,
+    );
+}
+
+#[test]
+fn names_outside_the_zones_are_left_alone() {
+    assert!(authority()
+        .lookup(&name("example.org"), QueryType::A, false)
+        .is_none());
+}
+
+/// The parent of `child.example.com`, with the DS for it in the generic form of RFC 3597
+fn parent_zone() -> Zone {
+    let text = "$ORIGIN example.com.\n\
+                @ 3600 IN SOA ns1 admin 1 3600 600 86400 300\n\
+                @ 3600 IN NS ns1\n\
+                ns1 3600 IN A 192.0.2.53\n\
+                child 3600 IN NS ns.child\n\
+                child 3600 IN TYPE43 \\# 6 303908020102\n\
+                ns.child 3600 IN A 192.0.2.54\n\
+                other 3600 IN NS ns.child\n";
+    Zone::parse(text, &DnsName::root()).unwrap()
+}
+
+const DS: QueryType = QueryType::UNKNOWN(43);
+
+#[test]
+fn ds_at_a_zone_cut_is_answered_by_the_parent() {
+    let mut authority = Authority::default();
+    authority.insert(parent_zone());
+
+    // Anything else at or below the cut is referred to the child
+    let referral = authority
+        .lookup(&name("www.child.example.com"), QueryType::A, false)
+        .unwrap();
+    assert!(!referral.header.authoritative_answer);
+    assert!(referral.answers.is_empty());
+    assert!(referral
+        .authorities
+        .iter()
+        .all(|rec| rec.qtype() == QueryType::NS));
+    assert_eq!(referral.resources.len(), 1);
+
+    let ds = authority
+        .lookup(&name("child.example.com"), DS, false)
+        .unwrap();
+    assert!(ds.header.authoritative_answer);
+    let [answer] = ds.answers.as_slice() else {
+        panic!("No DS alone in {ds:?}");
+    };
+    assert_eq!(answer.qtype(), DS);
+
+    // Without a DS at the cut, the parent says so rather than referring
+    let none = authority
+        .lookup(&name("other.example.com"), DS, false)
+        .unwrap();
+    assert_negative(&none, ResultCode::NOERROR);
+
+    // Also when the child zone is served too
+    let child = "$ORIGIN child.example.com.\n\
+                 @ 3600 IN SOA ns admin 1 3600 600 86400 300\n\
+                 @ 3600 IN NS ns\n\
+                 ns 3600 IN A 192.0.2.54\n";
+    authority.insert(Zone::parse(child, &DnsName::root()).unwrap());
+    let ds = authority
+        .lookup(&name("child.example.com"), DS, false)
+        .unwrap();
+    assert_eq!(ds.answers.len(), 1);
+    let soa = authority
+        .lookup(&name("child.example.com"), QueryType::SOA, false)
+        .unwrap();
+    assert_eq!(soa.answers[0].domain(), &name("child.example.com"));
+}
@@ -0,0 +1,159 @@
+//! Lists of domains answered by a policy instead of being resolved
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, process};
+
+use dns_server::blocklist::{BlockPolicy, Blocklist, Filtered};
+use dns_server::config::Config;
+use dns_server::hosts::Hosts;
+use dns_server::{DnsName, DnsQuestion, QueryType, RData, Resolver, ResultCode};
+
+const HOSTS: &str = "# Ads\n\
+                     127.0.0.1 localhost\n\
+                     ::1 ip6-localhost\n\
+                     0.0.0.0 ads.example.com tracker.example.com # two on a line\n\
+                     0.0.0.0\tmetrics.example.net\n";
+
+const DOMAINS: &str = "! Plain domains\n\
+                       malware.example\n\
+                       \n\
+                       phishing.example.org\n\
+                       bad..example\n";
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+/// Write a list where it can be loaded from, to a file of its own for every test
+fn list_file(name: &str, text: &str) -> PathBuf {
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let n = FILES.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!("dns-server-{}-{n}-{name}.txt", process::id()));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+/// Both lists loaded, with an exception for `ok.ads.example.com` and everything below it
+fn blocklist() -> Blocklist {
+    let files = [list_file("hosts", HOSTS), list_file("domains", DOMAINS)];
+    let sources: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+    let blocklist = Blocklist::load(&sources, &[name("ok.ads.example.com")], None);
+    for file in files {
+        fs::remove_file(file).unwrap();
+    }
+    blocklist
+}
+
+fn blocked(blocklist: &Blocklist, qname: &str) -> bool {
+    let qname = name(qname);
+    blocklist.blocked_by(&qname).is_some() && blocklist.allowed_by(&qname).is_none()
+}
+
+#[test]
+fn hosts_and_plain_domain_lists_are_read() {
+    let blocklist = blocklist();
+
+    for qname in [
+        "ads.example.com",
+        "tracker.example.com",
+        "metrics.example.net",
+        "malware.example",
+        "phishing.example.org",
+    ] {
+        assert!(blocked(&blocklist, qname), "{qname}");
+    }
+    // Neither the addresses, the local names, comments nor invalid names count as domains
+    for qname in ["localhost", "ip6-localhost", "example.com", "two", "line"] {
+        assert!(!blocked(&blocklist, qname), "{qname}");
+    }
+    assert_eq!(blocklist.len(), 5);
+}
+
+#[test]
+fn names_below_a_listed_domain_are_blocked() {
+    let blocklist = blocklist();
+
+    assert_eq!(
+        blocklist.blocked_by(&name("cdn.ADS.example.com")),
+        Some(&name("ads.example.com"))
+    );
+    assert!(blocked(&blocklist, "a.b.malware.example"));
+    // But not names it is below, or names that only end the same way
+    assert!(!blocked(&blocklist, "example.net"));
+    assert!(!blocked(&blocklist, "badads.example.com"));
+
+    // Exceptions cover the names below them too
+    assert!(!blocked(&blocklist, "ok.ads.example.com"));
+    assert!(!blocked(&blocklist, "www.ok.ads.example.com"));
+    assert!(blocked(&blocklist, "notok.ads.example.com"));
+}
+
+#[test]
+fn lists_that_fail_to_load_keep_their_previous_domains() {
+    let file = list_file("refreshed", DOMAINS);
+    let sources = [file.display().to_string()];
+    let previous = Blocklist::load(&sources, &[], None);
+    assert_eq!(previous.len(), 2);
+    fs::remove_file(&file).unwrap();
+
+    assert!(Blocklist::load(&sources, &[], None).is_empty());
+    let refreshed = Blocklist::load(&sources, &[], Some(&previous));
+    assert!(blocked(&refreshed, "malware.example"));
+    assert_eq!(refreshed.len(), 2);
+}
+
+#[test]
+fn blocked_names_are_answered_by_the_policy() {
+    let resolve = |policy, qname: &str, qtype| {
+        let hosts = Hosts::parse("192.0.2.1 ads.example.com ok.ads.example.com", 60);
+        let filtered = Filtered::new(blocklist(), policy, hosts);
+        filtered
+            .resolve(&DnsQuestion::new(name(qname), qtype))
+            .unwrap()
+    };
+
+    let null = resolve(BlockPolicy::Null, "ads.example.com", QueryType::A);
+    assert_eq!(null.header.rescode, ResultCode::NOERROR);
+    assert_eq!(
+        null.a_records().collect::<Vec<_>>(),
+        [Ipv4Addr::UNSPECIFIED]
+    );
+    let null = resolve(BlockPolicy::Null, "ads.example.com", QueryType::AAAA);
+    assert!(matches!(
+        null.answers[0].rdata,
+        RData::AAAA { addr } if addr == Ipv6Addr::UNSPECIFIED
+    ));
+    let null = resolve(BlockPolicy::Null, "ads.example.com", QueryType::TXT);
+    assert!(null.answers.is_empty());
+
+    let nxdomain = resolve(BlockPolicy::Nxdomain, "ads.example.com", QueryType::A);
+    assert_eq!(nxdomain.header.rescode, ResultCode::NXDOMAIN);
+    assert!(nxdomain.answers.is_empty());
+    let refused = resolve(BlockPolicy::Refused, "ads.example.com", QueryType::A);
+    assert_eq!(refused.header.rescode, ResultCode::REFUSED);
+
+    // Exceptions are passed on to the resolver it wraps
+    let allowed = resolve(BlockPolicy::Refused, "ok.ads.example.com", QueryType::A);
+    assert_eq!(
+        allowed.a_records().collect::<Vec<_>>(),
+        [Ipv4Addr::new(192, 0, 2, 1)]
+    );
+}
+
+#[test]
+fn policy_is_configured_by_name_and_null_by_default() {
+    let policy = |text: &str| {
+        let config: Config =
+            format!("upstream = [\"127.0.0.1:9\"]\n[blocklist]\nlists = []\n{text}")
+                .parse()
+                .unwrap();
+        config.blocklist.unwrap().policy
+    };
+
+    assert_eq!(policy(""), BlockPolicy::Null);
+    assert_eq!(policy("policy = \"nxdomain\""), BlockPolicy::Nxdomain);
+    assert_eq!(policy("policy = \"refused\""), BlockPolicy::Refused);
+    assert_eq!(policy("policy = \"null\""), BlockPolicy::Null);
+}
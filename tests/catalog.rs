@@ -0,0 +1,154 @@
+//! Catalog zones transferred from the mock server from the `test-util` feature
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use dns_server::authority::Authority;
+use dns_server::catalog::{member_zones, Catalog};
+use dns_server::config::CatalogConfig;
+use dns_server::mock::{MockServer, Reply};
+use dns_server::zone::Zone;
+use dns_server::{DnsName, QueryType, ResultCode};
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+/// A catalog listing `members` under IDs of their own
+fn catalog(serial: u32, members: &[&str]) -> String {
+    let mut text = format!(
+        "$ORIGIN catalog.example.\n\
+         @ 0 IN SOA invalid. invalid. {serial} 3600 600 86400 0\n\
+         @ 0 IN NS invalid.\n\
+         version 0 IN TXT \"2\"\n"
+    );
+    for (id, member) in members.iter().enumerate() {
+        text.push_str(&format!("{id}.zones 0 IN PTR {member}.\n"));
+    }
+    text
+}
+
+fn member(origin: &str) -> String {
+    format!(
+        "$ORIGIN {origin}.\n\
+         @ 300 IN SOA ns1 admin 1 3600 600 86400 300\n\
+         @ 300 IN NS ns1\n\
+         www 300 IN A 192.0.2.1\n"
+    )
+}
+
+/// A primary serving the catalog in `catalog` and every zone it lists, unless `down`
+fn primary(catalog: Arc<Mutex<String>>, down: Arc<AtomicBool>) -> MockServer {
+    MockServer::new(move |query| {
+        let origin = &query.questions[0].name;
+        let text = if *origin == name("catalog.example") {
+            catalog.lock().unwrap().clone()
+        } else if down.load(Ordering::Relaxed) {
+            return Reply::Rcode(ResultCode::REFUSED);
+        } else {
+            member(origin.as_str())
+        };
+        let zone = Zone::parse(&text, &DnsName::root()).unwrap();
+        Reply::Answer(zone.transfer_records().unwrap())
+    })
+    .unwrap()
+}
+
+fn config(server: &MockServer) -> CatalogConfig {
+    CatalogConfig {
+        origin: name("catalog.example"),
+        primary: server.addr(),
+        key: None,
+    }
+}
+
+#[test]
+fn members_come_and_go_with_the_catalog() {
+    let text = Arc::new(Mutex::new(catalog(1, &["a.example", "b.example"])));
+    let server = primary(Arc::clone(&text), Arc::default());
+    let catalog_zone = Catalog::new(&config(&server), &[]).unwrap();
+    let authority = RwLock::new(Authority::default());
+    let start = Instant::now();
+
+    // Everything is checked again at the refresh interval of the SOA records
+    let next = catalog_zone.refresh_at(&authority, start);
+    assert_eq!(next, start + Duration::from_secs(3600));
+    assert_eq!(
+        catalog_zone.members(),
+        [name("a.example"), name("b.example")]
+    );
+    let answer = authority
+        .read()
+        .unwrap()
+        .lookup(&name("www.a.example"), QueryType::A, false)
+        .unwrap();
+    assert_eq!(answer.answers.len(), 1);
+
+    *text.lock().unwrap() = catalog(2, &["b.example"]);
+    catalog_zone.refresh_at(&authority, next);
+    assert_eq!(catalog_zone.members(), [name("b.example")]);
+    let authority = authority.read().unwrap();
+    assert!(authority.zone(&name("a.example")).is_none());
+    assert!(authority.zone(&name("b.example")).is_some());
+}
+
+#[test]
+fn zones_served_already_are_left_alone() {
+    let text = Arc::new(Mutex::new(catalog(1, &["a.example"])));
+    let server = primary(text, Arc::default());
+    let catalog_zone = Catalog::new(&config(&server), &[]).unwrap();
+    let own = Zone::parse(&member("a.example"), &DnsName::root()).unwrap();
+    let authority = RwLock::new(Authority::new(vec![own]));
+
+    catalog_zone.refresh(&authority);
+    assert!(catalog_zone.members().is_empty());
+    assert_eq!(authority.read().unwrap().zones().len(), 1);
+}
+
+#[test]
+fn members_expire_when_the_primary_is_down() {
+    let down = Arc::new(AtomicBool::new(false));
+    let text = Arc::new(Mutex::new(catalog(1, &["a.example"])));
+    let server = primary(text, Arc::clone(&down));
+    let catalog_zone = Catalog::new(&config(&server), &[]).unwrap();
+    let authority = RwLock::new(Authority::default());
+    let start = Instant::now();
+    let lookup = || {
+        authority
+            .read()
+            .unwrap()
+            .lookup(&name("www.a.example"), QueryType::A, false)
+            .unwrap()
+    };
+
+    catalog_zone.refresh_at(&authority, start);
+    down.store(true, Ordering::Relaxed);
+    // Failed checks are retried at the retry interval until the zone expires
+    let next = catalog_zone.refresh_at(&authority, start + Duration::from_secs(3600));
+    assert_eq!(next, start + Duration::from_secs(4200));
+    assert_eq!(lookup().header.rescode, ResultCode::NOERROR);
+    let expired = start + Duration::from_secs(86400);
+    catalog_zone.refresh_at(&authority, expired);
+    assert_eq!(lookup().header.rescode, ResultCode::SERVFAIL);
+
+    down.store(false, Ordering::Relaxed);
+    catalog_zone.refresh_at(&authority, expired + Duration::from_secs(600));
+    assert_eq!(lookup().header.rescode, ResultCode::NOERROR);
+}
+
+#[test]
+fn other_schema_versions_are_rejected() {
+    let text = catalog(1, &["a.example"]).replace("\"2\"", "\"1\"");
+    let zone = Zone::parse(&text, &DnsName::root()).unwrap();
+
+    assert!(member_zones(&zone).is_err());
+}
+
+#[test]
+fn members_with_several_ptr_records_are_skipped() {
+    let text = catalog(1, &["a.example", "b.example"]) + "0.zones 0 IN PTR c.example.\n";
+    let zone = Zone::parse(&text, &DnsName::root()).unwrap();
+
+    assert_eq!(member_zones(&zone).unwrap(), [name("b.example")]);
+}
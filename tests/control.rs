@@ -0,0 +1,257 @@
+//! Commands sent to the control listener of a running server
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_server::cache::Cache;
+use dns_server::mock::{MockServer, Reply};
+use dns_server::server::{self, ServerContext};
+use dns_server::stats::Counters;
+use dns_server::{BytePacketBuffer, DnsName, DnsPacket, DnsRecord, QueryType, RData, ResultCode};
+
+/// A server forwarding to an upstream answering every A query with one address, and the address
+/// of its control listener
+struct Running {
+    dns: SocketAddr,
+    control: SocketAddr,
+    upstream: MockServer,
+}
+
+fn start() -> Running {
+    let upstream = MockServer::new(|query| {
+        let question = &query.questions[0];
+        let answers = match question.qtype {
+            QueryType::A => vec![DnsRecord::new(
+                question.name.clone(),
+                300,
+                RData::A {
+                    addr: Ipv4Addr::new(192, 0, 2, 1),
+                },
+            )],
+            _ => Vec::new(),
+        };
+        Reply::Answer(answers)
+    })
+    .unwrap();
+
+    let dns = loop {
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = udp.local_addr().unwrap();
+        if TcpListener::bind(addr).is_ok() {
+            break addr;
+        }
+    };
+    let control = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let config = format!(
+        "listen = \"{dns}\"\n\
+         control = \"{control}\"\n\
+         upstream = [\"{}\"]",
+        upstream.addr()
+    );
+    let context = ServerContext::new(config.parse().unwrap()).unwrap();
+    thread::spawn(move || server::run(Arc::new(context)));
+    thread::sleep(Duration::from_millis(100));
+
+    Running {
+        dns,
+        control,
+        upstream,
+    }
+}
+
+/// Send a command and read everything the server writes back
+fn command(control: SocketAddr, line: &str) -> String {
+    let mut stream = TcpStream::connect(control).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    stream.write_all(format!("{line}\n").as_bytes()).unwrap();
+    let mut output = String::new();
+    stream.read_to_string(&mut output).unwrap();
+    output
+}
+
+/// Ask the server for the address of `qname`
+fn ask(server: SocketAddr, qname: &str) -> DnsPacket {
+    let mut request = DnsPacket::query(qname, QueryType::A)
+        .id(0x1234)
+        .recursion_desired(true)
+        .build()
+        .unwrap();
+    let mut buf = BytePacketBuffer::new();
+    request.write(&mut buf).unwrap();
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    socket.send_to(&buf.buf[..buf.pos()], server).unwrap();
+    let mut buf = BytePacketBuffer::new();
+    let len = socket.recv(&mut buf.buf).unwrap();
+    buf.buf.truncate(len);
+    DnsPacket::from_buffer(&mut buf).unwrap()
+}
+
+#[test]
+fn flushed_answers_are_asked_for_again() {
+    let running = start();
+
+    ask(running.dns, "www.example.com");
+    ask(running.dns, "www.example.com");
+    assert_eq!(running.upstream.received().len(), 1);
+
+    assert_eq!(
+        command(running.control, "flush-cache"),
+        "Flushed 1 answers\n"
+    );
+    ask(running.dns, "www.example.com");
+    assert_eq!(running.upstream.received().len(), 2);
+
+    assert_eq!(
+        command(running.control, "flush-name www.example.com"),
+        "Flushed 1 answers\n"
+    );
+    assert_eq!(
+        command(running.control, "flush-name www.example.org"),
+        "Flushed 0 answers\n"
+    );
+}
+
+#[test]
+fn bad_commands_get_an_error_back() {
+    let running = start();
+
+    assert_eq!(
+        command(running.control, "flush-all"),
+        "error: Unknown command or arguments for flush-all\n"
+    );
+    assert_eq!(command(running.control, ""), "error: No command\n");
+    // Without a subscriber set up to allow it
+    assert_eq!(
+        command(running.control, "set-log-level debug"),
+        "error: The log level can't be changed\n"
+    );
+}
+
+#[test]
+fn dumped_records_read_back_as_the_answers() {
+    let running = start();
+    let www = ask(running.dns, "www.example.com");
+    ask(running.dns, "www.example.com");
+    ask(running.dns, "mail.example.org");
+
+    let dump = command(running.control, "dump-cache");
+    let lines: Vec<_> = dump.lines().collect();
+    assert_eq!(lines.len(), 4, "{dump}");
+    // Sorted by name, each answer followed by its records
+    assert!(lines[0].starts_with("mail.example.org A NOERROR ttl="));
+    assert!(lines[2].starts_with("www.example.com A NOERROR ttl="));
+    assert!(lines[2].ends_with(" hits=1"));
+
+    let record = lines[3].strip_prefix('\t').unwrap();
+    let record = DnsRecord::parse_line(record, &DnsName::root()).unwrap();
+    assert_eq!(record.domain(), www.answers[0].domain());
+    assert_eq!(record.rdata, www.answers[0].rdata);
+
+    // Only the domain asked for
+    let dump = command(running.control, "dump-cache example.com");
+    assert_eq!(dump.lines().count(), 2);
+    assert!(dump.starts_with("www.example.com A "));
+}
+
+#[test]
+fn dumps_hold_what_the_cache_answers() {
+    let cache = Cache::new(10);
+    let qname = DnsName::new("www.example.com").unwrap();
+    let query = DnsPacket::query("www.example.com", QueryType::A)
+        .build()
+        .unwrap();
+    let mut response = DnsPacket::response_to(&query);
+    response.answers.push(DnsRecord::new(
+        qname.clone(),
+        300,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+        },
+    ));
+    cache.insert(None, None, &qname, QueryType::A, &response);
+    cache.insert(Some("office"), None, &qname, QueryType::A, &response);
+    let cached = cache.get(None, None, &qname, QueryType::A).unwrap();
+
+    let dump = cache.dump(None);
+    assert_eq!(dump.len(), 2);
+    let everyone = &dump[0];
+    assert_eq!(everyone.view, None);
+    assert_eq!(everyone.network, None);
+    assert_eq!(everyone.qname, qname);
+    assert_eq!(everyone.qtype, QueryType::A);
+    assert_eq!(everyone.rescode, ResultCode::NOERROR);
+    assert!((299..=300).contains(&everyone.ttl));
+    assert_eq!(everyone.hits, 1);
+    assert_eq!(everyone.answers, cached.answers);
+    assert_eq!(dump[1].view.as_deref(), Some("office"));
+    assert_eq!(dump[1].hits, 0);
+
+    assert!(cache
+        .dump(Some(&DnsName::new("example.org").unwrap()))
+        .is_empty());
+}
+
+#[test]
+fn counters_add_up_by_type_and_result_code() {
+    let counters = Counters::default();
+    counters.query(Some(QueryType::A));
+    counters.query(Some(QueryType::A));
+    counters.query(Some(QueryType::AAAA));
+    counters.query(None);
+    counters.response(Some(ResultCode::NOERROR));
+    counters.response(Some(ResultCode::NOERROR));
+    counters.response(Some(ResultCode::FORMERR));
+    counters.response(None);
+    counters.timeout();
+    counters.cache(true);
+    counters.cache(false);
+    counters.cache(false);
+
+    let stats = counters.snapshot();
+    assert_eq!(stats.queries, 4);
+    assert_eq!(stats.qtypes, [(QueryType::A, 2), (QueryType::AAAA, 1)]);
+    assert_eq!(
+        stats.responses,
+        [(ResultCode::NOERROR, 2), (ResultCode::FORMERR, 1)]
+    );
+    assert_eq!(stats.dropped, 1);
+    assert_eq!(stats.timeouts, 1);
+    assert_eq!((stats.cache_hits, stats.cache_misses), (1, 2));
+}
+
+#[test]
+fn stats_count_the_queries_answered() {
+    let running = start();
+    ask(running.dns, "www.example.com");
+    ask(running.dns, "www.example.com");
+
+    let stats = command(running.control, "stats");
+    let value = |key: &str| {
+        stats
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no {key} in {stats}"))
+            .to_string()
+    };
+    assert_eq!(value("queries"), "2");
+    assert_eq!(value("queries.A"), "2");
+    assert_eq!(value("responses.NOERROR"), "2");
+    assert_eq!(value("cache.hits"), "1");
+    assert_eq!(value("cache.misses"), "1");
+    assert_eq!(value("cache.entries"), "1");
+    assert_eq!(value("zones"), "0");
+    let upstream = format!("upstream {}", running.upstream.addr());
+    assert!(value(&upstream).starts_with("up rtt="), "{stats}");
+}
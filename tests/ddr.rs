@@ -0,0 +1,106 @@
+//! Discovery of designated resolvers, against the mock server from the `test-util` feature
+
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::time::Duration;
+
+use dns_server::ddr::{designations, discover, Designation, RESOLVER_ARPA, SVCB};
+use dns_server::mock::{MockServer, Reply};
+use dns_server::resolver::RetryPolicy;
+use dns_server::{DnsName, DnsPacket, DnsRecord, QueryType, RData};
+
+const POLICY: RetryPolicy = RetryPolicy::new(Duration::from_millis(500), 1);
+
+/// The rdata of a SVCB record for a resolver speaking `alpn` at `hint`
+fn svcb(priority: u16, target: &str, alpn: &[&str], port: u16, hint: Ipv4Addr) -> Vec<u8> {
+    let mut data = priority.to_be_bytes().to_vec();
+    for label in target.split('.').filter(|label| !label.is_empty()) {
+        data.push(label.len() as u8);
+        data.extend_from_slice(label.as_bytes());
+    }
+    data.push(0);
+
+    let ids: Vec<u8> = alpn
+        .iter()
+        .flat_map(|id| [&[id.len() as u8], id.as_bytes()].concat())
+        .collect();
+    let params: [(u16, &[u8]); 4] = [
+        (1, &ids),
+        (3, &port.to_be_bytes()),
+        (4, &hint.octets()),
+        (7, b"/dns-query{?dns}"),
+    ];
+    for (key, value) in params {
+        data.extend_from_slice(&key.to_be_bytes());
+        data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        data.extend_from_slice(value);
+    }
+    data
+}
+
+fn record(data: Vec<u8>) -> DnsRecord {
+    DnsRecord::new(
+        DnsName::new(RESOLVER_ARPA).unwrap(),
+        300,
+        RData::UNKNOWN { qtype: SVCB, data },
+    )
+}
+
+#[test]
+fn designations_are_read_by_priority() {
+    let hint = Ipv4Addr::new(192, 0, 2, 1);
+    let mut response = DnsPacket::new();
+    response.answers = vec![
+        record(svcb(2, "doh.example.", &["h2"], 443, hint)),
+        // Alias mode, which can't be used to find a resolver
+        record(svcb(0, "alias.example.", &[], 443, hint)),
+        record(svcb(1, "dot.example.", &["dot"], 853, hint)),
+    ];
+
+    let found = designations(&response);
+    assert_eq!(
+        found[0],
+        Designation {
+            priority: 1,
+            target: DnsName::new("dot.example").unwrap(),
+            alpn: vec!["dot".to_string()],
+            port: Some(853),
+            hints: vec![IpAddr::V4(hint)],
+            dohpath: Some("/dns-query{?dns}".to_string()),
+        }
+    );
+    assert!(found[0].speaks_dot());
+    assert_eq!(found[1].target, DnsName::new("doh.example").unwrap());
+    assert!(!found[1].speaks_dot());
+    assert_eq!(found.len(), 2);
+}
+
+#[test]
+fn unverified_designation_isnt_used() {
+    // Accepts connections and closes them, so the TLS handshake never completes
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || listener.incoming().for_each(drop));
+
+    let server = MockServer::new(move |query| match query.questions[0].qtype {
+        QueryType::UNKNOWN(SVCB) => Reply::Answer(vec![record(svcb(
+            1,
+            "dot.example.",
+            &["dot"],
+            port,
+            Ipv4Addr::LOCALHOST,
+        ))]),
+        _ => Reply::Answer(Vec::new()),
+    })
+    .unwrap();
+
+    let designated = discover(server.addr(), POLICY, None).unwrap();
+    assert_eq!(designated, None);
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn upstream_without_designations_stays_plain() {
+    let server = MockServer::new(|_| Reply::Answer(Vec::new())).unwrap();
+
+    assert_eq!(discover(server.addr(), POLICY, None).unwrap(), None);
+}
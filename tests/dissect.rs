@@ -0,0 +1,104 @@
+//! Messages split into annotated fields, compared to the dump expected for them
+
+use dns_server::dissect::{self, Field};
+
+/// `www.example.com A` answered with a CNAME to `web.example.com` and its address, with every
+/// name after the question compressed
+#[rustfmt::skip]
+const RESPONSE: [u8; 67] = [
+    0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+    3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+    0x00, 0x01, 0x00, 0x01,
+    0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x06,
+    3, b'w', b'e', b'b', 0xc0, 0x10,
+    0xc0, 0x2d, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x04, 192, 0, 2, 1,
+];
+
+const DUMP: &str = "
+; header
+0000  12 34                    id: 4660
+0002  81 80                    flags: qr=1 opcode=0 aa=0 tc=0 rd=1 ra=1 z=0 ad=0 cd=0 rcode=NOERROR
+0004  00 01                    question count: 1
+0006  00 02                    answer count: 2
+0008  00 00                    authority count: 0
+000a  00 00                    additional count: 0
+
+; question 1: www.example.com
+000c  03 77 77 77              label: www
+0010  07 65 78 61 6d 70 6c 65  label: example
+0018  03 63 6f 6d              label: com
+001c  00                       root
+001d  00 01                    type: A
+001f  00 01                    class: 1
+
+; answer 1: www.example.com.\t300\tIN\tCNAME\tweb.example.com.
+0021  c0 0c                    pointer to 0x000c: www.example.com
+0023  00 05                    type: CNAME
+0025  00 01                    class: 1
+0027  00 00 01 2c              ttl: 300
+002b  00 06                    rdata length: 6
+002d  03 77 65 62              label: web
+0031  c0 10                    pointer to 0x0010: example.com
+
+; answer 2: web.example.com.\t300\tIN\tA\t192.0.2.1
+0033  c0 2d                    pointer to 0x002d: web.example.com
+0035  00 01                    type: A
+0037  00 01                    class: 1
+0039  00 00 01 2c              ttl: 300
+003d  00 04                    rdata length: 4
+003f  c0 00 02 01              rdata: 192.0.2.1
+";
+
+#[test]
+fn compressed_response() {
+    let fields = dissect::dissect(&RESPONSE);
+    assert_eq!(dissect::annotate(&RESPONSE, &fields), DUMP);
+
+    // Pointers take two bytes, wherever the name they point to is
+    let pointers: Vec<_> = fields
+        .iter()
+        .filter(|field| field.note.starts_with("pointer"))
+        .map(|field| (field.start, field.len))
+        .collect();
+    assert_eq!(pointers, [(0x21, 2), (0x31, 2), (0x33, 2)]);
+}
+
+#[test]
+fn pointers_past_the_end_are_shown_as_invalid() {
+    let mut wire = RESPONSE.to_vec();
+    wire[0x34] = 0x50;
+
+    let fields = dissect::dissect(&wire);
+    let pointer = fields
+        .iter()
+        .find(|field| field.start == 0x33 && field.len == 2)
+        .unwrap();
+    assert_eq!(pointer.note, "pointer to 0x0050: invalid (End of buffer)");
+    // The fields after it are still there
+    assert_eq!(
+        fields.last().unwrap().note,
+        "rdata: invalid (End of buffer)"
+    );
+}
+
+#[test]
+fn truncated_messages_end_with_a_note() {
+    let wire = &RESPONSE[..0x36];
+
+    let fields = dissect::dissect(wire);
+    assert_eq!(
+        fields[fields.len() - 2..],
+        [
+            Field {
+                start: 0x35,
+                len: 0,
+                note: String::from("message ends at 0x0035, in the middle of a field"),
+            },
+            Field {
+                start: 0x35,
+                len: 1,
+                note: String::from("trailing bytes"),
+            },
+        ]
+    );
+}
@@ -0,0 +1,168 @@
+//! AAAA records synthesized from A records for clients on IPv6-only networks, RFC 6147
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use dns_server::dns64::Dns64;
+use dns_server::{DnsName, DnsPacket, DnsRecord, QueryType, RData, ResultCode};
+
+fn name() -> DnsName {
+    DnsName::new("www.example.com").unwrap()
+}
+
+/// The response to a query for `qtype` with `answers`
+fn response(qtype: QueryType, answers: Vec<RData>) -> DnsPacket {
+    let query = DnsPacket::query("www.example.com", qtype).build().unwrap();
+    let mut packet = DnsPacket::response_to(&query);
+    packet.answers = answers
+        .into_iter()
+        .map(|rdata| DnsRecord::new(name(), 300, rdata))
+        .collect();
+    packet
+}
+
+/// A negative AAAA answer, cached for `minimum` seconds
+fn no_aaaa(minimum: u32) -> DnsPacket {
+    let mut packet = response(QueryType::AAAA, Vec::new());
+    packet.authorities.push(DnsRecord::new(
+        DnsName::new("example.com").unwrap(),
+        3600,
+        RData::SOA {
+            m_name: DnsName::new("ns1.example.com").unwrap(),
+            r_name: DnsName::new("admin.example.com").unwrap(),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum,
+        },
+    ));
+    packet
+}
+
+fn a(addr: [u8; 4]) -> RData {
+    RData::A {
+        addr: Ipv4Addr::from(addr),
+    }
+}
+
+fn aaaa(addr: &str) -> RData {
+    RData::AAAA {
+        addr: addr.parse().unwrap(),
+    }
+}
+
+fn addresses(packet: &DnsPacket) -> Vec<Ipv6Addr> {
+    packet
+        .answers
+        .iter()
+        .filter_map(|rec| match rec.rdata {
+            RData::AAAA { addr } => Some(addr),
+            _ => None,
+        })
+        .collect()
+}
+
+fn dns64(prefix: &str) -> Dns64 {
+    let dns64 = Dns64 {
+        prefix: prefix.parse().unwrap(),
+        ..Dns64::default()
+    };
+    dns64.validate().unwrap();
+    dns64
+}
+
+#[test]
+fn well_known_prefix_embeds_the_whole_address() {
+    let dns64 = Dns64::default();
+    let empty = no_aaaa(60);
+    assert!(dns64.needs_synthesis(&empty));
+
+    let a = response(QueryType::A, vec![a([192, 0, 2, 33]), a([198, 51, 100, 1])]);
+    let synthesized = dns64.synthesize(empty, a);
+    assert_eq!(synthesized.header.rescode, ResultCode::NOERROR);
+    assert_eq!(
+        addresses(&synthesized),
+        [
+            "64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap(),
+            "64:ff9b::c633:6401".parse().unwrap(),
+        ]
+    );
+    // Only cached as long as the missing AAAA records would have been
+    assert!(synthesized.answers.iter().all(|rec| rec.ttl() == 60));
+}
+
+#[test]
+fn custom_prefixes_skip_the_reserved_octet() {
+    // The examples of RFC 6052 section 2.4 for 192.0.2.33
+    let cases = [
+        ("2001:db8::/32", "2001:db8:c000:221::"),
+        ("2001:db8:100::/40", "2001:db8:1c0:2:21::"),
+        ("2001:db8:122::/48", "2001:db8:122:c000:2:2100::"),
+        ("2001:db8:122:300::/56", "2001:db8:122:3c0:0:221::"),
+        ("2001:db8:122:344::/64", "2001:db8:122:344:c0:2:2100:0"),
+        ("2001:db8:122:344::/96", "2001:db8:122:344::c000:221"),
+    ];
+    for (prefix, expected) in cases {
+        let a = response(QueryType::A, vec![a([192, 0, 2, 33])]);
+        let synthesized = dns64(prefix).synthesize(no_aaaa(300), a);
+        assert_eq!(
+            addresses(&synthesized),
+            [expected.parse::<Ipv6Addr>().unwrap()],
+            "{prefix}"
+        );
+    }
+}
+
+#[test]
+fn prefixes_of_other_lengths_are_refused() {
+    for prefix in ["2001:db8::/36", "2001:db8::/128", "192.0.2.0/24"] {
+        let dns64 = Dns64 {
+            prefix: prefix.parse().unwrap(),
+            ..Dns64::default()
+        };
+        assert!(dns64.validate().is_err(), "{prefix}");
+    }
+}
+
+#[test]
+fn real_aaaa_records_pass_through() {
+    let dns64 = Dns64::default();
+    let real = response(QueryType::AAAA, vec![aaaa("2001:db8::1")]);
+    assert!(!dns64.needs_synthesis(&real));
+
+    // Nor are names that don't exist given addresses
+    let mut nxdomain = no_aaaa(300);
+    nxdomain.header.rescode = ResultCode::NXDOMAIN;
+    assert!(!dns64.needs_synthesis(&nxdomain));
+
+    // And without A records to go on, the AAAA answer stays as it was
+    let empty = no_aaaa(300);
+    let unchanged = dns64.synthesize(empty.clone(), response(QueryType::A, Vec::new()));
+    assert_eq!(unchanged, empty);
+}
+
+#[test]
+fn excluded_addresses_count_as_missing() {
+    // IPv4-mapped addresses are excluded by default, RFC 6147 section 5.1.4
+    let dns64 = Dns64::default();
+    let mapped = response(QueryType::AAAA, vec![aaaa("::ffff:192.0.2.33")]);
+    assert!(dns64.needs_synthesis(&mapped));
+    let synthesized = dns64.synthesize(mapped, response(QueryType::A, vec![a([192, 0, 2, 33])]));
+    assert_eq!(
+        addresses(&synthesized),
+        ["64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap()]
+    );
+
+    // As are configured ones, but a real address among them is enough
+    let dns64 = Dns64 {
+        exclude: vec!["2001:db8:dead::/48".parse().unwrap()],
+        ..Dns64::default()
+    };
+    let excluded = response(QueryType::AAAA, vec![aaaa("2001:db8:dead::1")]);
+    assert!(dns64.needs_synthesis(&excluded));
+    let mixed = response(
+        QueryType::AAAA,
+        vec![aaaa("2001:db8:dead::1"), aaaa("2001:db8:beef::1")],
+    );
+    assert!(!dns64.needs_synthesis(&mixed));
+}
@@ -0,0 +1,167 @@
+//! Browsing for services with PTR queries, and resolving instances with SRV and TXT queries
+
+use std::time::Duration;
+
+use dns_server::dnssd::{self, ServiceInstance, Transport};
+use dns_server::mock::{MockServer, Reply};
+use dns_server::resolver::RetryPolicy;
+use dns_server::{DnsName, DnsRecord, QueryType, RData};
+
+const POLICY: RetryPolicy = RetryPolicy::new(Duration::from_millis(500), 1);
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+fn ptr(instance: &str) -> DnsRecord {
+    DnsRecord::new(
+        name("_http._tcp.example.com"),
+        120,
+        RData::PTR {
+            host: name(instance),
+        },
+    )
+}
+
+fn srv(instance: &str, host: &str, port: u16) -> DnsRecord {
+    DnsRecord::new(
+        name(instance),
+        120,
+        RData::SRV {
+            priority: 0,
+            weight: 0,
+            port,
+            host: name(host),
+        },
+    )
+}
+
+fn txt(instance: &str, data: &[&[u8]]) -> DnsRecord {
+    DnsRecord::new(
+        name(instance),
+        120,
+        RData::TXT {
+            data: data.iter().map(|s| s.to_vec()).collect(),
+        },
+    )
+}
+
+/// The names and types of the queries `server` received
+fn asked(server: &MockServer) -> Vec<(String, QueryType)> {
+    server
+        .received()
+        .iter()
+        .map(|received| {
+            let question = &received.packet.questions[0];
+            (question.name.to_string().to_lowercase(), question.qtype)
+        })
+        .collect()
+}
+
+#[test]
+fn instances_are_resolved_with_srv_and_txt_queries() {
+    let server = MockServer::new(|query| {
+        let question = &query.questions[0];
+        let qname = question.name.to_string().to_lowercase();
+        Reply::Answer(match (qname.as_str(), question.qtype) {
+            // Each instance only once, however many times it is listed
+            ("_http._tcp.example.com", QueryType::PTR) => vec![
+                ptr("web._http._tcp.example.com"),
+                ptr("admin._http._tcp.example.com"),
+                ptr("web._http._tcp.example.com"),
+            ],
+            ("web._http._tcp.example.com", QueryType::SRV) => {
+                vec![srv("web._http._tcp.example.com", "www.example.com", 80)]
+            }
+            ("web._http._tcp.example.com", QueryType::TXT) => {
+                vec![txt("web._http._tcp.example.com", &[b"path=/", b"secure"])]
+            }
+            ("admin._http._tcp.example.com", QueryType::SRV) => {
+                vec![srv(
+                    "admin._http._tcp.example.com",
+                    "admin.example.com",
+                    8080,
+                )]
+            }
+            _ => Vec::new(),
+        })
+    })
+    .unwrap();
+
+    let transport = Transport::Unicast(server.addr(), POLICY);
+    let instances = dnssd::browse(&name("_http._tcp.example.com"), transport).unwrap();
+    assert_eq!(
+        instances,
+        [
+            ServiceInstance {
+                name: name("admin._http._tcp.example.com"),
+                host: name("admin.example.com"),
+                port: 8080,
+                // Without a TXT record
+                txt: Vec::new(),
+            },
+            ServiceInstance {
+                name: name("web._http._tcp.example.com"),
+                host: name("www.example.com"),
+                port: 80,
+                txt: vec![b"path=/".to_vec(), b"secure".to_vec()],
+            },
+        ]
+    );
+    assert_eq!(asked(&server).len(), 5);
+}
+
+#[test]
+fn records_sent_with_the_ptr_answer_are_not_asked_for() {
+    // In the additional section, with the SRV and TXT records of the instance
+    let server = MockServer::new(|query| {
+        let question = &query.questions[0];
+        match question.qtype {
+            QueryType::PTR => Reply::Referral(
+                Vec::new(),
+                vec![
+                    ptr("web._http._tcp.example.com"),
+                    srv("web._http._tcp.example.com", "www.example.com", 80),
+                    txt("web._http._tcp.example.com", &[b"path=/"]),
+                ],
+            ),
+            _ => Reply::Answer(Vec::new()),
+        }
+    })
+    .unwrap();
+    let transport = Transport::Unicast(server.addr(), POLICY);
+    let service = name("_http._tcp.example.com");
+    let instances = dnssd::browse(&service, transport).unwrap();
+    assert_eq!(instances.len(), 1);
+    assert_eq!(instances[0].port, 80);
+    assert_eq!(instances[0].txt, [b"path=/".to_vec()]);
+    assert_eq!(
+        asked(&server),
+        [(String::from("_http._tcp.example.com"), QueryType::PTR)]
+    );
+}
+
+#[test]
+fn instances_without_srv_records_are_not_found() {
+    let server = MockServer::new(|_| Reply::Answer(Vec::new())).unwrap();
+    let transport = Transport::Unicast(server.addr(), POLICY);
+
+    let instance = name("web._http._tcp.example.com");
+    assert_eq!(dnssd::resolve(&instance, transport).unwrap(), None);
+    // No TXT query without the SRV record
+    assert_eq!(
+        asked(&server),
+        [(String::from("web._http._tcp.example.com"), QueryType::SRV)]
+    );
+}
+
+#[test]
+fn labels_are_shown_unescaped() {
+    let instance = ServiceInstance {
+        name: name(r"Office\ Printer\.2._ipp._tcp.local"),
+        host: name("printer.local"),
+        port: 631,
+        txt: Vec::new(),
+    };
+    assert_eq!(instance.label(), "Office Printer.2");
+}
@@ -0,0 +1,201 @@
+//! Zones signed with DNSSEC and the proofs served from them
+
+use std::net::Ipv4Addr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dns_server::authority::Authority;
+use dns_server::dnssec::{self, ZoneKey, FLAGS_KSK, FLAGS_ZSK};
+use dns_server::zone::Zone;
+use dns_server::{DnsName, DnsRecord, QueryType, RData, ResultCode};
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+/// A zone signed with a key signing key and a zone signing key
+fn signed_zone() -> Zone {
+    let text = "$ORIGIN example.com.\n\
+                @ 300 IN SOA ns1 admin 1 3600 600 86400 300\n\
+                @ 300 IN NS ns1\n\
+                ns1 300 IN A 192.0.2.53\n\
+                a 300 IN TXT \"a\"\n\
+                z.a 300 IN TXT \"z.a\"\n\
+                *.wild 300 IN TXT \"wild\"\n\
+                www 300 IN A 192.0.2.1\n";
+    let mut zone = Zone::parse(text, &DnsName::root()).unwrap();
+    zone.sign(vec![
+        ZoneKey::new(FLAGS_KSK, &[1; 32]),
+        ZoneKey::new(FLAGS_ZSK, &[2; 32]),
+    ]);
+    zone
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+/// Whether one of the DNSKEY records in `zone` made the signature over `rrset`
+fn verifies(zone: &Zone, rrsig: &DnsRecord, rrset: &[DnsRecord], now: u32) -> bool {
+    zone.records
+        .iter()
+        .filter(|rec| rec.qtype() == QueryType::DNSKEY)
+        .any(|dnskey| dnssec::verify(dnskey, rrsig, rrset, now))
+}
+
+/// Every signature among `records` with the RRset from `records` it covers
+fn signed_rrsets(records: &[DnsRecord]) -> Vec<(&DnsRecord, Vec<DnsRecord>)> {
+    records
+        .iter()
+        .filter_map(|rrsig| match rrsig.rdata {
+            RData::RRSIG { type_covered, .. } => {
+                let rrset = records
+                    .iter()
+                    .filter(|rec| rec.domain() == rrsig.domain() && rec.qtype() == type_covered)
+                    .cloned()
+                    .collect();
+                Some((rrsig, rrset))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn signatures_verify() {
+    let zone = signed_zone();
+    let signed = signed_rrsets(&zone.records);
+    // The SOA, NS and DNSKEY RRsets at the apex, 5 more and an NSEC record for every owner
+    assert_eq!(signed.len(), 14);
+
+    for (rrsig, rrset) in signed {
+        assert!(verifies(&zone, rrsig, &rrset, now()), "{rrsig}");
+
+        // Not when the RRset changed, or once the signature expired
+        let mut changed = rrset.clone();
+        changed.push(DnsRecord::new(
+            rrset[0].domain().clone(),
+            rrset[0].ttl(),
+            RData::A {
+                addr: Ipv4Addr::new(198, 51, 100, 1),
+            },
+        ));
+        assert!(!verifies(&zone, rrsig, &changed, now()), "{rrsig}");
+        let later = now() + 60 * 24 * 60 * 60;
+        assert!(!verifies(&zone, rrsig, &rrset, later), "{rrsig}");
+    }
+
+    // The DNSKEY RRset is signed by the key signing key, the rest by the zone signing key
+    let signer = |qtype: QueryType| {
+        zone.records.iter().find_map(|rec| match rec.rdata {
+            RData::RRSIG {
+                type_covered,
+                key_tag,
+                ..
+            } if type_covered == qtype => Some(key_tag),
+            _ => None,
+        })
+    };
+    let ksk = ZoneKey::new(FLAGS_KSK, &[1; 32]).key_tag();
+    let zsk = ZoneKey::new(FLAGS_ZSK, &[2; 32]).key_tag();
+    assert_eq!(signer(QueryType::DNSKEY), Some(ksk));
+    assert_eq!(signer(QueryType::SOA), Some(zsk));
+}
+
+#[test]
+fn nsec_chain_is_in_canonical_order() {
+    let zone = signed_zone();
+    let next = |owner: &DnsName| {
+        zone.records.iter().find_map(|rec| match &rec.rdata {
+            RData::NSEC { next, .. } if rec.domain() == owner => Some(next.clone()),
+            _ => None,
+        })
+    };
+
+    // Compared label by label from the right, so everything under `a` sorts before `ns1`
+    let mut chain = vec![zone.origin.clone()];
+    while chain.len() <= zone.records.len() {
+        let next = next(chain.last().unwrap()).unwrap();
+        if next == zone.origin {
+            break;
+        }
+        chain.push(next);
+    }
+    let expected = [
+        "example.com",
+        "a.example.com",
+        "z.a.example.com",
+        "ns1.example.com",
+        "*.wild.example.com",
+        "www.example.com",
+    ];
+    assert_eq!(chain, expected.map(name));
+}
+
+#[test]
+fn nxdomain_is_proven_with_nsec() {
+    let mut authority = Authority::default();
+    authority.insert(signed_zone());
+    let zone = &authority.zones()[0];
+
+    let response = authority
+        .lookup(&name("b.example.com"), QueryType::A, true)
+        .unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+    assert!(response.answers.is_empty());
+
+    // The name falls between `z.a` and `ns1`, and the wildcard at the apex before `a`
+    let nsecs: Vec<_> = response
+        .authorities
+        .iter()
+        .filter(|rec| rec.qtype() == QueryType::NSEC)
+        .map(|rec| rec.domain().clone())
+        .collect();
+    assert_eq!(nsecs, [name("z.a.example.com"), name("example.com")]);
+    assert!(response
+        .authorities
+        .iter()
+        .any(|rec| rec.qtype() == QueryType::SOA));
+
+    let signed = signed_rrsets(&response.authorities);
+    assert_eq!(signed.len(), 3);
+    for (rrsig, rrset) in signed {
+        assert!(verifies(zone, rrsig, &rrset, now()), "{rrsig}");
+    }
+
+    // Without the DO bit, none of it
+    let response = authority
+        .lookup(&name("b.example.com"), QueryType::A, false)
+        .unwrap();
+    assert_eq!(response.authorities.len(), 1);
+}
+
+#[test]
+fn wildcard_answers_are_proven_with_nsec() {
+    let mut authority = Authority::default();
+    authority.insert(signed_zone());
+    let zone = &authority.zones()[0];
+
+    let qname = name("host.wild.example.com");
+    let response = authority.lookup(&qname, QueryType::TXT, true).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+
+    // The signature counts the labels of the wildcard, so it verifies for the synthesized name
+    let signed = signed_rrsets(&response.answers);
+    assert_eq!(signed.len(), 1);
+    let (rrsig, rrset) = &signed[0];
+    assert_eq!(rrset[0].domain(), &qname);
+    assert!(matches!(rrsig.rdata, RData::RRSIG { labels: 3, .. }));
+    assert!(verifies(zone, rrsig, rrset, now()));
+
+    // And the NSEC of the wildcard proves the name itself doesn't exist
+    let nsec = response
+        .authorities
+        .iter()
+        .find(|rec| rec.qtype() == QueryType::NSEC)
+        .unwrap();
+    assert_eq!(nsec.domain(), &name("*.wild.example.com"));
+    assert!(matches!(&nsec.rdata, RData::NSEC { next, .. } if *next == name("www.example.com")));
+}
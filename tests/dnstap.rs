@@ -0,0 +1,121 @@
+//! Messages written as dnstap, protobuf in a Frame Streams file
+
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
+
+use dns_server::config::DnstapConfig;
+use dns_server::dnstap::Dnstap;
+
+/// The control frame starting a stream of `protobuf:dnstap.Dnstap`
+#[rustfmt::skip]
+const START: [u8; 42] = [
+    0, 0, 0, 0, 0, 0, 0, 0x22,
+    0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 22,
+    b'p', b'r', b'o', b't', b'o', b'b', b'u', b'f', b':',
+    b'd', b'n', b's', b't', b'a', b'p', b'.', b'D', b'n', b's', b't', b'a', b'p',
+];
+
+/// A protobuf field: its number, and its value as a number or bytes
+#[derive(Debug, PartialEq, Eq)]
+enum Value {
+    Number(u64),
+    Bytes(Vec<u8>),
+}
+
+fn varint(data: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+/// The fields of a protobuf message, in order
+fn fields(data: &[u8]) -> Vec<(u64, Value)> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = varint(data, &mut pos);
+        let value = match key & 7 {
+            0 => Value::Number(varint(data, &mut pos)),
+            2 => {
+                let len = varint(data, &mut pos) as usize;
+                pos += len;
+                Value::Bytes(data[pos - len..pos].to_vec())
+            }
+            5 => {
+                pos += 4;
+                let bytes = data[pos - 4..pos].try_into().unwrap();
+                Value::Number(u32::from_le_bytes(bytes).into())
+            }
+            wire_type => panic!("unexpected wire type {wire_type}"),
+        };
+        fields.push((key >> 3, value));
+    }
+    fields
+}
+
+fn field(fields: &[(u64, Value)], number: u64) -> &Value {
+    &fields.iter().find(|(n, _)| *n == number).unwrap().1
+}
+
+#[test]
+fn client_queries_are_framed_after_the_start_frame() {
+    let path = env::temp_dir().join(format!("dns-server-{}.dnstap", process::id()));
+    let dnstap = Dnstap::open(&DnstapConfig {
+        file: Some(path.clone()),
+        identity: Some(String::from("ns1")),
+        ..DnstapConfig::default()
+    })
+    .unwrap();
+
+    let client: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+    let local: SocketAddr = "[::ffff:192.0.2.53]:53".parse().unwrap();
+    let wire = [0x12, 0x34, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+    dnstap.client_query(client, local, true, &wire);
+
+    // Written by a thread of its own, flushed once the queue is empty
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut data = Vec::new();
+    while data.len() <= START.len() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+        data = fs::read(&path).unwrap();
+    }
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(data[..START.len()], START);
+    let frame = &data[START.len() + 4..];
+    let len = u32::from_be_bytes(data[START.len()..START.len() + 4].try_into().unwrap());
+    assert_eq!(len as usize, frame.len());
+
+    let dnstap = fields(frame);
+    assert_eq!(*field(&dnstap, 1), Value::Bytes(b"ns1".to_vec()));
+    assert_eq!(*field(&dnstap, 15), Value::Number(1));
+    let Value::Bytes(message) = field(&dnstap, 14) else {
+        panic!("no message");
+    };
+
+    let message = fields(message);
+    let expected = [
+        (1, Value::Number(5)),
+        (2, Value::Number(1)),
+        (3, Value::Number(2)),
+        (4, Value::Bytes(vec![192, 0, 2, 1])),
+        // The IPv4-mapped address of a dual-stack socket is given as IPv4
+        (5, Value::Bytes(vec![192, 0, 2, 53])),
+        (6, Value::Number(50000)),
+        (7, Value::Number(53)),
+    ];
+    assert_eq!(message[..expected.len()], expected);
+    // Then the time it came in, and the query itself
+    assert_eq!(message[7].0, 8);
+    assert_eq!(message[8].0, 9);
+    assert_eq!(message[9], (10, Value::Bytes(wire.to_vec())));
+}
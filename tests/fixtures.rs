@@ -0,0 +1,54 @@
+//! Responses in `tests/fixtures` parsed and compared to the expected output next to them
+//!
+//! Each `<name>.bin` is a message as received over UDP, with the names compressed the way servers
+//! send them, and `<name>.txt` the packet it parses to, or the error. Run with `UPDATE_FIXTURES=1`
+//! to write the output of the parser as the new expected output after a deliberate change.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use dns_server::{BytePacketBuffer, DnsPacket};
+
+fn check(name: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let wire = fs::read(dir.join(format!("{name}.bin"))).unwrap();
+    let mut buf = BytePacketBuffer { buf: wire, pos: 0 };
+    let output = match DnsPacket::from_buffer(&mut buf) {
+        Ok(packet) => format!("{packet:#?}\n"),
+        Err(e) => format!("Error: {e}\n"),
+    };
+
+    let expected_path = dir.join(format!("{name}.txt"));
+    if env::var_os("UPDATE_FIXTURES").is_some() {
+        fs::write(&expected_path, &output).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&expected_path).unwrap();
+    assert_eq!(output, expected, "{name}.bin parsed differently");
+}
+
+macro_rules! fixtures {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                check(stringify!($name));
+            }
+        )*
+    };
+}
+
+fixtures!(
+    a,
+    aaaa,
+    cname_chain,
+    cname_chain_long,
+    cut_off,
+    edns,
+    mx,
+    nxdomain_soa,
+    srv,
+    truncated,
+    txt,
+);
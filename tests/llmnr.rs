@@ -0,0 +1,103 @@
+//! LLMNR responses for single-label names, and the queries left for other hosts
+
+use std::net::Ipv4Addr;
+
+use dns_server::llmnr;
+use dns_server::server::ServerContext;
+use dns_server::{BytePacketBuffer, DnsPacket, DnsRecord, QueryType, RData};
+
+/// Bits of the third byte of the header
+const QR: u8 = 0x80;
+/// The AA bit of DNS, the conflict bit of LLMNR
+const C: u8 = 0x04;
+const TC: u8 = 0x02;
+
+fn context() -> ServerContext {
+    let config = "listen = \"127.0.0.1:0\"\n\
+                  upstream = [\"127.0.0.1:9\"]\n\
+                  llmnr = true\n\
+                  [[local-records]]\n\
+                  name = \"nas\"\n\
+                  type = \"A\"\n\
+                  value = \"192.168.1.10\"\n\
+                  [[local-records]]\n\
+                  name = \"nas.lan\"\n\
+                  type = \"A\"\n\
+                  value = \"192.168.1.10\"";
+    ServerContext::new(config.parse().unwrap()).unwrap()
+}
+
+fn query(qname: &str) -> DnsPacket {
+    DnsPacket::query(qname, QueryType::A)
+        .id(0x1234)
+        .build()
+        .unwrap()
+}
+
+/// The response as it goes on the wire
+fn wire(response: &mut DnsPacket) -> Vec<u8> {
+    let mut buf = BytePacketBuffer::new();
+    response.write(&mut buf).unwrap();
+    buf.buf[..buf.pos()].to_vec()
+}
+
+#[test]
+fn single_label_names_are_answered_without_the_conflict_bit() {
+    let context = context();
+    let query = query("nas");
+
+    let mut response = llmnr::answer(&context, &query).unwrap();
+    let wire = wire(&mut response);
+    assert_eq!(wire[..2], [0x12, 0x34]);
+    assert_eq!(wire[2] & (QR | C | TC), QR);
+    // The question is echoed as it was asked
+    assert_eq!(response.questions, query.questions);
+    assert_eq!(
+        response.answers,
+        [DnsRecord::new(
+            query.questions[0].name.clone(),
+            300,
+            RData::A {
+                addr: Ipv4Addr::new(192, 168, 1, 10)
+            },
+        )]
+    );
+}
+
+#[test]
+fn queries_setting_the_conflict_bit_are_still_answered_without_it() {
+    let context = context();
+    let mut query = query("nas");
+    query.header.authoritative_answer = true;
+
+    let mut response = llmnr::answer(&context, &query).unwrap();
+    assert_eq!(wire(&mut response)[2] & C, 0);
+}
+
+#[test]
+fn other_queries_are_left_for_other_hosts() {
+    let context = context();
+
+    // Names with more than one label, even known ones, and names this host doesn't have
+    assert!(llmnr::answer(&context, &query("nas.lan")).is_none());
+    assert!(llmnr::answer(&context, &query("printer")).is_none());
+
+    // Responses, such as those of another host claiming the name
+    let mut response = query("nas");
+    response.header.response = true;
+    assert!(llmnr::answer(&context, &response).is_none());
+
+    // Queries carrying records, and classes other than IN
+    let mut claim = query("nas");
+    claim.answers.push(DnsRecord::new(
+        claim.questions[0].name.clone(),
+        30,
+        RData::A {
+            addr: Ipv4Addr::new(192, 168, 1, 99),
+        },
+    ));
+    assert!(llmnr::answer(&context, &claim).is_none());
+    let mut chaos = query("nas");
+    chaos.questions[0].class = 3;
+    assert!(llmnr::answer(&context, &chaos).is_none());
+}
@@ -0,0 +1,128 @@
+//! mDNS responses as they go on the wire: where they are sent, and the top bit of the class
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use dns_server::config::MdnsConfig;
+use dns_server::mdns::{Responder, MDNS_IPV4, MDNS_PORT};
+use dns_server::{BytePacketBuffer, DnsName, DnsPacket, DnsRecord, QueryType, RData};
+
+/// Top bit of the class: a unicast response asked for in questions, cache flush in records
+const TOP_BIT: u16 = 0x8000;
+
+const ADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+
+fn responder() -> Responder {
+    Responder::new(&MdnsConfig {
+        names: vec![DnsName::new("host.local").unwrap()],
+        addresses: vec![IpAddr::V4(ADDR)],
+        ttl: 120,
+    })
+}
+
+fn group() -> SocketAddr {
+    (MDNS_IPV4, MDNS_PORT).into()
+}
+
+/// A responder on the link, asking from the mDNS port
+fn peer() -> SocketAddr {
+    (Ipv4Addr::new(192, 168, 1, 30), MDNS_PORT).into()
+}
+
+fn query(unicast: bool) -> DnsPacket {
+    let mut query = DnsPacket::query("host.local", QueryType::A)
+        .build()
+        .unwrap();
+    query.header.id = 0;
+    if unicast {
+        query.questions[0].class |= TOP_BIT;
+    }
+    query
+}
+
+/// The response sent for `query` from `src`, read back from the wire, and where it went
+fn respond(query: &DnsPacket, src: SocketAddr) -> Option<(DnsPacket, SocketAddr)> {
+    let (sent, dest) = responder().respond(query, src, group()).unwrap()?;
+    let mut buf = BytePacketBuffer::new();
+    buf.buf = sent.buf[..sent.pos()].to_vec();
+    Some((DnsPacket::from_buffer(&mut buf).unwrap(), dest))
+}
+
+#[test]
+fn questions_with_the_unicast_bit_are_answered_to_the_querier() {
+    let (response, dest) = respond(&query(true), peer()).unwrap();
+    assert_eq!(dest, peer());
+    assert!(response.header.response && response.header.authoritative_answer);
+    assert_eq!(response.header.id, 0);
+    assert!(response.questions.is_empty());
+
+    // Still flushing the caches of whoever gets it
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.answers[0].class, 1 | TOP_BIT);
+    assert_eq!(response.answers[0].rdata, RData::A { addr: ADDR });
+    assert_eq!(response.answers[0].ttl(), 120);
+}
+
+#[test]
+fn questions_without_it_are_answered_to_the_group() {
+    let (response, dest) = respond(&query(false), peer()).unwrap();
+    assert_eq!(dest, group());
+    assert_eq!(response.answers[0].class, 1 | TOP_BIT);
+
+    // One question asking for multicast is enough
+    let mut query = query(true);
+    let mut multicast = query.questions[0].clone();
+    multicast.class &= !TOP_BIT;
+    multicast.qtype = QueryType::AAAA;
+    query.questions.push(multicast);
+    let (_, dest) = respond(&query, peer()).unwrap();
+    assert_eq!(dest, group());
+}
+
+#[test]
+fn legacy_queriers_get_a_plain_unicast_response() {
+    let resolver: SocketAddr = "192.168.1.30:40000".parse().unwrap();
+    let mut query = query(false);
+    query.header.id = 0x1234;
+
+    let (response, dest) = respond(&query, resolver).unwrap();
+    assert_eq!(dest, resolver);
+    assert_eq!(response.header.id, 0x1234);
+    assert_eq!(response.questions, query.questions);
+    // Without the cache-flush bit, which other resolvers would take as part of the class
+    assert_eq!(response.answers[0].class, 1);
+    assert_eq!(response.answers[0].ttl(), 10);
+}
+
+#[test]
+fn known_answers_and_other_names_are_not_answered() {
+    let mut query = query(false);
+    query.answers.push(DnsRecord::new(
+        DnsName::new("host.local").unwrap(),
+        120,
+        RData::A { addr: ADDR },
+    ));
+    assert!(respond(&query, peer()).is_none());
+
+    let other = DnsPacket::query("other.local", QueryType::A)
+        .build()
+        .unwrap();
+    assert!(respond(&other, peer()).is_none());
+}
+
+#[test]
+fn announcements_hold_every_record() {
+    let responder = responder();
+    let announcement = responder.announcement();
+    // The address and the reverse name pointing back to the host
+    assert_eq!(announcement.answers.len(), 2);
+    assert_eq!(
+        announcement.answers[1].rdata,
+        RData::PTR {
+            host: DnsName::new("host.local").unwrap()
+        }
+    );
+    assert_eq!(
+        announcement.answers[1].domain(),
+        "20.1.168.192.in-addr.arpa"
+    );
+}
@@ -0,0 +1,163 @@
+//! DNS messages taken out of pcap captures, down from the file headers to the UDP and TCP payloads
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use dns_server::pcap::{self, CapturedMessage};
+
+/// `www.example.com A` as a client sends it
+#[rustfmt::skip]
+const QUERY: [u8; 33] = [
+    0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    3, b'w', b'w', b'w', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+    0x00, 0x01, 0x00, 0x01,
+];
+
+/// Captured at 1700000000.25 seconds
+const TIME: Duration = Duration::new(1_700_000_000, 250_000_000);
+
+fn client() -> SocketAddr {
+    "192.0.2.1:50000".parse().unwrap()
+}
+
+fn server() -> SocketAddr {
+    "192.0.2.53:53".parse().unwrap()
+}
+
+/// An IPv4 packet from the client to the server, without checksums since they aren't checked
+fn ipv4(protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let total_len = (20 + payload.len()) as u16;
+    let mut packet = vec![0x45, 0x00];
+    packet.extend(total_len.to_be_bytes());
+    packet.extend([0, 0, 0x40, 0x00, 64, protocol, 0, 0]);
+    packet.extend([192, 0, 2, 1, 192, 0, 2, 53]);
+    packet.extend(payload);
+    packet
+}
+
+fn ethernet(ip: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x02, 0, 0, 0, 0, 0x53, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00];
+    frame.extend(ip);
+    frame
+}
+
+/// A TCP segment from the client to the server with `seq` and `flags`
+fn tcp(seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::new();
+    segment.extend(50000_u16.to_be_bytes());
+    segment.extend(53_u16.to_be_bytes());
+    segment.extend(seq.to_be_bytes());
+    segment.extend([0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    segment.extend(payload);
+    ethernet(&ipv4(6, &segment))
+}
+
+/// A little-endian pcap file with microsecond timestamps and a record for every frame, all
+/// captured at `TIME`
+fn capture(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut file = vec![
+        0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 1, 0, 0, 0,
+    ];
+    for frame in frames {
+        let len = frame.len() as u32;
+        file.extend(1_700_000_000_u32.to_le_bytes());
+        file.extend(250_000_u32.to_le_bytes());
+        file.extend(len.to_le_bytes());
+        file.extend(len.to_le_bytes());
+        file.extend(frame);
+    }
+    file
+}
+
+fn udp_query() -> CapturedMessage {
+    CapturedMessage {
+        time: TIME,
+        src: client(),
+        dst: server(),
+        tcp: false,
+        wire: QUERY.to_vec(),
+    }
+}
+
+#[test]
+fn little_endian_microsecond_capture() {
+    #[rustfmt::skip]
+    let mut file = vec![
+        // Global header: magic, version 2.4, zone, accuracy, snapshot length, Ethernet
+        0xd4, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xff, 0xff, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        // Record header: seconds, microseconds, captured and original length
+        0x00, 0xf1, 0x53, 0x65, 0x90, 0xd0, 0x03, 0x00,
+        0x4b, 0x00, 0x00, 0x00, 0x4b, 0x00, 0x00, 0x00,
+        // Ethernet to IPv4
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x53, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00,
+        // IPv4 header of a UDP datagram, 61 bytes long
+        0x45, 0x00, 0x00, 0x3d, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+        0xc0, 0x00, 0x02, 0x01, 0xc0, 0x00, 0x02, 0x35,
+        // UDP header, from port 50000 to 53
+        0xc3, 0x50, 0x00, 0x35, 0x00, 0x29, 0x00, 0x00,
+    ];
+    file.extend(QUERY);
+
+    assert!(pcap::is_capture(&file));
+    assert_eq!(pcap::read_messages(&file).unwrap(), [udp_query()]);
+}
+
+#[test]
+fn big_endian_nanosecond_capture() {
+    #[rustfmt::skip]
+    let mut file = vec![
+        0xa1, 0xb2, 0x3c, 0x4d, 0x00, 0x02, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x65,
+        // Nanoseconds this time, and a raw IP frame
+        0x65, 0x53, 0xf1, 0x00, 0x0e, 0xe6, 0xb2, 0x80,
+        0x00, 0x00, 0x00, 0x3d, 0x00, 0x00, 0x00, 0x3d,
+    ];
+    let mut datagram = vec![0xc3, 0x50, 0x00, 0x35, 0x00, 0x29, 0x00, 0x00];
+    datagram.extend(QUERY);
+    file.extend(ipv4(17, &datagram));
+
+    assert!(pcap::is_capture(&file));
+    assert_eq!(pcap::read_messages(&file).unwrap(), [udp_query()]);
+}
+
+#[test]
+fn tcp_messages_are_reassembled_across_segments() {
+    let mut stream = (QUERY.len() as u16).to_be_bytes().to_vec();
+    stream.extend(QUERY);
+    let (first, rest) = stream.split_at(10);
+
+    let file = capture(&[
+        tcp(1000, 0x02, &[]),
+        tcp(1001, 0x18, first),
+        // Sent again, overlapping what came before
+        tcp(1001, 0x18, first),
+        tcp(1011, 0x18, rest),
+        tcp(1001 + stream.len() as u32, 0x11, &[]),
+    ]);
+
+    let messages = pcap::read_messages(&file).unwrap();
+    assert_eq!(
+        messages,
+        [CapturedMessage {
+            tcp: true,
+            ..udp_query()
+        }]
+    );
+}
+
+#[test]
+fn traffic_on_other_ports_and_truncated_files() {
+    let mut datagram = vec![0xc3, 0x50, 0x00, 0x50, 0x00, 0x29, 0x00, 0x00];
+    datagram.extend(QUERY);
+    let http = capture(&[ethernet(&ipv4(17, &datagram))]);
+    assert!(pcap::read_messages(&http).unwrap().is_empty());
+
+    let mut datagram = vec![0xc3, 0x50, 0x00, 0x35, 0x00, 0x29, 0x00, 0x00];
+    datagram.extend(QUERY);
+    let file = capture(&[ethernet(&ipv4(17, &datagram))]);
+    assert!(pcap::read_messages(&file[..file.len() - 1]).is_err());
+    assert!(!pcap::is_capture(&QUERY));
+}
@@ -0,0 +1,203 @@
+//! Forwarding through a SOCKS5 proxy to the mock server from the `test-util` feature
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use dns_server::mock::{MockServer, Reply};
+use dns_server::resolver::{lookup_https, lookup_observed, RetryPolicy};
+use dns_server::socks::Socks5Proxy;
+use dns_server::{BytePacketBuffer, DnsName, DnsPacket, DnsRecord, QueryType, RData};
+
+const POLICY: RetryPolicy = RetryPolicy::new(Duration::from_millis(500), 1);
+
+/// A SOCKS5 proxy without authentication on a free port, relaying UDP when `udp` is set and
+/// answering UDP ASSOCIATE with "command not supported" otherwise, like Tor does
+fn proxy(udp: bool) -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            thread::spawn(move || serve(stream, udp));
+        }
+    });
+
+    addr
+}
+
+fn serve(mut client: TcpStream, udp: bool) -> io::Result<()> {
+    let mut greeting = [0; 3];
+    client.read_exact(&mut greeting)?;
+    client.write_all(&[5, 0])?;
+
+    let mut request = [0; 10];
+    client.read_exact(&mut request)?;
+    let target = SocketAddr::from((
+        [request[4], request[5], request[6], request[7]],
+        u16::from_be_bytes([request[8], request[9]]),
+    ));
+    match request[1] {
+        1 => {
+            let upstream = TcpStream::connect(target)?;
+            client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+            let (mut from_client, mut to_client) = (client.try_clone()?, client);
+            let (mut to_upstream, mut from_upstream) = (upstream.try_clone()?, upstream);
+            thread::spawn(move || io::copy(&mut from_client, &mut to_upstream));
+            io::copy(&mut from_upstream, &mut to_client)?;
+        }
+        3 if udp => {
+            let relay = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+            let port = relay.local_addr()?.port().to_be_bytes();
+            client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, port[0], port[1]])?;
+            relay_datagrams(&relay)?;
+        }
+        _ => client.write_all(&[5, 7, 0, 1, 0, 0, 0, 0, 0, 0])?,
+    }
+
+    Ok(())
+}
+
+/// Pass each datagram from the client on to the server in its header, and the response back
+fn relay_datagrams(relay: &UdpSocket) -> io::Result<()> {
+    let upstream = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let mut buf = [0; 4096];
+    loop {
+        let (len, client) = relay.recv_from(&mut buf)?;
+        let target = SocketAddr::from((
+            [buf[4], buf[5], buf[6], buf[7]],
+            u16::from_be_bytes([buf[8], buf[9]]),
+        ));
+        upstream.send_to(&buf[10..len], target)?;
+
+        let mut response = buf[..10].to_vec();
+        let len = upstream.recv(&mut buf)?;
+        response.extend_from_slice(&buf[..len]);
+        relay.send_to(&response, client)?;
+    }
+}
+
+fn name() -> DnsName {
+    DnsName::new("www.example.com").unwrap()
+}
+
+fn upstream() -> MockServer {
+    MockServer::new(|_| {
+        Reply::Answer(vec![DnsRecord::new(
+            name(),
+            300,
+            RData::A {
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+    })
+    .unwrap()
+}
+
+fn lookup(server: &MockServer, proxy: &Socks5Proxy) -> dns_server::Result<DnsPacket> {
+    lookup_observed(
+        &name(),
+        QueryType::A,
+        server.addr(),
+        None,
+        POLICY,
+        Some(proxy),
+        &|_| {},
+    )
+}
+
+#[test]
+fn udp_is_relayed() {
+    let server = upstream();
+    let proxy = Socks5Proxy::new(proxy(true));
+
+    let response = lookup(&server, &proxy).unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert!(server.received().iter().all(|received| !received.tcp));
+}
+
+#[test]
+fn tcp_is_used_without_udp_relay() {
+    let server = upstream();
+    let proxy = Socks5Proxy::new(proxy(false));
+
+    let response = lookup(&server, &proxy).unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert!(server.received().iter().all(|received| received.tcp));
+}
+
+/// A DNS over HTTPS server without the TLS on a free port, answering every POST with an address
+fn doh_server() -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let r = answer_post(stream.unwrap());
+            eprintln!("POST {r:?}");
+        }
+    });
+
+    addr
+}
+
+fn answer_post(mut stream: TcpStream) -> io::Result<()> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head).to_lowercase();
+    let len = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|len| len.trim().parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body)?;
+
+    let mut response = DnsPacket::from_buffer(&mut BytePacketBuffer { buf: body, pos: 0 }).unwrap();
+    response.header.response = true;
+    response.answers.push(DnsRecord::new(
+        name(),
+        300,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+        },
+    ));
+    let mut buf = BytePacketBuffer::new();
+    response.write(&mut buf).unwrap();
+    let body = &buf.buf[..buf.pos()];
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)
+}
+
+#[test]
+fn https_goes_through_the_proxy_or_not_at_all() {
+    let server = doh_server();
+    let url = format!("http://doh.example:{}/dns-query", server.port());
+    let https = |proxy: &Socks5Proxy| {
+        lookup_https(
+            &name(),
+            QueryType::A,
+            &url,
+            server,
+            None,
+            POLICY,
+            Some(proxy),
+        )
+    };
+
+    let response = https(&Socks5Proxy::new(proxy(false))).unwrap();
+    assert_eq!(response.answers.len(), 1);
+
+    // A proxy that isn't there isn't gone around
+    let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let closed = Socks5Proxy::new(closed.local_addr().unwrap());
+    assert!(https(&closed).is_err());
+}
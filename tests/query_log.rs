@@ -0,0 +1,174 @@
+//! The query log: one line per query as text or JSON, rotated by size
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use std::{env, fs, process};
+
+use dns_server::config::{QueryLogConfig, QueryLogFormat};
+use dns_server::query_log::{QueryLog, Status};
+use dns_server::{DnsName, DnsPacket, DnsQuestion, DnsRecord, FailureReason, QueryType, RData};
+
+/// A log file of its own for every test
+fn log_path() -> PathBuf {
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let n = FILES.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("dns-server-{}-{n}.log", process::id()))
+}
+
+fn config(file: PathBuf, format: QueryLogFormat) -> QueryLogConfig {
+    QueryLogConfig {
+        file,
+        format,
+        max_size: 0,
+        rotate_interval: 0,
+        keep: 2,
+        anonymize: false,
+    }
+}
+
+fn question() -> DnsQuestion {
+    DnsQuestion::new(DnsName::new("WWW.example.com").unwrap(), QueryType::A)
+}
+
+/// The answer to [`question`] with two addresses
+fn response() -> DnsPacket {
+    let query = DnsPacket::query("www.example.com", QueryType::A)
+        .build()
+        .unwrap();
+    let mut response = DnsPacket::response_to(&query);
+    for last in [1, 2] {
+        response.answers.push(DnsRecord::new(
+            DnsName::new("www.example.com").unwrap(),
+            300,
+            RData::A {
+                addr: Ipv4Addr::new(192, 0, 2, last),
+            },
+        ));
+    }
+    response
+}
+
+fn client() -> IpAddr {
+    "198.51.100.7".parse().unwrap()
+}
+
+/// Whether `timestamp` looks like `2024-05-01T12:30:00.125Z`
+fn is_timestamp(timestamp: &str) -> bool {
+    let digits = |range: std::ops::Range<usize>| {
+        timestamp
+            .get(range)
+            .is_some_and(|part| part.bytes().all(|b| b.is_ascii_digit()))
+    };
+    timestamp.len() == 24
+        && digits(0..4)
+        && digits(5..7)
+        && digits(8..10)
+        && digits(11..13)
+        && digits(14..16)
+        && digits(17..19)
+        && digits(20..23)
+        && timestamp.ends_with('Z')
+        && &timestamp[10..11] == "T"
+}
+
+#[test]
+fn text_lines_have_the_fields_in_order() {
+    let path = log_path();
+    let log = QueryLog::open(config(path.clone(), QueryLogFormat::Text)).unwrap();
+    let latency = Duration::from_micros(12_345);
+    log.log(
+        client(),
+        &question(),
+        Some(&response()),
+        Status::Forwarded,
+        latency,
+    );
+    log.log(
+        client(),
+        &question(),
+        None,
+        Status::Failed(FailureReason::Timeout),
+        latency,
+    );
+    log.log(client(), &question(), None, Status::Dropped, latency);
+
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let lines: Vec<_> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+
+    // The name in lowercase, the result code, how it was answered, the latency and the answers
+    let (timestamp, rest) = lines[0].split_once(' ').unwrap();
+    assert!(is_timestamp(timestamp), "{timestamp}");
+    assert_eq!(
+        rest,
+        "198.51.100.7 www.example.com A NOERROR forwarded 12.3ms A 192.0.2.1, A 192.0.2.2"
+    );
+    // Failures give their reason instead of answers, and dropped queries have no result code
+    assert!(lines[1].ends_with(" www.example.com A - failed 12.3ms timeout"));
+    assert!(lines[2].ends_with(" www.example.com A - dropped 12.3ms"));
+}
+
+#[test]
+fn json_lines_are_objects_with_named_fields() {
+    let path = log_path();
+    let config = QueryLogConfig {
+        anonymize: true,
+        ..config(path.clone(), QueryLogFormat::Json)
+    };
+    let log = QueryLog::open(config).unwrap();
+    log.log(
+        client(),
+        &question(),
+        Some(&response()),
+        Status::Cached,
+        Duration::from_millis(2),
+    );
+
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let entry: serde_json::Value = serde_json::from_str(text.trim_end()).unwrap();
+    assert!(is_timestamp(entry["timestamp"].as_str().unwrap()));
+    // Only the network of the client is kept
+    assert_eq!(entry["client"], "198.51.100.0");
+    assert_eq!(entry["qname"], "www.example.com");
+    assert_eq!(entry["qtype"], "A");
+    assert_eq!(entry["rcode"], "NOERROR");
+    assert_eq!(entry["status"], "cached");
+    assert_eq!(
+        entry["answers"],
+        serde_json::json!(["A 192.0.2.1", "A 192.0.2.2"])
+    );
+    assert_eq!(entry["latency_ms"], 2.0);
+    assert!(entry.get("reason").is_none());
+}
+
+#[test]
+fn logs_are_rotated_when_they_grow_too_large() {
+    let path = log_path();
+    let config = QueryLogConfig {
+        max_size: 100,
+        ..config(path.clone(), QueryLogFormat::Text)
+    };
+    let log = QueryLog::open(config).unwrap();
+    // Each line is longer than the limit, so each one goes in a file of its own
+    for _ in 0..4 {
+        log.log(
+            client(),
+            &question(),
+            Some(&response()),
+            Status::Forwarded,
+            Duration::ZERO,
+        );
+    }
+
+    let rotated = |n: usize| PathBuf::from(format!("{}.{n}", path.display()));
+    for file in [path.clone(), rotated(1), rotated(2)] {
+        assert_eq!(fs::read_to_string(&file).unwrap().lines().count(), 1);
+        fs::remove_file(file).unwrap();
+    }
+    // Only two are kept
+    assert!(!rotated(3).exists());
+}
@@ -0,0 +1,109 @@
+//! The resolver configuration of each system, read from what its tools print
+
+use std::net::IpAddr;
+
+use dns_server::resolv_conf::ResolvConf;
+use dns_server::DnsName;
+
+fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+    addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+}
+
+fn names(names: &[&str]) -> Vec<DnsName> {
+    names
+        .iter()
+        .map(|name| DnsName::new(name).unwrap())
+        .collect()
+}
+
+const SCUTIL: &str = "\
+DNS configuration
+
+resolver #1
+  search domain[0] : corp.example
+  search domain[1] : example
+  nameserver[0] : 192.168.1.1
+  nameserver[1] : fe80::1%en0
+  if_index : 6 (en0)
+  flags    : Request A records, Request AAAA records
+  reach    : 0x00020002 (Reachable,Directly Reachable Address)
+
+resolver #2
+  domain   : local
+  options  : mdns
+  timeout  : 5
+  flags    : Request A records, Request AAAA records
+  reach    : 0x00000000 (Not Reachable)
+  order    : 300000
+
+DNS configuration (for scoped queries)
+
+resolver #1
+  search domain[0] : corp.example
+  nameserver[0] : 10.0.0.53
+  if_index : 6 (en0)
+  flags    : Scoped, Request A records
+";
+
+#[test]
+fn scutil_default_resolvers_are_read() {
+    let conf = ResolvConf::parse_scutil(SCUTIL);
+
+    assert_eq!(conf.nameservers, addrs(&["192.168.1.1", "fe80::1"]));
+    assert_eq!(conf.search, names(&["corp.example", "example"]));
+}
+
+const REGISTRY: &str = r"
+HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters
+    Hostname    REG_SZ    desktop
+    Domain    REG_SZ
+    SearchList    REG_SZ    corp.example,example
+    NameServer    REG_SZ
+
+HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters\Interfaces\{1}
+    EnableDHCP    REG_DWORD    0x1
+    NameServer    REG_SZ    1.1.1.1,9.9.9.9
+    DhcpNameServer    REG_SZ    192.168.1.1
+    DhcpDomain    REG_SZ    home.arpa
+
+HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters\Interfaces\{2}
+    EnableDHCP    REG_DWORD    0x1
+    DhcpNameServer    REG_SZ    10.0.0.53 10.0.0.54
+
+HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Tcpip6\Parameters\Interfaces\{1}
+    NameServer    REG_SZ    2606:4700:4700::1111
+";
+
+#[test]
+fn windows_registry_nameservers_are_read() {
+    let conf = ResolvConf::parse_windows_registry(REGISTRY);
+
+    // Servers set by hand win over the ones from DHCP of the same interface
+    assert_eq!(
+        conf.nameservers,
+        addrs(&[
+            "1.1.1.1",
+            "9.9.9.9",
+            "10.0.0.53",
+            "10.0.0.54",
+            "2606:4700:4700::1111"
+        ])
+    );
+    // The search list of the system wins over the domains of the interfaces
+    assert_eq!(conf.search, names(&["corp.example", "example"]));
+}
+
+#[test]
+fn windows_interface_domains_are_searched_without_a_search_list() {
+    let registry = REGISTRY.replace("corp.example,example", "");
+    let conf = ResolvConf::parse_windows_registry(&registry);
+
+    assert_eq!(conf.search, names(&["home.arpa"]));
+}
+
+#[test]
+fn upstreams_fall_back_to_a_public_resolver() {
+    let conf = ResolvConf::parse("search example\n");
+
+    assert_eq!(conf.upstreams(), ["8.8.8.8:53".parse().unwrap()]);
+}
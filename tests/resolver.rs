@@ -0,0 +1,173 @@
+//! Iterative resolution from a mock root, which plays every nameserver of the hierarchy
+
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use dns_server::mock::{MockServer, Reply};
+use dns_server::resolver::{recursive_lookup_from, RetryPolicy};
+use dns_server::{DnsName, DnsPacket, DnsRecord, QueryType, RData, ResultCode};
+
+const POLICY: RetryPolicy = RetryPolicy::new(Duration::from_millis(200), 1);
+
+const QNAME: &str = "www.a.b.example.com";
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+/// A referral to `zone`, served from the loopback address like everything else
+fn referral(zone: &str) -> Reply {
+    let host = name(&format!("ns.{zone}"));
+    Reply::Referral(
+        vec![DnsRecord::new(
+            name(zone),
+            300,
+            RData::NS { host: host.clone() },
+        )],
+        vec![DnsRecord::new(
+            host,
+            300,
+            RData::A {
+                addr: Ipv4Addr::LOCALHOST,
+            },
+        )],
+    )
+}
+
+/// The root delegating `com`, which delegates `example.com`, where `b.example.com` is answered
+/// with `ent` and `QNAME` has an address
+fn hierarchy(ent: Reply) -> MockServer {
+    MockServer::new(move |query| {
+        let qname = &query.questions[0].name;
+        if *qname == "com" {
+            referral("com")
+        } else if *qname == "example.com" {
+            referral("example.com")
+        } else if *qname == "b.example.com" {
+            ent.clone()
+        } else if *qname == QNAME {
+            Reply::Answer(vec![DnsRecord::new(
+                qname.clone(),
+                300,
+                RData::A {
+                    addr: Ipv4Addr::new(192, 0, 2, 1),
+                },
+            )])
+        } else {
+            Reply::Answer(Vec::new())
+        }
+    })
+    .unwrap()
+}
+
+fn resolve(root: &MockServer, qtype: QueryType) -> DnsPacket {
+    recursive_lookup_from(&name(QNAME), qtype, root.addr(), POLICY, &mut |_, _| {}).unwrap()
+}
+
+/// The questions the hierarchy was asked, in order and in any case
+fn asked(root: &MockServer) -> Vec<(String, QueryType)> {
+    root.received()
+        .iter()
+        .map(|received| {
+            let question = &received.packet.questions[0];
+            (question.name.as_str().to_ascii_lowercase(), question.qtype)
+        })
+        .collect()
+}
+
+fn expected(questions: &[(&str, QueryType)]) -> Vec<(String, QueryType)> {
+    questions
+        .iter()
+        .map(|(qname, qtype)| ((*qname).to_string(), *qtype))
+        .collect()
+}
+
+#[test]
+fn each_step_reveals_one_label_below_the_known_cut() {
+    let root = hierarchy(Reply::Answer(Vec::new()));
+
+    let response = resolve(&root, QueryType::A);
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(
+        response.a_records().collect::<Vec<_>>(),
+        [Ipv4Addr::new(192, 0, 2, 1)]
+    );
+    assert_eq!(response.questions[0].name, QNAME);
+
+    // Past the last cut, the same nameserver is asked one label more at a time, with the type
+    // only given away in the last question
+    assert_eq!(
+        asked(&root),
+        expected(&[
+            ("com", QueryType::A),
+            ("example.com", QueryType::A),
+            ("b.example.com", QueryType::A),
+            ("a.b.example.com", QueryType::A),
+            (QNAME, QueryType::A),
+        ])
+    );
+    assert!(root
+        .received()
+        .iter()
+        .all(|r| !r.packet.header.recursion_desired));
+}
+
+#[test]
+fn nxdomain_for_a_minimized_name_asks_for_the_whole_name() {
+    // Broken servers say empty non-terminals don't exist, RFC 9156 section 2.3
+    let root = hierarchy(Reply::Rcode(ResultCode::NXDOMAIN));
+
+    let response = resolve(&root, QueryType::AAAA);
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(
+        asked(&root),
+        expected(&[
+            ("com", QueryType::A),
+            ("example.com", QueryType::A),
+            ("b.example.com", QueryType::A),
+            (QNAME, QueryType::AAAA),
+        ])
+    );
+}
+
+#[test]
+fn errors_for_empty_non_terminals_are_passed_over() {
+    for rescode in [ResultCode::REFUSED, ResultCode::SERVFAIL] {
+        let root = hierarchy(Reply::Rcode(rescode));
+
+        let response = resolve(&root, QueryType::A);
+        assert_eq!(response.answers.len(), 1, "{rescode:?}");
+        let names: Vec<_> = asked(&root).into_iter().map(|(qname, _)| qname).collect();
+        assert_eq!(
+            names,
+            [
+                "com",
+                "example.com",
+                "b.example.com",
+                "a.b.example.com",
+                QNAME
+            ],
+            "{rescode:?}"
+        );
+    }
+}
+
+#[test]
+fn nxdomain_for_the_whole_name_is_the_answer() {
+    let root = MockServer::new(|query| {
+        let qname = &query.questions[0].name;
+        if *qname == "com" {
+            referral("com")
+        } else if qname.label_count() < 5 {
+            Reply::Answer(Vec::new())
+        } else {
+            Reply::Rcode(ResultCode::NXDOMAIN)
+        }
+    })
+    .unwrap();
+
+    let response = resolve(&root, QueryType::A);
+    assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+    assert_eq!(asked(&root).len(), 5);
+}
@@ -0,0 +1,627 @@
+//! Packets written to the wire and read back must come out the same
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use proptest::collection::{btree_set, vec};
+use proptest::prelude::*;
+
+use dns_server::buffer::TCP_MAX_LEN;
+use dns_server::edns::{
+    opt_record, ClientSubnet, EdnsOption, OPTION_CLIENT_SUBNET, QUERY_BLOCK_LEN,
+};
+use dns_server::name::MAX_NAME_LEN;
+use dns_server::{
+    BytePacketBuffer, DnsError, DnsHeader, DnsName, DnsPacket, DnsQuestion, DnsRecord, RData,
+};
+use dns_server::{QueryType, ResultCode};
+
+fn round_trip(packet: &mut DnsPacket, len: usize) -> dns_server::Result<DnsPacket> {
+    let mut buf = BytePacketBuffer::with_len(len);
+    packet.write(&mut buf)?;
+    buf.buf.truncate(buf.pos());
+    buf.pos = 0;
+    DnsPacket::from_buffer(&mut buf)
+}
+
+/// Short labels, and some of the longest allowed
+fn label() -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => "[a-zA-Z0-9_-]{1,12}",
+        1 => "[a-zA-Z0-9_-]{63}",
+    ]
+}
+
+/// Names from the root up to the longest that fit on the wire, in mixed case since names compare
+/// without it
+fn name() -> impl Strategy<Value = DnsName> {
+    vec(label(), 0..8).prop_map(|labels| {
+        let mut wire_len = 1;
+        let labels: Vec<_> = labels
+            .into_iter()
+            .take_while(|label| {
+                wire_len += label.len() + 1;
+                wire_len <= MAX_NAME_LEN
+            })
+            .collect();
+        DnsName::new(&labels.join(".")).unwrap()
+    })
+}
+
+fn query_type() -> impl Strategy<Value = QueryType> {
+    any::<u16>().prop_map(QueryType::from)
+}
+
+fn result_code() -> impl Strategy<Value = ResultCode> {
+    (0..=10u8).prop_map(ResultCode::from)
+}
+
+fn header() -> impl Strategy<Value = DnsHeader> {
+    (any::<u16>(), any::<[bool; 8]>(), 0..16u8, result_code()).prop_map(
+        |(id, flags, opcode, rescode)| DnsHeader {
+            id,
+            recursion_desired: flags[0],
+            truncated_message: flags[1],
+            authoritative_answer: flags[2],
+            opcode,
+            response: flags[3],
+            rescode,
+            checking_disabled: flags[4],
+            authed_data: flags[5],
+            z: flags[6],
+            recursion_available: flags[7],
+            ..DnsHeader::new()
+        },
+    )
+}
+
+fn question() -> impl Strategy<Value = DnsQuestion> {
+    (name(), query_type(), any::<u16>()).prop_map(|(name, qtype, class)| DnsQuestion {
+        name,
+        qtype,
+        class,
+    })
+}
+
+/// Any bytes that fit in the 255 bytes of a character string, and some that fill it
+fn character_string() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        4 => vec(any::<u8>(), 0..64),
+        1 => vec(any::<u8>(), 255),
+    ]
+}
+
+/// Every record type the server reads and writes, with the NSEC types as a set in the numeric
+/// order of the bitmap, and unknown records of the private use types
+fn record() -> impl Strategy<Value = DnsRecord> {
+    let ttl = any::<u32>();
+    prop_oneof![
+        (name(), any::<[u8; 4]>(), ttl).prop_map(|(domain, addr, ttl)| DnsRecord::new(
+            domain,
+            ttl,
+            RData::A {
+                addr: Ipv4Addr::from(addr)
+            }
+        )),
+        (name(), any::<[u8; 16]>(), ttl).prop_map(|(domain, addr, ttl)| DnsRecord::new(
+            domain,
+            ttl,
+            RData::AAAA {
+                addr: Ipv6Addr::from(addr)
+            }
+        )),
+        (name(), name(), ttl).prop_map(|(domain, host, ttl)| DnsRecord::new(
+            domain,
+            ttl,
+            RData::NS { host }
+        )),
+        (name(), name(), ttl).prop_map(|(domain, host, ttl)| DnsRecord::new(
+            domain,
+            ttl,
+            RData::CNAME { host }
+        )),
+        (name(), name(), ttl).prop_map(|(domain, host, ttl)| DnsRecord::new(
+            domain,
+            ttl,
+            RData::PTR { host }
+        )),
+        (name(), name(), name(), any::<[u32; 5]>(), ttl).prop_map(
+            |(domain, m_name, r_name, [serial, refresh, retry, expire, minimum], ttl)| {
+                DnsRecord::new(
+                    domain,
+                    ttl,
+                    RData::SOA {
+                        m_name,
+                        r_name,
+                        serial,
+                        refresh,
+                        retry,
+                        expire,
+                        minimum,
+                    },
+                )
+            }
+        ),
+        (name(), any::<u16>(), name(), ttl).prop_map(|(domain, priority, host, ttl)| {
+            DnsRecord::new(domain, ttl, RData::MX { priority, host })
+        }),
+        (name(), character_string(), character_string(), ttl).prop_map(|(domain, cpu, os, ttl)| {
+            DnsRecord::new(domain, ttl, RData::HINFO { cpu, os })
+        }),
+        (name(), vec(character_string(), 0..4), ttl)
+            .prop_map(|(domain, data, ttl)| DnsRecord::new(domain, ttl, RData::TXT { data })),
+        (name(), 65280..=65534u16, vec(any::<u8>(), 0..64), ttl).prop_map(
+            |(domain, qtype, data, ttl)| DnsRecord::new(
+                domain,
+                ttl,
+                RData::UNKNOWN { qtype, data }
+            )
+        ),
+        (name(), any::<[u16; 3]>(), name(), ttl).prop_map(
+            |(domain, [priority, weight, port], host, ttl)| DnsRecord::new(
+                domain,
+                ttl,
+                RData::SRV {
+                    priority,
+                    weight,
+                    port,
+                    host
+                }
+            )
+        ),
+        (
+            name(),
+            any::<u16>(),
+            any::<u32>(),
+            vec((any::<u16>(), vec(any::<u8>(), 0..32)), 0..4)
+        )
+            .prop_map(|(name, class, ttl, options)| DnsRecord {
+                name,
+                class,
+                ttl,
+                rdata: RData::OPT {
+                    options: options
+                        .into_iter()
+                        .map(|(code, data)| EdnsOption { code, data })
+                        .collect(),
+                },
+            }),
+        (
+            name(),
+            query_type(),
+            any::<(u8, u8, u16)>(),
+            any::<[u32; 3]>(),
+            name(),
+            vec(any::<u8>(), 0..96),
+            ttl
+        )
+            .prop_map(
+                |(
+                    domain,
+                    type_covered,
+                    (algorithm, labels, key_tag),
+                    [original_ttl, expiration, inception],
+                    signer,
+                    signature,
+                    ttl,
+                )| DnsRecord::new(
+                    domain,
+                    ttl,
+                    RData::RRSIG {
+                        type_covered,
+                        algorithm,
+                        labels,
+                        original_ttl,
+                        expiration,
+                        inception,
+                        key_tag,
+                        signer,
+                        signature
+                    }
+                )
+            ),
+        (name(), name(), btree_set(any::<u16>(), 0..8), ttl).prop_map(
+            |(domain, next, types, ttl)| DnsRecord::new(
+                domain,
+                ttl,
+                RData::NSEC {
+                    next,
+                    types: types.into_iter().map(QueryType::from).collect()
+                }
+            )
+        ),
+        (name(), any::<(u16, u8, u8)>(), vec(any::<u8>(), 0..64), ttl).prop_map(
+            |(domain, (flags, protocol, algorithm), public_key, ttl)| {
+                DnsRecord::new(
+                    domain,
+                    ttl,
+                    RData::DNSKEY {
+                        flags,
+                        protocol,
+                        algorithm,
+                        public_key,
+                    },
+                )
+            }
+        ),
+    ]
+}
+
+fn packet() -> impl Strategy<Value = DnsPacket> {
+    (
+        header(),
+        vec(question(), 0..4),
+        vec(record(), 0..8),
+        vec(record(), 0..4),
+        vec(record(), 0..4),
+    )
+        .prop_map(
+            |(header, questions, answers, authorities, resources)| DnsPacket {
+                header,
+                questions,
+                answers,
+                authorities,
+                resources,
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn packets_round_trip(mut packet in packet()) {
+        let read = round_trip(&mut packet, TCP_MAX_LEN).unwrap();
+        prop_assert_eq!(read, packet);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    /// Packets grown record by record to the size limit either round trip or fail to write, but
+    /// never come back different
+    #[test]
+    fn large_packets_round_trip(mut packet in packet(), records in vec(record(), 100..400)) {
+        packet.answers.extend(records);
+        if let Ok(read) = round_trip(&mut packet, TCP_MAX_LEN) {
+            prop_assert_eq!(read, packet);
+        }
+    }
+}
+
+#[test]
+fn root_name_query() {
+    let mut packet = DnsPacket::new();
+    packet
+        .questions
+        .push(DnsQuestion::new(DnsName::root(), QueryType::NS));
+
+    let read = round_trip(&mut packet, TCP_MAX_LEN).unwrap();
+    assert_eq!(read, packet);
+    assert!(read.questions[0].name.is_root());
+}
+
+#[test]
+fn name_case_is_kept() {
+    let mut packet = DnsPacket::query("WwW.ExAmple.COM", QueryType::A)
+        .build()
+        .unwrap();
+    let mut buf = BytePacketBuffer::new();
+    packet.write(&mut buf).unwrap();
+    let sent = buf.buf[..buf.pos()].to_vec();
+
+    let mut read = round_trip(&mut packet, TCP_MAX_LEN).unwrap();
+    assert_eq!(read.questions[0].name.as_str(), "WwW.ExAmple.COM");
+    let mut buf = BytePacketBuffer::new();
+    read.write(&mut buf).unwrap();
+    assert_eq!(buf.buf[..buf.pos()], sent[..]);
+}
+
+#[test]
+fn binary_labels_are_escaped() {
+    // A zero octet, a dot, a backslash, a space and an octet that isn't ASCII in one label
+    let name = DnsName::new(r"\000\.\\\032\255a.example").unwrap();
+    assert_eq!(name.label_count(), 2);
+    assert_eq!(name.parent().unwrap(), DnsName::new("example").unwrap());
+    assert_eq!(name.wire_len(), 1 + 6 + 1 + 7 + 1);
+    let mut packet = DnsPacket::new();
+    packet
+        .questions
+        .push(DnsQuestion::new(name.clone(), QueryType::A));
+    let mut buf = BytePacketBuffer::new();
+    packet.write(&mut buf).unwrap();
+    assert_eq!(buf.buf[12..19], [6, 0, b'.', b'\\', b' ', 0xff, b'a']);
+
+    let read = round_trip(&mut packet, TCP_MAX_LEN).unwrap();
+    assert_eq!(
+        read.questions[0].name.as_str(),
+        r"\000\.\\\032\255a.example"
+    );
+    assert_eq!(read, packet);
+}
+
+#[test]
+fn escaped_names_equal_their_octets() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let hash = |name: &DnsName| {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    // `\065` is `A`, which matches `a` in any case
+    let escaped = DnsName::new(r"\065bc.example").unwrap();
+    let plain = DnsName::new("abc.example").unwrap();
+    assert_eq!(escaped, plain);
+    assert_eq!(escaped, "ABC.example.");
+    assert_eq!(hash(&escaped), hash(&plain));
+    assert_eq!(escaped.cmp(&plain), std::cmp::Ordering::Equal);
+    assert!(escaped.is_subdomain_of(&DnsName::new(r"\069xample").unwrap()));
+
+    // An escaped dot is part of a label rather than between two
+    let dotted = DnsName::new(r"abc\.example").unwrap();
+    assert_ne!(dotted, plain);
+    assert!(!dotted.is_subdomain_of(&DnsName::new("example").unwrap()));
+}
+
+#[test]
+fn character_strings_are_octets() {
+    let record: DnsRecord = r#"example.com. 300 IN TXT "\255\"x" plain\059"#.parse().unwrap();
+    assert_eq!(
+        record.rdata,
+        RData::TXT {
+            data: vec![vec![0xff, b'"', b'x'], b"plain;".to_vec()]
+        }
+    );
+    assert_eq!(record.rdata.to_string(), r#""\255\"x" "plain;""#);
+
+    let mut packet = DnsPacket::new();
+    packet.answers.push(record);
+    assert_eq!(round_trip(&mut packet, TCP_MAX_LEN).unwrap(), packet);
+}
+
+#[test]
+fn classes_are_shown_by_mnemonic_or_number() {
+    let mut record: DnsRecord = "example.com. 300 IN TXT x".parse().unwrap();
+    assert_eq!(record.to_string(), "example.com.\t300\tIN\tTXT\t\"x\"");
+
+    record.class = 3;
+    assert_eq!(record.to_string(), "example.com.\t300\tCH\tTXT\t\"x\"");
+    record.class = 10;
+    assert_eq!(record.to_string(), "example.com.\t300\tCLASS10\tTXT\t\"x\"");
+}
+
+#[test]
+fn character_strings_over_255_octets_are_refused() {
+    let mut packet = DnsPacket::new();
+    packet.answers.push(DnsRecord::new(
+        DnsName::new("example.com").unwrap(),
+        300,
+        RData::TXT {
+            data: vec![vec![b'x'; 256]],
+        },
+    ));
+    let mut buf = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    assert!(matches!(
+        packet.write(&mut buf),
+        Err(DnsError::CharacterStringTooLong(256))
+    ));
+
+    let long = format!("example.com. 300 IN TXT {}", "x".repeat(256));
+    assert!(long.parse::<DnsRecord>().is_err());
+}
+
+#[test]
+fn reads_past_the_end_leave_the_position_alone() {
+    // A character string claiming 10 octets with 3 left
+    let mut buf = BytePacketBuffer {
+        buf: vec![10, b'a', b'b', b'c'],
+        pos: 0,
+    };
+    assert!(matches!(
+        RData::read(&mut buf, 16, 4),
+        Err(DnsError::BufferOverrun)
+    ));
+    assert_eq!(buf.pos(), 1);
+}
+
+#[test]
+fn longest_name() {
+    // Three labels of 63 and one of 61, 255 bytes with the length octets and the root
+    let labels = [
+        "a".repeat(63),
+        "b".repeat(63),
+        "c".repeat(63),
+        "d".repeat(61),
+    ];
+    let name = DnsName::new(&labels.join(".")).unwrap();
+    let mut packet = DnsPacket::new();
+    packet.answers.push(DnsRecord::new(
+        name.clone(),
+        300,
+        RData::CNAME { host: name.clone() },
+    ));
+
+    let read = round_trip(&mut packet, TCP_MAX_LEN).unwrap();
+    assert_eq!(read, packet);
+}
+
+#[test]
+fn names_one_octet_too_long_are_rejected() {
+    let labels = [
+        "a".repeat(63),
+        "b".repeat(63),
+        "c".repeat(63),
+        "d".repeat(62),
+    ];
+
+    let name = DnsName::new(&labels.join("."));
+    assert!(matches!(name, Err(DnsError::NameTooLong(_))));
+    let label = DnsName::new(&format!("{}.example", "a".repeat(64)));
+    assert!(matches!(label, Err(DnsError::LabelTooLong(_))));
+}
+
+#[test]
+fn trailing_dots_are_normalized() {
+    let name = DnsName::new("www.example.com.").unwrap();
+    assert_eq!(name, DnsName::new("www.example.com").unwrap());
+    assert_eq!(name.to_fqdn(), "www.example.com.");
+    assert_eq!(DnsName::root().to_fqdn(), ".");
+    assert!(matches!(
+        DnsName::new("www.example.com.."),
+        Err(DnsError::EmptyLabel(_))
+    ));
+}
+
+#[test]
+fn hostnames_are_letters_digits_and_hyphens() {
+    for valid in [
+        "www.example.com",
+        "xn--bcher-kva.example",
+        "4.3.2.1.in-addr.arpa",
+    ] {
+        assert!(
+            DnsName::new(valid).unwrap().check_hostname().is_ok(),
+            "{valid}"
+        );
+    }
+    for invalid in [
+        "_dns.resolver.arpa",
+        "*.example.com",
+        "-www.example.com",
+        "a b.example",
+    ] {
+        let name = DnsName::new(invalid).unwrap();
+        assert!(
+            matches!(name.check_hostname(), Err(DnsError::InvalidHostname(_))),
+            "{invalid}"
+        );
+    }
+}
+
+#[test]
+fn packet_filling_the_buffer() {
+    let mut packet = DnsPacket::new();
+    packet.answers.push(DnsRecord::new(
+        DnsName::root(),
+        300,
+        RData::TXT {
+            data: vec![b"x".repeat(255); 40],
+        },
+    ));
+    let mut buf = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    packet.write(&mut buf).unwrap();
+    let len = buf.pos();
+
+    // Exactly as long as the message fits, one byte less is too short
+    assert_eq!(round_trip(&mut packet, len).unwrap(), packet);
+    assert!(round_trip(&mut packet, len - 1).is_err());
+}
+
+#[test]
+fn opcode_kept_to_its_bits() {
+    let mut packet = DnsPacket::new();
+    packet.header.opcode = 0x15;
+
+    let read = round_trip(&mut packet, TCP_MAX_LEN).unwrap();
+    assert_eq!(read.header.opcode, 0x05);
+    assert!(!read.header.response);
+}
+
+#[test]
+fn padding_fills_whole_blocks() {
+    let mut packet = DnsPacket::query("www.example.com", QueryType::A)
+        .build()
+        .unwrap();
+    packet.resources.push(opt_record(false, Vec::new()));
+    packet.pad(QUERY_BLOCK_LEN).unwrap();
+
+    let mut buf = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    packet.write(&mut buf).unwrap();
+    assert_eq!(buf.pos(), QUERY_BLOCK_LEN);
+    // Padding again replaces the option rather than adding another block
+    packet.pad(QUERY_BLOCK_LEN).unwrap();
+    assert_eq!(round_trip(&mut packet, TCP_MAX_LEN).unwrap(), packet);
+    let mut buf = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    packet.write(&mut buf).unwrap();
+    assert_eq!(buf.pos(), QUERY_BLOCK_LEN);
+
+    assert!(matches!(packet.pad(0), Err(DnsError::Config(_))));
+}
+
+#[test]
+fn messages_without_edns_are_not_padded() {
+    let mut packet = DnsPacket::query("www.example.com", QueryType::A)
+        .build()
+        .unwrap();
+    packet.pad(QUERY_BLOCK_LEN).unwrap();
+
+    assert!(packet.resources.is_empty());
+}
+
+#[test]
+fn client_subnet_is_cut_to_its_prefix() {
+    let subnet = ClientSubnet::new(Ipv4Addr::new(198, 51, 100, 77).into(), 22);
+    assert_eq!(subnet.addr, Ipv4Addr::new(198, 51, 100, 0));
+    assert_eq!(subnet.source_prefix, 22);
+    // Only the octets the prefix covers are sent
+    let option = subnet.to_option();
+    assert_eq!(option.data, [0, 1, 22, 0, 198, 51, 100]);
+    assert_eq!(ClientSubnet::from_option(&option), Some(subnet));
+
+    let addr = "2001:db8:1234:5678::1".parse::<Ipv6Addr>().unwrap();
+    let subnet = ClientSubnet::new(addr.into(), 56);
+    assert_eq!(
+        subnet.addr,
+        "2001:db8:1234:5600::".parse::<Ipv6Addr>().unwrap()
+    );
+    assert_eq!(subnet.to_option().data.len(), 4 + 7);
+
+    // Prefixes are capped at the length of the address, and 0 sends no address at all
+    let addr = Ipv4Addr::new(192, 0, 2, 1).into();
+    assert_eq!(ClientSubnet::new(addr, 40).source_prefix, 32);
+    assert_eq!(ClientSubnet::new(addr, 0).to_option().data, [0, 1, 0, 0]);
+
+    // Bits set past the prefix, or octets beyond it, are malformed
+    for data in [
+        vec![0, 1, 20, 0, 198, 51, 100],
+        vec![0, 1, 16, 0, 198, 51, 0],
+    ] {
+        let option = EdnsOption {
+            code: OPTION_CLIENT_SUBNET,
+            data,
+        };
+        assert_eq!(ClientSubnet::from_option(&option), None);
+    }
+}
+
+#[test]
+fn rdata_must_fill_its_length() {
+    let mut packet = DnsPacket::new();
+    packet.answers.push(DnsRecord::new(
+        DnsName::new("www.example.com").unwrap(),
+        300,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+        },
+    ));
+    let mut buf = BytePacketBuffer::with_len(TCP_MAX_LEN);
+    packet.write(&mut buf).unwrap();
+    let wire = buf.buf[..buf.pos()].to_vec();
+    let len_at = wire.len() - 6;
+
+    // An address with a byte to spare, and one a byte short of its length
+    for (data_len, extra) in [(5u16, &[0][..]), (3, &[])] {
+        let mut wire = wire.clone();
+        wire[len_at..len_at + 2].copy_from_slice(&data_len.to_be_bytes());
+        wire.extend_from_slice(extra);
+        let mut buf = BytePacketBuffer::with_len(wire.len());
+        buf.buf.copy_from_slice(&wire);
+
+        let result = DnsPacket::from_buffer(&mut buf);
+        assert!(
+            matches!(result, Err(DnsError::RdataLength(len)) if len == data_len),
+            "{result:?}"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Response policy zones rewriting the answers to queries the server forwards
+
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::{env, fs, process};
+
+use dns_server::mock::{MockServer, Reply};
+use dns_server::server::{self, ServerContext};
+use dns_server::{BytePacketBuffer, DnsPacket, DnsRecord, QueryType, RData, ResultCode};
+
+/// The first policy zone, which decides over the second wherever both have a rule
+const FIRST: &str = "$ORIGIN first.rpz.\n\
+                     @ 300 IN SOA ns admin 1 3600 600 86400 300\n\
+                     @ 300 IN NS ns\n\
+                     nx.example.com 300 IN CNAME .\n\
+                     nodata.example.com 300 IN CNAME *.\n\
+                     pass.example.com 300 IN CNAME rpz-passthru.\n\
+                     local.example.com 300 IN A 192.0.2.200\n\
+                     local.example.com 300 IN TXT \"local\"\n\
+                     *.wild.example.com 300 IN CNAME .\n\
+                     24.0.100.51.198.rpz-ip 300 IN CNAME .\n";
+
+const SECOND: &str = "$ORIGIN second.rpz.\n\
+                      @ 300 IN SOA ns admin 1 3600 600 86400 300\n\
+                      @ 300 IN NS ns\n\
+                      nx.example.com 300 IN A 192.0.2.222\n\
+                      pass.example.com 300 IN CNAME .\n\
+                      second.example.com 300 IN CNAME .\n";
+
+/// Write a policy zone where the server can load it from, to a file of its own for every test
+fn zone_file(name: &str, text: &str) -> PathBuf {
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let n = FILES.fetch_add(1, Ordering::Relaxed);
+    let path = env::temp_dir().join(format!("dns-server-{}-{n}-{name}.zone", process::id()));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+/// Start a server with both policy zones, forwarding `example.com` to an upstream that answers
+/// `bad.example.com` with an address in the network of the IP trigger, and everything else with
+/// another one
+fn start() -> (SocketAddr, MockServer) {
+    let upstream = MockServer::new(|query| {
+        let question = &query.questions[0];
+        let addr = if question.name == "bad.example.com" {
+            Ipv4Addr::new(198, 51, 100, 1)
+        } else {
+            Ipv4Addr::new(192, 0, 2, 1)
+        };
+        let answers = match question.qtype {
+            QueryType::A => vec![DnsRecord::new(
+                question.name.clone(),
+                300,
+                RData::A { addr },
+            )],
+            _ => Vec::new(),
+        };
+        Reply::Answer(answers)
+    })
+    .unwrap();
+
+    let addr = loop {
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = udp.local_addr().unwrap();
+        if TcpListener::bind(addr).is_ok() {
+            break addr;
+        }
+    };
+    let files = [zone_file("first", FIRST), zone_file("second", SECOND)];
+    let config = format!(
+        "listen = \"{addr}\"\n\
+         upstream = [\"127.0.0.1:9\"]\n\
+         [[forward]]\n\
+         domain = \"example.com\"\n\
+         upstream = [\"{}\"]\n\
+         [[rpz]]\n\
+         origin = \"first.rpz\"\n\
+         file = \"{}\"\n\
+         [[rpz]]\n\
+         origin = \"second.rpz\"\n\
+         file = \"{}\"",
+        upstream.addr(),
+        files[0].display(),
+        files[1].display(),
+    );
+    let context = ServerContext::new(config.parse().unwrap()).unwrap();
+    // Loaded, so they can go
+    for file in files {
+        fs::remove_file(file).unwrap();
+    }
+    thread::spawn(move || server::run(Arc::new(context)));
+    thread::sleep(Duration::from_millis(100));
+
+    (addr, upstream)
+}
+
+/// Ask the server a question and wait for the response, `None` if there is none
+fn ask(server: SocketAddr, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+    let mut request = DnsPacket::query(qname, qtype)
+        .id(0x1234)
+        .recursion_desired(true)
+        .build()
+        .unwrap();
+    let mut buf = BytePacketBuffer::new();
+    request.write(&mut buf).unwrap();
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    socket.send_to(&buf.buf[..buf.pos()], server).unwrap();
+
+    let mut buf = BytePacketBuffer::new();
+    let len = socket.recv(&mut buf.buf).ok()?;
+    buf.buf.truncate(len);
+    Some(DnsPacket::from_buffer(&mut buf).unwrap())
+}
+
+fn addresses(packet: &DnsPacket) -> Vec<Ipv4Addr> {
+    packet.a_records().collect()
+}
+
+/// The names the upstream was asked for, in any case
+fn forwarded(upstream: &MockServer) -> Vec<String> {
+    upstream
+        .received()
+        .iter()
+        .map(|received| {
+            received.packet.questions[0]
+                .name
+                .as_str()
+                .to_ascii_lowercase()
+        })
+        .collect()
+}
+
+#[test]
+fn nxdomain_nodata_and_passthru() {
+    let (server, upstream) = start();
+
+    let nx = ask(server, "nx.example.com", QueryType::A).unwrap();
+    assert_eq!(nx.header.rescode, ResultCode::NXDOMAIN);
+    assert!(nx.answers.is_empty());
+
+    let nodata = ask(server, "nodata.example.com", QueryType::A).unwrap();
+    assert_eq!(nodata.header.rescode, ResultCode::NOERROR);
+    assert!(nodata.answers.is_empty());
+
+    // Neither was asked upstream, the one passed through was
+    let pass = ask(server, "pass.example.com", QueryType::A).unwrap();
+    assert_eq!(pass.header.rescode, ResultCode::NOERROR);
+    assert_eq!(addresses(&pass), [Ipv4Addr::new(192, 0, 2, 1)]);
+    assert_eq!(forwarded(&upstream), ["pass.example.com"]);
+}
+
+#[test]
+fn local_data_answers_for_its_types() {
+    let (server, upstream) = start();
+
+    let a = ask(server, "local.example.com", QueryType::A).unwrap();
+    assert_eq!(addresses(&a), [Ipv4Addr::new(192, 0, 2, 200)]);
+    assert_eq!(a.answers[0].domain(), "local.example.com");
+
+    let txt = ask(server, "local.example.com", QueryType::TXT).unwrap();
+    assert!(matches!(
+        txt.answers.as_slice(),
+        [DnsRecord { rdata: RData::TXT { data }, .. }] if *data == [b"local".to_vec()]
+    ));
+
+    // Other types exist without records
+    let aaaa = ask(server, "local.example.com", QueryType::AAAA).unwrap();
+    assert_eq!(aaaa.header.rescode, ResultCode::NOERROR);
+    assert!(aaaa.answers.is_empty());
+    assert!(forwarded(&upstream).is_empty());
+}
+
+#[test]
+fn wildcards_trigger_for_names_below_them() {
+    let (server, _upstream) = start();
+
+    for qname in ["a.wild.example.com", "a.b.wild.example.com"] {
+        let response = ask(server, qname, QueryType::A).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NXDOMAIN, "{qname}");
+    }
+
+    // But not for the name the wildcard is under
+    let response = ask(server, "wild.example.com", QueryType::A).unwrap();
+    assert_eq!(addresses(&response), [Ipv4Addr::new(192, 0, 2, 1)]);
+}
+
+#[test]
+fn addresses_in_answers_trigger() {
+    let (server, upstream) = start();
+
+    let response = ask(server, "bad.example.com", QueryType::A).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NXDOMAIN);
+    assert!(response.answers.is_empty());
+    assert_eq!(forwarded(&upstream), ["bad.example.com"]);
+}
+
+#[test]
+fn first_zone_with_a_rule_decides() {
+    let (server, _upstream) = start();
+
+    // Over a rule of the second zone, even when the first one says to pass through
+    let nx = ask(server, "nx.example.com", QueryType::A).unwrap();
+    assert_eq!(nx.header.rescode, ResultCode::NXDOMAIN);
+    let pass = ask(server, "pass.example.com", QueryType::A).unwrap();
+    assert_eq!(addresses(&pass), [Ipv4Addr::new(192, 0, 2, 1)]);
+
+    // The second zone still decides where the first has no rule
+    let second = ask(server, "second.example.com", QueryType::A).unwrap();
+    assert_eq!(second.header.rescode, ResultCode::NXDOMAIN);
+}
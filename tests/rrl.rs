@@ -0,0 +1,78 @@
+//! Response Rate Limiting of floods of the same response
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::thread;
+use std::time::Duration;
+
+use dns_server::config::RateLimitConfig;
+use dns_server::rrl::{self, RateLimiter, Verdict};
+use dns_server::{DnsName, DnsPacket, DnsRecord, QueryType, RData};
+
+const RATE: u32 = 20;
+
+fn limiter() -> RateLimiter {
+    RateLimiter::new(RateLimitConfig {
+        responses_per_second: RATE,
+        window: 1,
+        slip: 2,
+        exempt: vec!["192.0.2.128/25".parse().unwrap()],
+        ..RateLimitConfig::default()
+    })
+}
+
+fn response(qname: &str) -> DnsPacket {
+    let query = DnsPacket::query(qname, QueryType::A).build().unwrap();
+    DnsPacket::response_to(&query)
+}
+
+fn client(last: u8) -> IpAddr {
+    Ipv4Addr::new(192, 0, 2, last).into()
+}
+
+#[test]
+fn flood_is_dropped_and_slipped_until_the_window_passes() {
+    let limiter = limiter();
+    let www = response("www.example.com");
+
+    // A second worth goes out, then the rest are dropped with every second one slipped
+    let verdicts: Vec<_> = (0..RATE + 4)
+        .map(|_| limiter.check(client(1), &www))
+        .collect();
+    assert!(verdicts[..RATE as usize]
+        .iter()
+        .all(|v| *v == Verdict::Send));
+    assert_eq!(
+        verdicts[RATE as usize..],
+        [Verdict::Drop, Verdict::Slip, Verdict::Drop, Verdict::Slip]
+    );
+
+    // The rest of the network shares the bucket, other answers, networks and exempt clients don't
+    assert_ne!(limiter.check(client(2), &www), Verdict::Send);
+    let other = response("other.example.com");
+    assert_eq!(limiter.check(client(1), &other), Verdict::Send);
+    let elsewhere = Ipv4Addr::new(198, 51, 100, 1).into();
+    assert_eq!(limiter.check(elsewhere, &www), Verdict::Send);
+    assert_eq!(limiter.check(client(200), &www), Verdict::Send);
+
+    // The debt is capped at a window worth, so staying quiet that long is forgiven
+    thread::sleep(Duration::from_millis(1200));
+    assert_eq!(limiter.check(client(1), &www), Verdict::Send);
+}
+
+#[test]
+fn slipped_responses_are_truncated_questions() {
+    let mut www = response("www.example.com");
+    www.answers.push(DnsRecord::new(
+        DnsName::new("www.example.com").unwrap(),
+        300,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, 80),
+        },
+    ));
+
+    let slipped = rrl::truncated(&www);
+    assert!(slipped.header.truncated_message);
+    assert_eq!(slipped.header.id, www.header.id);
+    assert_eq!(slipped.questions, www.questions);
+    assert!(slipped.answers.is_empty());
+}
@@ -0,0 +1,442 @@
+//! Requests the server can't answer normally, sent to it over UDP
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_server::edns::{
+    opt_record, ClientSubnet, EdnsOption, TcpKeepalive, EDE_NETWORK_ERROR, EDE_OTHER,
+    OPTION_PADDING, RESPONSE_BLOCK_LEN,
+};
+use dns_server::mock::{MockServer, Reply};
+use dns_server::server::{self, Listeners, ServerContext};
+use dns_server::tsig::{TsigAlgorithm, TsigKey, TsigSession, BADKEY};
+use dns_server::{
+    BytePacketBuffer, DnsName, DnsPacket, DnsQuestion, DnsRecord, QueryType, RData, ResultCode,
+};
+
+/// Start a server answering `local.lan` on a free port, returning its address
+fn start() -> SocketAddr {
+    start_with("")
+}
+
+/// Start a server with `config` on top of the local record for `local.lan`, where `$listen` is the
+/// address it listens on
+fn start_with(config: &str) -> SocketAddr {
+    let addr = loop {
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = udp.local_addr().unwrap();
+        if TcpListener::bind(addr).is_ok() {
+            break addr;
+        }
+    };
+    let config = config.replace("$listen", &addr.to_string());
+    let config = format!(
+        "listen = \"{addr}\"\n\
+         upstream = [\"127.0.0.1:9\"]\n\
+         {config}\n\
+         [[local-records]]\n\
+         name = \"local.lan\"\n\
+         type = \"A\"\n\
+         value = \"192.0.2.1\""
+    );
+    let context = ServerContext::new(config.parse().unwrap()).unwrap();
+    thread::spawn(move || server::run(Arc::new(context)));
+    thread::sleep(Duration::from_millis(100));
+
+    addr
+}
+
+/// Send a message and wait for the response, `None` if there is none
+fn exchange(server: SocketAddr, wire: &[u8]) -> Option<DnsPacket> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    socket.send_to(wire, server).unwrap();
+
+    let mut buf = BytePacketBuffer::new();
+    let len = socket.recv(&mut buf.buf).ok()?;
+    buf.buf.truncate(len);
+    Some(DnsPacket::from_buffer(&mut buf).unwrap())
+}
+
+/// Send a message over a new TCP connection and wait for the response
+fn exchange_tcp(server: SocketAddr, wire: &[u8]) -> DnsPacket {
+    let mut stream = TcpStream::connect(server).unwrap();
+    let buf = BytePacketBuffer {
+        buf: wire.to_vec(),
+        pos: wire.len(),
+    };
+    buf.write_to(&mut stream).unwrap();
+
+    DnsPacket::read_from(&mut stream).unwrap()
+}
+
+fn query() -> DnsPacket {
+    DnsPacket::query("local.lan", QueryType::A)
+        .id(0x1234)
+        .recursion_desired(true)
+        .build()
+        .unwrap()
+}
+
+fn wire(mut packet: DnsPacket) -> Vec<u8> {
+    let mut buf = BytePacketBuffer::new();
+    packet.write(&mut buf).unwrap();
+    buf.buf[..buf.pos()].to_vec()
+}
+
+#[test]
+fn query_is_answered() {
+    let server = start();
+
+    let response = exchange(server, &wire(query())).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn several_questions_are_formerr() {
+    let server = start();
+    let mut packet = query();
+    let name = packet.questions[0].name.clone();
+    packet
+        .questions
+        .push(DnsQuestion::new(name, QueryType::AAAA));
+
+    let response = exchange(server, &wire(packet)).unwrap();
+    assert_eq!(response.header.id, 0x1234);
+    assert_eq!(response.header.rescode, ResultCode::FORMERR);
+    assert!(response.answers.is_empty());
+}
+
+#[test]
+fn no_question_is_formerr() {
+    let server = start();
+    let mut packet = query();
+    packet.questions.clear();
+
+    let response = exchange(server, &wire(packet)).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::FORMERR);
+}
+
+#[test]
+fn other_opcodes_are_notimp() {
+    let server = start();
+    // IQUERY, STATUS and ones that were never assigned
+    for opcode in [1, 2, 3, 15] {
+        let mut packet = query();
+        packet.header.opcode = opcode;
+
+        let response = exchange(server, &wire(packet)).unwrap();
+        assert_eq!(response.header.opcode, opcode);
+        assert_eq!(response.header.rescode, ResultCode::NOTIMP);
+        assert!(response.questions.is_empty());
+    }
+}
+
+#[test]
+fn responses_are_not_answered() {
+    let server = start();
+    let mut packet = query();
+    packet.header.response = true;
+
+    assert!(exchange(server, &wire(packet)).is_none());
+}
+
+#[test]
+fn unparsable_question_is_formerr() {
+    let server = start();
+    let mut wire = wire(query());
+    // The question claims a label longer than what is left of the message
+    wire[12] = 60;
+
+    let response = exchange(server, &wire).unwrap();
+    assert_eq!(response.header.id, 0x1234);
+    assert_eq!(response.header.rescode, ResultCode::FORMERR);
+}
+
+#[test]
+fn truncated_header_is_formerr() {
+    let server = start();
+    let wire = wire(query());
+
+    let response = exchange(server, &wire[..5]).unwrap();
+    assert_eq!(response.header.id, 0x1234);
+    assert!(response.header.recursion_desired);
+    assert_eq!(response.header.rescode, ResultCode::FORMERR);
+    // Without even the ID there is nothing to answer
+    assert!(exchange(server, &wire[..1]).is_none());
+}
+
+#[test]
+fn missing_additional_record_is_formerr() {
+    let server = start();
+    let mut wire = wire(query());
+    // A record is announced in the additional section, where a TSIG record would be
+    wire[11] = 1;
+
+    let response = exchange(server, &wire).unwrap();
+    assert_eq!(response.header.id, 0x1234);
+    assert_eq!(response.header.rescode, ResultCode::FORMERR);
+}
+
+fn any(name: &str) -> DnsPacket {
+    DnsPacket::query(name, QueryType::ANY)
+        .recursion_desired(true)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn any_is_answered_with_hinfo() {
+    let server = start();
+
+    // Names the server doesn't know aren't forwarded either, the upstream isn't listening
+    for name in ["local.lan", "www.example.com"] {
+        let response = exchange(server, &wire(any(name))).unwrap();
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(matches!(
+            response.answers[..],
+            [DnsRecord {
+                rdata: RData::HINFO { ref cpu, .. },
+                ..
+            }] if cpu == b"RFC8482"
+        ));
+    }
+}
+
+#[test]
+fn any_is_answered_with_every_record_when_configured() {
+    let server = start_with("any-queries = \"all\"");
+
+    let response = exchange(server, &wire(any("local.lan"))).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert!(matches!(
+        response.answers[..],
+        [DnsRecord {
+            rdata: RData::A { .. },
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn tcp_keepalive_is_advertised_over_tcp_only() {
+    let server = start();
+    let mut request = query();
+    request
+        .resources
+        .push(opt_record(false, vec![TcpKeepalive::default().to_option()]));
+
+    let response = exchange_tcp(server, &wire(request.clone()));
+    assert_eq!(response.answers.len(), 1);
+    let keepalive = response.tcp_keepalive().unwrap();
+    assert_eq!(keepalive.timeout, Some(Duration::from_secs(10)));
+
+    let response = exchange(server, &wire(request)).unwrap();
+    assert_eq!(response.tcp_keepalive(), None);
+}
+
+#[test]
+fn padded_queries_get_padded_responses() {
+    let server = start();
+    let mut request = query();
+    let padding = EdnsOption {
+        code: OPTION_PADDING,
+        data: vec![0; 16],
+    };
+    request.resources.push(opt_record(false, vec![padding]));
+
+    let mut response = exchange_tcp(server, &wire(request));
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(wire(response.clone()).len(), RESPONSE_BLOCK_LEN);
+    // Far shorter without it
+    response.resources.clear();
+    assert!(wire(response).len() < RESPONSE_BLOCK_LEN / 2);
+}
+
+#[test]
+fn failures_say_why_to_clients_with_edns() {
+    let server = start();
+    let mut request = DnsPacket::query("unknown.lan", QueryType::A)
+        .id(0x1234)
+        .recursion_desired(true)
+        .build()
+        .unwrap();
+
+    // Without EDNS there is nowhere to say it
+    let response = exchange(server, &wire(request.clone())).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    assert!(response.edns().is_none());
+
+    // The upstream isn't listening, so it refuses the query
+    request.resources.push(opt_record(false, Vec::new()));
+    let response = exchange(server, &wire(request)).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    let errors: Vec<_> = response.extended_errors().collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].info_code, EDE_NETWORK_ERROR);
+}
+
+#[test]
+fn answers_are_cached_for_the_scope_of_their_subnet() {
+    // Tailored to the /24 of the client for `www`, the same for everyone for `global`, whatever
+    // case the name is asked in
+    let upstream = MockServer::new(|query| {
+        let question = &query.questions[0];
+        let last = match query.client_subnet().map(|subnet| subnet.addr) {
+            Some(IpAddr::V4(addr)) => addr.octets()[2],
+            _ => 1,
+        };
+        let answer = Reply::Answer(vec![DnsRecord::new(
+            question.name.clone(),
+            300,
+            RData::A {
+                addr: Ipv4Addr::new(192, 0, 2, last),
+            },
+        )]);
+        if question
+            .name
+            .as_str()
+            .eq_ignore_ascii_case("www.example.com")
+        {
+            Reply::Scoped(24, Box::new(answer))
+        } else {
+            answer
+        }
+    })
+    .unwrap();
+    let server = start_with(&format!(
+        "[client-subnet]\n\
+         ipv4-prefix = 24\n\
+         [[forward]]\n\
+         domain = \"example.com\"\n\
+         upstream = [\"{}\"]",
+        upstream.addr()
+    ));
+    let ask = |qname: &str, subnet: Option<ClientSubnet>| {
+        let mut request = DnsPacket::query(qname, QueryType::A)
+            .id(0x1234)
+            .recursion_desired(true)
+            .build()
+            .unwrap();
+        let options = subnet
+            .map(|subnet| subnet.to_option())
+            .into_iter()
+            .collect();
+        request.resources.push(opt_record(false, options));
+        let response = exchange(server, &wire(request)).unwrap();
+        let RData::A { addr } = response.answers[0].rdata else {
+            panic!("No address in {response:?}");
+        };
+        (
+            addr,
+            response.client_subnet().map(|subnet| subnet.scope_prefix),
+        )
+    };
+    let client = |a, b, c, d| Some(ClientSubnet::new(Ipv4Addr::new(a, b, c, d).into(), 32));
+
+    // Clients in different networks get their own answers, cached for the rest of their network
+    let tailored = |last| (Ipv4Addr::new(192, 0, 2, last), Some(24));
+    assert_eq!(
+        ask("www.example.com", client(198, 51, 100, 7)),
+        tailored(100)
+    );
+    assert_eq!(
+        ask("www.example.com", client(203, 0, 113, 9)),
+        tailored(113)
+    );
+    assert_eq!(
+        ask("www.example.com", client(198, 51, 100, 200)),
+        tailored(100)
+    );
+    assert_eq!(
+        ask("www.example.com", client(203, 0, 113, 1)),
+        tailored(113)
+    );
+    let sent: Vec<_> = upstream
+        .received()
+        .iter()
+        .map(|received| received.packet.client_subnet())
+        .collect();
+    // Cut to the configured prefix on the way upstream
+    let network = |a, b, c| Some(ClientSubnet::new(Ipv4Addr::new(a, b, c, 0).into(), 24));
+    assert_eq!(sent, [network(198, 51, 100), network(203, 0, 113)]);
+
+    // Clients on the local network send no subnet, and don't get the answer for another network
+    assert_eq!(ask("www.example.com", None).0, Ipv4Addr::new(192, 0, 2, 1));
+    assert_eq!(upstream.received().len(), 3);
+
+    // An answer with a scope of 0 is for everyone
+    let shared = (Ipv4Addr::new(192, 0, 2, 100), Some(0));
+    assert_eq!(ask("global.example.com", client(198, 51, 100, 7)), shared);
+    assert_eq!(ask("global.example.com", client(203, 0, 113, 9)), shared);
+    assert_eq!(ask("global.example.com", None), (shared.0, None));
+    assert_eq!(upstream.received().len(), 4);
+}
+
+#[test]
+fn forwarding_loop_is_caught() {
+    let server = start_with(
+        "[[forward]]\n\
+         domain = \"loop.lan\"\n\
+         upstream = [\"$listen\"]",
+    );
+    let mut request = DnsPacket::query("www.loop.lan", QueryType::A)
+        .id(0x1234)
+        .recursion_desired(true)
+        .build()
+        .unwrap();
+    request.resources.push(opt_record(false, Vec::new()));
+
+    // The query comes back to the server as soon as it forwards it, which fails it, and the
+    // reason is passed back down to the client
+    let response = exchange(server, &wire(request)).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+    let error = response.extended_errors().next().unwrap();
+    assert_eq!(error.info_code, EDE_OTHER);
+    assert_eq!(error.extra_text, "Forwarding loop");
+}
+
+#[test]
+fn requests_signed_with_unknown_keys_are_notauth() {
+    let server = start_with(
+        "[[keys]]\n\
+         name = \"transfer-key\"\n\
+         algorithm = \"hmac-sha256\"\n\
+         secret = \"c2VjcmV0\"",
+    );
+    let key = TsigKey {
+        name: DnsName::new("other-key").unwrap(),
+        algorithm: TsigAlgorithm::HmacSha256,
+        secret: b"secret".to_vec(),
+    };
+    let mut request = query()
+        .write_signed(Some(&mut TsigSession::new(&key)))
+        .unwrap();
+    request.buf.truncate(request.pos());
+
+    // The TSIG record in the response tells the client why, RFC 8945 section 5.3.2
+    let response = exchange(server, &request.buf).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NOTAUTH);
+    let tsig = response.resources.last().unwrap();
+    assert_eq!(tsig.name, key.name);
+    let RData::UNKNOWN { qtype: 250, data } = &tsig.rdata else {
+        panic!("No TSIG record in {response:?}");
+    };
+    let error = &data[data.len() - 4..data.len() - 2];
+    assert_eq!(u16::from_be_bytes([error[0], error[1]]), BADKEY);
+}
+
+#[test]
+fn sockets_passed_to_another_process_are_ignored() {
+    std::env::set_var("LISTEN_PID", (std::process::id() + 1).to_string());
+    std::env::set_var("LISTEN_FDS", "2");
+
+    assert!(Listeners::from_systemd().unwrap().is_none());
+    // Unset either way, so nothing started from here takes them for its own
+    assert!(std::env::var_os("LISTEN_FDS").is_none());
+}
@@ -0,0 +1,144 @@
+//! Resolvers stacked with the `Resolver` trait, forwarding to the mock server from the `test-util`
+//! feature
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use dns_server::cache::{Cache, Cached};
+use dns_server::hosts::Hosts;
+use dns_server::mock::{MockServer, Reply};
+use dns_server::resolver::{Forwarder, RetryPolicy};
+use dns_server::server::{handle_query, ServerContext};
+use dns_server::upstream::Upstreams;
+use dns_server::{
+    DnsError, DnsName, DnsPacket, DnsQuestion, DnsRecord, QueryType, RData, Resolver, ResultCode,
+};
+
+const POLICY: RetryPolicy = RetryPolicy::new(Duration::from_millis(100), 1);
+
+fn question(name: &str) -> DnsQuestion {
+    DnsQuestion::new(DnsName::new(name).unwrap(), QueryType::A)
+}
+
+fn answer(name: &str) -> Reply {
+    Reply::Answer(vec![DnsRecord::new(
+        DnsName::new(name).unwrap(),
+        300,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+        },
+    )])
+}
+
+fn forwarder(server: &MockServer) -> Forwarder {
+    Forwarder::new(Upstreams::new(vec![server.addr()]), POLICY)
+}
+
+#[test]
+fn fallback_asks_the_next_resolver() {
+    let server = MockServer::scripted([answer("www.example.com")]).unwrap();
+    let hosts = Hosts::parse("192.0.2.7 printer.lan", 60);
+    let stack = hosts.or(forwarder(&server));
+
+    let response = stack.resolve(&question("printer.lan")).unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert!(server.received().is_empty());
+
+    let response = stack.resolve(&question("www.example.com")).unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn cached_answers_are_not_asked_again() {
+    let server = MockServer::scripted([answer("www.example.com")]).unwrap();
+    let stack = Cached::new(Cache::new(10), forwarder(&server));
+
+    for _ in 0..3 {
+        let response = stack.resolve(&question("www.example.com")).unwrap();
+        assert_eq!(response.answers.len(), 1);
+    }
+    assert_eq!(server.received().len(), 1);
+    assert_eq!(stack.cache().len(), 1);
+}
+
+#[test]
+fn cached_ttls_are_kept_within_the_limits() {
+    let server = MockServer::scripted([answer("www.example.com")]).unwrap();
+    let stack = Cached::new(
+        Cache::new(10).with_ttl_limits(600, 86400),
+        forwarder(&server),
+    );
+
+    // Raised from 300 when forwarded and when answered from the cache alike
+    for _ in 0..2 {
+        let response = stack.resolve(&question("www.example.com")).unwrap();
+        assert!((599..=600).contains(&response.answers[0].ttl()));
+    }
+
+    let server = MockServer::scripted([answer("www.example.com")]).unwrap();
+    let stack = Cached::new(Cache::new(10).with_ttl_limits(0, 60), forwarder(&server));
+    for _ in 0..2 {
+        let response = stack.resolve(&question("www.example.com")).unwrap();
+        assert!((59..=60).contains(&response.answers[0].ttl()));
+    }
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn no_answer_without_a_fallback() {
+    let hosts = Hosts::parse("192.0.2.7 printer.lan", 60);
+
+    let result = hosts.resolve(&question("www.example.com"));
+    assert!(matches!(result, Err(DnsError::NoAnswer(_))));
+}
+
+/// Answers names under `db.internal`, like a backend of an embedder would
+struct Database;
+
+impl Resolver for Database {
+    fn resolve(&self, question: &DnsQuestion) -> dns_server::Result<DnsPacket> {
+        let db = DnsName::new("db.internal").unwrap();
+        if !question.name.is_subdomain_of(&db) {
+            return Err(DnsError::NoAnswer(question.name.to_string()));
+        }
+
+        let mut packet = DnsPacket::new();
+        packet.header.authoritative_answer = true;
+        packet.answers.push(DnsRecord::new(
+            question.name.clone(),
+            60,
+            RData::A {
+                addr: Ipv4Addr::new(10, 0, 0, 1),
+            },
+        ));
+        Ok(packet)
+    }
+}
+
+#[test]
+fn server_asks_the_plugged_resolver_before_forwarding() {
+    let server = MockServer::scripted([answer("www.example.com")]).unwrap();
+    let config = format!("upstream = [\"{}\"]", server.addr())
+        .parse()
+        .unwrap();
+    let mut context = ServerContext::new(config).unwrap();
+    context.resolver = Some(Box::new(Database));
+    let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    let query = DnsPacket::query("a.db.internal", QueryType::A)
+        .build()
+        .unwrap();
+    let response = handle_query(&context, &query, client).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::NOERROR);
+    assert!(response.header.authoritative_answer);
+    assert_eq!(response.answers.len(), 1);
+    assert!(server.received().is_empty());
+
+    let query = DnsPacket::query("www.example.com", QueryType::A)
+        .build()
+        .unwrap();
+    let response = handle_query(&context, &query, client).unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(server.received().len(), 1);
+}
@@ -0,0 +1,233 @@
+//! Zone transfers over TCP, AXFR from RFC 5936 and IXFR from RFC 1995
+
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use dns_server::authority::Authority;
+use dns_server::buffer::TCP_MAX_LEN;
+use dns_server::transfer::{self, write_transfer};
+use dns_server::zone::Zone;
+use dns_server::{BytePacketBuffer, DnsName, DnsPacket, DnsRecord, QueryType, RData};
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+fn soa(serial: u32) -> DnsRecord {
+    DnsRecord::new(
+        name("example.com"),
+        300,
+        RData::SOA {
+            m_name: name("ns1.example.com"),
+            r_name: name("admin.example.com"),
+            serial,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+        },
+    )
+}
+
+fn a(owner: &str, last: u8) -> DnsRecord {
+    let addr = Ipv4Addr::new(192, 0, 2, last);
+    DnsRecord::new(name(owner), 300, RData::A { addr })
+}
+
+/// A zone at `serial` with `www` and whatever `extra` holds
+fn zone(serial: u32, extra: &[DnsRecord]) -> Zone {
+    let mut records = vec![
+        soa(serial),
+        DnsRecord::new(
+            name("example.com"),
+            300,
+            RData::NS {
+                host: name("ns1.example.com"),
+            },
+        ),
+        a("ns1.example.com", 53),
+        a("www.example.com", 1),
+    ];
+    records.extend_from_slice(extra);
+    Zone::new(name("example.com"), records)
+}
+
+/// A zone too large for one TCP message
+fn large_zone() -> Zone {
+    let text = vec![b'x'; 200];
+    let extra: Vec<_> = (0..1000)
+        .map(|i| {
+            DnsRecord::new(
+                name(&format!("host{i}.example.com")),
+                300,
+                RData::TXT {
+                    data: vec![text.clone()],
+                },
+            )
+        })
+        .collect();
+    zone(1, &extra)
+}
+
+/// A primary serving `authority` on the loopback address, along with the types of the transfers
+/// it was asked for
+fn primary(authority: Authority) -> (SocketAddr, Arc<Mutex<Vec<QueryType>>>) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let asked = Arc::new(Mutex::new(Vec::new()));
+
+    let requests = Arc::clone(&asked);
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let mut buf = BytePacketBuffer::read_from(&mut stream).unwrap();
+            let request = DnsPacket::from_buffer(&mut buf).unwrap();
+            let question = &request.questions[0];
+            requests.lock().unwrap().push(question.qtype);
+
+            // Like the server, the whole zone is sent when the journal doesn't go back far enough
+            let records = match question.qtype {
+                QueryType::IXFR => {
+                    let serial = match request.authorities[0].rdata {
+                        RData::SOA { serial, .. } => serial,
+                        _ => panic!("IXFR without an SOA"),
+                    };
+                    authority.incremental_transfer(&question.name, serial)
+                }
+                _ => authority.transfer(&question.name),
+            };
+            write_transfer(&mut stream, &request, records.unwrap(), None).unwrap();
+        }
+    });
+
+    (addr, asked)
+}
+
+fn serving(zone: Zone) -> Authority {
+    let mut authority = Authority::default();
+    authority.insert(zone);
+    authority
+}
+
+/// Split a stream of length-prefixed messages
+fn messages(mut wire: &[u8]) -> Vec<DnsPacket> {
+    let mut packets = Vec::new();
+    while !wire.is_empty() {
+        let mut buf = BytePacketBuffer::read_from(&mut wire).unwrap();
+        packets.push(DnsPacket::from_buffer(&mut buf).unwrap());
+    }
+    packets
+}
+
+#[test]
+fn axfr_is_bracketed_by_the_soa_across_messages() {
+    let zone = large_zone();
+    let records = zone.transfer_records().unwrap();
+    let request = DnsPacket::query("example.com", QueryType::AXFR)
+        .id(0x1234)
+        .build()
+        .unwrap();
+
+    let mut wire = Vec::new();
+    write_transfer(&mut wire, &request, records.clone(), None).unwrap();
+    let messages = messages(&wire);
+    assert!(messages.len() > 1, "{} messages", messages.len());
+
+    // Only the first message repeats the question, and every one answers the request
+    assert_eq!(messages[0].questions, request.questions);
+    assert!(messages[1..].iter().all(|m| m.questions.is_empty()));
+    for message in &messages {
+        assert_eq!(message.header.id, 0x1234);
+        assert!(message.header.response && message.header.authoritative_answer);
+    }
+
+    let sent: Vec<_> = messages.into_iter().flat_map(|m| m.answers).collect();
+    assert_eq!(sent, records);
+    assert_eq!(sent.first(), Some(&soa(1)));
+    assert_eq!(sent.last(), Some(&soa(1)));
+    assert_eq!(sent.iter().filter(|rec| **rec == soa(1)).count(), 2);
+    assert!(wire.len() > TCP_MAX_LEN);
+}
+
+#[test]
+fn axfr_reads_every_message_of_the_transfer() {
+    let zone = large_zone();
+    let mut expected = zone.transfer_records().unwrap();
+    let (addr, asked) = primary(serving(zone));
+
+    // The closing SOA is dropped
+    let records = transfer::axfr(&name("example.com"), addr, None).unwrap();
+    expected.pop();
+    assert_eq!(records, expected);
+    assert_eq!(*asked.lock().unwrap(), [QueryType::AXFR]);
+}
+
+#[test]
+fn ixfr_applies_the_journaled_changes() {
+    // The primary went from 1 to 2 adding a record, and from 2 to 3 removing `www`
+    let mut served = zone(1, &[]);
+    served.update(zone(2, &[a("new.example.com", 2)]).records);
+    let mut latest = zone(3, &[a("new.example.com", 2)]).records;
+    latest.retain(|rec| *rec != a("www.example.com", 1));
+    served.update(latest);
+    let (addr, asked) = primary(serving(served.clone()));
+
+    let mut secondary = zone(1, &[]);
+    assert!(transfer::ixfr(&mut secondary, addr, None).unwrap());
+    assert_eq!(secondary.serial(), Some(3));
+    assert!(secondary.records.contains(&a("new.example.com", 2)));
+    assert!(!secondary.records.contains(&a("www.example.com", 1)));
+    assert_eq!(secondary.records.len(), served.records.len());
+    // Applied change by change, so the secondary can pass them on in turn
+    assert_eq!(secondary.journal.since(1).unwrap().count(), 2);
+    assert_eq!(secondary.journal.since(2).unwrap().count(), 1);
+
+    // Up to date, only the SOA comes back
+    assert!(!transfer::ixfr(&mut secondary, addr, None).unwrap());
+    assert_eq!(*asked.lock().unwrap(), [QueryType::IXFR, QueryType::IXFR]);
+}
+
+#[test]
+fn ixfr_from_a_serial_too_old_gets_the_whole_zone() {
+    let mut served = zone(1, &[]);
+    served.update(zone(2, &[a("new.example.com", 2)]).records);
+    let (addr, asked) = primary(serving(served.clone()));
+
+    // Serial 0 was never in the journal of the primary
+    let mut secondary = zone(0, &[a("old.example.com", 3)]);
+    assert!(transfer::ixfr(&mut secondary, addr, None).unwrap());
+    assert_eq!(secondary.serial(), Some(2));
+    assert!(secondary.records.contains(&a("new.example.com", 2)));
+    assert!(!secondary.records.contains(&a("old.example.com", 3)));
+    assert_eq!(*asked.lock().unwrap(), [QueryType::IXFR]);
+}
+
+#[test]
+fn ixfr_answered_with_only_a_newer_soa_falls_back_to_axfr() {
+    // A primary without a journal may answer with just its SOA, RFC 1995 section 4
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let served = zone(5, &[a("new.example.com", 2)]);
+    let full = served.transfer_records().unwrap();
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    let requests = Arc::clone(&asked);
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let mut buf = BytePacketBuffer::read_from(&mut stream).unwrap();
+            let request = DnsPacket::from_buffer(&mut buf).unwrap();
+            let qtype = request.questions[0].qtype;
+            requests.lock().unwrap().push(qtype);
+            let records = match qtype {
+                QueryType::IXFR => vec![soa(5)],
+                _ => full.clone(),
+            };
+            write_transfer(&mut stream, &request, records, None).unwrap();
+        }
+    });
+
+    let mut secondary = zone(1, &[]);
+    assert!(transfer::ixfr(&mut secondary, addr, None).unwrap());
+    assert_eq!(secondary.serial(), Some(5));
+    assert!(secondary.records.contains(&a("new.example.com", 2)));
+    assert_eq!(*asked.lock().unwrap(), [QueryType::IXFR, QueryType::AXFR]);
+}
@@ -0,0 +1,77 @@
+//! Responses that don't fit the payload size of the client
+
+use std::net::Ipv4Addr;
+
+use dns_server::edns::opt_record;
+use dns_server::{BytePacketBuffer, DnsName, DnsPacket, DnsRecord, QueryType, RData};
+
+fn name(i: usize) -> DnsName {
+    DnsName::new(&format!("host-{i}.example.com")).unwrap()
+}
+
+fn a(i: usize) -> DnsRecord {
+    DnsRecord::new(
+        name(i),
+        300,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, i as u8),
+        },
+    )
+}
+
+fn response(answers: usize, additional: usize) -> DnsPacket {
+    let query = DnsPacket::query("example.com", QueryType::A)
+        .build()
+        .unwrap();
+    let mut response = DnsPacket::response_to(&query);
+    response.answers = (0..answers).map(a).collect();
+    response.resources = (0..additional).map(a).collect();
+    response.resources.push(opt_record(false, Vec::new()));
+    response
+}
+
+/// Write into a UDP sized buffer and read back what was sent
+fn send(mut packet: DnsPacket) -> DnsPacket {
+    let mut buf = BytePacketBuffer::new();
+    packet.write_truncated(&mut buf).unwrap();
+    assert!(buf.pos() <= 512);
+
+    let mut sent = BytePacketBuffer {
+        buf: buf.buf[..buf.pos()].to_vec(),
+        pos: 0,
+    };
+    DnsPacket::from_buffer(&mut sent).unwrap()
+}
+
+#[test]
+fn response_that_fits_is_left_alone() {
+    let packet = response(3, 3);
+
+    let sent = send(packet.clone());
+    assert!(!sent.header.truncated_message);
+    assert_eq!(sent.answers, packet.answers);
+    assert_eq!(sent.resources, packet.resources);
+}
+
+#[test]
+fn answers_that_do_not_fit_set_tc() {
+    let sent = send(response(40, 5));
+    assert!(sent.header.truncated_message);
+    assert!(!sent.answers.is_empty() && sent.answers.len() < 40);
+    assert_eq!(
+        sent.answers[..],
+        response(40, 0).answers[..sent.answers.len()]
+    );
+    // The OPT record is kept, the rest of the additional section isn't
+    assert_eq!(sent.resources.len(), 1);
+    assert!(sent.edns().is_some());
+}
+
+#[test]
+fn additional_records_are_left_out_without_tc() {
+    let sent = send(response(3, 40));
+    assert!(!sent.header.truncated_message);
+    assert_eq!(sent.answers.len(), 3);
+    assert!(sent.resources.len() < 41);
+    assert!(sent.edns().is_some());
+}
@@ -0,0 +1,321 @@
+//! Signing and verifying messages with TSIG, RFC 8945
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+
+use dns_server::tsig::{TsigAlgorithm, TsigKey, TsigSession, BADKEY, BADSIG, BADTIME, FUDGE};
+use dns_server::{
+    BytePacketBuffer, DnsError, DnsName, DnsPacket, DnsRecord, QueryType, RData, ResultCode,
+};
+
+fn key(name: &str, secret: &[u8]) -> TsigKey {
+    TsigKey {
+        name: DnsName::new(name).unwrap(),
+        algorithm: TsigAlgorithm::HmacSha256,
+        secret: secret.to_vec(),
+    }
+}
+
+fn query() -> DnsPacket {
+    DnsPacket::query("example.com", QueryType::AXFR)
+        .build()
+        .unwrap()
+}
+
+/// One message of a response, told apart by `serial`
+fn response(serial: u8) -> DnsPacket {
+    let mut packet = DnsPacket::response_to(&query());
+    packet.answers.push(DnsRecord::new(
+        DnsName::new("example.com").unwrap(),
+        300,
+        RData::TXT {
+            data: vec![vec![serial]],
+        },
+    ));
+    packet
+}
+
+/// The message as sent, signed when given a session
+fn sent(mut packet: DnsPacket, tsig: Option<&mut TsigSession<'_>>) -> BytePacketBuffer {
+    let mut buf = packet.write_signed(tsig).unwrap();
+    buf.buf.truncate(buf.pos());
+    buf.pos = 0;
+    buf
+}
+
+fn buffer(buf: Vec<u8>) -> BytePacketBuffer {
+    BytePacketBuffer { buf, pos: 0 }
+}
+
+/// The NOTAUTH response to a request failing verification
+fn rejected(request: &mut BytePacketBuffer, keys: &[TsigKey]) -> BytePacketBuffer {
+    let mut packet = DnsPacket::response_to(&query());
+    packet.header.rescode = ResultCode::NOTAUTH;
+    let mut buf = BytePacketBuffer::new();
+    packet.write(&mut buf).unwrap();
+    TsigSession::reject(request, keys, &mut buf).unwrap();
+    buf.buf.truncate(buf.pos());
+    buf.pos = 0;
+    buf
+}
+
+/// The MAC, error and other data of the TSIG record ending a message
+fn tsig_fields(buf: &mut BytePacketBuffer) -> (Vec<u8>, u16, Vec<u8>) {
+    let packet = DnsPacket::from_buffer(buf).unwrap();
+    let Some(RData::UNKNOWN { qtype: 250, data }) = packet.resources.last().map(|rec| &rec.rdata)
+    else {
+        panic!("No TSIG record in {packet:?}");
+    };
+
+    // Past the algorithm name, the time signed and the fudge
+    let mut pos = 0;
+    while data[pos] != 0 {
+        pos += usize::from(data[pos]) + 1;
+    }
+    pos += 1 + 8;
+    let u16_at = |pos: usize| u16::from_be_bytes([data[pos], data[pos + 1]]);
+    let mac_len = usize::from(u16_at(pos));
+    let mac = data[pos + 2..pos + 2 + mac_len].to_vec();
+    pos += 2 + mac_len + 2;
+
+    (mac, u16_at(pos), data[pos + 4..].to_vec())
+}
+
+fn wire(name: &DnsName) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for label in name.labels() {
+        wire.push(label.len() as u8);
+        wire.extend_from_slice(label.as_bytes());
+    }
+    wire.push(0);
+    wire
+}
+
+/// A request signed at `time_signed`, where [`TsigSession::sign`] always signs at the current time
+fn signed_at(key: &TsigKey, time_signed: u64) -> BytePacketBuffer {
+    let mut buf = sent(query(), None);
+    let algorithm = DnsName::new("hmac-sha256").unwrap();
+    let time = &time_signed.to_be_bytes()[2..];
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key.secret).unwrap();
+    mac.update(&buf.buf);
+    mac.update(&wire(&key.name));
+    mac.update(&[0, 255, 0, 0, 0, 0]);
+    mac.update(&wire(&algorithm));
+    mac.update(time);
+    mac.update(&FUDGE.to_be_bytes());
+    mac.update(&[0; 4]);
+    let mac = mac.finalize().into_bytes();
+
+    let mut rdata = wire(&algorithm);
+    rdata.extend_from_slice(time);
+    rdata.extend_from_slice(&FUDGE.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(&mac);
+    rdata.extend_from_slice(&buf.buf[..2]);
+    rdata.extend_from_slice(&[0; 4]);
+
+    buf.buf.extend(wire(&key.name));
+    buf.buf.extend_from_slice(&[0, 250, 0, 255, 0, 0, 0, 0]);
+    buf.buf
+        .extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    buf.buf.extend(rdata);
+    buf.buf[11] += 1;
+    buf
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn signed_exchange_verifies() {
+    let client_key = key("transfer-key", b"secret");
+    let keys = [key("other-key", b"other"), client_key.clone()];
+
+    let mut client = TsigSession::new(&client_key);
+    let mut request = sent(query(), Some(&mut client));
+    let mut server = TsigSession::verify_request(&mut request, &keys)
+        .unwrap()
+        .unwrap();
+    assert_eq!(server.key().name, client_key.name);
+    // Left at the start of the request for parsing it
+    assert_eq!(
+        DnsPacket::from_buffer(&mut request)
+            .unwrap()
+            .questions
+            .len(),
+        1
+    );
+
+    let mut reply = sent(response(1), Some(&mut server));
+    client.verify(&mut reply).unwrap();
+}
+
+#[test]
+fn unsigned_requests_have_no_session() {
+    let keys = [key("transfer-key", b"secret")];
+
+    let session = TsigSession::verify_request(&mut sent(query(), None), &keys).unwrap();
+    assert!(session.is_none());
+}
+
+#[test]
+fn tampered_messages_fail() {
+    let key = key("transfer-key", b"secret");
+    let keys = [key.clone()];
+    let signed = sent(query(), Some(&mut TsigSession::new(&key))).buf;
+    let (mac, _, _) = tsig_fields(&mut buffer(signed.clone()));
+    let mac_at = signed
+        .windows(mac.len())
+        .position(|window| window == mac)
+        .unwrap();
+
+    let mut question = buffer(signed.clone());
+    question.buf[13] ^= 0x20;
+    let mut mac = buffer(signed);
+    mac.buf[mac_at] ^= 0xff;
+    let wrong_secret = sent(
+        query(),
+        Some(&mut TsigSession::new(&self::key("transfer-key", b"guess"))),
+    );
+    for mut request in [question, mac, wrong_secret] {
+        assert!(matches!(
+            TsigSession::verify_request(&mut request, &keys),
+            Err(DnsError::Tsig(_))
+        ));
+    }
+}
+
+#[test]
+fn replies_only_verify_for_their_request() {
+    let key = key("transfer-key", b"secret");
+    let keys = [key.clone()];
+
+    let mut client = TsigSession::new(&key);
+    sent(query(), Some(&mut client));
+    let other = DnsPacket::query("example.org", QueryType::AXFR)
+        .build()
+        .unwrap();
+    let mut other_request = sent(other, Some(&mut TsigSession::new(&key)));
+    let mut server = TsigSession::verify_request(&mut other_request, &keys)
+        .unwrap()
+        .unwrap();
+
+    let mut reply = sent(response(1), Some(&mut server));
+    assert!(matches!(client.verify(&mut reply), Err(DnsError::Tsig(_))));
+}
+
+#[test]
+fn continued_messages_chain_their_macs() {
+    let key = key("transfer-key", b"secret");
+    let keys = [key.clone()];
+
+    let mut client = TsigSession::new(&key);
+    let mut request = sent(query(), Some(&mut client));
+    let mut server = TsigSession::verify_request(&mut request, &keys)
+        .unwrap()
+        .unwrap();
+    for serial in 1..=3 {
+        let mut message = sent(response(serial), Some(&mut server));
+        client.verify(&mut message).unwrap();
+    }
+
+    // A dropped message breaks the chain
+    let mut client = TsigSession::new(&key);
+    let mut request = sent(query(), Some(&mut client));
+    let mut server = TsigSession::verify_request(&mut request, &keys)
+        .unwrap()
+        .unwrap();
+    let mut first = sent(response(1), Some(&mut server));
+    sent(response(2), Some(&mut server));
+    let mut third = sent(response(3), Some(&mut server));
+    client.verify(&mut first).unwrap();
+    assert!(matches!(client.verify(&mut third), Err(DnsError::Tsig(_))));
+}
+
+#[test]
+fn unsigned_messages_are_covered_by_the_next_mac() {
+    let key = key("transfer-key", b"secret");
+    let keys = [key.clone()];
+
+    // The first message of a response has to be signed
+    let mut client = TsigSession::new(&key);
+    sent(query(), Some(&mut client));
+    let mut unsigned = sent(response(1), None);
+    assert!(matches!(
+        client.verify(&mut unsigned),
+        Err(DnsError::Tsig(_))
+    ));
+
+    // A message slipped in unsigned is accepted, but the signer didn't cover it in the next MAC
+    let mut client = TsigSession::new(&key);
+    let mut request = sent(query(), Some(&mut client));
+    let mut server = TsigSession::verify_request(&mut request, &keys)
+        .unwrap()
+        .unwrap();
+    let mut first = sent(response(1), Some(&mut server));
+    let mut slipped_in = sent(response(2), None);
+    let mut next = sent(response(3), Some(&mut server));
+    client.verify(&mut first).unwrap();
+    client.verify(&mut slipped_in).unwrap();
+    assert!(matches!(client.verify(&mut next), Err(DnsError::Tsig(_))));
+}
+
+#[test]
+fn unknown_keys_are_rejected_with_badkey() {
+    let keys = [key("transfer-key", b"secret")];
+    let mut request = sent(
+        query(),
+        Some(&mut TsigSession::new(&key("other-key", b"secret"))),
+    );
+    assert!(TsigSession::verify_request(&mut request, &keys).is_err());
+
+    let (mac, error, other) = tsig_fields(&mut rejected(&mut request, &keys));
+    assert_eq!(error, BADKEY);
+    assert!(mac.is_empty());
+    assert!(other.is_empty());
+}
+
+#[test]
+fn bad_signatures_are_rejected_with_badsig() {
+    let keys = [key("transfer-key", b"secret")];
+    let mut request = sent(
+        query(),
+        Some(&mut TsigSession::new(&key("transfer-key", b"guess"))),
+    );
+
+    let (mac, error, other) = tsig_fields(&mut rejected(&mut request, &keys));
+    assert_eq!(error, BADSIG);
+    assert!(mac.is_empty());
+    assert!(other.is_empty());
+}
+
+#[test]
+fn late_requests_are_rejected_with_the_server_time() {
+    let key = key("transfer-key", b"secret");
+    let keys = [key.clone()];
+    assert!(
+        TsigSession::verify_request(&mut signed_at(&key, now()), &keys)
+            .unwrap()
+            .is_some()
+    );
+
+    let mut request = signed_at(&key, now() - u64::from(FUDGE) - 60);
+    assert!(matches!(
+        TsigSession::verify_request(&mut request, &keys),
+        Err(DnsError::Tsig(_))
+    ));
+
+    let (mac, error, other) = tsig_fields(&mut rejected(&mut request, &keys));
+    assert_eq!(error, BADTIME);
+    assert_eq!(mac.len(), 32);
+    let mut time = [0; 8];
+    time[2..].copy_from_slice(&other);
+    assert!(u64::from_be_bytes(time).abs_diff(now()) <= 1);
+}
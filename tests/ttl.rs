@@ -0,0 +1,63 @@
+//! TTLs counting down from a fixed moment, so nothing depends on how fast the tests run
+
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use dns_server::ttl::Ttl;
+use dns_server::{DnsName, DnsRecord, RData};
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn remaining_seconds_count_down_to_zero() {
+    let start = Instant::now();
+    let ttl = Ttl::starting_at(300, start);
+    assert_eq!(ttl.original(), 300);
+    assert_eq!(ttl.expires(), start + secs(300));
+
+    assert_eq!(ttl.remaining_at(start), 300);
+    assert_eq!(ttl.remaining_at(start + secs(100)), 200);
+    // The second it is in counts, so a record isn't sent on with 0 before it expired
+    assert_eq!(ttl.remaining_at(start + Duration::from_millis(299_500)), 1);
+    assert!(!ttl.is_expired_at(start + Duration::from_millis(299_500)));
+
+    // Saturating once it expired, however long ago
+    assert_eq!(ttl.remaining_at(start + secs(300)), 0);
+    assert!(ttl.is_expired_at(start + secs(300)));
+    assert_eq!(ttl.remaining_at(start + secs(10_000)), 0);
+    assert!(Ttl::starting_at(0, start).is_expired_at(start));
+}
+
+#[test]
+fn clamped_ttls_keep_their_start() {
+    let start = Instant::now();
+    let ttl = Ttl::starting_at(30, start).clamp(60, 3600);
+    assert_eq!(ttl.original(), 60);
+    assert_eq!(ttl.remaining_at(start + secs(20)), 40);
+
+    let ttl = Ttl::starting_at(86_400, start).clamp(60, 3600);
+    assert_eq!(ttl.remaining_at(start + secs(600)), 3000);
+    assert_eq!(Ttl::starting_at(300, start).clamp(60, 3600).original(), 300);
+}
+
+#[test]
+fn records_count_down_from_when_they_were_received() {
+    let received = Instant::now();
+    let mut record = DnsRecord::new(
+        DnsName::new("www.example.com").unwrap(),
+        120,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+        },
+    );
+
+    let ttl = record.ttl_from(received);
+    assert_eq!(ttl, Ttl::starting_at(120, received));
+    let later = received + secs(45);
+    record.set_ttl(ttl.remaining_at(later));
+    assert_eq!(record.ttl(), 75);
+    // Counting again from there ends at the same moment
+    assert_eq!(record.ttl_from(later).expires(), ttl.expires());
+}
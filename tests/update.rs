@@ -0,0 +1,216 @@
+//! Dynamic updates from RFC 2136 applied to a zone
+
+use std::net::Ipv4Addr;
+
+use dns_server::update::{self, UpdateClass, UpdateMessage, UpdateRecord};
+use dns_server::zone::Zone;
+use dns_server::{DnsHeader, DnsName, DnsQuestion, DnsRecord, QueryType, RData, ResultCode};
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+fn zone() -> Zone {
+    let text = "$ORIGIN example.com.\n\
+                @ 300 IN SOA ns1 admin 1 3600 600 86400 300\n\
+                @ 300 IN NS ns1\n\
+                ns1 300 IN A 192.0.2.53\n\
+                www 300 IN A 192.0.2.1\n";
+    Zone::parse(text, &DnsName::root()).unwrap()
+}
+
+fn a(owner: &str, addr: [u8; 4]) -> DnsRecord {
+    let addr = Ipv4Addr::from(addr);
+    DnsRecord::new(name(owner), 300, RData::A { addr })
+}
+
+fn ns(host: &str) -> DnsRecord {
+    let host = name(host);
+    DnsRecord::new(name("example.com"), 300, RData::NS { host })
+}
+
+/// An entry without rdata, which asks about or deletes a whole name or RRset
+fn rrset(owner: &str, qtype: QueryType, class: UpdateClass) -> UpdateRecord {
+    UpdateRecord {
+        name: name(owner),
+        qtype,
+        class,
+        ttl: 0,
+        record: None,
+    }
+}
+
+/// An entry for a single record, added in the Internet class or deleted in class NONE
+fn rr(record: DnsRecord, class: UpdateClass) -> UpdateRecord {
+    UpdateRecord {
+        name: record.domain().clone(),
+        qtype: record.qtype(),
+        class,
+        ttl: if class == UpdateClass::IN {
+            record.ttl
+        } else {
+            0
+        },
+        record: Some(record),
+    }
+}
+
+/// A prerequisite for an RRset to hold exactly the records given this way
+fn exists(record: DnsRecord) -> UpdateRecord {
+    UpdateRecord {
+        ttl: 0,
+        ..rr(record, UpdateClass::IN)
+    }
+}
+
+fn update(prerequisites: Vec<UpdateRecord>, updates: Vec<UpdateRecord>) -> UpdateMessage {
+    UpdateMessage {
+        header: DnsHeader::new(),
+        zones: vec![DnsQuestion::new(name("example.com"), QueryType::SOA)],
+        prerequisites,
+        updates,
+    }
+}
+
+fn prerequisite(pr: UpdateRecord) -> Result<bool, ResultCode> {
+    let added = rr(a("new.example.com", [192, 0, 2, 2]), UpdateClass::IN);
+    update::apply(&mut zone(), &update(vec![pr], vec![added]))
+}
+
+#[test]
+fn prerequisites_refuse_with_their_rcode() {
+    use QueryType::{A, ANY};
+    // Class ANY asks for a name or RRset to be in use, class NONE for it not to be
+    let (in_use, not_in_use) = (UpdateClass::ANY, UpdateClass::NONE);
+
+    let cases = [
+        (
+            rrset("www.example.com", ANY, not_in_use),
+            ResultCode::YXDOMAIN,
+        ),
+        (rrset("new.example.com", ANY, in_use), ResultCode::NXDOMAIN),
+        (rrset("www.example.com", A, not_in_use), ResultCode::YXRRSET),
+        (rrset("new.example.com", A, in_use), ResultCode::NXRRSET),
+        (
+            exists(a("www.example.com", [192, 0, 2, 9])),
+            ResultCode::NXRRSET,
+        ),
+        (rrset("www.example.org", A, in_use), ResultCode::NOTZONE),
+    ];
+    for (pr, rcode) in cases {
+        assert_eq!(prerequisite(pr.clone()), Err(rcode), "{pr:?}");
+    }
+
+    // And let the update through when they hold
+    let holding = [
+        rrset("www.example.com", ANY, in_use),
+        rrset("new.example.com", ANY, not_in_use),
+        rrset("www.example.com", A, in_use),
+        rrset("new.example.com", A, not_in_use),
+        exists(a("www.example.com", [192, 0, 2, 1])),
+    ];
+    for pr in holding {
+        assert_eq!(prerequisite(pr.clone()), Ok(true), "{pr:?}");
+    }
+}
+
+#[test]
+fn apex_soa_is_never_deleted() {
+    let mut zone = zone();
+    let soa = zone.soa().unwrap().clone();
+
+    let deletions = [
+        rrset("example.com", QueryType::SOA, UpdateClass::ANY),
+        rrset("example.com", QueryType::ANY, UpdateClass::ANY),
+        rr(soa.clone(), UpdateClass::NONE),
+    ];
+    for deletion in deletions {
+        assert_eq!(
+            update::apply(&mut zone, &update(Vec::new(), vec![deletion])),
+            Ok(false)
+        );
+        assert_eq!(zone.soa(), Some(&soa));
+    }
+}
+
+#[test]
+fn last_apex_ns_is_never_deleted() {
+    let mut zone = zone();
+    let apex_ns = |zone: &Zone| {
+        zone.records
+            .iter()
+            .filter(|rec| rec.qtype() == QueryType::NS)
+            .count()
+    };
+
+    let delete = update(
+        Vec::new(),
+        vec![rr(ns("ns1.example.com"), UpdateClass::NONE)],
+    );
+    assert_eq!(update::apply(&mut zone, &delete), Ok(false));
+    assert_eq!(apex_ns(&zone), 1);
+    let delete_all = update(
+        Vec::new(),
+        vec![rrset("example.com", QueryType::NS, UpdateClass::ANY)],
+    );
+    assert_eq!(update::apply(&mut zone, &delete_all), Ok(false));
+    assert_eq!(apex_ns(&zone), 1);
+
+    // With a second one, either can go
+    let add = update(Vec::new(), vec![rr(ns("ns2.example.com"), UpdateClass::IN)]);
+    assert_eq!(update::apply(&mut zone, &add), Ok(true));
+    assert_eq!(update::apply(&mut zone, &delete), Ok(true));
+    assert_eq!(
+        zone.records.iter().find(|rec| rec.qtype() == QueryType::NS),
+        Some(&ns("ns2.example.com"))
+    );
+}
+
+#[test]
+fn updates_are_journaled_for_ixfr() {
+    let mut zone = zone();
+    let added = a("new.example.com", [192, 0, 2, 2]);
+    let removed = a("www.example.com", [192, 0, 2, 1]);
+
+    let changes = update(
+        Vec::new(),
+        vec![
+            rr(added.clone(), UpdateClass::IN),
+            rr(removed.clone(), UpdateClass::NONE),
+        ],
+    );
+    assert_eq!(update::apply(&mut zone, &changes), Ok(true));
+    assert_eq!(zone.serial(), Some(2));
+
+    let diffs: Vec<_> = zone.journal.since(1).unwrap().collect();
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].to_soa, *zone.soa().unwrap());
+    assert_eq!(diffs[0].added, [added]);
+    assert_eq!(diffs[0].removed, [removed]);
+
+    // An update changing nothing leaves the serial and the journal alone
+    assert_eq!(update::apply(&mut zone, &changes), Ok(false));
+    assert_eq!(zone.serial(), Some(2));
+    assert_eq!(zone.journal.since(1).unwrap().count(), 1);
+}
+
+#[test]
+fn records_of_unknown_types_survive_the_zone_file() {
+    let mut zone = zone();
+    let unknown = DnsRecord::new(
+        name("www.example.com"),
+        300,
+        RData::UNKNOWN {
+            qtype: 65280,
+            data: vec![0xde, 0xad, 0, 0xbe, 0xef],
+        },
+    );
+    let add = update(Vec::new(), vec![rr(unknown.clone(), UpdateClass::IN)]);
+    assert_eq!(update::apply(&mut zone, &add), Ok(true));
+
+    // Written in the generic form of RFC 3597, which loads back into the same record
+    let text = zone.to_text();
+    assert!(text.contains("TYPE65280\t\\# 5 dead00beef"), "{text}");
+    let loaded = Zone::parse(&text, &DnsName::root()).unwrap();
+    assert!(loaded.records.contains(&unknown));
+}
@@ -0,0 +1,306 @@
+//! Forwarding to upstream servers, against the mock server from the `test-util` feature
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use dns_server::config::Config;
+use dns_server::mock::{MockServer, Reply};
+use dns_server::resolver::{lookup, lookup_ip, lookup_over, Forwarder, IpPreference, RetryPolicy};
+use dns_server::upstream::{Transport, Upstreams};
+use dns_server::{DnsError, DnsName, DnsQuestion, DnsRecord, QueryType, RData, ResultCode};
+
+const POLICY: RetryPolicy = RetryPolicy::new(Duration::from_millis(100), 1);
+
+fn name() -> DnsName {
+    DnsName::new("www.example.com").unwrap()
+}
+
+fn answer() -> Reply {
+    Reply::Answer(vec![DnsRecord::new(
+        name(),
+        300,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+        },
+    )])
+}
+
+#[test]
+fn answer_echoes_the_question() {
+    let server = MockServer::scripted([answer()]).unwrap();
+
+    let response = lookup(&name(), QueryType::A, server.addr(), POLICY).unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.questions[0].name.as_str(), "www.example.com");
+    // The case sent was echoed, so there was no need to ask again over TCP
+    let received = server.received();
+    assert_eq!(received.len(), 1);
+    assert!(!received[0].tcp);
+}
+
+#[test]
+fn result_code_is_passed_on() {
+    let server = MockServer::scripted([Reply::Rcode(ResultCode::SERVFAIL)]).unwrap();
+
+    let response = lookup(&name(), QueryType::A, server.addr(), POLICY).unwrap();
+    assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+}
+
+#[test]
+fn unknown_records_are_passed_on_intact() {
+    let record = DnsRecord::new(
+        name(),
+        300,
+        RData::UNKNOWN {
+            qtype: 65280,
+            data: vec![0xde, 0xad, 0xbe, 0xef],
+        },
+    );
+    let server = MockServer::scripted([Reply::Answer(vec![record.clone()])]).unwrap();
+
+    let response = lookup(&name(), QueryType::UNKNOWN(65280), server.addr(), POLICY).unwrap();
+    assert_eq!(response.answers, [record]);
+}
+
+#[test]
+fn truncated_reply_is_retried_over_tcp() {
+    let server = MockServer::scripted([Reply::Truncated, answer()]).unwrap();
+
+    let response = lookup(&name(), QueryType::A, server.addr(), POLICY).unwrap();
+    assert!(!response.header.truncated_message);
+    assert_eq!(response.answers.len(), 1);
+    let received = server.received();
+    assert_eq!(received.len(), 2);
+    assert!(!received[0].tcp);
+    assert!(received[1].tcp);
+}
+
+#[test]
+fn slow_reply_is_retried() {
+    let slow = Reply::Delayed(Duration::from_millis(500), Box::new(answer()));
+    let server = MockServer::scripted([slow, answer()]).unwrap();
+
+    let response = lookup(&name(), QueryType::A, server.addr(), POLICY).unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(server.received().len(), 2);
+}
+
+#[test]
+fn malformed_reply_is_dropped() {
+    let server = MockServer::scripted([Reply::Malformed(vec![0xde, 0xad]), answer()]).unwrap();
+
+    let response = lookup(&name(), QueryType::A, server.addr(), POLICY).unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(server.received().len(), 2);
+}
+
+#[test]
+fn wrong_id_is_invalid() {
+    let spoofed = Reply::WrongId(Box::new(answer()));
+    let server = MockServer::scripted([spoofed.clone(), spoofed]).unwrap();
+
+    let result = lookup(&name(), QueryType::A, server.addr(), POLICY);
+    assert!(matches!(result, Err(DnsError::InvalidResponse(_))));
+}
+
+#[test]
+fn silent_server_times_out() {
+    let server = MockServer::scripted([]).unwrap();
+
+    let result = lookup(&name(), QueryType::A, server.addr(), POLICY);
+    assert!(matches!(result, Err(DnsError::Timeout)));
+    assert_eq!(server.received().len(), 2);
+}
+
+#[test]
+fn fails_over_to_the_next_upstream() {
+    let silent = MockServer::scripted([]).unwrap();
+    let working = MockServer::scripted([answer()]).unwrap();
+    let upstreams = Upstreams::new(vec![silent.addr(), working.addr()]);
+
+    let response = upstreams
+        .query(|server| lookup(&name(), QueryType::A, server, POLICY))
+        .unwrap();
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(working.received().len(), 1);
+}
+
+#[test]
+fn tcp_transport_never_uses_udp() {
+    let server = MockServer::scripted([answer()]).unwrap();
+    let question = DnsQuestion::new(name(), QueryType::A);
+
+    let response = lookup_over(
+        &Transport::Tcp,
+        &question,
+        server.addr(),
+        None,
+        POLICY,
+        None,
+        &|_| {},
+    )
+    .unwrap();
+    assert_eq!(response.answers.len(), 1);
+    let received = server.received();
+    assert_eq!(received.len(), 1);
+    assert!(received[0].tcp);
+}
+
+#[test]
+fn kept_alive_connection_is_reused() {
+    let server =
+        MockServer::new(|_| Reply::KeepAlive(Duration::from_secs(5), Box::new(answer()))).unwrap();
+    let question = DnsQuestion::new(name(), QueryType::A);
+    let locals = Mutex::new(Vec::new());
+
+    for _ in 0..2 {
+        let response = lookup_over(
+            &Transport::Tcp,
+            &question,
+            server.addr(),
+            None,
+            POLICY,
+            None,
+            &|exchange| locals.lock().unwrap().push(exchange.local),
+        )
+        .unwrap();
+        assert_eq!(response.answers.len(), 1);
+    }
+    let received = server.received();
+    assert!(received[0].packet.tcp_keepalive().is_some());
+    assert_eq!(received.len(), 2);
+    // Both queries went over the same connection
+    let locals = locals.into_inner().unwrap();
+    assert!(locals.iter().all(|local| *local == locals[0]));
+}
+
+#[test]
+fn forward_rules_pick_their_transport() {
+    let config: Config = r#"
+        [[forward]]
+        domain = "corp.internal"
+        upstream = "10.0.0.2"
+
+        [[forward]]
+        domain = "."
+        upstream = ["1.1.1.1", "1.0.0.1:8853"]
+        transport = "tls"
+        tls-name = "cloudflare-dns.com"
+    "#
+    .parse()
+    .unwrap();
+
+    let corp = config.forward[0].upstreams().unwrap();
+    assert_eq!(*corp.transport(), Transport::Udp);
+    let everything = config.forward[1].upstreams().unwrap();
+    assert_eq!(
+        *everything.transport(),
+        Transport::Tls {
+            name: DnsName::new("cloudflare-dns.com").unwrap()
+        }
+    );
+    // The default port is the one of DNS over TLS, unless another one is given
+    let addrs: Vec<_> = everything
+        .health()
+        .into_iter()
+        .map(|(addr, _)| addr)
+        .collect();
+    assert_eq!(
+        addrs,
+        [
+            "1.1.1.1:853".parse().unwrap(),
+            "1.0.0.1:8853".parse().unwrap()
+        ]
+    );
+}
+
+#[test]
+fn forward_rules_need_what_their_transport_uses() {
+    let invalid = [
+        // No name to check the certificates against
+        "transport = \"tls\"",
+        "transport = \"https\"",
+        "transport = \"https\"\nurl = \"http://dns.example/dns-query\"",
+        "tls-name = \"dns.example\"",
+    ];
+    for rule in invalid {
+        let config = format!("[[forward]]\ndomain = \"example\"\nupstream = \"192.0.2.1\"\n{rule}");
+        assert!(config.parse::<Config>().is_err(), "{rule}");
+    }
+
+    // Through the proxy like everything else
+    let proxied = "[proxy]\naddr = \"127.0.0.1:9050\"\n\
+        [[forward]]\ndomain = \"example\"\nupstream = \"192.0.2.1\"\n\
+        transport = \"https\"\nurl = \"https://dns.example/dns-query\"";
+    assert!(proxied.parse::<Config>().is_ok());
+}
+
+/// A server where `www.example.com` is an alias of `web.example.net`, which has an IPv4 and an
+/// IPv6 address. AAAA answers stop at the alias, like some forwarders send them.
+fn dual_stack() -> MockServer {
+    MockServer::new(|query| {
+        let question = &query.questions[0];
+        let target = DnsName::new("web.example.net").unwrap();
+        let alias = DnsRecord::new(
+            name(),
+            300,
+            RData::CNAME {
+                host: target.clone(),
+            },
+        );
+        let rdata = match question.qtype {
+            QueryType::A => RData::A {
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+            _ => RData::AAAA {
+                addr: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+            },
+        };
+        let addr = DnsRecord::new(target.clone(), 300, rdata);
+        match (question.qtype, question.name == target) {
+            (QueryType::A, _) => Reply::Answer(vec![alias, addr]),
+            (_, false) => Reply::Answer(vec![alias]),
+            (_, true) => Reply::Answer(vec![addr]),
+        }
+    })
+    .unwrap()
+}
+
+#[test]
+fn lookup_ip_merges_both_families() {
+    let server = dual_stack();
+    let forwarder = Forwarder::new(Upstreams::new(vec![server.addr()]), POLICY);
+    let v4: IpAddr = Ipv4Addr::new(192, 0, 2, 1).into();
+    let v6: IpAddr = "2001:db8::1".parse().unwrap();
+
+    let addrs = lookup_ip(&forwarder, &name(), IpPreference::Ipv6First).unwrap();
+    assert_eq!(addrs, [v6, v4]);
+    // The AAAA query is sent again for the end of the chain
+    assert_eq!(server.received().len(), 3);
+
+    let addrs = lookup_ip(&forwarder, &name(), IpPreference::Ipv4First).unwrap();
+    assert_eq!(addrs, [v4, v6]);
+}
+
+#[test]
+fn lookup_ip_gets_by_with_one_family() {
+    let server = MockServer::new(|query| match query.questions[0].qtype {
+        QueryType::A => answer(),
+        _ => Reply::Rcode(ResultCode::SERVFAIL),
+    })
+    .unwrap();
+    let forwarder = Forwarder::new(Upstreams::new(vec![server.addr()]), POLICY);
+
+    let addrs = lookup_ip(&forwarder, &name(), IpPreference::default()).unwrap();
+    assert_eq!(addrs, [IpAddr::from(Ipv4Addr::new(192, 0, 2, 1))]);
+}
+
+#[test]
+fn lookup_ip_of_a_missing_name_is_empty() {
+    let server = MockServer::new(|_| Reply::Rcode(ResultCode::NXDOMAIN)).unwrap();
+    let forwarder = Forwarder::new(Upstreams::new(vec![server.addr()]), POLICY);
+
+    let addrs = lookup_ip(&forwarder, &name(), IpPreference::default()).unwrap();
+    assert!(addrs.is_empty());
+}
@@ -0,0 +1,138 @@
+//! Views answering clients from some networks differently than everyone else
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_server::server::{self, ServerContext};
+use dns_server::{BytePacketBuffer, DnsName, DnsPacket, QueryType};
+
+/// Two views with a network in common, and a third only for the loopback network
+const VIEWS: &str = "[[views]]\n\
+                     name = \"office\"\n\
+                     match-clients = [\"192.0.2.0/25\", \"2001:db8:1::/48\"]\n\
+                     [[views.local-records]]\n\
+                     name = \"www.example.lan\"\n\
+                     type = \"A\"\n\
+                     value = \"10.0.0.1\"\n\
+                     [[views]]\n\
+                     name = \"building\"\n\
+                     match-clients = [\"192.0.2.0/24\", \"127.0.0.0/8\"]\n\
+                     [[views.local-records]]\n\
+                     name = \"www.example.lan\"\n\
+                     type = \"A\"\n\
+                     value = \"10.0.0.2\"\n\
+                     [[views]]\n\
+                     name = \"loopback\"\n\
+                     match-clients = [\"127.0.0.0/8\"]\n\
+                     [[views.local-records]]\n\
+                     name = \"www.example.lan\"\n\
+                     type = \"A\"\n\
+                     value = \"10.0.0.3\"\n";
+
+fn config(addr: SocketAddr) -> String {
+    format!(
+        "listen = \"{addr}\"\n\
+         upstream = [\"127.0.0.1:9\"]\n\
+         [[local-records]]\n\
+         name = \"www.example.lan\"\n\
+         type = \"A\"\n\
+         value = \"192.0.2.80\"\n\
+         [[local-records]]\n\
+         name = \"shared.example.lan\"\n\
+         type = \"A\"\n\
+         value = \"192.0.2.81\"\n\
+         {VIEWS}"
+    )
+}
+
+fn free_addr() -> SocketAddr {
+    loop {
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = udp.local_addr().unwrap();
+        if TcpListener::bind(addr).is_ok() {
+            break addr;
+        }
+    }
+}
+
+fn context() -> ServerContext {
+    ServerContext::new(config(free_addr()).parse().unwrap()).unwrap()
+}
+
+/// The name of the view for queries from `client`
+fn view_of(context: &ServerContext, client: &str) -> Option<String> {
+    let client: IpAddr = client.parse().unwrap();
+    context.view(client).map(|view| view.name.clone())
+}
+
+/// The address the server answers for `qname` to queries from the loopback network
+fn ask(server: SocketAddr, qname: &str) -> Ipv4Addr {
+    let mut request = DnsPacket::query(qname, QueryType::A)
+        .id(0x1234)
+        .recursion_desired(true)
+        .build()
+        .unwrap();
+    let mut buf = BytePacketBuffer::new();
+    request.write(&mut buf).unwrap();
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    socket.send_to(&buf.buf[..buf.pos()], server).unwrap();
+    let mut buf = BytePacketBuffer::new();
+    let len = socket.recv(&mut buf.buf).unwrap();
+    buf.buf.truncate(len);
+    let response = DnsPacket::from_buffer(&mut buf).unwrap();
+    let addr = response.a_records().next().unwrap();
+
+    addr
+}
+
+#[test]
+fn views_are_picked_by_the_source_network() {
+    let context = context();
+
+    assert_eq!(view_of(&context, "192.0.2.1").as_deref(), Some("office"));
+    assert_eq!(
+        view_of(&context, "192.0.2.200").as_deref(),
+        Some("building")
+    );
+    assert_eq!(
+        view_of(&context, "2001:db8:1:2::1").as_deref(),
+        Some("office")
+    );
+    assert_eq!(view_of(&context, "2001:db8:2::1"), None);
+    assert_eq!(view_of(&context, "198.51.100.1"), None);
+}
+
+#[test]
+fn first_matching_view_wins() {
+    let context = context();
+
+    // Both `building` and `loopback` match, and `building` comes first
+    assert_eq!(view_of(&context, "127.0.0.1").as_deref(), Some("building"));
+    let www = DnsName::new("www.example.lan").unwrap();
+    let answer = context
+        .view("127.0.0.1".parse().unwrap())
+        .and_then(|view| view.lookup(&www, QueryType::A, false))
+        .unwrap();
+    assert_eq!(answer.a_records().next(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+}
+
+#[test]
+fn view_answers_go_before_the_server() {
+    let addr = free_addr();
+    let context = ServerContext::new(config(addr).parse().unwrap()).unwrap();
+    thread::spawn(move || server::run(Arc::new(context)));
+    thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(ask(addr, "www.example.lan"), Ipv4Addr::new(10, 0, 0, 2));
+    // Names the view doesn't have are answered like for anyone else
+    assert_eq!(
+        ask(addr, "shared.example.lan"),
+        Ipv4Addr::new(192, 0, 2, 81)
+    );
+}
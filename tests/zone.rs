@@ -0,0 +1,297 @@
+//! Records read from master files and from single lines in the same format, and the names and
+//! options written for queries about them
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_server::edns::{EdnsOption, OPTION_NSID};
+use dns_server::resolver::reverse_name;
+use dns_server::server::{self, ServerContext};
+use dns_server::{
+    BytePacketBuffer, DnsError, DnsName, DnsPacket, DnsRecord, QueryType, RData, Zone,
+};
+
+fn name(name: &str) -> DnsName {
+    DnsName::new(name).unwrap()
+}
+
+fn a(owner: &str, ttl: u32, last: u8) -> DnsRecord {
+    DnsRecord::new(
+        name(owner),
+        ttl,
+        RData::A {
+            addr: Ipv4Addr::new(192, 0, 2, last),
+        },
+    )
+}
+
+/// The line a zone file was rejected at
+fn error_line(text: &str) -> usize {
+    match Zone::parse(text, &name("example.com")) {
+        Err(DnsError::Zone { line, .. }) => line,
+        other => panic!("expected a zone error, got {other:?}"),
+    }
+}
+
+const ZONE: &str = r"
+$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2024050101 ; serial
+            2h         ; refresh
+            15m        ; retry
+            1w         ; expire
+            300 )      ; minimum
+    IN  NS  ns1
+ns1     A   192.0.2.53 ; relative to the origin
+www 60  A   192.0.2.1
+            A   192.0.2.2
+mail.example.com. IN 600 MX 10 mail
+$ORIGIN lab.example.com.
+host        A   192.0.2.10
+";
+
+#[test]
+fn directives_parentheses_and_relative_names() {
+    // The origin given is replaced by the one the file sets
+    let zone = Zone::parse(ZONE, &name("example.org")).unwrap();
+    assert_eq!(zone.origin, name("example.com"));
+    assert_eq!(zone.serial(), Some(2_024_050_101));
+
+    assert_eq!(
+        zone.records,
+        [
+            DnsRecord::new(
+                name("example.com"),
+                3600,
+                RData::SOA {
+                    m_name: name("ns1.example.com"),
+                    r_name: name("hostmaster.example.com"),
+                    serial: 2_024_050_101,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 604_800,
+                    minimum: 300,
+                },
+            ),
+            // A blank owner is the owner of the record before
+            DnsRecord::new(
+                name("example.com"),
+                3600,
+                RData::NS {
+                    host: name("ns1.example.com"),
+                },
+            ),
+            a("ns1.example.com", 3600, 53),
+            a("www.example.com", 60, 1),
+            // Without a TTL of its own, $TTL is used rather than the last one given
+            a("www.example.com", 3600, 2),
+            DnsRecord::new(
+                name("mail.example.com"),
+                600,
+                RData::MX {
+                    priority: 10,
+                    host: name("mail.example.com"),
+                },
+            ),
+            a("host.lab.example.com", 3600, 10),
+        ]
+    );
+}
+
+#[test]
+fn ttls_carry_over_without_a_default() {
+    let zone = Zone::parse(
+        "www 300 A 192.0.2.1\nftp A 192.0.2.2\n",
+        &name("example.com"),
+    )
+    .unwrap();
+    assert_eq!(
+        zone.records,
+        [a("www.example.com", 300, 1), a("ftp.example.com", 300, 2)]
+    );
+    // Without any records, the origin is the one given
+    let zone = Zone::parse("; nothing here\n", &name("example.com")).unwrap();
+    assert_eq!(zone.origin, name("example.com"));
+    assert!(zone.records.is_empty());
+}
+
+#[test]
+fn errors_give_the_line_they_are_on() {
+    assert_eq!(error_line("www A 192.0.2.1"), 1);
+    assert_eq!(
+        error_line("$TTL 300\nwww A 192.0.2.1\n\n$INCLUDE other.zone"),
+        4
+    );
+    assert_eq!(error_line("$TTL 300\n  A 192.0.2.1"), 2);
+    assert_eq!(error_line("$TTL 300\nwww CH A 192.0.2.1"), 2);
+    assert_eq!(
+        error_line("$TTL 300\nwww A 192.0.2.1\nftp A not-an-address"),
+        3
+    );
+    // Parentheses left open
+    assert_eq!(error_line("$TTL 300\n@ SOA ns1 hostmaster ( 1 2 3 4 5"), 2);
+}
+
+#[test]
+fn single_records_are_completed_with_the_origin() {
+    let origin = name("example.com");
+    let record = DnsRecord::parse_line("www 300 IN CNAME host.example.com.", &origin).unwrap();
+    assert_eq!(
+        record,
+        DnsRecord::new(
+            name("www.example.com"),
+            300,
+            RData::CNAME {
+                host: name("host.example.com"),
+            },
+        )
+    );
+
+    // The class is optional and may come before the TTL, and @ is the origin itself
+    let record = DnsRecord::parse_line("@ IN 60 MX 10 mail ; the mail server", &origin).unwrap();
+    assert_eq!(
+        record,
+        DnsRecord::new(
+            name("example.com"),
+            60,
+            RData::MX {
+                priority: 10,
+                host: name("mail.example.com"),
+            },
+        )
+    );
+    // Names ending in a dot are left as they are
+    let record = DnsRecord::parse_line("www.example.org. 1h A 192.0.2.1", &origin).unwrap();
+    assert_eq!(record, a("www.example.org", 3600, 1));
+
+    // Parsed on their own, names are relative to the root
+    let record: DnsRecord = "www.example.org 1h A 192.0.2.1".parse().unwrap();
+    assert_eq!(record, a("www.example.org", 3600, 1));
+}
+
+#[test]
+fn single_records_need_an_owner_a_ttl_and_nothing_else() {
+    let origin = name("example.com");
+    for line in [
+        "",
+        "www IN A 192.0.2.1",
+        " 300 A 192.0.2.1",
+        "$TTL 300",
+        "www 300 A 192.0.2.1\nftp 300 A 192.0.2.2",
+        "www 300 A",
+    ] {
+        assert!(
+            matches!(
+                DnsRecord::parse_line(line, &origin),
+                Err(DnsError::Zone { .. })
+            ),
+            "{line:?}"
+        );
+    }
+}
+
+#[test]
+fn reverse_names_of_addresses() {
+    let v4: IpAddr = "192.0.2.10".parse().unwrap();
+    assert_eq!(reverse_name(v4), name("10.2.0.192.in-addr.arpa"));
+
+    // A label for every nibble, lowest first, with the zeros written out
+    let v6: IpAddr = "2001:db8::567:89ab".parse().unwrap();
+    assert_eq!(
+        reverse_name(v6),
+        name("b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa")
+    );
+    assert_eq!(reverse_name(v6).label_count(), 34);
+
+    // Mapped addresses keep their IPv6 form
+    let mapped: IpAddr = "::ffff:192.0.2.10".parse().unwrap();
+    assert_eq!(
+        reverse_name(mapped),
+        name("a.0.2.0.0.0.0.c.f.f.f.f.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.ip6.arpa")
+    );
+}
+
+/// A server answering `local.lan` from its local records, with `extra` added to its config
+fn serve(extra: &str) -> SocketAddr {
+    let addr = loop {
+        let udp = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = udp.local_addr().unwrap();
+        if TcpListener::bind(addr).is_ok() {
+            break addr;
+        }
+    };
+    let config = format!(
+        "listen = \"{addr}\"\n\
+         upstream = [\"127.0.0.1:9\"]\n\
+         {extra}\n\
+         [[local-records]]\n\
+         name = \"local.lan\"\n\
+         type = \"A\"\n\
+         value = \"192.0.2.1\""
+    );
+    let context = ServerContext::new(config.parse().unwrap()).unwrap();
+    thread::spawn(move || server::run(Arc::new(context)));
+    thread::sleep(Duration::from_millis(100));
+
+    addr
+}
+
+/// Ask `server` for `local.lan`, with the EDNS options given or without EDNS at all
+fn ask(server: SocketAddr, options: Option<Vec<EdnsOption>>) -> DnsPacket {
+    let mut builder = DnsPacket::query("local.lan", QueryType::A).id(0x1234);
+    if let Some(options) = options {
+        builder = builder.edns(1232);
+        for option in options {
+            builder = builder.option(option);
+        }
+    }
+    let mut request = builder.build().unwrap();
+    let mut buf = BytePacketBuffer::new();
+    request.write(&mut buf).unwrap();
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    socket.send_to(&buf.buf[..buf.pos()], server).unwrap();
+    let mut buf = BytePacketBuffer::new();
+    let len = socket.recv(&mut buf.buf).unwrap();
+    buf.buf.truncate(len);
+    DnsPacket::from_buffer(&mut buf).unwrap()
+}
+
+fn nsid_request() -> EdnsOption {
+    EdnsOption {
+        code: OPTION_NSID,
+        data: Vec::new(),
+    }
+}
+
+#[test]
+fn nsid_is_sent_to_clients_asking_for_it() {
+    let server = serve("nsid = \"ns1.fra\"");
+
+    let response = ask(server, Some(vec![nsid_request()]));
+    assert_eq!(response.answers.len(), 1);
+    assert_eq!(response.nsid(), Some(&b"ns1.fra"[..]));
+    assert_eq!(response.edns_options(OPTION_NSID).count(), 1);
+
+    // Only when asked, and only with EDNS
+    assert!(ask(server, Some(Vec::new())).nsid().is_none());
+    let response = ask(server, None);
+    assert!(response.edns().is_none());
+    assert_eq!(response.answers.len(), 1);
+}
+
+#[test]
+fn nsid_is_not_sent_without_one_configured() {
+    let server = serve("");
+
+    let response = ask(server, Some(vec![nsid_request()]));
+    assert!(response.edns().is_some());
+    assert_eq!(response.edns_options(OPTION_NSID).count(), 0);
+    assert!(response.nsid().is_none());
+}